@@ -0,0 +1,4 @@
+-- 002_add_disc_and_track_number.sql
+-- Up migration
+ALTER TABLE tracks ADD COLUMN disc_number INTEGER;
+ALTER TABLE tracks ADD COLUMN track_number INTEGER;
@@ -0,0 +1,3 @@
+-- 003_add_track_file_mtime.sql
+-- Up migration
+ALTER TABLE tracks ADD COLUMN file_mtime TEXT;
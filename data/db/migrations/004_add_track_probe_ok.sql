@@ -0,0 +1,4 @@
+-- 004_add_track_probe_ok.sql
+-- Up migration
+-- tracks synced before this column existed are assumed to have readable tags
+ALTER TABLE tracks ADD COLUMN probe_ok INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,20 @@
+-- 005_add_artist_musicbrainz_id.sql
+-- Up migration
+-- Artist names stop being unique: two bands can share a name and be told apart by their MusicBrainz id.
+-- SQLite can't drop a constraint, so the table is rebuilt. Albums keep referencing the same ids,
+-- the foreign key check is deferred until the rebuilt table is back under its old name.
+PRAGMA defer_foreign_keys = ON;
+
+CREATE TABLE artists_rebuilt (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    musicbrainz_id TEXT
+);
+
+INSERT INTO artists_rebuilt(id, name) SELECT id, name FROM artists;
+
+DROP TABLE artists;
+ALTER TABLE artists_rebuilt RENAME TO artists;
+
+-- one artist per name without an id, as before; same-named artists need distinct ids
+CREATE UNIQUE INDEX artists_name_musicbrainz_id ON artists(name, COALESCE(musicbrainz_id, ''));
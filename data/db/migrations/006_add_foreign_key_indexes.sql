@@ -0,0 +1,6 @@
+-- 006_add_foreign_key_indexes.sql
+-- Up migration
+-- Lookups by parent (tracks of an album, albums of an artist, orphan checks) were full table scans.
+-- UNIQUE(name, artist_id) on albums leads with the name, so it doesn't help artist_id lookups.
+CREATE INDEX IF NOT EXISTS idx_tracks_album_id ON tracks(album_id);
+CREATE INDEX IF NOT EXISTS idx_albums_artist_id ON albums(artist_id);
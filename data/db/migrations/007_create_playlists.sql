@@ -0,0 +1,21 @@
+-- 007_create_playlists.sql
+-- Up migration
+-- Hand-made playlists. Tracks are ordered by position; removing one leaves a gap, the order stays intact.
+-- Deleting a track takes it out of every playlist.
+CREATE TABLE IF NOT EXISTS playlists (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS playlist_tracks (
+    playlist_id BLOB NOT NULL,
+    track_id BLOB NOT NULL,
+    position INTEGER NOT NULL CHECK (position >= 0),
+
+    PRIMARY KEY (playlist_id, position),
+    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+    FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_playlist_tracks_track_id ON playlist_tracks(track_id);
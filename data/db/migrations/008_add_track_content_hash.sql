@@ -0,0 +1,5 @@
+-- 008_add_track_content_hash.sql
+-- Up migration
+-- tracks synced before this column existed stay NULL until the next sync fills them in
+ALTER TABLE tracks ADD COLUMN content_hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_tracks_content_hash ON tracks(content_hash);
@@ -0,0 +1,5 @@
+-- 009_add_track_genre.sql
+-- Up migration
+-- NULL for tracks without a genre tag, and for tracks synced before this column existed
+ALTER TABLE tracks ADD COLUMN genre TEXT;
+CREATE INDEX IF NOT EXISTS idx_tracks_genre ON tracks(genre COLLATE NOCASE);
@@ -0,0 +1,115 @@
+use std::process::ExitCode;
+
+use crate::{repository::RepositoryError, services::{ScanError, SyncServiceError}, utils::{config::ConfigLoadingError, instance_lock::InstanceLockError}};
+
+/// Exit codes the binary reports, so cron jobs and monitoring can tell failures apart without
+/// parsing output. 2 is left out on purpose, clap already uses it for usage errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AppExitCode {
+    Success = 0,
+    Failure = 1,
+    ConfigError = 3,
+    ScanError = 4,
+    DatabaseError = 5,
+    StorageFull = 6,
+    AlreadyRunning = 7,
+    IOError = 8,
+    /// `verify` found the database out of step with the library.
+    Inconsistent = 9,
+}
+
+impl AppExitCode {
+    /// Picks the code for the first error in the chain this app knows about.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if let Some(sync_err) = cause.downcast_ref::<SyncServiceError>() {
+                    return Some(Self::from_sync_error(sync_err));
+                }
+                if let Some(repo_err) = cause.downcast_ref::<RepositoryError>() {
+                    return Some(Self::from_repository_error(repo_err));
+                }
+                if cause.is::<ConfigLoadingError>() {
+                    return Some(Self::ConfigError);
+                }
+                if cause.is::<ScanError>() {
+                    return Some(Self::ScanError);
+                }
+                if cause.is::<sqlx::Error>() || cause.is::<sqlx::migrate::MigrateError>() {
+                    return Some(Self::DatabaseError);
+                }
+                if let Some(lock_err) = cause.downcast_ref::<InstanceLockError>() {
+                    return Some(match lock_err {
+                        InstanceLockError::AlreadyRunning { .. } => Self::AlreadyRunning,
+                        InstanceLockError::IOError { .. } => Self::IOError
+                    });
+                }
+                if cause.is::<std::io::Error>() {
+                    return Some(Self::IOError);
+                }
+
+                None
+            })
+            .unwrap_or(Self::Failure)
+    }
+
+    fn from_sync_error(err: &SyncServiceError) -> Self {
+        match err {
+            SyncServiceError::ConfigLoadingError(_) => Self::ConfigError,
+            SyncServiceError::RepositoryError(repo_err) => Self::from_repository_error(repo_err),
+            SyncServiceError::Sqlx(_) => Self::DatabaseError,
+            SyncServiceError::StorageFull(_) => Self::StorageFull,
+            SyncServiceError::ScanError(_) => Self::ScanError,
+            SyncServiceError::IOError(_) => Self::IOError,
+            SyncServiceError::Inconsistent(_) => Self::Inconsistent,
+            _ => Self::Failure
+        }
+    }
+
+    fn from_repository_error(err: &RepositoryError) -> Self {
+        match err {
+            RepositoryError::StorageFull(_) => Self::StorageFull,
+            _ => Self::DatabaseError
+        }
+    }
+}
+
+impl From<AppExitCode> for ExitCode {
+    fn from(code: AppExitCode) -> Self {
+        ExitCode::from(code as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code_follows_error_category() {
+        let config_err = anyhow::Error::new(ConfigLoadingError::FailedToReadConfig("missing".to_string()));
+        assert_eq!(AppExitCode::from_error(&config_err), AppExitCode::ConfigError);
+
+        let full_err = anyhow::Error::new(SyncServiceError::RepositoryError(RepositoryError::StorageFull("full".to_string())));
+        assert_eq!(AppExitCode::from_error(&full_err), AppExitCode::StorageFull);
+
+        let db_err = anyhow::Error::new(RepositoryError::RowNotFound);
+        assert_eq!(AppExitCode::from_error(&db_err), AppExitCode::DatabaseError);
+
+        let scan_err = anyhow::Error::new(SyncServiceError::ScanError(ScanError::IOError(std::io::Error::other("boom"))));
+        assert_eq!(AppExitCode::from_error(&scan_err), AppExitCode::ScanError);
+
+        let inconsistent_err = anyhow::Error::new(SyncServiceError::Inconsistent(3));
+        assert_eq!(AppExitCode::from_error(&inconsistent_err), AppExitCode::Inconsistent);
+    }
+
+    #[test]
+    fn test_context_does_not_hide_the_category() {
+        let err = anyhow::Error::new(ConfigLoadingError::FailedToReadConfig("missing".to_string())).context("While starting the server");
+        assert_eq!(AppExitCode::from_error(&err), AppExitCode::ConfigError);
+
+        assert_eq!(AppExitCode::from_error(&anyhow!("something else")), AppExitCode::Failure);
+    }
+}
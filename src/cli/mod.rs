@@ -1,16 +1,62 @@
-use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand};
+use log::LevelFilter;
+
+pub mod exit_code;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Don't print reports, only signal the outcome through the exit code. Errors still go to stderr
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// Log more: -v for info, -vv for debug, -vvv for trace. Without it RUST_LOG applies, warn by default
+    #[arg(long, short, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log level to use: off, error, warn, info, debug or trace. Unlike -v it can also turn logging down
+    #[arg(long = "log-level", value_name = "LEVEL", global = true, conflicts_with = "verbose")]
+    pub level: Option<LevelFilter>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    /// The level asked for with `--log-level` or `-v`, `None` when neither was given.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        if self.level.is_some() {
+            return self.level;
+        }
+
+        match self.verbose {
+            0 => None,
+            1 => Some(LevelFilter::Info),
+            2 => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace)
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Serve(ServerArgs),
     Prepare(PrepareArgs),
+    Backup(BackupArgs),
+    RepairPaths(RepairPathsArgs),
+    /// Rebuild the database file to give back the space left by deleted rows. Needs as much free disk
+    /// space as the database takes and an exclusive lock on it, so the server must not be running
+    Compact,
+    /// Check the database against the music library without changing either. Lists missing and untracked
+    /// files and orphaned albums and artists, and exits with 9 if anything is out of place
+    Verify,
+    /// List the tracks that are the same song, by artist, album, title and duration, under different files
+    Duplicates,
+    /// Print the configuration as resolved from config.toml, defaults included, and whether each of its
+    /// paths exists. Needs neither the database nor ffmpeg
+    Config,
 }
 
 /// Arguments for the `serve` command
@@ -27,6 +73,14 @@ pub struct ServerArgs {
     #[arg(long, group = "action")]
     pub scan: bool,
 
+    /// Write a snapshot of the scanned library (path → size + mtime) to the given file
+    #[arg(long, value_name = "FILE", requires = "scan")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Compare the library against a previously written snapshot and print what changed
+    #[arg(long, value_name = "FILE", requires = "scan")]
+    pub compare: Option<PathBuf>,
+
     /// Resample audio files
     #[arg(long, group = "action")]
     pub resample: bool,
@@ -34,6 +88,11 @@ pub struct ServerArgs {
     /// Sync with a remote backup
     #[arg(long, group = "action")]
     pub sync: bool,
+
+    /// Print what --sync would change without touching the database
+    // `requires` alone lets the other actions through, they share a group with --sync
+    #[arg(long, requires = "sync", conflicts_with_all = ["dry_start", "scan", "resample"])]
+    pub dry_run: bool,
 }
 
 /// Arguments for the `prepare` command
@@ -42,4 +101,62 @@ pub struct PrepareArgs {
     /// Use development-specific settings
     #[arg(long)]
     pub dev: bool,
-}
\ No newline at end of file
+}
+
+/// Arguments for the `repair-paths` command
+#[derive(Args, Debug)]
+pub struct RepairPathsArgs {
+    /// Directory the library used to be in, as the tracks were synced from it
+    #[arg(long)]
+    pub from: PathBuf,
+
+    /// Directory the library is in now
+    #[arg(long)]
+    pub to: PathBuf,
+
+    /// Only report what would be rewritten
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Arguments for the `backup` command
+#[derive(Args, Debug)]
+pub struct BackupArgs {
+    /// Where to write the backup. Defaults to a timestamped file next to the database
+    #[arg(long)]
+    pub to: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbose_count_maps_to_log_level() {
+        let level = |args: &[&str]| Cli::try_parse_from(args).expect("Arguments should parse").log_level();
+
+        assert_eq!(level(&["home-server", "serve"]), None);
+        assert_eq!(level(&["home-server", "-v", "serve"]), Some(LevelFilter::Info));
+        assert_eq!(level(&["home-server", "serve", "-vv"]), Some(LevelFilter::Debug));
+        assert_eq!(level(&["home-server", "-vvvv", "backup"]), Some(LevelFilter::Trace));
+    }
+
+    #[test]
+    fn test_log_level_flag() {
+        let level = |args: &[&str]| Cli::try_parse_from(args).map(|cli| cli.log_level());
+
+        assert_eq!(level(&["home-server", "--log-level", "off", "serve"]).ok(), Some(Some(LevelFilter::Off)));
+        assert_eq!(level(&["home-server", "serve", "--log-level", "DEBUG"]).ok(), Some(Some(LevelFilter::Debug)));
+        assert!(level(&["home-server", "--log-level", "loud", "serve"]).is_err());
+        assert!(level(&["home-server", "-v", "--log-level", "error", "serve"]).is_err());
+    }
+
+    #[test]
+    fn test_dry_run_requires_sync() {
+        let cli = Cli::try_parse_from(["home-server", "serve", "--sync", "--dry-run"]).expect("Arguments should parse");
+        assert!(matches!(cli.command, Commands::Serve(ServerArgs { sync: true, dry_run: true, .. })));
+
+        assert!(Cli::try_parse_from(["home-server", "serve", "--dry-run"]).is_err());
+        assert!(Cli::try_parse_from(["home-server", "serve", "--scan", "--dry-run"]).is_err());
+    }
+}
@@ -1,8 +1,18 @@
-use super::{Uuid, ValidationError};
+use chrono::{Datelike, Local};
+
+use super::{Uuid, ValidationError, Serialize, Deserialize};
 
 use crate::utils::normalizations::normalize_name;
 
-#[derive(Debug, Clone, Hash)]
+/// No album predates the first sound recordings.
+pub const MIN_ALBUM_YEAR: u32 = 1860;
+
+/// Latest year an album can be from. Releases are often tagged ahead of time, so next year is allowed.
+pub fn max_album_year() -> u32 {
+    u32::try_from(Local::now().year() + 1).unwrap_or(u32::MAX)
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Album {
     id: Uuid,
     name: String,
@@ -31,6 +41,9 @@ impl Album {
     {
         let norm_name = normalize_name(&name.into());
         if norm_name.len() == 0 { return Err(ValidationError::NameIsEmptyString); }
+        if let Some(year) = year && !(MIN_ALBUM_YEAR..=max_album_year()).contains(&year) {
+            return Err(ValidationError::YearOutOfRange(year));
+        }
 
         Ok(
             Self { id, name: norm_name, artist_id, year }
@@ -52,4 +65,33 @@ impl Album {
     pub fn year(&self) -> Option<u32> {
         self.year
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_album_accepts_a_plausible_year() {
+        let album = Album::new(Uuid::new_v4(), "Wonder What's Next", Uuid::new_v4(), Some(2002)).unwrap();
+        assert_eq!(album.year(), Some(2002));
+
+        let next_year = Album::new(Uuid::new_v4(), "Upcoming", Uuid::new_v4(), Some(max_album_year())).unwrap();
+        assert_eq!(next_year.year(), Some(max_album_year()));
+    }
+
+    #[test]
+    fn test_album_rejects_years_out_of_range() {
+        let far_future = Album::new(Uuid::new_v4(), "Garbage Tag", Uuid::new_v4(), Some(9999));
+        assert!(matches!(far_future, Err(ValidationError::YearOutOfRange(9999))));
+
+        let too_early = Album::new(Uuid::new_v4(), "Garbage Tag", Uuid::new_v4(), Some(MIN_ALBUM_YEAR - 1));
+        assert!(matches!(too_early, Err(ValidationError::YearOutOfRange(_))));
+    }
+
+    #[test]
+    fn test_album_without_year() {
+        let album = Album::new(Uuid::new_v4(), "Untagged", Uuid::new_v4(), None).unwrap();
+        assert_eq!(album.year(), None);
+    }
 }
\ No newline at end of file
@@ -1,10 +1,13 @@
 use super::{Uuid, ValidationError};
 use crate::utils::normalizations::normalize_name;
 
+/// Names aren't unique in reality (there is more than one "Nirvana"), so same-named artists
+/// can be told apart by `musicbrainz_id`. Equality still only looks at the name.
 #[derive(Clone, Debug)]
 pub struct Artist {
     id: Uuid,
-    name: String
+    name: String,
+    musicbrainz_id: Option<String>
 }
 
 impl AsRef<Artist> for Artist {
@@ -30,7 +33,7 @@ impl Artist {
         if norm_name.len() == 0 { return Err(ValidationError::NameIsEmptyString); }
 
         Ok(
-            Self { id, name: norm_name }
+            Self { id, name: norm_name, musicbrainz_id: None }
         )
     }
 
@@ -42,6 +45,10 @@ impl Artist {
         &self.name
     }
 
+    pub fn musicbrainz_id(&self) -> Option<&str> {
+        self.musicbrainz_id.as_deref()
+    }
+
     pub fn set_name<S>(&mut self, name: S) -> Result<(), ValidationError> 
     where S: Into<String>
     {
@@ -55,4 +62,8 @@ impl Artist {
     pub fn set_id(&mut self, id: Uuid) -> () {
         self.id = id
     }
+
+    pub fn set_musicbrainz_id(&mut self, musicbrainz_id: Option<String>) {
+        self.musicbrainz_id = musicbrainz_id
+    }
 }
\ No newline at end of file
@@ -1,8 +1,13 @@
-use std::path::PathBuf;
+use std::{path::{Path, PathBuf}, time::SystemTime};
 
-use lofty::{file::{AudioFile, TaggedFile, TaggedFileExt}, tag::Accessor};
+use chrono::{Datelike, NaiveDate};
+
+use lofty::{file::{AudioFile, TaggedFile, TaggedFileExt}, tag::{Accessor, ItemKey}};
+use sha2::{Digest, Sha256};
 
 use crate::utils::normalizations::normalize_name;
+use serde::Serializer;
+
 use super::{Serialize, Deserialize, OsStr, LoftyFileType};
 
 #[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
@@ -10,6 +15,10 @@ pub enum AudioFileType {
     Flac,
     Mp3,
     Wav,
+    /// Ogg Vorbis.
+    Ogg,
+    /// AAC in an MP4 container.
+    M4a,
     Unknown
 }
 
@@ -20,15 +29,19 @@ impl AudioFileType {
             LoftyFileType::Flac => AudioFileType::Flac,
             LoftyFileType::Mpeg => AudioFileType::Mp3,
             LoftyFileType::Wav => AudioFileType::Wav,
+            LoftyFileType::Vorbis => AudioFileType::Ogg,
+            LoftyFileType::Mp4 => AudioFileType::M4a,
             _other => AudioFileType::Unknown,
         }
     }
 
     pub fn from_extension_str(extension: &str) -> Self {
-        match extension {
+        match extension.to_lowercase().as_str() {
             "flac" => AudioFileType::Flac,
             "mp3" => AudioFileType::Mp3,
             "wav" => AudioFileType::Wav,
+            "ogg" => AudioFileType::Ogg,
+            "m4a" => AudioFileType::M4a,
             _other => AudioFileType::Unknown
         }
     }
@@ -45,14 +58,41 @@ impl AudioFileType {
             AudioFileType::Flac => "flac",
             AudioFileType::Mp3 => "mp3",
             AudioFileType::Wav => "wav",
+            AudioFileType::Ogg => "ogg",
+            AudioFileType::M4a => "m4a",
             AudioFileType::Unknown => "unknown"
         }
     }
 
+    /// Encoder ffmpeg should write this type with, the extension isn't always a codec name.
+    pub fn ffmpeg_codec(&self) -> &'static str {
+        match self {
+            AudioFileType::Ogg => "libvorbis",
+            AudioFileType::M4a => "aac",
+            other => other.as_str()
+        }
+    }
+
+    /// Content-Type the file is served with; the stored type wins over whatever the extension says.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFileType::Flac => "audio/flac",
+            AudioFileType::Mp3 => "audio/mpeg",
+            AudioFileType::Wav => "audio/wav",
+            AudioFileType::Ogg => "audio/ogg",
+            AudioFileType::M4a => "audio/mp4",
+            AudioFileType::Unknown => "application/octet-stream"
+        }
+    }
+
     pub fn is_supported_extension(extension: &OsStr) -> bool {
         let ext_str = extension.to_string_lossy().to_lowercase();
 
-        matches!(ext_str.as_str(), "flac" | "mp3" | "wav")
+        matches!(ext_str.as_str(), "flac" | "mp3" | "wav" | "ogg" | "m4a")
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        matches!(self, AudioFileType::Flac | AudioFileType::Wav)
     }
 
     pub fn get_resample_target_rate(&self) -> u32 {
@@ -72,8 +112,12 @@ pub struct AudioFileMetadata {
     pub album_year: Option<u32>,
 
     pub track_name: String,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
     pub track_duration: u32,
-    pub sample_rate: Option<u32>
+    pub sample_rate: Option<u32>,
+    /// `None` when there's no genre tag, or it's blank.
+    pub genre: Option<String>
 }
 
 impl Default for AudioFileMetadata {
@@ -83,8 +127,11 @@ impl Default for AudioFileMetadata {
             album_name: "unknown album".to_string(),
             album_year: None,
             track_name: "unknown track".to_string(),
+            disc_number: None,
+            track_number: None,
             track_duration: 0,
-            sample_rate: None
+            sample_rate: None,
+            genre: None
         }
     }
 }
@@ -114,26 +161,161 @@ impl AudioFileMetadata {
                 || normalize_name("unknown album"),
                 |s| normalize_name(&s)
             ),
-            album_year: lofty_tag.year(),
+            album_year: lofty_tag.get_string(&ItemKey::Year)
+                .or_else(|| lofty_tag.get_string(&ItemKey::RecordingDate))
+                .map_or_else(|| lofty_tag.year(), parse_year),
             track_name: lofty_tag.title().map_or_else(
                 || normalize_name("unknown track"),
                 |s| normalize_name(&s)
             ),
+            disc_number: lofty_tag.get_string(&ItemKey::DiscNumber)
+                .and_then(parse_numerator)
+                .or_else(|| lofty_tag.disk()),
+            track_number: lofty_tag.get_string(&ItemKey::TrackNumber)
+                .and_then(parse_numerator)
+                .or_else(|| lofty_tag.track()),
 
             track_duration: tagged_file.properties().duration().as_secs().try_into().unwrap_or(0),
-            sample_rate: tagged_file.properties().sample_rate()
+            sample_rate: tagged_file.properties().sample_rate(),
+            genre: lofty_tag.genre()
+                .map(|genre| genre.trim().to_string())
+                .filter(|genre| !genre.is_empty())
        }
     }
+
+    /// Hex SHA-256 of the artist, album, title and duration. Copies of a song under another name,
+    /// or in another format, end up with the same fingerprint; the file isn't read for it.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in [self.artist_name.as_str(), self.album_name.as_str(), self.track_name.as_str()] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(self.track_duration.to_le_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Extracts the year out of `YYYY`, `YYYY-MM-DD` or `YYYY/MM/DD`. Anything else is `None`.
+pub(crate) fn parse_year(value: &str) -> Option<u32> {
+    let value = value.trim();
+
+    if value.len() == 4 && value.chars().all(|c| c.is_ascii_digit()) {
+        return value.parse().ok();
+    }
+
+    ["%Y-%m-%d", "%Y/%m/%d"].iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .and_then(|date| u32::try_from(date.year()).ok())
+}
+
+// Paths that aren't valid UTF-8 would make the default PathBuf serialization fail the whole response.
+fn serialize_path_lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+fn serialize_file_type_ext<S: Serializer>(file_type: &AudioFileType, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(file_type.as_str())
 }
 
-#[derive(Debug, Clone)]
+/// Parses position tags like "1" or "1/2" (disc one of two) by taking the numerator.
+fn parse_numerator(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct AudioFileDescriptor {
+    #[serde(serialize_with = "serialize_path_lossy")]
     pub path: PathBuf,
     pub file_size: u64,
+    /// Last modification time as reported by the filesystem, `None` where it isn't available.
+    pub modified: Option<SystemTime>,
+    #[serde(serialize_with = "serialize_file_type_ext")]
     pub file_type: AudioFileType,
-    pub metadata: AudioFileMetadata
+    pub metadata: AudioFileMetadata,
+    /// `false` when the metadata are defaults because the tags couldn't be read.
+    pub probe_ok: bool,
+    /// `AudioFileMetadata::fingerprint`, `None` when the tags couldn't be read.
+    pub content_hash: Option<String>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // TODO: cache
-    // modified_time: SystemTime,
-    // checksum: Option<u64>,
+    #[test]
+    fn test_parse_year_plain() {
+        assert_eq!(parse_year("2023"), Some(2023));
+        assert_eq!(parse_year(" 1970 "), Some(1970));
+    }
+
+    #[test]
+    fn test_parse_year_dashed_date() {
+        assert_eq!(parse_year("2023-05-01"), Some(2023));
+    }
+
+    #[test]
+    fn test_parse_year_slashed_date() {
+        assert_eq!(parse_year("2023/05/01"), Some(2023));
+    }
+
+    #[test]
+    fn test_parse_year_garbage() {
+        assert_eq!(parse_year("sometime in the 90s"), None);
+        assert_eq!(parse_year("2023-13-45"), None);
+        assert_eq!(parse_year("23"), None);
+        assert_eq!(parse_year(""), None);
+    }
+
+    #[test]
+    fn test_descriptor_serialization() {
+        let descriptor = AudioFileDescriptor {
+            path: PathBuf::from("music/artist/track.flac"),
+            file_size: 420,
+            modified: None,
+            file_type: AudioFileType::Flac,
+            metadata: AudioFileMetadata { album_year: Some(2002), ..Default::default() },
+            probe_ok: true,
+            content_hash: None
+        };
+
+        let json = serde_json::to_value(&descriptor).expect("Descriptor should serialize");
+
+        assert_eq!(json["path"], "music/artist/track.flac");
+        assert_eq!(json["file_type"], "flac");
+        assert_eq!(json["file_size"], 420);
+        assert_eq!(json["metadata"]["album_year"], 2002);
+    }
+
+    #[test]
+    fn test_ogg_and_m4a_round_trip() {
+        for file_type in [AudioFileType::Ogg, AudioFileType::M4a] {
+            assert_eq!(AudioFileType::from_extension_str(file_type.as_str()), file_type);
+            assert!(AudioFileType::is_supported_extension(OsStr::new(file_type.as_str())));
+            assert!(!file_type.is_lossless());
+        }
+
+        assert_eq!(AudioFileType::from_os_ext(OsStr::new("OGG")), AudioFileType::Ogg);
+        assert_eq!(AudioFileType::from_lofty(&LoftyFileType::Vorbis), AudioFileType::Ogg);
+        assert_eq!(AudioFileType::from_lofty(&LoftyFileType::Mp4), AudioFileType::M4a);
+        assert_eq!(AudioFileType::M4a.ffmpeg_codec(), "aac");
+        assert_eq!(AudioFileType::M4a.mime_type(), "audio/mp4");
+    }
+
+    #[test]
+    fn test_fingerprint_follows_tags_and_duration() {
+        let metadata = AudioFileMetadata { artist_name: "chevelle".to_string(), track_name: "the red".to_string(), track_duration: 238, ..Default::default() };
+
+        let other_format = AudioFileMetadata { sample_rate: Some(44100), ..metadata.clone() };
+        assert_eq!(metadata.fingerprint(), other_format.fingerprint());
+        assert_eq!(metadata.fingerprint().len(), 64);
+
+        let other_duration = AudioFileMetadata { track_duration: 239, ..metadata.clone() };
+        assert_ne!(metadata.fingerprint(), other_duration.fingerprint());
+
+        // fields are separated, so text can't shift from one into the next
+        let shifted = AudioFileMetadata { artist_name: "chevellethe".to_string(), track_name: " red".to_string(), ..metadata.clone() };
+        assert_ne!(metadata.fingerprint(), shifted.fingerprint());
+    }
 }
\ No newline at end of file
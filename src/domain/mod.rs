@@ -3,6 +3,7 @@ pub mod album;
 pub mod artist;
 pub mod uploaded;
 pub mod audiofile;
+pub mod playlist;
 
 use std::ffi::OsStr;
 use serde::{Serialize, Deserialize};
@@ -25,7 +26,10 @@ pub enum ValidationError {
     DurationIsZero,
 
     #[error("File size cannot be zero.")]
-    FileSizeIsZero    
+    FileSizeIsZero,
+
+    #[error("Year {0} is out of range.")]
+    YearOutOfRange(u32)
 }
 
 #[derive(Debug)]
@@ -0,0 +1,37 @@
+use super::{Uuid, ValidationError};
+
+/// A list of tracks put together by hand. The name only has the surrounding whitespace trimmed, case and
+/// everything else are kept: unlike artist and album names it doesn't come from tags and isn't matched against anything.
+#[derive(Clone, Debug)]
+pub struct Playlist {
+    id: Uuid,
+    name: String
+}
+
+impl AsRef<Playlist> for Playlist {
+    fn as_ref(&self) -> &Playlist {
+        self
+    }
+}
+
+impl Playlist {
+
+    pub fn new<S>(id: Uuid, name: S) -> Result<Self, ValidationError>
+    where S: Into<String>
+    {
+        let name = name.into().trim().to_string();
+        if name.is_empty() { return Err(ValidationError::NameIsEmptyString); }
+
+        Ok(
+            Self { id, name }
+        )
+    }
+
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
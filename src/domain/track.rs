@@ -1,5 +1,6 @@
-use std::{fmt::Debug, path::PathBuf};
+use std::{fmt::Debug, path::PathBuf, str::FromStr};
 use chrono::NaiveDateTime;
+use serde::de::IntoDeserializer;
 
 use crate::domain::audiofile::AudioFileType;
 use crate::domain::uploaded::Uploaded;
@@ -17,7 +18,13 @@ pub struct Track {
     file_size: u64,
     file_type: AudioFileType,
     uploaded: Uploaded,
-    date_added: Option<NaiveDateTime>
+    date_added: Option<NaiveDateTime>,
+    disc_number: Option<u32>,
+    track_number: Option<u32>,
+    file_mtime: Option<NaiveDateTime>,
+    probe_ok: bool,
+    content_hash: Option<String>,
+    genre: Option<String>
 }
 
 impl AsRef<Track> for Track {
@@ -56,7 +63,13 @@ impl Track {
                 file_size,
                 file_type,
                 uploaded,
-                date_added
+                date_added,
+                disc_number: None,
+                track_number: None,
+                file_mtime: None,
+                probe_ok: true,
+                content_hash: None,
+                genre: None
             }
         )
     }
@@ -96,4 +109,79 @@ impl Track {
     pub fn date_added(&self) -> &Option<NaiveDateTime> {
         &self.date_added
     }
-}
\ No newline at end of file
+
+    pub fn disc_number(&self) -> Option<u32> {
+        self.disc_number
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.track_number
+    }
+
+    pub fn set_disc_number(&mut self, disc_number: Option<u32>) {
+        self.disc_number = disc_number
+    }
+
+    pub fn set_track_number(&mut self, track_number: Option<u32>) {
+        self.track_number = track_number
+    }
+
+    /// Modification time of the file (UTC) when it was last synced.
+    pub fn file_mtime(&self) -> &Option<NaiveDateTime> {
+        &self.file_mtime
+    }
+
+    pub fn set_file_mtime(&mut self, file_mtime: Option<NaiveDateTime>) {
+        self.file_mtime = file_mtime
+    }
+
+    /// `false` when the tags couldn't be read during the scan and the track got default metadata.
+    pub fn probe_ok(&self) -> bool {
+        self.probe_ok
+    }
+
+    pub fn set_probe_ok(&mut self, probe_ok: bool) {
+        self.probe_ok = probe_ok
+    }
+
+    /// Fingerprint of the file the duplicates are found by, `None` for tracks synced before it was kept.
+    pub fn content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
+    pub fn set_content_hash(&mut self, content_hash: Option<String>) {
+        self.content_hash = content_hash
+    }
+
+    /// Genre as tagged, `None` when the file has no genre tag.
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn set_genre(&mut self, genre: Option<String>) {
+        self.genre = genre
+    }
+}
+
+/// Order of a track listing. Ties are broken by path, so paging through a listing is stable.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSort {
+    #[default]
+    Name,
+    /// Newest first.
+    DateAdded,
+    Duration,
+    /// By artist, then album, then position on the album.
+    Artist
+}
+
+/// Parsed the way serde reads it, so `?sort=` and `default_track_sort` accept the same names.
+impl FromStr for TrackSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+            .map_err(|_: serde::de::value::Error| format!("Unknown track sort '{}', expected name, date_added, duration or artist", s))
+    }
+}
@@ -1,103 +1,194 @@
-use std::path::{PathBuf};
+use std::{process::ExitCode, sync::Arc};
 
 use clap::Parser;
-use anyhow::Error;
+use indicatif::{ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use anyhow::{anyhow, Context, Error};
 
 use home_server::{
-    cli::{Cli, Commands}, 
-    services::{prepare::{create_fixture_audio_files, run_prepare_devspace, run_prepare_userspace}, resample::{FfmpegResampler, ResampleConfig, ResampleService, ResampleStrategy}, scanner::MediaScanner, sync::MusicLibSyncService}, 
-    utils::{config::get_config, db::get_application_db}, 
-    web::routes::create_router
+    cli::{exit_code::AppExitCode, Cli, Commands}, 
+    repository::SqliteTracksRepository,
+    services::{prepare::{create_fixture_audio_files, run_prepare_devspace, run_prepare_userspace}, repair_paths::repair_paths, resample::{FfmpegResampler, ResampleConfig, ResampleService}, scanner::MediaScanner, sync::{MusicLibSyncService, SyncPlan, SyncServiceReport, VerifyReport}, SyncServiceError}, 
+    utils::{config::{get_config, Config, ListenAddress, MediaConfig}, db::{default_backup_path, get_application_db, Database}, instance_lock::InstanceLock, progress}, 
+    web::{listener::ServerListener, routes::{create_router, create_router_and_state, RouterSettings}, StartupStatus}
 };
 
+// println! that stays silent under --quiet
+macro_rules! report {
+    ($quiet:expr, $($arg:tt)*) => {
+        if !$quiet {
+            println!($($arg)*);
+        }
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    init_logger(&cli);
+
+    match run(&cli).await {
+        Ok(()) => AppExitCode::Success.into(),
+        Err(err) => {
+            log::error!("{:?}", err);
+            AppExitCode::from_error(&err).into()
+        }
+    }
+}
+
+/// Before anything else runs, so config loading and DB setup are logged too.
+/// `--log-level` or `-v` win over RUST_LOG; with none of them, only warnings and errors are shown.
+fn init_logger(cli: &Cli) {
+    let mut builder = match cli.log_level() {
+        Some(level) => {
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(level);
+            builder
+        },
+        None => env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+    };
+
+    // lines are written through the progress bars, which get out of the way while one is printed
+    let logger = builder.build();
+    let level = logger.filter();
+    if LogWrapper::new(progress::multi_progress().clone(), logger).try_init().is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+async fn run(cli: &Cli) -> Result<(), Error> {
+    let quiet = cli.quiet;
 
     match &cli.command {
         Commands::Serve(args) => {
 
+            // held until the end of this arm, so the lock is released once the server has shut down
+            let _instance_lock = InstanceLock::acquire(&get_config()?.server.lock_path)?;
+
             if args.dry_start {
 
                 let db = get_application_db().await?;
-                let app = create_router(db.get_pool()).await?;
+                let config = get_config()?;
+                let app = create_router(db.get_pool(), RouterSettings::from_config(config), Arc::new(StartupStatus::ready())).await?;
 
-                let address = "0.0.0.0:8080";
-                let listener = tokio::net::TcpListener::bind(address).await?;
+                let (listener, address) = bind_listener(config).await?;
 
-                println!("Listening on http://{}", address);
+                report!(quiet, "Listening on {}", address);
 
-                axum::serve(listener, app).await?;
+                listener.serve(app, shutdown_signal()).await?;
 
             } else if args.scan {
 
                 let config = get_config()?;
-                let scanner = MediaScanner::new(config.media.music_path.clone());
-                let scanning_result = scanner.scan_music_lib()?;
+                let scanner = MediaScanner::from_config(config);
+
+                let pb = if quiet { ProgressBar::hidden() } else { progress::track(ProgressBar::new_spinner()) };
+                pb.set_style(ProgressStyle::default_spinner().template("{spinner:.green} [{elapsed_precise}] {msg}")?);
+                let bar_style = ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")?
+                    .progress_chars("#>-");
+
+                // a spinner while walking, then a bar once the walk has counted the audio files
+                let scanning_result = scanner.scan_music_lib_with_progress(|progress| {
+                    if progress.files_processed == 0 {
+                        pb.set_message(format!("{} files seen, {} audio: {}", progress.files_seen, progress.audio_files_found, progress.current_path.display()));
+                        pb.tick();
+                        return;
+                    }
+
+                    if pb.length().is_none() {
+                        pb.set_length(progress.audio_files_found as u64);
+                        pb.set_style(bar_style.clone());
+                    }
+                    pb.set_position(progress.files_processed as u64);
+                })?;
+                pb.finish_and_clear();
 
                 if scanning_result.descriptors.is_empty() && scanning_result.errors.is_empty() {
-                    println!("Music library is empty. Consider adding some tracks into ./data/media/music/");
+                    report!(quiet, "Music library is empty. Consider adding some tracks into ./data/media/music/");
                 } else {
-                    println!("{:?}", scanning_result);
+                    report!(quiet, "{:?}", scanning_result);
+                }
+
+                if let Some(previous_path) = &args.compare {
+                    let diff = scanner.diff_against_snapshot(previous_path)?;
+
+                    if diff.is_empty() {
+                        report!(quiet, "No changes since {}", previous_path.display());
+                    } else {
+                        diff.added.iter().for_each(|path| report!(quiet, "+ {}", path.display()));
+                        diff.removed.iter().for_each(|path| report!(quiet, "- {}", path.display()));
+                        diff.changed.iter().for_each(|path| report!(quiet, "~ {}", path.display()));
+                    }
+                }
+
+                if let Some(snapshot_path) = &args.snapshot {
+                    scanner.snapshot()?.write_to(snapshot_path)?;
+                    report!(quiet, "Snapshot written to {}", snapshot_path.display());
                 }
 
             } else if args.resample {
 
                 let config = get_config()?;
 
-                let scanner = MediaScanner::new(config.media.music_path.clone());
-                let scanning_result = scanner.scan_music_lib()?;
+                if !config.features.resample {
+                    return Err(anyhow!("Resampling is disabled. Set `resample = true` under [features] in config.toml to use --resample."));
+                }
 
-                let resample_cofig = ResampleConfig {
-                    strategy: ResampleStrategy::InPlace,
-                    ..Default::default()
-                };
-                let ffmpeg_resampler = FfmpegResampler { ffmpeg_path: PathBuf::from("./ffmpeg/ffmpeg.exe")};
-                let resample_service = ResampleService::new(resample_cofig, ffmpeg_resampler);
+                let resample_service = build_resample_service(&config.media)?;
+
+                let scanner = MediaScanner::from_config(config);
+                let scanning_result = scanner.scan_music_lib()?;
 
                 let resample_report = resample_service.resample_library(&scanning_result);
-                println!("{:?}", resample_report);
+                report!(quiet, "{:?}", resample_report);
 
             } else if args.sync {
 
                 let db = get_application_db().await?;
                 let config = get_config()?;
 
-                let sync_service = MusicLibSyncService::new(db.get_pool(), config.media.music_path.clone()).await?;
-                let sync_report = sync_service.synchronize().await?;
+                let mut sync_service = MusicLibSyncService::from_config(db.get_pool(), config).await?;
 
-                println!("{:?}", sync_report);
+                if args.dry_run {
+                    print_sync_plan(quiet, &sync_service.plan().await?);
+                } else {
+                    let sync_report = sync_service.synchronize().await?;
+                    report!(quiet, "{}", sync_report);
+                    print_sync_failures(&sync_report);
+                }
 
             } else {
 
                 let db = get_application_db().await?;
                 let config = get_config()?;
 
-                let scanner = MediaScanner::new(config.media.music_path.clone());
-                let scanning_result = scanner.scan_music_lib()?;
-
-                let resample_cofig = ResampleConfig {
-                    strategy: ResampleStrategy::InPlace,
-                    ..Default::default()
-                };
-
-                let ffmpeg_resampler = FfmpegResampler { ffmpeg_path: PathBuf::from("./ffmpeg/ffmpeg.exe")};
-                let resample_service = ResampleService::new(resample_cofig, ffmpeg_resampler);
-
-                let _resample_report = resample_service.resample_library(&scanning_result);
+                // a read-only instance must not touch the library or the DB, so it serves whatever was synced last
+                let read_only = config.server.read_only;
+                if read_only {
+                    report!(quiet, "Read-only mode: skipping resample and sync");
+                }
 
-                let sync_service = MusicLibSyncService::new(db.get_pool(), config.media.music_path.clone()).await?;
-                let _sync_report = sync_service.synchronize().await?;
+                let startup = Arc::new(if read_only { StartupStatus::ready() } else { StartupStatus::starting() });
+                let (app, app_state) = create_router_and_state(db.get_pool(), RouterSettings::from_config(config), Arc::clone(&startup)).await?;
 
-                let app = create_router(db.get_pool()).await?;
+                let (listener, address) = bind_listener(config).await?;
 
-                let address = "0.0.0.0:8080";
-                let listener = tokio::net::TcpListener::bind(address).await?;
+                report!(quiet, "Listening on {}", address);
 
-                println!("Listening on http://{}", address);
+                // the listener is already up, so /health can tell clients the server is starting
+                if !read_only {
+                    tokio::spawn(async move {
+                        if let Err(err) = initial_sync(db, config).await {
+                            log::error!("Initial sync has failed, serving what was synced before: {:?}", err);
+                        }
+                        // reads went on during the sync, what they cached may have been moved or re-tagged since
+                        app_state.invalidate_all();
+                        startup.mark_ready();
+                    });
+                }
 
-                axum::serve(listener, app).await?;
+                listener.serve(app, shutdown_signal()).await?;
 
             }
         },
@@ -105,13 +196,90 @@ async fn main() -> Result<(), Error> {
         Commands::Prepare(args) => {
             
             if args.dev {
-                println!("UNDER CONSTRUCTION");
+                report!(quiet, "UNDER CONSTRUCTION");
                 let config = get_config()?;
                 create_fixture_audio_files(config)?;
             } else {
-                println!("\n\nRunning preparation service..");
+                report!(quiet, "\n\nRunning preparation service..");
                 run_prepare_userspace().await?;
-                println!("Preparation service is complete.");
+                report!(quiet, "Preparation service is complete.");
+            }
+        },
+
+        Commands::Backup(args) => {
+            let db = get_application_db().await?;
+            let config = get_config()?;
+
+            let dest = args.to.clone().unwrap_or_else(|| default_backup_path(&config.database.path));
+            let backup_size = db.backup_into(&dest).await?;
+
+            report!(quiet, "Database backed up to {} ({} bytes)", dest.display(), backup_size);
+        },
+
+        Commands::Compact => {
+            // VACUUM can't run next to a server holding the DB, and a server starting halfway through would hang on the lock
+            let _instance_lock = InstanceLock::acquire(&get_config()?.server.lock_path)?;
+
+            let db = get_application_db().await?;
+            let config = get_config()?;
+
+            report!(quiet, "Compacting {}, this needs as much free disk space as the database takes..", config.database.path.display());
+            let (size_before, size_after) = db.compact(&config.database.path).await?;
+
+            report!(quiet, "Database compacted: {} -> {} bytes", size_before, size_after);
+        },
+
+        Commands::Config => {
+            let config = get_config()?;
+            report!(quiet, "{}", toml::to_string_pretty(config)?);
+
+            for (key, path) in config.paths() {
+                let note = if path.exists() { "exists" } else { "missing" };
+                report!(quiet, "{} = {} ({})", key, path.display(), note);
+            }
+        },
+
+        Commands::Duplicates => {
+            let db = get_application_db().await?;
+            let groups = SqliteTracksRepository::new().find_duplicate_groups(db.get_pool()).await?;
+
+            if groups.is_empty() {
+                report!(quiet, "No duplicates found");
+            }
+
+            for group in &groups {
+                report!(quiet, "{} ({} copies):", group[0].name(), group.len());
+                group.iter().for_each(|track| report!(quiet, "  {}", track.file_path().display()));
+            }
+        },
+
+        Commands::Verify => {
+            let db = get_application_db().await?;
+            let config = get_config()?;
+
+            let sync_service = MusicLibSyncService::from_config(db.get_pool(), config).await?;
+
+            let verify_report = sync_service.verify().await?;
+            print_verify_report(quiet, &verify_report);
+
+            if !verify_report.is_consistent() {
+                return Err(SyncServiceError::Inconsistent(verify_report.inconsistencies()).into());
+            }
+        },
+
+        Commands::RepairPaths(args) => {
+            let db = get_application_db().await?;
+            let report = repair_paths(db.get_pool(), &args.from, &args.to, args.dry_run).await?;
+
+            if !report.collisions.is_empty() {
+                report.collisions.iter().for_each(|(old, new)| log::warn!("{} -> {} is already taken", old.display(), new.display()));
+                return Err(anyhow!("{} of {} paths would collide with existing tracks, nothing was changed", report.collisions.len(), report.matched));
+            }
+
+            if report.dry_run {
+                report!(quiet, "Would update {} track paths", report.matched);
+            } else {
+                report!(quiet, "Updated {} track paths", report.updated);
             }
         }
     }
@@ -119,3 +287,114 @@ async fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Resample (when enabled) and sync that `serve` runs on startup, in the background.
+async fn initial_sync(db: &'static Database, config: &'static Config) -> Result<(), Error> {
+    if config.features.resample {
+        tokio::task::spawn_blocking(move || -> Result<(), Error> {
+            // without ffmpeg the library is still synced, just not resampled
+            let resample_service = match build_resample_service(&config.media) {
+                Ok(resample_service) => resample_service,
+                Err(err) => {
+                    log::error!("Skipping the startup resample: {}", err);
+                    return Ok(());
+                }
+            };
+
+            let scanner = MediaScanner::from_config(config);
+            let scanning_result = scanner.scan_music_lib()?;
+
+            let _resample_report = resample_service.resample_library(&scanning_result);
+            Ok(())
+        }).await??;
+    }
+
+    let mut sync_service = MusicLibSyncService::from_config(db.get_pool(), config).await?;
+    let _sync_report = sync_service.synchronize().await?;
+
+    Ok(())
+}
+
+/// Lists the planned changes the way `serve --scan --compare` lists changed files, then the totals.
+fn print_sync_plan(quiet: bool, plan: &SyncPlan) {
+    if plan.is_empty() {
+        report!(quiet, "Nothing to sync");
+        return;
+    }
+
+    plan.additions.tracks.iter().for_each(|path| report!(quiet, "+ {}", path.display()));
+    plan.deletions.tracks.iter().for_each(|(_, path)| report!(quiet, "- {}", path.display()));
+    plan.updated_tracks.iter().for_each(|path| report!(quiet, "~ {}", path.display()));
+    plan.moved_tracks.iter().for_each(|(old, new)| report!(quiet, "> {} -> {}", old.display(), new.display()));
+
+    report!(quiet, "Would add {} tracks, {} albums, {} artists", plan.additions.tracks.len(), plan.additions.albums.len(), plan.additions.artists.len());
+    report!(quiet, "Would delete {} tracks, {} albums, {} artists", plan.deletions.tracks.len(), plan.deletions.album_ids.len(), plan.deletions.artist_ids.len());
+    report!(quiet, "Would update {} tracks and move {}", plan.updated_tracks.len(), plan.moved_tracks.len());
+}
+
+/// Rows and files that didn't make it into the DB, printed even with --quiet since the summary line only counts them.
+fn print_sync_failures(report: &SyncServiceReport) {
+    let saves = [("track", &report.added_tracks), ("album", &report.added_albums), ("artist", &report.added_artists)];
+    for (kind, saved) in saves {
+        for outcome in saved.failed() {
+            if let Err(err) = &outcome.result {
+                eprintln!("Failed to add {} #{}: {}", kind, outcome.batch_index, err);
+            }
+        }
+    }
+
+    let deletes = [("track", &report.deleted_tracks), ("album", &report.deleted_albums), ("artist", &report.deleted_artists)];
+    for (kind, deleted) in deletes {
+        deleted.failed.iter().for_each(|(id, err)| eprintln!("Failed to delete {} {}: {}", kind, id, err));
+    }
+
+    report.skipped_files.iter().for_each(|path| eprintln!("Skipped {}, its size couldn't be read", path.display()));
+}
+
+fn print_verify_report(quiet: bool, report: &VerifyReport) {
+    if report.is_consistent() {
+        report!(quiet, "Database matches the music library");
+        return;
+    }
+
+    report!(quiet, "Missing files ({}):", report.missing_files.len());
+    report.missing_files.iter().for_each(|(_, path)| report!(quiet, "  {}", path.display()));
+    report!(quiet, "Untracked files ({}):", report.untracked_files.len());
+    report.untracked_files.iter().for_each(|path| report!(quiet, "  {}", path.display()));
+    report!(quiet, "Moved files ({}):", report.moved_files.len());
+    report.moved_files.iter().for_each(|(old, new)| report!(quiet, "  {} -> {}", old.display(), new.display()));
+    report!(quiet, "Stale tags ({}):", report.stale_tags.len());
+    report.stale_tags.iter().for_each(|path| report!(quiet, "  {}", path.display()));
+    report!(quiet, "Orphaned albums ({}):", report.orphaned_album_ids.len());
+    report.orphaned_album_ids.iter().for_each(|id| report!(quiet, "  {}", id));
+    report!(quiet, "Orphaned artists ({}):", report.orphaned_artist_ids.len());
+    report.orphaned_artist_ids.iter().for_each(|id| report!(quiet, "  {}", id));
+    report!(quiet, "Empty albums ({}):", report.empty_album_ids.len());
+    report.empty_album_ids.iter().for_each(|id| report!(quiet, "  {}", id));
+    report!(quiet, "Empty artists ({}):", report.empty_artist_ids.len());
+    report.empty_artist_ids.iter().for_each(|id| report!(quiet, "  {}", id));
+}
+
+/// Fails right away if ffmpeg can't be run, before any scanning.
+fn build_resample_service(media: &MediaConfig) -> Result<ResampleService<FfmpegResampler>, Error> {
+    let ffmpeg_resampler = FfmpegResampler::new(media.ffmpeg_exe_path.clone(), &media.resample)?;
+    let config = ResampleConfig::from_settings(&media.resample, &media.music_path, &media.resampled_music_path);
+
+    Ok(ResampleService::new(config, ffmpeg_resampler))
+}
+
+/// Binds `server.listen`, or `server.host` and `server.port` without it, naming the address on failure so a taken port or a typo is obvious.
+async fn bind_listener(config: &Config) -> Result<(ServerListener, ListenAddress), Error> {
+    let address = config.server.listen_address()?;
+    let bound = ServerListener::bind(&config.server).await
+        .with_context(|| format!("Failed to bind the server to {}", address))?;
+
+    Ok(bound)
+}
+
+async fn shutdown_signal() {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for the shutdown signal: {}", err);
+        std::future::pending::<()>().await;
+    }
+}
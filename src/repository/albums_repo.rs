@@ -2,8 +2,8 @@ use futures::{Stream, StreamExt};
 use sqlx::{Executor, FromRow, QueryBuilder, Row, Sqlite, SqliteConnection};
 use uuid::Uuid;
 
-use crate::domain::{album::Album, BatchDeleteReport, BatchSaveOutcome, BatchSaveReport, ValidationError};
-use super::{IntoUuid, RepositoryError};
+use crate::{domain::{album::{max_album_year, Album, MIN_ALBUM_YEAR}, BatchDeleteReport, BatchSaveOutcome, BatchSaveReport, ValidationError}, utils::normalizations::normalize_name};
+use super::{escape_like, IntoUuid, RepositoryError};
 
 #[derive(FromRow)]
 struct DbAlbum {
@@ -17,13 +17,15 @@ impl TryFrom<DbAlbum> for Album {
     type Error = AlbumConversionError;
 
     fn try_from(db_album: DbAlbum) -> Result<Self, Self::Error> {
-        let year = match db_album.year {
-            Some(int_year) => {
-                if int_year <= 0 { return Err(AlbumConversionError::YearLessOrEqualToZero(int_year)); };
-                Some(u32::try_from(int_year)?)
-            },
-            None =>  None
-        };
+        // rows saved before years were validated can hold whatever the tags said,
+        // a bad year is dropped rather than making the whole album unreadable
+        let year = db_album.year.and_then(|int_year| match u32::try_from(int_year) {
+            Ok(year) if (MIN_ALBUM_YEAR..=max_album_year()).contains(&year) => Some(year),
+            _ => {
+                log::warn!("Ignoring the out of range year {} of album {:?}", int_year, db_album.name);
+                None
+            }
+        });
 
         Ok(
             Self::new(
@@ -42,13 +44,7 @@ pub enum AlbumConversionError {
     UuidConversionError(#[from] uuid::Error),
 
     #[error(transparent)]
-    ValidationError(#[from] ValidationError),
-
-    #[error("Error during conversion of {0} into u32.")]
-    YearLessOrEqualToZero(i64),
-
-    #[error("Album year value {0} out of range for u32.")]
-    YearOutOfRange(#[from] std::num::TryFromIntError),
+    ValidationError(#[from] ValidationError)
 }
 
 pub struct SqliteAlbumsRepository;
@@ -113,7 +109,18 @@ impl SqliteAlbumsRepository {
     }
 
     pub async fn batch_save<A>(&self, connection: &mut SqliteConnection, albums: &[A]) -> Result<BatchSaveReport, RepositoryError>
-    where A: AsRef<Album> + Sync,
+    where A: AsRef<Album> + Sync
+    {
+        self.batch_save_iter(connection, albums).await
+    }
+
+    /// Same as `batch_save`, for any iterator of albums, owned or borrowed. The sync keeps its new albums
+    /// in a map keyed by name and artist and saves its `values()` as they are, without copying them into a slice.
+    pub async fn batch_save_iter<I>(&self, connection: &mut SqliteConnection, albums: I) -> Result<BatchSaveReport, RepositoryError>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<Album> + Send
     {
         // This is per row INSERT, so there is n = albums.len() queries.
         // Speed was sacrificed for an ability to dynamicly insert items of the batch 
@@ -123,7 +130,7 @@ impl SqliteAlbumsRepository {
 
         let mut batch_report = BatchSaveReport::new();
 
-        for (index, album) in albums.iter().enumerate() {
+        for (index, album) in albums.into_iter().enumerate() {
             let album = album.as_ref();
 
             let id = album.id();
@@ -176,6 +183,8 @@ impl SqliteAlbumsRepository {
             .map_err(RepositoryError::AlbumDataMapping)
     }
 
+    /// One album with the given name. Generic titles like "Greatest Hits" exist for many artists and
+    /// the one picked is arbitrary; use `by_name_and_artist_fetch` when the artist is known, `by_name_fetch_all` otherwise.
     pub async fn by_name_fetch<'e, E, S>(&self, executor: E, name: S) -> Result<Option<Album>, RepositoryError>
     where
         E: Executor<'e, Database = Sqlite>,
@@ -187,12 +196,56 @@ impl SqliteAlbumsRepository {
         )
         .bind(name_string)
         .fetch_optional(executor)
-        .await?;
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_album.map(Album::try_from)
+        .transpose()
+        .map_err(RepositoryError::AlbumDataMapping)
+    }
+
+    /// The album with the given name by the given artist.
+    pub async fn by_name_and_artist_fetch<'e, E, S, ID>(&self, executor: E, name: S, artist_id: ID) -> Result<Option<Album>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        S: Into<String>,
+        ID: IntoUuid + Send + Sync
+    {
+        let name_string = name.into();
+        let artist_id = artist_id.into_uuid()?;
+        let db_album = sqlx::query_as::<_, DbAlbum>(
+            "SELECT * FROM albums WHERE name = ? AND artist_id = ? LIMIT 1;"
+        )
+        .bind(name_string)
+        .bind(artist_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
 
         db_album.map(Album::try_from)
         .transpose()
         .map_err(RepositoryError::AlbumDataMapping)
     }
+
+    /// Every album with the given name, across all artists.
+    pub async fn by_name_fetch_all<'e, E, S>(&self, executor: E, name: S) -> Result<Vec<Album>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        S: Into<String>
+    {
+        let name_string = name.into();
+        let db_albums = sqlx::query_as::<_, DbAlbum>(
+            "SELECT * FROM albums WHERE name = ? ORDER BY artist_id;"
+        )
+        .bind(name_string)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_albums.into_iter()
+            .map(|db_album| Album::try_from(db_album).map_err(RepositoryError::AlbumDataMapping))
+            .collect()
+    }
     
     pub async fn stream_all<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Album, RepositoryError>> + 'e
     where E: Executor<'e, Database = Sqlite> + 'e
@@ -209,6 +262,122 @@ impl SqliteAlbumsRepository {
         })
     }
 
+    /// Same as `stream_all`, ordered by name and then by id.
+    pub async fn stream_all_ordered<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Album, RepositoryError>> + 'e
+    where E: Executor<'e, Database = Sqlite> + 'e
+    {
+        sqlx::query_as::<_, DbAlbum>(
+            "SELECT * FROM albums ORDER BY name, id;"
+        )
+        .fetch(executor)
+        .map(|db_alb_result|{
+            match db_alb_result {
+                Ok(db_alb) => Album::try_from(db_alb).map_err(RepositoryError::AlbumDataMapping),
+                Err(err) => Err(RepositoryError::from_sqlx_error(err))
+            }
+        })
+    }
+
+    /// One page of albums ordered by name, ties broken by id so the pages don't overlap.
+    pub async fn fetch_page<'e, E>(&self, executor: E, limit: i64, offset: i64) -> Result<Vec<Album>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_albums = sqlx::query_as::<_, DbAlbum>(
+            "SELECT id, name, artist_id, year
+            FROM albums
+            ORDER BY name, id
+            LIMIT ? OFFSET ?;"
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_albums.into_iter()
+            .map(|db_album| Album::try_from(db_album).map_err(RepositoryError::AlbumDataMapping))
+            .collect()
+    }
+
+    /// Albums whose name contains `query`, ignoring ASCII case, ordered by name. Wildcards in `query` are matched literally.
+    pub async fn search_by_name<'e, E>(&self, executor: E, query: &str, limit: i64) -> Result<Vec<Album>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_albums = sqlx::query_as::<_, DbAlbum>(
+            "SELECT id, name, artist_id, year
+            FROM albums
+            WHERE name LIKE '%' || ? || '%' ESCAPE '\\' COLLATE NOCASE
+            ORDER BY name, id
+            LIMIT ?;"
+        )
+        .bind(escape_like(query))
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_albums.into_iter()
+            .map(|db_album| Album::try_from(db_album).map_err(RepositoryError::AlbumDataMapping))
+            .collect()
+    }
+
+    /// Albums matching `query` the way users type it: case, punctuation and extra spaces don't matter
+    /// and the words may come in any order, so "wonder whats next" finds "Wonder What's Next".
+    /// Stored names are normalized by `Album::new` already, only the query needs it. Exact matches
+    /// come first, then names starting with the query, then the shortest names.
+    pub async fn search_albums_fuzzy<'e, E>(&self, executor: E, query: &str, limit: i64) -> Result<Vec<Album>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>
+    {
+        let normalized_query = normalize_name(query);
+        let words = normalized_query.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let joined_query = words.join(" ");
+
+        let mut qbuilder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT id, name, artist_id, year FROM albums WHERE "
+        );
+        for (index, word) in words.iter().enumerate() {
+            if index > 0 {
+                qbuilder.push(" AND ");
+            }
+            qbuilder.push("name LIKE '%' || ")
+                .push_bind(escape_like(word))
+                .push(" || '%' ESCAPE '\\'");
+        }
+        qbuilder.push(" ORDER BY CASE WHEN name = ")
+            .push_bind(joined_query.clone())
+            .push(" THEN 0 WHEN name LIKE ")
+            .push_bind(escape_like(&joined_query))
+            .push(" || '%' ESCAPE '\\' THEN 1 ELSE 2 END, length(name), name, id LIMIT ")
+            .push_bind(limit);
+
+        let db_albums = qbuilder.build_query_as::<DbAlbum>()
+            .fetch_all(executor)
+            .await
+            .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_albums.into_iter()
+            .map(|db_album| Album::try_from(db_album).map_err(RepositoryError::AlbumDataMapping))
+            .collect()
+    }
+
+    /// Total number of albums, to work out the number of pages for `fetch_page`.
+    pub async fn count<'e, E>(&self, executor: E) -> Result<u64, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM albums;")
+            .fetch_one(executor)
+            .await?;
+
+        Ok(u64::try_from(count)?)
+    }
+
     pub async fn all_by_artist<'e, E, ID>(&self, executor: E, artist_id: ID) -> Result<Vec<Album>, RepositoryError>
     where 
         E: Executor<'e, Database = Sqlite>,
@@ -230,6 +399,44 @@ impl SqliteAlbumsRepository {
             
     }
     
+    pub async fn count_by_artist<'e, E, ID>(&self, executor: E, artist_id: ID) -> Result<u64, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        let artist_id = artist_id.into_uuid()?;
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM albums WHERE artist_id = ?;"
+        ).bind(artist_id)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(u64::try_from(count)?)
+    }
+
+    /// Albums without a single track, e.g. after tracks were deleted by hand. A sync only prunes the
+    /// albums that lose their last track during that sync, so these stay until removed; `verify` reports them.
+    /// Ordered by name.
+    pub async fn empty_albums<'e, E>(&self, executor: E) -> Result<Vec<Album>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_albums = sqlx::query_as::<_, DbAlbum>(
+            "SELECT a.id, a.name, a.artist_id, a.year
+            FROM albums a
+            LEFT JOIN tracks t ON t.album_id = a.id
+            WHERE t.id IS NULL
+            ORDER BY a.name, a.id;"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_albums.into_iter()
+            .map(|db_album| Album::try_from(db_album).map_err(RepositoryError::AlbumDataMapping))
+            .collect()
+    }
+
     pub async fn delete<'e, ID, E>(&self, executor: E, id: ID) -> Result<(), RepositoryError>
     where
         ID: IntoUuid + Send + Sync,
@@ -343,14 +550,16 @@ impl SqliteAlbumsRepository {
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::Display;
+    use std::{collections::HashMap, fmt::Display, path::PathBuf};
+
+    use futures::TryStreamExt;
 
     use sqlx::{SqlitePool, Transaction};
 
     use super::*;
     use crate::{
-        repository::{test_helpers::{prepare_db, TestSetupError}, SqliteArtistsRepository}, 
-        domain::artist::Artist
+        repository::{test_helpers::{assert_pages_of_ten, assert_search_by_name, prepare_db, TestSetupError}, SqliteArtistsRepository, SqliteTracksRepository}, 
+        domain::{artist::Artist, audiofile::AudioFileType, track::Track, uploaded::Uploaded}
     };
 
     const UUID_BYTES: [u8; 16] = [
@@ -422,7 +631,7 @@ mod tests {
                     new_uuid(&album_name),
                     album_name,
                     new_uuid("Default Artist"),
-                    Some(2000 + i as u32)
+                    Some(1900 + i as u32)
                 ).expect("Error during test setup: album fields validation has failed.")
             })
             .collect()
@@ -436,7 +645,7 @@ mod tests {
                     new_uuid(&album_name),
                     album_name,
                     artist_id,
-                    Some(2000 + i as u32)
+                    Some(1900 + i as u32)
                 ).expect("Error during test setup: album fields validation has failed.")
             })
             .collect()
@@ -934,4 +1143,176 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn batch_save_iter_takes_map_values_and_owned_albums() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(20)?;
+        // the way the sync passes them
+        let by_id: HashMap<Uuid, &Album> = ctx.entities[0..10].iter().map(|album| (*album.id(), album)).collect();
+        let owned_chunk = ctx.entities[10..20].to_vec();
+        let owned_ids = owned_chunk.iter().map(|entity| *entity.id()).collect::<Vec<_>>();
+
+        let mut connection = ctx.pool.acquire().await?;
+
+        let borrowed_result = ctx.repo.batch_save_iter(&mut connection, by_id.values()).await?;
+        assert_eq!(borrowed_result.successful_ids().len(), 10);
+        assert!(borrowed_result.successful_ids().iter().all(|id| by_id.contains_key(id)));
+
+        let owned_result = ctx.repo.batch_save_iter(&mut connection, owned_chunk).await?;
+        assert_eq!(owned_result.successful_ids(), owned_ids);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn same_named_albums_across_artists() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let other_artist = Artist::new(new_uuid("Other Artist"), "Other Artist")?;
+        SqliteArtistsRepository::new().save(&ctx.pool, &other_artist).await?;
+
+        let first = Album::new(new_uuid("greatest hits default"), "Greatest Hits", *ctx.artist.id(), Some(1999))?;
+        let second = Album::new(new_uuid("greatest hits other"), "Greatest Hits", *other_artist.id(), Some(2004))?;
+        ctx.repo.save_all(&ctx.pool, &[&first, &second]).await?;
+
+        let mut all_ids = ctx.repo.by_name_fetch_all(&ctx.pool, first.name()).await?
+            .iter()
+            .map(|album| *album.id())
+            .collect::<Vec<_>>();
+        all_ids.sort();
+        let mut expected_ids = vec![*first.id(), *second.id()];
+        expected_ids.sort();
+        assert_eq!(all_ids, expected_ids);
+
+        let by_other = ctx.repo.by_name_and_artist_fetch(&ctx.pool, first.name(), other_artist.id()).await?;
+        assert_eq!(by_other.map(|album| *album.id()), Some(*second.id()));
+
+        let mut tx = ctx.tx().await?;
+        let by_default = ctx.repo.by_name_and_artist_fetch(&mut *tx, first.name(), ctx.artist.id()).await?;
+        assert_eq!(by_default.map(|album| *album.id()), Some(*first.id()));
+        tx.commit().await?;
+
+        let stranger = new_uuid("Stranger");
+        assert!(ctx.repo.by_name_and_artist_fetch(&ctx.pool, first.name(), stranger).await?.is_none());
+        assert!(ctx.repo.by_name_fetch_all(&ctx.pool, "Best Of").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_page_boundaries() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(25)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+
+        let mut expected = ctx.entities.iter().map(|album| album.name().to_string()).collect::<Vec<_>>();
+        expected.sort();
+
+        assert_pages_of_ten(|limit, offset| ctx.repo.fetch_page(&ctx.pool, limit, offset), |album| album.name().to_string(), expected).await?;
+        assert_eq!(ctx.repo.count(&ctx.pool).await?, 25);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_by_name_is_case_insensitive() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        for name in ["Closure", "Forfeit", "Clay"] {
+            ctx.repo.save(&ctx.pool, Album::new(new_uuid(name), name, *ctx.artist.id(), None)?).await?;
+        }
+
+        assert_search_by_name(|query, limit| ctx.repo.search_by_name(&ctx.pool, query, limit), |album: &Album| album.name().to_string()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_all_ordered_is_sorted_by_name() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(25)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+
+        let albums = ctx.repo.stream_all_ordered(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+
+        assert_eq!(albums.len(), 25);
+        assert!(albums.windows(2).all(|pair| pair[0].name() <= pair[1].name()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_albums_fuzzy_ignores_punctuation_and_ranks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let albums = ["Wonder What's Next", "What's Next?", "Next", "Whats Next For Us", "Closure"].iter()
+            .map(|name| Album::new(new_uuid(name), *name, *ctx.artist.id(), None))
+            .collect::<Result<Vec<_>, _>>()?;
+        ctx.repo.save_all(&ctx.pool, &albums).await?;
+
+        let names = |albums: Vec<Album>| albums.iter().map(|album| album.name().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names(ctx.repo.search_albums_fuzzy(&ctx.pool, "wonder whats next", 10).await?), vec!["wonder whats next"]);
+        assert_eq!(names(ctx.repo.search_albums_fuzzy(&ctx.pool, "  Wonder, What's   NEXT!", 10).await?), vec!["wonder whats next"]);
+        assert_eq!(names(ctx.repo.search_albums_fuzzy(&ctx.pool, "next wonder", 10).await?), vec!["wonder whats next"]);
+
+        // exact match, then the prefix match, then by length
+        assert_eq!(
+            names(ctx.repo.search_albums_fuzzy(&ctx.pool, "whats next", 10).await?),
+            vec!["whats next", "whats next for us", "wonder whats next"]
+        );
+        assert_eq!(names(ctx.repo.search_albums_fuzzy(&ctx.pool, "whats next", 1).await?), vec!["whats next"]);
+
+        assert!(ctx.repo.search_albums_fuzzy(&ctx.pool, "?!", 10).await?.is_empty());
+        assert!(ctx.repo.search_albums_fuzzy(&ctx.pool, "%", 10).await?.is_empty());
+        assert!(ctx.repo.search_albums_fuzzy(&ctx.pool, "forfeit", 10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn empty_albums_are_the_ones_without_tracks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let with_tracks = Album::new(new_uuid("with tracks"), "With Tracks", *ctx.artist.id(), None)?;
+        let empty = Album::new(new_uuid("empty"), "Empty", *ctx.artist.id(), None)?;
+        ctx.repo.save_all(&ctx.pool, &[&with_tracks, &empty]).await?;
+
+        assert_eq!(ctx.repo.empty_albums(&ctx.pool).await?.len(), 2);
+
+        let track = Track::new(new_uuid("track"), "track", *with_tracks.id(), 60, PathBuf::from("music/track.flac"), 1024, AudioFileType::Flac, Uploaded::Denis, None)?;
+        SqliteTracksRepository::new().save(&ctx.pool, &track).await?;
+
+        let empty_ids = ctx.repo.empty_albums(&ctx.pool).await?.iter().map(|album| *album.id()).collect::<Vec<_>>();
+        assert_eq!(empty_ids, vec![*empty.id()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn legacy_rows_with_out_of_range_years_are_readable() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+
+        // written straight into the table, the way rows saved before the year check could look
+        for (name, year) in [("far future", 9999_i64), ("too early", 1000), ("negative", -5), ("fine", 1999)] {
+            sqlx::query("INSERT INTO albums(id, name, artist_id, year) VALUES (?, ?, ?, ?);")
+                .bind(new_uuid(name))
+                .bind(name)
+                .bind(ctx.artist.id())
+                .bind(year)
+                .execute(&ctx.pool)
+                .await?;
+        }
+
+        let far_future = ctx.repo.by_id_fetch(&ctx.pool, new_uuid("far future")).await?.expect("Album should be readable");
+        assert_eq!(far_future.year(), None);
+
+        let mut years = ctx.repo.stream_all(&ctx.pool).await
+            .map_ok(|album| (album.name().to_string(), album.year()))
+            .try_collect::<Vec<_>>()
+            .await?;
+        years.sort();
+        assert_eq!(years, vec![
+            ("far future".to_string(), None),
+            ("fine".to_string(), Some(1999)),
+            ("negative".to_string(), None),
+            ("too early".to_string(), None)
+        ]);
+
+        Ok(())
+    }
 }
\ No newline at end of file
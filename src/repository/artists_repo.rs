@@ -3,20 +3,22 @@ use sqlx::{Executor, FromRow, QueryBuilder, Row, Sqlite, SqliteConnection};
 use uuid::Uuid;
 
 use crate::domain::{BatchDeleteReport, BatchSaveOutcome, BatchSaveReport, ValidationError, artist::Artist};
-use super::{IntoUuid, RepositoryError};
+use super::{escape_like, IntoUuid, RepositoryError};
 
 #[derive(FromRow)]
 struct DbArtist {
     id: Vec<u8>,
-    name: String
+    name: String,
+    musicbrainz_id: Option<String>
 }
 
 impl TryFrom<DbArtist> for Artist {
     type Error = ArtistConversionError;
     fn try_from(db_artist: DbArtist) -> Result<Self, Self::Error> {
-        Ok(
-            Self::new(Uuid::from_slice(&db_artist.id)?, db_artist.name)?
-        )
+        let mut artist = Self::new(Uuid::from_slice(&db_artist.id)?, db_artist.name)?;
+        artist.set_musicbrainz_id(db_artist.musicbrainz_id);
+
+        Ok(artist)
     }
 }
 
@@ -44,11 +46,12 @@ impl SqliteArtistsRepository {
         A: AsRef<Artist> + Sync
     {   
         let db_artist = sqlx::query_as::<_, DbArtist>(
-            "INSERT INTO artists(id, name) 
-            VALUES (?, ?)
+            "INSERT INTO artists(id, name, musicbrainz_id) 
+            VALUES (?, ?, ?)
             RETURNING *;")
             .bind(artist.as_ref().id())
             .bind(artist.as_ref().name())
+            .bind(artist.as_ref().musicbrainz_id())
             .fetch_one(executor)
             .await
             .map_err(RepositoryError::from_sqlx_error)?;
@@ -66,13 +69,14 @@ impl SqliteArtistsRepository {
         }
 
         let mut qbuilder: QueryBuilder<Sqlite> = QueryBuilder::new(
-            "INSERT INTO artists(id, name) "
+            "INSERT INTO artists(id, name, musicbrainz_id) "
         );
 
         qbuilder.push_values(artists.iter(), |mut builder, artist| {
             builder
             .push_bind(artist.as_ref().id())
-            .push_bind(artist.as_ref().name());
+            .push_bind(artist.as_ref().name())
+            .push_bind(artist.as_ref().musicbrainz_id());
         });
 
         qbuilder.push("RETURNING id;");
@@ -89,8 +93,18 @@ impl SqliteArtistsRepository {
     }
 
     pub async fn batch_save<A>(&self, connection: &mut SqliteConnection, artists: &[A]) -> Result<BatchSaveReport, RepositoryError>
-    where 
-        A: AsRef<Artist> + Sync
+    where A: AsRef<Artist> + Sync
+    {
+        self.batch_save_iter(connection, artists).await
+    }
+
+    /// Same as `batch_save`, for any iterator of artists. The sync saves the `values()` of its map of new
+    /// artists this way, see `SqliteAlbumsRepository::batch_save_iter`.
+    pub async fn batch_save_iter<I>(&self, connection: &mut SqliteConnection, artists: I) -> Result<BatchSaveReport, RepositoryError>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<Artist> + Send
     {
         // This is per row INSERT, so there is n = artists.len() queries.
         // Speed was sacrificed for an ability to dynamicly insert items of the batch 
@@ -148,24 +162,47 @@ impl SqliteArtistsRepository {
             .map_err(RepositoryError::ArtistDataMapping)
     }
 
+    /// One artist with the given name. Names aren't unique, when several artists share it
+    /// the one picked is arbitrary; use `by_name_fetch_all` to tell them apart.
     pub async fn by_name_fetch<'e, E, S>(&self, executor: E, name: S) -> Result<Option<Artist>, RepositoryError>
     where
         E: Executor<'e, Database = Sqlite>,
         S: Into<String>
     {
         let name_string = name.into();
-        let db_album = sqlx::query_as::<_, DbArtist>(
+        let db_artist = sqlx::query_as::<_, DbArtist>(
             "SELECT * FROM artists WHERE name = ? LIMIT 1;"
         )
         .bind(name_string)
         .fetch_optional(executor)
-        .await?;
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
 
-        db_album.map(Artist::try_from)
+        db_artist.map(Artist::try_from)
         .transpose()
         .map_err(RepositoryError::ArtistDataMapping)
     }
     
+    /// Every artist with the given name, e.g. two different bands both called "Nirvana".
+    pub async fn by_name_fetch_all<'e, E, S>(&self, executor: E, name: S) -> Result<Vec<Artist>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        S: Into<String>
+    {
+        let name_string = name.into();
+        let db_artists = sqlx::query_as::<_, DbArtist>(
+            "SELECT * FROM artists WHERE name = ? ORDER BY musicbrainz_id;"
+        )
+        .bind(name_string)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_artists.into_iter()
+            .map(|db_artist| Artist::try_from(db_artist).map_err(RepositoryError::ArtistDataMapping))
+            .collect()
+    }
+
     pub async fn stream_all<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Artist, RepositoryError>> +'e
     where E: Executor<'e, Database = Sqlite> +'e
     {
@@ -180,6 +217,95 @@ impl SqliteArtistsRepository {
             })
     }
     
+    /// Same as `stream_all`, ordered by name and then by id.
+    pub async fn stream_all_ordered<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Artist, RepositoryError>> +'e
+    where E: Executor<'e, Database = Sqlite> +'e
+    {
+        sqlx::query_as::<_, DbArtist>(
+            "SELECT * FROM artists ORDER BY name, id;")
+            .fetch(executor)
+            .map(|db_art_res|{
+                match db_art_res {
+                    Ok(db_artist) => Artist::try_from(db_artist).map_err(RepositoryError::ArtistDataMapping),
+                    Err(err) => Err(RepositoryError::from_sqlx_error(err))
+                }
+            })
+    }
+
+    /// One page of artists ordered by name, ties broken by id so the pages don't overlap.
+    pub async fn fetch_page<'e, E>(&self, executor: E, limit: i64, offset: i64) -> Result<Vec<Artist>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_artists = sqlx::query_as::<_, DbArtist>(
+            "SELECT * FROM artists ORDER BY name, id LIMIT ? OFFSET ?;"
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_artists.into_iter()
+            .map(|db_artist| Artist::try_from(db_artist).map_err(RepositoryError::ArtistDataMapping))
+            .collect()
+    }
+
+    /// Artists whose name contains `query`, ignoring ASCII case, ordered by name. Wildcards in `query` are matched literally.
+    pub async fn search_by_name<'e, E>(&self, executor: E, query: &str, limit: i64) -> Result<Vec<Artist>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_artists = sqlx::query_as::<_, DbArtist>(
+            "SELECT * FROM artists WHERE name LIKE '%' || ? || '%' ESCAPE '\\' COLLATE NOCASE ORDER BY name, id LIMIT ?;"
+        )
+        .bind(escape_like(query))
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_artists.into_iter()
+            .map(|db_artist| Artist::try_from(db_artist).map_err(RepositoryError::ArtistDataMapping))
+            .collect()
+    }
+
+    /// Artists without a single track under any of their albums, including artists with no albums at all.
+    /// Like `empty_albums`, a sync leaves them alone and `verify` reports them. Ordered by name.
+    pub async fn empty_artists<'e, E>(&self, executor: E) -> Result<Vec<Artist>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_artists = sqlx::query_as::<_, DbArtist>(
+            "SELECT ar.id, ar.name, ar.musicbrainz_id
+            FROM artists ar
+            LEFT JOIN albums a ON a.artist_id = ar.id
+            LEFT JOIN tracks t ON t.album_id = a.id
+            GROUP BY ar.id
+            HAVING COUNT(t.id) = 0
+            ORDER BY ar.name, ar.id;"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_artists.into_iter()
+            .map(|db_artist| Artist::try_from(db_artist).map_err(RepositoryError::ArtistDataMapping))
+            .collect()
+    }
+
+    /// Total number of artists, to work out the number of pages for `fetch_page`.
+    pub async fn count<'e, E>(&self, executor: E) -> Result<u64, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM artists;")
+            .fetch_one(executor)
+            .await?;
+
+        Ok(u64::try_from(count)?)
+    }
+    
     pub async fn delete<'e, ID, E>(&self, executor: E, id: ID) -> Result<(), RepositoryError>
     where
         ID: IntoUuid + Send + Sync,
@@ -304,8 +430,10 @@ mod tests {
 
     use sqlx::{SqlitePool, Transaction};
 
+    use std::path::PathBuf;
+
     use super::*;
-    use crate::repository::{test_helpers::{prepare_db, TestSetupError}};
+    use crate::{domain::{album::Album, audiofile::AudioFileType, track::Track, uploaded::Uploaded}, repository::{test_helpers::{assert_pages_of_ten, assert_search_by_name, prepare_db, TestSetupError}, SqliteAlbumsRepository, SqliteTracksRepository}};
 
     const UUID_BYTES: [u8; 16] = [
         0xdc, 0xbf, 0x30, 0xd5, 
@@ -807,4 +935,80 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn same_named_artists_by_name_fetch_all() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+
+        let mut grunge = Artist::new(new_uuid("nirvana grunge"), "Nirvana")?;
+        grunge.set_musicbrainz_id(Some("5b11f4ce-a62d-471e-81fc-a69a8278c7da".to_string()));
+        let mut sixties = Artist::new(new_uuid("nirvana sixties"), "Nirvana")?;
+        sixties.set_musicbrainz_id(Some("9282c8b4-ca0b-4c6b-b7e3-4f7762dfc4d6".to_string()));
+
+        ctx.repo.save(&ctx.pool, &grunge).await?;
+        ctx.repo.save(&ctx.pool, &sixties).await?;
+
+        let nirvanas = ctx.repo.by_name_fetch_all(&ctx.pool, "nirvana").await?;
+        let ids = nirvanas.iter().map(|artist| *artist.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![*grunge.id(), *sixties.id()]);
+        assert_eq!(nirvanas[0].musicbrainz_id(), grunge.musicbrainz_id());
+
+        // without an id to tell them apart the name is still unique
+        let anonymous = Artist::new(new_uuid("nirvana anonymous"), "Nirvana")?;
+        ctx.repo.save(&ctx.pool, &anonymous).await?;
+        let duplicate = Artist::new(new_uuid("nirvana duplicate"), "Nirvana")?;
+        assert!(ctx.repo.save(&ctx.pool, &duplicate).await.is_err());
+
+        assert!(ctx.repo.by_name_fetch_all(&ctx.pool, "silverchair").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_page_boundaries() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(25)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+
+        let mut expected = ctx.entities.iter().map(|artist| artist.name().to_string()).collect::<Vec<_>>();
+        expected.sort();
+
+        assert_pages_of_ten(|limit, offset| ctx.repo.fetch_page(&ctx.pool, limit, offset), |artist| artist.name().to_string(), expected).await?;
+        assert_eq!(ctx.repo.count(&ctx.pool).await?, 25);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_by_name_is_case_insensitive() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        for name in ["Closure", "Forfeit", "Clay"] {
+            ctx.repo.save(&ctx.pool, Artist::new(new_uuid(name), name)?).await?;
+        }
+
+        assert_search_by_name(|query, limit| ctx.repo.search_by_name(&ctx.pool, query, limit), |artist: &Artist| artist.name().to_string()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn empty_artists_have_no_tracks_under_their_albums() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(3)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+        let [with_tracks, with_empty_album, without_albums] = [&ctx.entities[0], &ctx.entities[1], &ctx.entities[2]];
+
+        let full_album = Album::new(new_uuid("full album"), "Full Album", *with_tracks.id(), None)?;
+        let empty_album = Album::new(new_uuid("empty album"), "Empty Album", *with_empty_album.id(), None)?;
+        SqliteAlbumsRepository::new().save_all(&ctx.pool, &[&full_album, &empty_album]).await?;
+
+        for idx in 0..2 {
+            let track = Track::new(new_uuid(&format!("track {}", idx)), format!("track {}", idx), *full_album.id(), 60, PathBuf::from(format!("music/track_{}.flac", idx)), 1024, AudioFileType::Flac, Uploaded::Denis, None)?;
+            SqliteTracksRepository::new().save(&ctx.pool, &track).await?;
+        }
+
+        let empty_ids = ctx.repo.empty_artists(&ctx.pool).await?.iter().map(|artist| *artist.id()).collect::<Vec<_>>();
+        // "test artist 2" then "test artist 3", by name
+        assert_eq!(empty_ids, vec![*with_empty_album.id(), *without_albums.id()]);
+
+        Ok(())
+    }
 }
@@ -1,14 +1,17 @@
 pub mod artists_repo;
 pub mod albums_repo;
 pub mod tracks_repo;
+pub mod playlists_repo;
 
 pub use artists_repo::SqliteArtistsRepository;
 pub use albums_repo::SqliteAlbumsRepository;
 pub use tracks_repo::SqliteTracksRepository;
+pub use playlists_repo::SqlitePlaylistsRepository;
 
 use artists_repo::ArtistConversionError;
 use albums_repo::AlbumConversionError;
 use tracks_repo::TrackConversionError;
+use playlists_repo::PlaylistConversionError;
 use crate::domain::UploadedParseError;
 
 use uuid::Uuid;
@@ -50,6 +53,9 @@ pub enum RepositoryError {
     #[error("Data mapping error for Track: {0}")]
     TrackDataMapping(#[from] TrackConversionError),
 
+    #[error("Data mapping error for Playlist: {0}")]
+    PlaylistDataMapping(#[from] PlaylistConversionError),
+
     #[error("Uploaded conversion error: {0}")]
     UploadedConversion(#[from] UploadedParseError),
 
@@ -60,7 +66,10 @@ pub enum RepositoryError {
     ConnectionError(String),
 
     #[error("Something went wrong, dude, idk what, look at this: {0}")]
-    GenericDatabaseError(#[from] sqlx::Error),
+    GenericDatabaseError(sqlx::Error),
+
+    #[error("Database storage is full (SQLITE_FULL): free some disk space and try again. {0}")]
+    StorageFull(String),
 
     #[error("A constraint was violated: {description}")]
     ConstraintViolation { description: String },
@@ -92,6 +101,11 @@ impl RepositoryError {
                             description: db_error.message().to_string()
                         };
                     }
+
+                    // 13: SQLITE_FULL, the disk (or the temp dir) has run out of space
+                    if code_str == "13" {
+                        return Self::StorageFull(db_error.message().to_string());
+                    }
                 }
 
                 Self::GenericDatabaseError(sqlx_error)
@@ -102,6 +116,13 @@ impl RepositoryError {
     }
 }
 
+// Manual impl instead of #[from], so errors bubbling up through `?` get classified too.
+impl From<sqlx::Error> for RepositoryError {
+    fn from(sqlx_error: sqlx::Error) -> Self {
+        Self::from_sqlx_error(sqlx_error)
+    }
+}
+
 /* Helper trait for id parameter of repository functions */
 pub trait IntoUuid {
     fn into_uuid(&self) -> Result<Uuid, RepositoryError>;
@@ -137,9 +158,20 @@ impl IntoUuid for &String {
     }
 }
 
+/// Escapes the LIKE wildcards in `pattern`, for queries with `ESCAPE '\'`, so that `%` and `_`
+/// coming from a user match literally.
+pub(crate) fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[cfg(test)]
 pub(crate) mod test_helpers {
 
+    use std::future::Future;
+
     use sqlx::{SqlitePool, Error as SqlxError};
 
     use crate::domain::ValidationError;
@@ -174,4 +206,157 @@ pub(crate) mod test_helpers {
         Ok(pool)
             
     }
+
+    /// Pages through 25 stored rows ten at a time: two full pages, a short one, then nothing.
+    /// The pages have to add up to `expected`, the names of all 25 sorted.
+    pub async fn assert_pages_of_ten<T, F, Fut>(fetch_page: F, name: impl Fn(&T) -> String, expected: Vec<String>) -> Result<(), RepositoryError>
+    where
+        F: Fn(i64, i64) -> Fut,
+        Fut: Future<Output = Result<Vec<T>, RepositoryError>>
+    {
+        let mut paged = Vec::new();
+        for (offset, expected_len) in [(0, 10), (10, 10), (20, 5)] {
+            let page = fetch_page(10, offset).await?;
+            assert_eq!(page.len(), expected_len);
+            paged.extend(page.iter().map(&name));
+        }
+        assert_eq!(paged, expected);
+
+        assert!(fetch_page(10, 25).await?.is_empty());
+
+        Ok(())
+    }
+
+    /// Runs a `search_by_name` over stored rows named Closure, Forfeit and Clay: any case of the query
+    /// matches, results are ordered by name and cut at the limit, and LIKE wildcards only match themselves.
+    pub async fn assert_search_by_name<T, F, Fut>(search: F, name: impl Fn(&T) -> String) -> Result<(), RepositoryError>
+    where
+        F: Fn(&'static str, i64) -> Fut,
+        Fut: Future<Output = Result<Vec<T>, RepositoryError>>
+    {
+        let names = |found: Vec<T>| found.iter().map(&name).collect::<Vec<_>>();
+
+        assert_eq!(names(search("cl", 10).await?), vec!["clay", "closure"]);
+        assert_eq!(names(search("CL", 10).await?), vec!["clay", "closure"]);
+        assert_eq!(names(search("cl", 1).await?), vec!["clay"]);
+
+        assert!(search("%", 10).await?.is_empty());
+        assert!(search("cl_y", 10).await?.is_empty());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, error::Error as StdError, fmt};
+
+    use sqlx::error::{DatabaseError, ErrorKind};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct SyntheticDbError {
+        code: &'static str,
+        message: &'static str
+    }
+
+    impl fmt::Display for SyntheticDbError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.message)
+        }
+    }
+
+    impl StdError for SyntheticDbError {}
+
+    impl DatabaseError for SyntheticDbError {
+        fn message(&self) -> &str {
+            self.message
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn synthetic(code: &'static str, message: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(SyntheticDbError { code, message }))
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("100%_pure"), "100\\%\\_pure");
+        assert_eq!(escape_like("back\\slash"), "back\\\\slash");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+
+    #[test]
+    fn test_sqlite_full_maps_to_storage_full() {
+        let err = RepositoryError::from_sqlx_error(synthetic("13", "database or disk is full"));
+        assert!(matches!(err, RepositoryError::StorageFull(ref msg) if msg == "database or disk is full"));
+
+        // the same has to happen when the error bubbles up through `?`
+        let err: RepositoryError = synthetic("13", "database or disk is full").into();
+        assert!(matches!(err, RepositoryError::StorageFull(_)));
+    }
+
+    #[test]
+    fn test_other_codes_are_not_storage_full() {
+        let err = RepositoryError::from_sqlx_error(synthetic("2067", "UNIQUE constraint failed"));
+        assert!(matches!(err, RepositoryError::ConstraintViolation { .. }));
+
+        let err = RepositoryError::from_sqlx_error(synthetic("5", "database is locked"));
+        assert!(matches!(err, RepositoryError::GenericDatabaseError(_)));
+    }
+
+    async fn query_plan(pool: &sqlx::SqlitePool, query: &str) -> String {
+        let rows = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", query))
+            .fetch_all(pool)
+            .await
+            .expect("EXPLAIN QUERY PLAN has failed");
+
+        rows.iter()
+            .map(|row| sqlx::Row::get::<String, _>(row, "detail"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[tokio::test]
+    async fn test_parent_lookups_use_indexes() {
+        let pool = test_helpers::prepare_db().await.expect("Failed to prepare the test db");
+
+        for query in [
+            "SELECT id FROM tracks WHERE album_id = x'00'",
+            "SELECT COUNT(*) FROM tracks WHERE album_id = x'00'"
+        ] {
+            let plan = query_plan(&pool, query).await;
+            assert!(plan.contains("INDEX idx_tracks_album_id"), "{} is not indexed:\n{}", query, plan);
+        }
+
+        let plan = query_plan(&pool, "SELECT COUNT(*) FROM albums WHERE artist_id = x'00'").await;
+        assert!(plan.contains("INDEX idx_albums_artist_id"), "albums by artist are not indexed:\n{}", plan);
+
+        // orphan checks: the correlated subqueries must hit the index, not scan the child table per row
+        let plan = query_plan(&pool, "SELECT id FROM albums WHERE NOT EXISTS (SELECT 1 FROM tracks WHERE tracks.album_id = albums.id)").await;
+        assert!(plan.contains("INDEX idx_tracks_album_id"), "orphan albums are not indexed:\n{}", plan);
+
+        let plan = query_plan(&pool, "SELECT id FROM artists WHERE NOT EXISTS (SELECT 1 FROM albums WHERE albums.artist_id = artists.id)").await;
+        assert!(plan.contains("INDEX idx_albums_artist_id"), "orphan artists are not indexed:\n{}", plan);
+    }
 }
\ No newline at end of file
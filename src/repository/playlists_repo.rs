@@ -0,0 +1,228 @@
+use sqlx::{Executor, FromRow, Sqlite};
+use uuid::Uuid;
+
+use crate::domain::{playlist::Playlist, ValidationError};
+use super::{IntoUuid, RepositoryError};
+
+#[derive(FromRow)]
+struct DbPlaylist {
+    id: Vec<u8>,
+    name: String
+}
+
+impl TryFrom<DbPlaylist> for Playlist {
+    type Error = PlaylistConversionError;
+
+    fn try_from(db_playlist: DbPlaylist) -> Result<Self, Self::Error> {
+        Ok(Self::new(Uuid::from_slice(&db_playlist.id)?, db_playlist.name)?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PlaylistConversionError {
+    #[error("Uuid conversion error: {0}")]
+    UuidConversionError(#[from] uuid::Error),
+
+    #[error(transparent)]
+    ValidationError(#[from] ValidationError)
+}
+
+/// Playlists and their entries. The tracks of a playlist, in order, come from `SqliteTracksRepository::by_playlist`.
+#[derive(Default)]
+pub struct SqlitePlaylistsRepository;
+
+impl SqlitePlaylistsRepository {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl SqlitePlaylistsRepository {
+    pub async fn save<'e, E, P>(&self, executor: E, playlist: P) -> Result<Playlist, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Playlist> + Sync
+    {
+        let db_playlist = sqlx::query_as::<_, DbPlaylist>(
+            "INSERT INTO playlists(id, name)
+            VALUES (?, ?)
+            RETURNING id, name;"
+        )
+        .bind(playlist.as_ref().id())
+        .bind(playlist.as_ref().name())
+        .fetch_one(executor)
+        .await?;
+
+        Ok(db_playlist.try_into()?)
+    }
+
+    pub async fn by_id_fetch<'e, E, ID>(&self, executor: E, id: ID) -> Result<Option<Playlist>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        let id = id.into_uuid()?;
+        let db_playlist = sqlx::query_as::<_, DbPlaylist>(
+            "SELECT id, name FROM playlists WHERE id = ? LIMIT 1;"
+        )
+        .bind(id)
+        .fetch_optional(executor)
+        .await?;
+
+        db_playlist.map(Playlist::try_from)
+            .transpose()
+            .map_err(RepositoryError::PlaylistDataMapping)
+    }
+
+    /// Every playlist, oldest first.
+    pub async fn all<'e, E>(&self, executor: E) -> Result<Vec<Playlist>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_playlists = sqlx::query_as::<_, DbPlaylist>(
+            "SELECT id, name FROM playlists ORDER BY created_at, name;"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_playlists.into_iter()
+            .map(|db_playlist| Playlist::try_from(db_playlist).map_err(RepositoryError::PlaylistDataMapping))
+            .collect()
+    }
+
+    /// Appends the track to the end of the playlist and returns its position there.
+    /// The same track can be added more than once.
+    pub async fn add_track<'e, E, PID, TID>(&self, executor: E, playlist_id: PID, track_id: TID) -> Result<u32, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        PID: IntoUuid + Send + Sync,
+        TID: IntoUuid + Send + Sync
+    {
+        let playlist_id = playlist_id.into_uuid()?;
+        let track_id = track_id.into_uuid()?;
+
+        // one statement, so two concurrent appends can't both pick the same position
+        let position = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO playlist_tracks(playlist_id, track_id, position)
+            SELECT ?, ?, COALESCE(MAX(position) + 1, 0) FROM playlist_tracks WHERE playlist_id = ?
+            RETURNING position;"
+        )
+        .bind(playlist_id)
+        .bind(track_id)
+        .bind(playlist_id)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(u32::try_from(position)?)
+    }
+
+    /// Takes every occurrence of the track out of the playlist, the rest keeps its order.
+    /// Returns how many entries were removed.
+    pub async fn remove_track<'e, E, PID, TID>(&self, executor: E, playlist_id: PID, track_id: TID) -> Result<u64, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        PID: IntoUuid + Send + Sync,
+        TID: IntoUuid + Send + Sync
+    {
+        let playlist_id = playlist_id.into_uuid()?;
+        let track_id = track_id.into_uuid()?;
+
+        let result = sqlx::query(
+            "DELETE FROM playlist_tracks WHERE playlist_id = ? AND track_id = ?;"
+        )
+        .bind(playlist_id)
+        .bind(track_id)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::{
+        domain::{album::Album, artist::Artist, audiofile::AudioFileType, track::Track, uploaded::Uploaded},
+        repository::{test_helpers::{prepare_db, TestSetupError}, SqliteAlbumsRepository, SqliteArtistsRepository, SqliteTracksRepository}
+    };
+
+    struct TestContext {
+        pool: SqlitePool,
+        repo: SqlitePlaylistsRepository,
+        tracks: Vec<Track>
+    }
+
+    impl TestContext {
+        async fn new() -> Result<Self, TestSetupError> {
+            let pool = prepare_db().await?;
+
+            let artist = Artist::new(Uuid::new_v4(), "Playlist Artist")?;
+            let album = Album::new(Uuid::new_v4(), "Playlist Album", *artist.id(), None)?;
+            SqliteArtistsRepository::new().save(&pool, &artist).await?;
+            SqliteAlbumsRepository::new().save(&pool, &album).await?;
+
+            let tracks = (0..3)
+                .map(|idx| Track::new(Uuid::new_v4(), format!("track {}", idx), *album.id(), 100, format!("playlist/{}.flac", idx).into(), 1024, AudioFileType::Flac, Uploaded::Denis, None))
+                .collect::<Result<Vec<_>, _>>()?;
+            for track in &tracks {
+                SqliteTracksRepository::new().save(&pool, track).await?;
+            }
+
+            Ok(Self { pool, repo: SqlitePlaylistsRepository::new(), tracks })
+        }
+    }
+
+    #[tokio::test]
+    async fn playlist_keeps_track_order() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let playlist = ctx.repo.save(&ctx.pool, Playlist::new(Uuid::new_v4(), "  Road Trip ")?).await?;
+        assert_eq!(playlist.name(), "Road Trip");
+
+        let order = [&ctx.tracks[2], &ctx.tracks[0], &ctx.tracks[1], &ctx.tracks[0]];
+        for (expected_position, track) in order.iter().enumerate() {
+            let position = ctx.repo.add_track(&ctx.pool, playlist.id(), track.id()).await?;
+            assert_eq!(position as usize, expected_position);
+        }
+
+        let ids = |tracks: Vec<Track>| tracks.iter().map(|track| *track.id()).collect::<Vec<_>>();
+        let tracks_repo = SqliteTracksRepository::new();
+        assert_eq!(ids(tracks_repo.by_playlist(&ctx.pool, playlist.id()).await?), order.iter().map(|track| *track.id()).collect::<Vec<_>>());
+
+        // both entries of the track go, the rest keeps its order and new ones go after it
+        assert_eq!(ctx.repo.remove_track(&ctx.pool, playlist.id(), ctx.tracks[0].id()).await?, 2);
+        assert_eq!(ctx.repo.add_track(&ctx.pool, playlist.id(), ctx.tracks[0].id()).await?, 3);
+        assert_eq!(ids(tracks_repo.by_playlist(&ctx.pool, playlist.id()).await?), vec![*ctx.tracks[2].id(), *ctx.tracks[1].id(), *ctx.tracks[0].id()]);
+
+        // a deleted track drops out of the playlist
+        tracks_repo.delete(&ctx.pool, ctx.tracks[1].id()).await?;
+        assert_eq!(ids(tracks_repo.by_playlist(&ctx.pool, playlist.id()).await?), vec![*ctx.tracks[2].id(), *ctx.tracks[0].id()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_unknown_track_or_playlist_fails() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let playlist = ctx.repo.save(&ctx.pool, Playlist::new(Uuid::new_v4(), "Empty")?).await?;
+
+        let unknown_track = ctx.repo.add_track(&ctx.pool, playlist.id(), Uuid::new_v4()).await;
+        assert!(matches!(unknown_track, Err(RepositoryError::ConstraintViolation { .. })));
+
+        let unknown_playlist = ctx.repo.add_track(&ctx.pool, Uuid::new_v4(), ctx.tracks[0].id()).await;
+        assert!(matches!(unknown_playlist, Err(RepositoryError::ConstraintViolation { .. })));
+
+        assert!(ctx.repo.by_id_fetch(&ctx.pool, Uuid::new_v4()).await?.is_none());
+        assert_eq!(ctx.repo.all(&ctx.pool).await?.iter().map(|playlist| *playlist.id()).collect::<Vec<_>>(), vec![*playlist.id()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blank_playlist_name_is_rejected() {
+        assert!(matches!(Playlist::new(Uuid::new_v4(), "   "), Err(ValidationError::NameIsEmptyString)));
+    }
+}
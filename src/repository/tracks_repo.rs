@@ -1,4 +1,4 @@
-use std::{convert::Infallible, path::{Path, PathBuf}, str::FromStr};
+use std::{collections::{HashMap, HashSet}, convert::Infallible, path::{Path, PathBuf}, str::FromStr};
 
 use futures::{Stream, StreamExt};
 use sqlx::{Executor, FromRow, QueryBuilder, Row, Sqlite, SqliteConnection};
@@ -6,9 +6,9 @@ use chrono::NaiveDateTime;
 use uuid::Uuid;
 
 use crate::domain::{audiofile::AudioFileType, BatchDeleteReport, BatchSaveOutcome, BatchSaveReport, UploadedParseError, ValidationError};
-use crate::domain::track::Track;
+use crate::domain::track::{Track, TrackSort};
 use crate::domain::uploaded::Uploaded;
-use super::{IntoUuid, RepositoryError};
+use super::{escape_like, IntoUuid, RepositoryError};
 
 #[derive(FromRow)]
 struct DbTrack {
@@ -20,14 +20,19 @@ struct DbTrack {
     file_size: i64,
     file_type: String,
     uploaded: String,
-    date_added: Option<NaiveDateTime>
+    date_added: Option<NaiveDateTime>,
+    disc_number: Option<i64>,
+    track_number: Option<i64>,
+    file_mtime: Option<NaiveDateTime>,
+    probe_ok: bool,
+    content_hash: Option<String>,
+    genre: Option<String>
 }
 
 impl TryFrom<DbTrack> for Track {
     type Error = TrackConversionError;
     fn try_from(db_track: DbTrack) -> Result<Self, Self::Error> {
-        Ok(
-            Self::new(
+        let mut track = Self::new(
                 Uuid::from_slice(&db_track.id)?,
                 db_track.name,
                 Uuid::from_slice(&db_track.album_id)?,
@@ -37,8 +42,16 @@ impl TryFrom<DbTrack> for Track {
                 AudioFileType::from_extension_str(&db_track.file_type),
                 db_track.uploaded.try_into()?,
                 db_track.date_added,
-            ).map_err(|err| TrackConversionError::ValidationError(err))?
-        )
+            ).map_err(|err| TrackConversionError::ValidationError(err))?;
+
+        track.set_disc_number(db_track.disc_number.map(u32::try_from).transpose()?);
+        track.set_track_number(db_track.track_number.map(u32::try_from).transpose()?);
+        track.set_file_mtime(db_track.file_mtime);
+        track.set_probe_ok(db_track.probe_ok);
+        track.set_content_hash(db_track.content_hash);
+        track.set_genre(db_track.genre);
+
+        Ok(track)
     }
 }
 
@@ -60,6 +73,40 @@ pub enum TrackConversionError {
     ValidationError(#[from] ValidationError)
 }
 
+// A streamed query borrows its SQL for as long as the stream lives, so it has to be 'static
+macro_rules! ordered_tracks_query {
+    ($order_by:literal) => {
+        concat!(
+            "SELECT t.id, t.name, t.album_id, t.duration, t.file_path, t.file_size, t.file_type, t.uploaded, t.date_added, t.disc_number, t.track_number, t.file_mtime, t.probe_ok, t.content_hash, t.genre
+            FROM tracks t
+            JOIN albums al ON al.id = t.album_id
+            JOIN artists ar ON ar.id = al.artist_id
+            ORDER BY ", $order_by, ";"
+        )
+    };
+}
+
+impl TrackSort {
+    fn order_by(&self) -> &'static str {
+        match self {
+            TrackSort::Name => "t.name, t.file_path",
+            TrackSort::DateAdded => "t.date_added DESC, t.file_path",
+            TrackSort::Duration => "t.duration, t.file_path",
+            TrackSort::Artist => "ar.name, al.name, t.disc_number, t.track_number, t.file_path"
+        }
+    }
+
+    /// The whole table in this order. Must stay in line with `order_by`.
+    fn ordered_query(&self) -> &'static str {
+        match self {
+            TrackSort::Name => ordered_tracks_query!("t.name, t.file_path"),
+            TrackSort::DateAdded => ordered_tracks_query!("t.date_added DESC, t.file_path"),
+            TrackSort::Duration => ordered_tracks_query!("t.duration, t.file_path"),
+            TrackSort::Artist => ordered_tracks_query!("ar.name, al.name, t.disc_number, t.track_number, t.file_path")
+        }
+    }
+}
+
 pub struct SqliteTracksRepository;
 
 impl SqliteTracksRepository {
@@ -79,9 +126,9 @@ impl SqliteTracksRepository {
         let file_path_str = track.as_ref().file_path().to_string_lossy();
 
         let db_track = sqlx::query_as::<_, DbTrack>(
-            "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added) 
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-            RETURNING id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added;")
+            "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre) 
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre;")
             .bind(&track.as_ref().id())
             .bind(&track.as_ref().name())
             .bind(&track.as_ref().album_id())
@@ -91,6 +138,12 @@ impl SqliteTracksRepository {
             .bind(&track.as_ref().file_type().as_str())
             .bind(&uploaded_str)
             .bind(&track.as_ref().date_added())
+            .bind(track.as_ref().disc_number())
+            .bind(track.as_ref().track_number())
+            .bind(track.as_ref().file_mtime())
+            .bind(track.as_ref().probe_ok())
+            .bind(track.as_ref().content_hash())
+            .bind(track.as_ref().genre())
             .fetch_one(executor)
             .await?;
 
@@ -107,7 +160,7 @@ impl SqliteTracksRepository {
         }
 
         let mut qbuilder: QueryBuilder<Sqlite> = QueryBuilder::new(
-            "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added) "
+            "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre) "
         );
 
         qbuilder.push_values(tracks.iter(), |mut b, track| {
@@ -122,7 +175,13 @@ impl SqliteTracksRepository {
                 .push_bind(track.as_ref().file_size() as i64)
                 .push_bind(track.as_ref().file_type().as_str())
                 .push_bind(uploaded_str)
-                .push_bind(track.as_ref().date_added());
+                .push_bind(track.as_ref().date_added())
+                .push_bind(track.as_ref().disc_number())
+                .push_bind(track.as_ref().track_number())
+                .push_bind(track.as_ref().file_mtime())
+                .push_bind(track.as_ref().probe_ok())
+                .push_bind(track.as_ref().content_hash())
+                .push_bind(track.as_ref().genre());
         });
 
         qbuilder.push("RETURNING id;");
@@ -143,6 +202,17 @@ impl SqliteTracksRepository {
 
     pub async fn batch_save<T>(&self, connection: &mut SqliteConnection, tracks: &[T]) -> Result<BatchSaveReport, RepositoryError>
     where T: AsRef<Track> + Sync
+    {
+        self.batch_save_iter(connection, tracks).await
+    }
+
+    /// Same as `batch_save`, for any iterator of tracks. A sync committing in one transaction saves all of
+    /// its new tracks through this, one committing in chunks goes through `batch_save` chunk by chunk.
+    pub async fn batch_save_iter<I>(&self, connection: &mut SqliteConnection, tracks: I) -> Result<BatchSaveReport, RepositoryError>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<Track> + Send
     {
         // This is per row INSERT, so there is n = albums.len() queries.
         // Speed was sacrificed for an ability to dynamicly insert items of the batch 
@@ -151,7 +221,7 @@ impl SqliteTracksRepository {
 
         let mut batch_report = BatchSaveReport::new();
 
-        for (index, track) in tracks.iter().enumerate() {
+        for (index, track) in tracks.into_iter().enumerate() {
             let track = track.as_ref();
 
             let id = track.id();
@@ -164,19 +234,25 @@ impl SqliteTracksRepository {
             let file_path = track.file_path().to_string_lossy();
             let date_added = track.date_added();
 
-            let saving_result = sqlx::query_scalar!(
-                "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added) 
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-                RETURNING id;",
-                id,
-                name,
-                ablum_id,
-                duration,
-                file_path,
-                file_size,
-                file_type,
-                uploaded_str,
-                date_added)
+            let saving_result = sqlx::query_scalar::<_, Vec<u8>>(
+                "INSERT INTO tracks(id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre) 
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                RETURNING id;")
+                .bind(id)
+                .bind(name)
+                .bind(ablum_id)
+                .bind(duration)
+                .bind(file_path)
+                .bind(file_size)
+                .bind(file_type)
+                .bind(uploaded_str)
+                .bind(date_added)
+                .bind(track.disc_number())
+                .bind(track.track_number())
+                .bind(track.file_mtime())
+                .bind(track.probe_ok())
+                .bind(track.content_hash())
+                .bind(track.genre())
                 .fetch_one(&mut *connection)
                 .await
                 .map_err(RepositoryError::from_sqlx_error)
@@ -205,7 +281,7 @@ impl SqliteTracksRepository {
     {
         let uuid = id.into_uuid()?;
         let db_track = sqlx::query_as::<_, DbTrack>(
-            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added 
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
             FROM tracks 
             WHERE id = ? 
             LIMIT 1;"
@@ -228,7 +304,7 @@ impl SqliteTracksRepository {
         let path_ref = path.as_ref();
         if let Some(path_str) = path_ref.to_str() {
             let db_track = sqlx::query_as::<_, DbTrack>(
-                "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added 
+                "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
                 FROM tracks 
                 WHERE file_path = ? 
                 LIMIT 1;"
@@ -247,12 +323,12 @@ impl SqliteTracksRepository {
         
     }
 
-    pub async fn stream_all<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Track, RepositoryError>> + Send + 'e
+    pub async fn stream_all<'e, E>(&self, executor: E) -> impl Stream<Item = Result<Track, RepositoryError>> + Send + use<'e, E>
     where 
         E: Executor<'e, Database = Sqlite> + Send + 'e,
     {
         sqlx::query_as::<_, DbTrack>(
-            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added 
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
             FROM tracks"
         )
         .fetch(executor)
@@ -264,6 +340,241 @@ impl SqliteTracksRepository {
         })
     }
 
+    /// Same as `stream_all`, in the given order. Slower, prefer `stream_all` where the order doesn't matter.
+    pub async fn stream_all_ordered<'e, E>(&self, executor: E, sort: TrackSort) -> impl Stream<Item = Result<Track, RepositoryError>> + Send + use<'e, E>
+    where 
+        E: Executor<'e, Database = Sqlite> + Send + 'e,
+    {
+        sqlx::query_as::<_, DbTrack>(sort.ordered_query())
+        .fetch(executor)
+        .map(|db_track_res|{
+            match db_track_res {
+                Ok(db_track) => Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping),
+                Err(sqlx_err) => Err(RepositoryError::from_sqlx_error(sqlx_err))
+            }
+        })
+    }
+
+    /// One page of tracks ordered by name, for callers that can't hold all of them. Ties are broken by id,
+    /// so the pages don't overlap.
+    pub async fn fetch_page<'e, E>(&self, executor: E, limit: i64, offset: i64) -> Result<Vec<Track>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks 
+            ORDER BY name, id 
+            LIMIT ? OFFSET ?;"
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping))
+            .collect()
+    }
+
+    /// Tracks whose name contains `query`, ignoring ASCII case, ordered by name. Wildcards in `query` are matched literally.
+    pub async fn search_by_name<'e, E>(&self, executor: E, query: &str, limit: i64) -> Result<Vec<Track>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks 
+            WHERE name LIKE '%' || ? || '%' ESCAPE '\\' COLLATE NOCASE 
+            ORDER BY name, id 
+            LIMIT ?;"
+        )
+        .bind(escape_like(query))
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping))
+            .collect()
+    }
+
+    /// Total number of tracks, to work out the number of pages for `fetch_page`.
+    pub async fn count<'e, E>(&self, executor: E) -> Result<u64, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM tracks;")
+            .fetch_one(executor)
+            .await?;
+
+        Ok(u64::try_from(count)?)
+    }
+
+    /// Tracks whose path starts with `prefix`, ordered by path. LIKE wildcards in the prefix are matched literally.
+    pub async fn by_path_prefix<'e, E, P>(&self, executor: E, prefix: P, limit: u32, offset: u32) -> Result<Vec<Track>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Path> + Send + Sync
+    {
+        self.fetch_by_path_prefix(executor, prefix, "t.file_path", limit, offset).await
+    }
+
+    /// Same as `by_path_prefix`, in the given order.
+    pub async fn by_path_prefix_sorted<'e, E, P>(&self, executor: E, prefix: P, sort: TrackSort, limit: u32, offset: u32) -> Result<Vec<Track>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Path> + Send + Sync
+    {
+        self.fetch_by_path_prefix(executor, prefix, sort.order_by(), limit, offset).await
+    }
+
+    // `order_by` is spliced into the query, it must only ever come from `TrackSort` or a literal
+    async fn fetch_by_path_prefix<'e, E, P>(&self, executor: E, prefix: P, order_by: &'static str, limit: u32, offset: u32) -> Result<Vec<Track>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Path> + Send + Sync
+    {
+        let prefix_ref = prefix.as_ref();
+        let Some(prefix_str) = prefix_ref.to_str() else {
+            return Err(RepositoryError::InvalidPathEncoding(prefix_ref.to_path_buf()));
+        };
+
+        let escaped_prefix = escape_like(prefix_str);
+
+        let query = format!(
+            "SELECT t.id, t.name, t.album_id, t.duration, t.file_path, t.file_size, t.file_type, t.uploaded, t.date_added, t.disc_number, t.track_number, t.file_mtime, t.probe_ok, t.content_hash, t.genre
+            FROM tracks t
+            JOIN albums al ON al.id = t.album_id
+            JOIN artists ar ON ar.id = al.artist_id
+            WHERE t.file_path LIKE ? || '%' ESCAPE '\\'
+            ORDER BY {}
+            LIMIT ? OFFSET ?",
+            order_by
+        );
+
+        let db_tracks = sqlx::query_as::<_, DbTrack>(&query)
+        .bind(escaped_prefix)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping))
+            .collect()
+    }
+
+    /// Tracks of the playlist in playlist order. A track added twice comes back twice.
+    pub async fn by_playlist<'e, E, ID>(&self, executor: E, playlist_id: ID) -> Result<Vec<Track>, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        let playlist_id = playlist_id.into_uuid()?;
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT t.id, t.name, t.album_id, t.duration, t.file_path, t.file_size, t.file_type, t.uploaded, t.date_added, t.disc_number, t.track_number, t.file_mtime, t.probe_ok, t.content_hash, t.genre
+            FROM playlist_tracks pt
+            JOIN tracks t ON t.id = pt.track_id
+            WHERE pt.playlist_id = ?
+            ORDER BY pt.position"
+        )
+        .bind(playlist_id)
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping))
+            .collect()
+    }
+
+    /// Tracks that were synced with default metadata because their tags couldn't be read.
+    pub async fn all_unprobed<'e, E>(&self, executor: E) -> Result<Vec<Track>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks
+            WHERE probe_ok = 0
+            ORDER BY file_path"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping))
+            .collect()
+    }
+
+    /// Tracks sharing a content hash, one group per hash with at least two tracks, ordered by path.
+    /// Tracks without a hash are left out.
+    pub async fn find_duplicate_groups<'e, E>(&self, executor: E) -> Result<Vec<Vec<Track>>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks
+            WHERE content_hash IN (
+                SELECT content_hash FROM tracks
+                WHERE content_hash IS NOT NULL
+                GROUP BY content_hash
+                HAVING COUNT(*) > 1
+            )
+            ORDER BY content_hash, file_path"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        let mut groups: Vec<Vec<Track>> = Vec::new();
+        for db_track in db_tracks {
+            let track = Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping)?;
+
+            match groups.last_mut() {
+                Some(group) if group[0].content_hash() == track.content_hash() => group.push(track),
+                _ => groups.push(vec![track])
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Tracks that have a track number, grouped by album, all in one query.
+    /// Albums without a single numbered track have no entry.
+    pub async fn numbered_by_album<'e, E>(&self, executor: E) -> Result<HashMap<Uuid, Vec<Track>>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>
+    {
+        let db_tracks = sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks
+            WHERE track_number IS NOT NULL"
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        let mut by_album: HashMap<Uuid, Vec<Track>> = HashMap::new();
+        for db_track in db_tracks {
+            let track = Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping)?;
+            by_album.entry(*track.album_id()).or_default().push(track);
+        }
+
+        Ok(by_album)
+    }
+
     pub async fn all_by_album<'e, E, ID>(&self, executor: E, album_id: ID) -> Result<Vec<Track>, RepositoryError>
     where 
         E: Executor<'e, Database = Sqlite>,
@@ -272,9 +583,10 @@ impl SqliteTracksRepository {
         let album_id = album_id.into_uuid()?;
 
         let db_tracks = sqlx::query_as::<_, DbTrack>(
-            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added 
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
             FROM tracks
-            WHERE album_id = ?"
+            WHERE album_id = ?
+            ORDER BY disc_number IS NULL, disc_number, track_number IS NULL, track_number, name"
         ).bind(album_id)
         .fetch_all(executor)
         .await
@@ -286,13 +598,54 @@ impl SqliteTracksRepository {
             .collect()
     }
 
+    pub async fn count_by_album<'e, E, ID>(&self, executor: E, album_id: ID) -> Result<u64, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        let album_id = album_id.into_uuid()?;
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM tracks WHERE album_id = ?;"
+        ).bind(album_id)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(u64::try_from(count)?)
+    }
+
+    /// Number of tracks in each of the given albums. Albums without tracks are left out of the map.
+    pub async fn count_by_albums<'e, E, ID>(&self, executor: E, album_ids: &[ID]) -> Result<HashMap<Uuid, u64>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        if album_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut qbuilder = QueryBuilder::new(
+            "SELECT album_id, COUNT(*) FROM tracks WHERE album_id IN ("
+        );
+        let mut separated = qbuilder.separated(", ");
+        for id in album_ids.iter() {
+            let uuid = id.into_uuid()?;
+            separated.push_bind(uuid);
+        }
+        separated.push_unseparated(") GROUP BY album_id;");
+
+        let rows = qbuilder.build_query_as::<(Vec<u8>, i64)>().fetch_all(executor).await?;
+        rows.into_iter()
+            .map(|(album_id, count)| Ok((Uuid::from_slice(&album_id)?, u64::try_from(count)?)))
+            .collect()
+    }
+
     pub async fn stream_by_uploaded<'e, E>(&self, executor: E, uploaded_by: Uploaded) -> impl Stream<Item = Result<Track, RepositoryError>> + Send + 'e
     where 
         E: Executor<'e, Database = Sqlite> +'e,
     {   
         let uploaded_str: &str = uploaded_by.into();
         sqlx::query_as::<_, DbTrack>(
-            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added 
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
             FROM tracks
             WHERE uploaded = ?"
         ).bind(uploaded_str)
@@ -305,6 +658,115 @@ impl SqliteTracksRepository {
         })
     }
     
+    /// Tracks tagged with `genre`, matched case-insensitively. Tracks without a genre are never returned.
+    pub async fn stream_by_genre<'e, E>(&self, executor: E, genre: &'e str) -> impl Stream<Item = Result<Track, RepositoryError>> + Send + 'e
+    where 
+        E: Executor<'e, Database = Sqlite> +'e,
+    {   
+        sqlx::query_as::<_, DbTrack>(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks
+            WHERE genre = ? COLLATE NOCASE"
+        ).bind(genre)
+        .fetch(executor)
+        .map(|db_track_res|{
+            match db_track_res {
+                Ok(db_track) => Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping),
+                Err(sqlx_err) => Err(RepositoryError::from_sqlx_error(sqlx_err))
+            }
+        })
+    }
+
+    pub async fn set_uploaded<'e, E, ID>(&self, executor: E, id: ID, uploaded: Uploaded) -> Result<Track, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync
+    {
+        let id = id.into_uuid()?;
+        let uploaded_str: &str = uploaded.into();
+
+        let db_track = sqlx::query_as::<_, DbTrack>(
+            "UPDATE tracks SET uploaded = ?
+            WHERE id = ?
+            RETURNING id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre;"
+        )
+        .bind(uploaded_str)
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        match db_track {
+            Some(db_track) => Ok(db_track.try_into()?),
+            None => Err(RepositoryError::IdNotFound(id))
+        }
+    }
+
+    /// Overwrites the stored row with `track`, matched by id, and returns it as stored.
+    ///
+    /// `file_path` and `date_added` are left alone: the first has `set_file_path`, the second never changes.
+    pub async fn update<'e, E, T>(&self, executor: E, track: T) -> Result<Track, RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        T: AsRef<Track> + Sync
+    {
+        let track = track.as_ref();
+        let uploaded_str: &str = track.uploaded().into();
+
+        let db_track = sqlx::query_as::<_, DbTrack>(
+            "UPDATE tracks SET name = ?, album_id = ?, duration = ?, file_size = ?, file_type = ?, uploaded = ?, disc_number = ?, track_number = ?, file_mtime = ?, probe_ok = ?, content_hash = ?, genre = ?
+            WHERE id = ?
+            RETURNING id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre;"
+        )
+        .bind(track.name())
+        .bind(track.album_id())
+        .bind(track.duration())
+        .bind(track.file_size() as i64)
+        .bind(track.file_type().as_str())
+        .bind(uploaded_str)
+        .bind(track.disc_number())
+        .bind(track.track_number())
+        .bind(track.file_mtime())
+        .bind(track.probe_ok())
+        .bind(track.content_hash())
+        .bind(track.genre())
+        .bind(track.id())
+        .fetch_optional(executor)
+        .await
+        .map_err(RepositoryError::from_sqlx_error)?;
+
+        match db_track {
+            Some(db_track) => Ok(db_track.try_into()?),
+            None => Err(RepositoryError::IdNotFound(*track.id()))
+        }
+    }
+
+    pub async fn set_file_path<'e, E, ID, P>(&self, executor: E, id: ID, path: P) -> Result<(), RepositoryError>
+    where
+        E: Executor<'e, Database = Sqlite>,
+        ID: IntoUuid + Send + Sync,
+        P: AsRef<Path> + Send + Sync
+    {
+        let id = id.into_uuid()?;
+        let path_ref = path.as_ref();
+        let Some(path_str) = path_ref.to_str() else {
+            return Err(RepositoryError::InvalidPathEncoding(path_ref.to_path_buf()));
+        };
+
+        let result = sqlx::query("UPDATE tracks SET file_path = ? WHERE id = ?;")
+            .bind(path_str)
+            .bind(id)
+            .execute(executor)
+            .await
+            .map_err(RepositoryError::from_sqlx_error)?;
+
+        if result.rows_affected() > 0 {
+            Ok(())
+        } else {
+            Err(RepositoryError::IdNotFound(id))
+        }
+    }
+
     pub async fn delete<'e, ID, E>(&self, executor: E, id: ID) -> Result<(), RepositoryError>
     where
         ID: IntoUuid + Send + Sync,
@@ -396,6 +858,66 @@ impl SqliteTracksRepository {
         }
     }
 
+    /// Batch variant of `path_exists`: returns those of `paths` that belong to a stored track.
+    pub async fn paths_exist<'e, E, P>(&self, executor: E, paths: &[P]) -> Result<HashSet<PathBuf>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Path> + Send + Sync
+    {
+        if paths.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let mut qbuilder = QueryBuilder::new(
+            "SELECT file_path FROM tracks WHERE file_path IN ("
+        );
+        let mut separated = qbuilder.separated(", ");
+        for path in paths.iter() {
+            let path_str = path.as_ref().to_str()
+                .ok_or_else(|| RepositoryError::InvalidPathEncoding(path.as_ref().to_path_buf()))?;
+            separated.push_bind(path_str);
+        }
+        separated.push_unseparated(");");
+
+        let stored = qbuilder.build_query_scalar::<String>().fetch_all(executor).await?;
+
+        Ok(stored.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Batch variant of `by_path_fetch`: the stored tracks among `paths`, keyed by their stored path.
+    /// Paths without a track are left out. Keep the batch well below SQLite's limit on bound parameters.
+    pub async fn by_paths_map<'e, E, P>(&self, executor: E, paths: &[P]) -> Result<HashMap<PathBuf, Track>, RepositoryError>
+    where 
+        E: Executor<'e, Database = Sqlite>,
+        P: AsRef<Path> + Send + Sync
+    {
+        if paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut qbuilder = QueryBuilder::new(
+            "SELECT id, name, album_id, duration, file_path, file_size, file_type, uploaded, date_added, disc_number, track_number, file_mtime, probe_ok, content_hash, genre 
+            FROM tracks WHERE file_path IN ("
+        );
+        let mut separated = qbuilder.separated(", ");
+        for path in paths.iter() {
+            let path_str = path.as_ref().to_str()
+                .ok_or_else(|| RepositoryError::InvalidPathEncoding(path.as_ref().to_path_buf()))?;
+            separated.push_bind(path_str);
+        }
+        separated.push_unseparated(");");
+
+        let db_tracks = qbuilder.build_query_as::<DbTrack>().fetch_all(executor).await?;
+
+        db_tracks
+            .into_iter()
+            .map(|db_track| {
+                let track = Track::try_from(db_track).map_err(RepositoryError::TrackDataMapping)?;
+                Ok((track.file_path().clone(), track))
+            })
+            .collect()
+    }
+
     pub async fn path_exists<'e, E, P>(&self, executor: E, path: P) -> Result<bool, RepositoryError>
     where 
         E: Executor<'e, Database = Sqlite>,
@@ -431,10 +953,11 @@ mod tests {
     use std::fmt::Display;
 
     use chrono::Local;
+    use futures::TryStreamExt;
     use sqlx::{SqlitePool, Transaction};
 
     use super::*;
-    use crate::repository::{SqliteArtistsRepository, SqliteAlbumsRepository, test_helpers::{prepare_db, TestSetupError}};
+    use crate::repository::{SqliteArtistsRepository, SqliteAlbumsRepository, test_helpers::{assert_pages_of_ten, assert_search_by_name, prepare_db, TestSetupError}};
     use crate::domain::{artist::Artist, album::Album};
 
     const UUID_BYTES: [u8; 16] = [
@@ -462,7 +985,7 @@ mod tests {
             let alb_repo = SqliteAlbumsRepository::new();
 
             let artist = Artist::new(new_uuid("Default Artist"), "Default Artist Name")?;
-            let album = Album::new(new_uuid("Default Album"), "Default Album Name", artist.id().clone(), Some(2042))?;
+            let album = Album::new(new_uuid("Default Album"), "Default Album Name", artist.id().clone(), Some(2012))?;
 
             art_repo.save(&pool, &artist).await?;
             alb_repo.save(&pool, &album).await?;
@@ -493,7 +1016,7 @@ mod tests {
         where S: AsRef<[u8]> + ?Sized + Display
         {
             let artist = Artist::new(new_uuid(artist_id), format!("Default Artist Name {}", artist_id))?;
-            let album = Album::new(new_uuid(album_id), format!("Default Album Name {}", album_id), artist.id().clone(), Some(2042))?;
+            let album = Album::new(new_uuid(album_id), format!("Default Album Name {}", album_id), artist.id().clone(), Some(2012))?;
 
             self.art_repo.save(&self.pool, &artist).await?;
             self.alb_repo.save(&self.pool, &album).await?;
@@ -1082,4 +1605,394 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn set_uploaded_success() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(1)?;
+        ctx.repo.save(&ctx.pool, &ctx.entities[0]).await?;
+
+        let updated = ctx.repo.set_uploaded(&ctx.pool, ctx.entities[0].id(), Uploaded::Masha).await?;
+        assert!(matches!(updated.uploaded(), Uploaded::Masha));
+
+        let fetched = ctx.repo.by_id_fetch(&ctx.pool, ctx.entities[0].id()).await?.expect("track was saved above");
+        assert!(matches!(fetched.uploaded(), Uploaded::Masha));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_uploaded_not_found() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let fake_id = new_uuid("should not exist");
+
+        let result = ctx.repo.set_uploaded(&ctx.pool, &fake_id, Uploaded::Masha).await;
+        assert!(matches!(result, Err(RepositoryError::IdNotFound(id)) if id == fake_id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_by_album_orders_by_disc_and_track_number() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        // (disc, track) pairs, shuffled so that insertion order doesn't match the expected one.
+        let positions = [(2, 2), (1, 2), (2, 1), (1, 1)];
+        let mut tracks = create_tracks_with_album(positions.len() as u16, album_id);
+        for (track, (disc, number)) in tracks.iter_mut().zip(positions) {
+            track.set_disc_number(Some(disc));
+            track.set_track_number(Some(number));
+        }
+
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let fetched = ctx.repo.all_by_album(&ctx.pool, &album_id).await?;
+        let fetched_positions: Vec<(Option<u32>, Option<u32>)> = fetched
+            .iter()
+            .map(|track| (track.disc_number(), track.track_number()))
+            .collect();
+
+        assert_eq!(
+            fetched_positions,
+            vec![(Some(1), Some(1)), (Some(1), Some(2)), (Some(2), Some(1)), (Some(2), Some(2))]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn numbered_by_album_leaves_out_unnumbered_tracks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        let mut tracks = create_tracks_with_album(3, album_id);
+        tracks[0].set_track_number(Some(2));
+        tracks[2].set_track_number(Some(1));
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let by_album = ctx.repo.numbered_by_album(&ctx.pool).await?;
+        let mut ids = by_album[&album_id].iter().map(|track| *track.id()).collect::<Vec<_>>();
+        ids.sort();
+        let mut expected = vec![*tracks[0].id(), *tracks[2].id()];
+        expected.sort();
+
+        assert_eq!(by_album.len(), 1);
+        assert_eq!(ids, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn by_path_prefix_nested_and_escaped() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        let paths = [
+            "t:/music/rock/a.mp3",
+            "t:/music/rock/live/b.mp3",
+            "t:/music/rockabilly/c.mp3",
+            "t:/music/100%_pure/d.mp3",
+            "t:/music/100xxpure/e.mp3",
+        ];
+        let tracks: Vec<Track> = create_tracks_with_album(paths.len() as u16, album_id)
+            .into_iter()
+            .zip(paths)
+            .map(|(track, path)| Track::new(
+                *track.id(), track.name(), album_id, track.duration(), PathBuf::from(path),
+                track.file_size(), AudioFileType::Mp3, Uploaded::Denis, None
+            ).expect("Error during test setup: track fields validation has failed."))
+            .collect();
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let fetched_paths = |tracks: Vec<Track>| tracks.iter().map(|t| t.file_path().to_string_lossy().to_string()).collect::<Vec<_>>();
+
+        let rock = ctx.repo.by_path_prefix(&ctx.pool, "t:/music/rock/", 100, 0).await?;
+        assert_eq!(fetched_paths(rock), vec!["t:/music/rock/a.mp3", "t:/music/rock/live/b.mp3"]);
+
+        let escaped = ctx.repo.by_path_prefix(&ctx.pool, "t:/music/100%_pure/", 100, 0).await?;
+        assert_eq!(fetched_paths(escaped), vec!["t:/music/100%_pure/d.mp3"]);
+
+        let paged = ctx.repo.by_path_prefix(&ctx.pool, "t:/music/", 2, 1).await?;
+        assert_eq!(paged.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_unprobed_returns_only_default_metadata_tracks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        let mut tracks = create_tracks_with_album(3, album_id);
+        tracks[1].set_probe_ok(false);
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let unprobed = ctx.repo.all_unprobed(&ctx.pool).await?;
+
+        assert_eq!(unprobed.len(), 1);
+        assert_eq!(unprobed[0].id(), tracks[1].id());
+        assert!(!unprobed[0].probe_ok());
+
+        let probed = ctx.repo.by_id_fetch(&ctx.pool, tracks[0].id()).await?.expect("Track should exist");
+        assert!(probed.probe_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn by_path_prefix_sorted_orders() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let default_album_id = new_uuid("Default Album");
+
+        let abba = Artist::new(new_uuid("abba"), "abba")?;
+        let abba_album = Album::new(new_uuid("abba album"), "arrival", *abba.id(), None)?;
+        ctx.art_repo.save(&ctx.pool, &abba).await?;
+        ctx.alb_repo.save(&ctx.pool, &abba_album).await?;
+
+        // (name, album, duration, added day)
+        let specs = [("bravo", default_album_id, 300, 1), ("alpha", default_album_id, 100, 3), ("charlie", *abba_album.id(), 200, 2)];
+        for (name, album_id, duration, day) in specs {
+            let added = chrono::NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(0, 0, 0);
+            let track = Track::new(new_uuid(name), name, album_id, duration, PathBuf::from(format!("t:/sorted/{}.mp3", name)), 100, AudioFileType::Mp3, Uploaded::Denis, added)?;
+            ctx.repo.save(&ctx.pool, &track).await?;
+        }
+
+        let names = |tracks: Vec<Track>| tracks.iter().map(|t| t.name().to_string()).collect::<Vec<_>>();
+        let sorted = |sort: TrackSort| ctx.repo.by_path_prefix_sorted(&ctx.pool, "t:/sorted/", sort, 100, 0);
+
+        assert_eq!(names(sorted(TrackSort::Name).await?), vec!["alpha", "bravo", "charlie"]);
+        assert_eq!(names(sorted(TrackSort::DateAdded).await?), vec!["alpha", "charlie", "bravo"]);
+        assert_eq!(names(sorted(TrackSort::Duration).await?), vec!["alpha", "charlie", "bravo"]);
+        // "abba" before "default artist name"
+        assert_eq!(names(sorted(TrackSort::Artist).await?), vec!["charlie", "alpha", "bravo"]);
+
+        for sort in [TrackSort::Name, TrackSort::DateAdded, TrackSort::Duration, TrackSort::Artist] {
+            assert!(sort.ordered_query().trim_end_matches(';').ends_with(sort.order_by()));
+
+            // the names serde writes are the ones `FromStr` reads
+            let name = serde_json::to_value(sort).expect("TrackSort should serialize");
+            assert_eq!(name.as_str().map(str::parse::<TrackSort>), Some(Ok(sort)));
+        }
+
+        assert_eq!("date_added".parse::<TrackSort>(), Ok(TrackSort::DateAdded));
+        assert!("name; DROP TABLE tracks".parse::<TrackSort>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_all_ordered_is_sorted() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(20)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+
+        let by_name = ctx.repo.stream_all_ordered(&ctx.pool, TrackSort::Name).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(by_name.len(), 20);
+        assert!(by_name.windows(2).all(|pair| pair[0].name() <= pair[1].name()));
+
+        let by_duration = ctx.repo.stream_all_ordered(&ctx.pool, TrackSort::Duration).await.try_collect::<Vec<_>>().await?;
+        assert!(by_duration.windows(2).all(|pair| pair[0].duration() <= pair[1].duration()));
+        assert_eq!(by_duration.first().map(|track| track.name()), Some("test track 1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ogg_and_m4a_file_types_round_trip() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let default_album_id = new_uuid("Default Album");
+
+        for file_type in [AudioFileType::Ogg, AudioFileType::M4a] {
+            let track = Track::new(Uuid::new_v4(), "track", default_album_id, 100, PathBuf::from(format!("t:/types/track.{}", file_type.as_str())), 100, file_type.clone(), Uploaded::Denis, None)?;
+            ctx.repo.save(&ctx.pool, &track).await?;
+
+            let fetched = ctx.repo.by_id_fetch(&ctx.pool, *track.id()).await?.expect("Track should be stored");
+            assert_eq!(fetched.file_type(), &file_type);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_success() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(1)?;
+        let stored = ctx.repo.save(&ctx.pool, &ctx.entities[0]).await?;
+
+        ctx.associate("Other Album", "Other Artist").await?;
+        let mut changed = Track::new(*stored.id(), "Fixed Typo", new_uuid("Other Album"), 321, PathBuf::from("t:/elsewhere.ogg"), 2048, AudioFileType::Ogg, Uploaded::Denis, None)?;
+        changed.set_track_number(Some(7));
+
+        let updated = ctx.repo.update(&ctx.pool, &changed).await?;
+
+        assert_eq!(updated.name(), changed.name());
+        assert_eq!(updated.album_id(), &new_uuid("Other Album"));
+        assert_eq!(updated.duration(), 321);
+        assert_eq!(updated.file_size(), 2048);
+        assert_eq!(updated.file_type(), &AudioFileType::Ogg);
+        assert_eq!(updated.track_number(), Some(7));
+        // path and date added are not touched
+        assert_eq!(updated.file_path(), stored.file_path());
+        assert_eq!(updated.date_added(), stored.date_added());
+
+        let fetched = ctx.repo.by_id_fetch(&ctx.pool, *stored.id()).await?.expect("Track should still be stored");
+        assert_eq!(fetched.name(), changed.name());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_missing_id() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(1)?;
+
+        let result = ctx.repo.update(&ctx.pool, &ctx.entities[0]).await;
+
+        assert!(matches!(result, Err(RepositoryError::IdNotFound(id)) if id == *ctx.entities[0].id()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paths_exist_returns_stored_subset() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(3)?;
+        ctx.repo.save(&ctx.pool, &ctx.entities[0]).await?;
+        ctx.repo.save(&ctx.pool, &ctx.entities[1]).await?;
+
+        let fake_path = PathBuf::from("F:/fake/as/fuck");
+        let asked = [ctx.entities[0].file_path(), ctx.entities[1].file_path(), ctx.entities[2].file_path(), &fake_path];
+        let stored = ctx.repo.paths_exist(&ctx.pool, &asked).await?;
+
+        assert_eq!(stored, HashSet::from([ctx.entities[0].file_path().clone(), ctx.entities[1].file_path().clone()]));
+        assert!(ctx.repo.paths_exist::<_, PathBuf>(&ctx.pool, &[]).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn by_paths_map_keys_stored_tracks_by_path() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(3)?;
+        ctx.repo.save(&ctx.pool, &ctx.entities[0]).await?;
+        ctx.repo.save(&ctx.pool, &ctx.entities[2]).await?;
+
+        let fake_path = PathBuf::from("F:/not/stored");
+        let asked = [ctx.entities[0].file_path(), ctx.entities[1].file_path(), ctx.entities[2].file_path(), &fake_path];
+        let stored = ctx.repo.by_paths_map(&ctx.pool, &asked).await?;
+
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[ctx.entities[0].file_path()].id(), ctx.entities[0].id());
+        assert_eq!(stored[ctx.entities[2].file_path()].id(), ctx.entities[2].id());
+        assert!(!stored.contains_key(ctx.entities[1].file_path()));
+        assert!(ctx.repo.by_paths_map::<_, PathBuf>(&ctx.pool, &[]).await?.is_empty());
+
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+            let non_utf8 = PathBuf::from(OsStr::from_bytes(b"t:/bad/\xff.mp3"));
+            let result = ctx.repo.by_paths_map(&ctx.pool, &[ctx.entities[0].file_path(), &non_utf8]).await;
+            assert!(matches!(result, Err(RepositoryError::InvalidPathEncoding(path)) if path == non_utf8));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn count_by_albums_groups_tracks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(3)?;
+        ctx.associate("Other Album", "Other Artist").await?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+        ctx.repo.save_all(&ctx.pool, &create_tracks_with_album(2, new_uuid("Other Album"))).await?;
+
+        let empty_album = new_uuid("No Such Album");
+        let counts = ctx.repo.count_by_albums(&ctx.pool, &[new_uuid("Default Album"), new_uuid("Other Album"), empty_album]).await?;
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&new_uuid("Default Album")], 3);
+        assert_eq!(counts[&new_uuid("Other Album")], 2);
+        assert!(!counts.contains_key(&empty_album));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_page_boundaries() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(25)?;
+        ctx.repo.save_all(&ctx.pool, &ctx.entities).await?;
+
+        let mut expected = ctx.entities.iter().map(|track| track.name().to_string()).collect::<Vec<_>>();
+        expected.sort();
+
+        assert_pages_of_ten(|limit, offset| ctx.repo.fetch_page(&ctx.pool, limit, offset), |track| track.name().to_string(), expected).await?;
+        assert_eq!(ctx.repo.count(&ctx.pool).await?, 25);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_by_name_is_case_insensitive() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        for (i, name) in ["Closure", "Forfeit", "Clay"].into_iter().enumerate() {
+            let track = Track::new(new_uuid(name), name, new_uuid("Default Album"), 200, PathBuf::from(format!("T:/search/{}.mp3", i)), 100, AudioFileType::Mp3, Uploaded::Denis, None)?;
+            ctx.repo.save(&ctx.pool, &track).await?;
+        }
+
+        assert_search_by_name(|query, limit| ctx.repo.search_by_name(&ctx.pool, query, limit), |track: &Track| track.name().to_string()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_duplicate_groups_skips_tracks_without_hash() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        let mut tracks = create_tracks_with_album(6, album_id);
+        for (track, hash) in tracks.iter_mut().zip([Some("b"), Some("a"), Some("b"), Some("a"), Some("c"), None]) {
+            track.set_content_hash(hash.map(str::to_string));
+        }
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let groups = ctx.repo.find_duplicate_groups(&ctx.pool).await?;
+        let group_ids = groups.iter()
+            .map(|group| group.iter().map(|track| *track.id()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(group_ids, vec![
+            vec![*tracks[1].id(), *tracks[3].id()],
+            vec![*tracks[0].id(), *tracks[2].id()]
+        ]);
+        assert_eq!(groups[0][0].content_hash(), Some("a"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_by_genre_ignores_case_and_untagged_tracks() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_id = new_uuid("Default Album");
+
+        let mut tracks = create_tracks_with_album(3, album_id);
+        tracks[0].set_genre(Some("Post-Rock".to_string()));
+        tracks[1].set_genre(Some("post-rock".to_string()));
+        ctx.repo.save_all(&ctx.pool, &tracks).await?;
+
+        let found = ctx.repo.stream_by_genre(&ctx.pool, "POST-ROCK").await.try_collect::<Vec<_>>().await?;
+        let found_ids = found.iter().map(|track| *track.id()).collect::<HashSet<_>>();
+        assert_eq!(found_ids, HashSet::from([*tracks[0].id(), *tracks[1].id()]));
+
+        let untagged = ctx.repo.by_id_fetch(&ctx.pool, tracks[2].id()).await?.expect("Track should exist");
+        assert_eq!(untagged.genre(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zero_file_size_is_rejected() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?.with_entities(1)?;
+        let track = ctx.repo.save(&ctx.pool, &ctx.entities[0]).await?;
+
+        // the sync never stores one, see `ScannedLibrary::push`
+        sqlx::query("UPDATE tracks SET file_size = 0 WHERE id = ?;").bind(track.id()).execute(&ctx.pool).await?;
+        let fetched = ctx.repo.by_id_fetch(&ctx.pool, track.id()).await;
+        assert!(matches!(fetched, Err(RepositoryError::TrackDataMapping(TrackConversionError::ValidationError(ValidationError::FileSizeIsZero)))));
+
+        Ok(())
+    }
 }
\ No newline at end of file
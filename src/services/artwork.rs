@@ -0,0 +1,399 @@
+use std::{io::Cursor, num::NonZeroUsize, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}, time::SystemTime};
+
+use futures::TryStreamExt;
+use image::{DynamicImage, ImageFormat};
+use lofty::{file::TaggedFileExt, picture::PictureType};
+use lru::LruCache;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{domain::album::Album, repository::{SqliteAlbumsRepository, SqliteTracksRepository}};
+use super::ArtworkServiceError;
+
+/// File stems that count as a folder image, regardless of the extension.
+const FOLDER_IMAGE_STEMS: [&str; 2] = ["cover", "folder"];
+
+/// Max dimensions a cover can be downscaled to. Anything else is rejected, which keeps the
+/// cover cache at one original and a handful of thumbnails per album.
+pub const COVER_SIZES: [u32; 4] = [64, 128, 256, 512];
+
+/// Finds albums that have neither embedded art nor a folder image.
+///
+/// This is the planning step of the art backfill: it only reports, nothing is fetched or written.
+pub struct MissingArtworkService;
+
+impl MissingArtworkService {
+    /// Streams every album, looks at the embedded pictures of its first track and at the
+    /// album directory (`cover.*` / `folder.*`), and returns the albums that have neither.
+    ///
+    /// Albums without tracks are skipped since there is no directory to look into.
+    /// Relative track paths are resolved against `music_path`.
+    pub async fn find(pool: &SqlitePool, music_path: &Path) -> Result<Vec<Album>, ArtworkServiceError> {
+        let tracks_repo = SqliteTracksRepository::new();
+        let mut candidates = Vec::new();
+
+        let mut albums = SqliteAlbumsRepository::new().stream_all(pool).await;
+        while let Some(album) = albums.try_next().await? {
+            let first_track = tracks_repo.all_by_album(pool, album.id()).await?.into_iter().next();
+
+            if let Some(track) = first_track {
+                candidates.push((album, resolve_path(music_path, track.file_path())));
+            }
+        }
+
+        // lofty and the directory listing are blocking, keep them off the async workers
+        let missing = tokio::task::spawn_blocking(move || {
+            candidates.into_iter()
+                .filter(|(_, track_path)| !has_embedded_art(track_path) && !has_folder_image(track_path))
+                .map(|(album, _)| album)
+                .collect::<Vec<_>>()
+        }).await?;
+
+        Ok(missing)
+    }
+}
+
+/// Album id and the requested size, `None` being the original.
+type CoverKey = (Uuid, Option<u32>);
+
+/// Every album in a handful of sizes fits for a library of a few hundred albums; beyond that the least recently served go first.
+const DEFAULT_COVER_CACHE_SIZE: NonZeroUsize = NonZeroUsize::new(1024).unwrap();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverImage {
+    pub data: Vec<u8>,
+    pub mime_type: String
+}
+
+/// The file a cover was read from, the track for embedded art or the folder image, with its mtime at the time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverSource {
+    path: PathBuf,
+    modified: Option<SystemTime>
+}
+
+impl CoverSource {
+    fn of(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), modified: modified_time(path) }
+    }
+
+    /// Still the same file: it exists and hasn't been written to since.
+    fn is_unchanged(&self) -> bool {
+        self.modified.is_some() && modified_time(&self.path) == self.modified
+    }
+}
+
+/// Bounded LRU of the covers already served. An entry only counts while its source file is unchanged,
+/// so new art shows up without a restart.
+pub struct CoverCache {
+    covers: Mutex<LruCache<CoverKey, (Arc<CoverImage>, CoverSource)>>
+}
+
+impl Default for CoverCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_COVER_CACHE_SIZE)
+    }
+}
+
+impl CoverCache {
+    /// Holds up to `capacity` covers, every size counting as one.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self { covers: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    pub fn get(&self, album_id: Uuid, size: Option<u32>) -> Option<Arc<CoverImage>> {
+        let (cover, source) = self.lock().get(&(album_id, size)).cloned()?;
+
+        // stat the file outside of the lock
+        if source.is_unchanged() {
+            Some(cover)
+        } else {
+            self.lock().pop(&(album_id, size));
+            None
+        }
+    }
+
+    pub fn insert(&self, album_id: Uuid, size: Option<u32>, cover: Arc<CoverImage>, source: CoverSource) {
+        self.lock().put((album_id, size), (cover, source));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<CoverKey, (Arc<CoverImage>, CoverSource)>> {
+        // a poisoned lock only means some request panicked, the cached covers are still fine
+        self.covers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Serves album covers: the embedded art of the album's first track, or the folder image when there is none.
+pub struct CoverService;
+
+impl CoverService {
+    /// Returns the album cover, downscaled to fit into `size` x `size` when a size is given.
+    /// `size` has to be one of [`COVER_SIZES`]. Covers smaller than the requested size are returned as they are.
+    ///
+    /// `Ok(None)` means the album has no tracks or no art at all.
+    pub async fn album_cover(pool: &SqlitePool, music_path: &Path, cache: &CoverCache, album_id: Uuid, size: Option<u32>) -> Result<Option<Arc<CoverImage>>, ArtworkServiceError> {
+        if let Some(size) = size && !COVER_SIZES.contains(&size) {
+            return Err(ArtworkServiceError::UnsupportedCoverSize(size));
+        }
+
+        if let Some(cover) = cache.get(album_id, size) {
+            return Ok(Some(cover));
+        }
+
+        let first_track = SqliteTracksRepository::new().all_by_album(pool, album_id).await?.into_iter().next();
+        let Some(track) = first_track else {
+            return Ok(None);
+        };
+        let track_path = resolve_path(music_path, track.file_path());
+
+        // reading tags and decoding images is blocking and CPU heavy
+        let cover = tokio::task::spawn_blocking(move || -> Result<Option<(CoverImage, CoverSource)>, ArtworkServiceError> {
+            let Some((original, source)) = read_cover(&track_path)? else {
+                return Ok(None);
+            };
+
+            match size {
+                Some(size) => Ok(Some((resize_cover(&original, size)?, source))),
+                None => Ok(Some((original, source)))
+            }
+        }).await??;
+
+        Ok(cover.map(|(cover, source)| {
+            let cover = Arc::new(cover);
+            cache.insert(album_id, size, Arc::clone(&cover), source);
+            cover
+        }))
+    }
+}
+
+// the source is taken before reading, a write in between only means reading the cover again next time
+fn read_cover(track_path: &Path) -> Result<Option<(CoverImage, CoverSource)>, ArtworkServiceError> {
+    let track_source = CoverSource::of(track_path);
+    if let Some(cover) = embedded_cover(track_path) {
+        return Ok(Some((cover, track_source)));
+    }
+
+    match folder_image_path(track_path) {
+        Some(image_path) => {
+            let source = CoverSource::of(&image_path);
+            let cover = CoverImage {
+                data: std::fs::read(&image_path)?,
+                mime_type: mime_guess::from_path(&image_path).first_or_octet_stream().to_string()
+            };
+            Ok(Some((cover, source)))
+        },
+        None => Ok(None)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Downscales to fit into `max_dimension` x `max_dimension`, keeping the aspect ratio. The result is always a JPEG.
+fn resize_cover(cover: &CoverImage, max_dimension: u32) -> Result<CoverImage, ArtworkServiceError> {
+    let image = image::load_from_memory(&cover.data)?;
+    if image.width() <= max_dimension && image.height() <= max_dimension {
+        return Ok(cover.clone());
+    }
+
+    // JPEG has no alpha channel, so transparent covers get flattened
+    let thumbnail = DynamicImage::ImageRgb8(image.thumbnail(max_dimension, max_dimension).to_rgb8());
+
+    let mut data = Vec::new();
+    thumbnail.write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)?;
+
+    Ok(CoverImage { data, mime_type: "image/jpeg".to_string() })
+}
+
+fn resolve_path(music_path: &Path, track_path: &Path) -> PathBuf {
+    if track_path.is_relative() && !track_path.exists() {
+        music_path.join(track_path)
+    } else {
+        track_path.to_path_buf()
+    }
+}
+
+fn has_embedded_art(track_path: &Path) -> bool {
+    match lofty::read_from_path(track_path) {
+        Ok(tagged_file) => tagged_file.tags().iter().any(|tag| !tag.pictures().is_empty()),
+        Err(err) => {
+            log::warn!("Could not read tags of {}: {}. Assuming no embedded art.", track_path.display(), err);
+            false
+        }
+    }
+}
+
+/// The front cover if the file has one, otherwise whatever picture comes first.
+fn embedded_cover(track_path: &Path) -> Option<CoverImage> {
+    let tagged_file = lofty::read_from_path(track_path).ok()?;
+    let pictures: Vec<_> = tagged_file.tags().iter().flat_map(|tag| tag.pictures()).collect();
+
+    let picture = pictures.iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or(pictures.first())?;
+
+    Some(CoverImage {
+        data: picture.data().to_vec(),
+        mime_type: picture.mime_type().map_or("application/octet-stream", |mime| mime.as_str()).to_string()
+    })
+}
+
+fn has_folder_image(track_path: &Path) -> bool {
+    folder_image_path(track_path).is_some()
+}
+
+fn folder_image_path(track_path: &Path) -> Option<PathBuf> {
+    let album_dir = track_path.parent()?;
+
+    let Ok(entries) = std::fs::read_dir(album_dir) else {
+        log::warn!("Could not read album directory {}", album_dir.display());
+        return None;
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .find(|path| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+                .is_some_and(|stem| FOLDER_IMAGE_STEMS.contains(&stem.as_str()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Local;
+    use uuid::Uuid;
+
+    use crate::domain::{artist::Artist, audiofile::AudioFileType, track::Track, uploaded::Uploaded};
+    use crate::repository::SqliteArtistsRepository;
+    use crate::services::test_helpers::{prepare_db, TestSetupError};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_find_albums_without_art() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+        // track paths are lowercased on save, so the dir name must not contain upper case letters
+        let temp_dir = tempfile::Builder::new()
+            .prefix(&format!("artwork-{}", Uuid::new_v4()))
+            .rand_bytes(0)
+            .tempdir()?;
+
+        let artist = Artist::new(Uuid::new_v4(), "some artist")?;
+        SqliteArtistsRepository::new().save(&pool, &artist).await?;
+
+        let with_cover = Album::new(Uuid::new_v4(), "with cover", *artist.id(), None)?;
+        let without_cover = Album::new(Uuid::new_v4(), "without cover", *artist.id(), None)?;
+        let without_tracks = Album::new(Uuid::new_v4(), "without tracks", *artist.id(), None)?;
+
+        for (album, dir_name) in [(&with_cover, "with"), (&without_cover, "without")] {
+            SqliteAlbumsRepository::new().save(&pool, album).await?;
+
+            let album_dir = temp_dir.path().join(dir_name);
+            fs::create_dir(&album_dir)?;
+
+            // not a real mp3, so lofty finds no embedded art
+            let track_path = album_dir.join("track.mp3");
+            fs::write(&track_path, b"not really an mp3")?;
+
+            let track = Track::new(Uuid::new_v4(), "track", *album.id(), 100, track_path, 17, AudioFileType::Mp3, Uploaded::Denis, Some(Local::now().naive_local()))?;
+            SqliteTracksRepository::new().save(&pool, &track).await?;
+        }
+        SqliteAlbumsRepository::new().save(&pool, &without_tracks).await?;
+
+        fs::write(temp_dir.path().join("with").join("Cover.JPG"), b"picture")?;
+
+        let missing = MissingArtworkService::find(&pool, temp_dir.path()).await.expect("Search has failed");
+
+        assert_eq!(missing, vec![without_cover]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_album_cover_is_downscaled_and_cached() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+        let temp_dir = tempfile::Builder::new()
+            .prefix(&format!("cover-{}", Uuid::new_v4()))
+            .rand_bytes(0)
+            .tempdir()?;
+
+        let artist = Artist::new(Uuid::new_v4(), "some artist")?;
+        let album = Album::new(Uuid::new_v4(), "some album", *artist.id(), None)?;
+        SqliteArtistsRepository::new().save(&pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(&pool, &album).await?;
+
+        let track_path = temp_dir.path().join("track.mp3");
+        fs::write(&track_path, b"not really an mp3")?;
+        let track = Track::new(Uuid::new_v4(), "track", *album.id(), 100, track_path, 17, AudioFileType::Mp3, Uploaded::Denis, Some(Local::now().naive_local()))?;
+        SqliteTracksRepository::new().save(&pool, &track).await?;
+
+        let cover_path = temp_dir.path().join("cover.png");
+        image::RgbImage::new(1000, 500).save(&cover_path).expect("Failed to write the cover");
+
+        let cache = CoverCache::default();
+
+        let original = CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), None).await
+            .expect("Cover lookup has failed")
+            .expect("Album should have a cover");
+        assert_eq!(original.data, fs::read(&cover_path)?);
+        assert_eq!(original.mime_type, "image/png");
+
+        let thumbnail = CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), Some(128)).await
+            .expect("Cover lookup has failed")
+            .expect("Album should have a cover");
+        let decoded = image::load_from_memory(&thumbnail.data).expect("Thumbnail should be a valid image");
+        assert_eq!((decoded.width(), decoded.height()), (128, 64));
+        assert_eq!(thumbnail.mime_type, "image/jpeg");
+
+        // served from the cache while the file stays the same
+        let cached = CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), Some(128)).await
+            .expect("Cover lookup has failed");
+        assert!(cached.is_some_and(|cached| Arc::ptr_eq(&cached, &thumbnail)));
+
+        // a new cover replaces the cached one
+        image::RgbImage::new(500, 1000).save(&cover_path).expect("Failed to write the cover");
+        let an_hour_later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+        fs::File::options().write(true).open(&cover_path)?.set_modified(an_hour_later)?;
+        let replaced = CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), Some(128)).await
+            .expect("Cover lookup has failed")
+            .expect("Album should have a cover");
+        let decoded = image::load_from_memory(&replaced.data).expect("Thumbnail should be a valid image");
+        assert_eq!((decoded.width(), decoded.height()), (64, 128));
+
+        // and a removed one isn't served anymore
+        fs::remove_file(&cover_path)?;
+        assert_eq!(CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), Some(128)).await.expect("Cover lookup has failed"), None);
+
+        let unsupported = CoverService::album_cover(&pool, temp_dir.path(), &cache, *album.id(), Some(100)).await;
+        assert!(matches!(unsupported, Err(ArtworkServiceError::UnsupportedCoverSize(100))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cover_cache_drops_the_least_recently_served() -> Result<(), std::io::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let cover_path = temp_dir.path().join("cover.jpg");
+        fs::write(&cover_path, b"cover")?;
+
+        let cache = CoverCache::new(NonZeroUsize::new(2).expect("non-zero"));
+        let cover = Arc::new(CoverImage { data: b"cover".to_vec(), mime_type: "image/jpeg".to_string() });
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        cache.insert(first, None, Arc::clone(&cover), CoverSource::of(&cover_path));
+        cache.insert(second, None, Arc::clone(&cover), CoverSource::of(&cover_path));
+        assert!(cache.get(first, None).is_some());
+        cache.insert(third, None, Arc::clone(&cover), CoverSource::of(&cover_path));
+
+        assert!(cache.get(first, None).is_some());
+        assert!(cache.get(second, None).is_none());
+        assert!(cache.get(third, None).is_some());
+
+        Ok(())
+    }
+}
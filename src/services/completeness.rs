@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{domain::{album::Album, track::Track}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteTracksRepository}};
+
+/// A track number an album should have but doesn't.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct MissingTrack {
+    pub disc_number: Option<u32>,
+    pub track_number: u32
+}
+
+#[derive(Debug)]
+pub struct IncompleteAlbum {
+    pub album: Album,
+    pub missing: Vec<MissingTrack>
+}
+
+/// Albums whose track numbers have gaps, e.g. 1, 2, 4, 5 is missing 3. Every disc is counted from 1 up to its highest
+/// track number, so a track missing from the end of a disc goes unnoticed: track totals aren't read from the tags.
+///
+/// Tracks without a track number are left out, an album with none of them numbered is never reported.
+pub async fn find_incomplete_albums(pool: &SqlitePool) -> Result<Vec<IncompleteAlbum>, RepositoryError> {
+    let numbered = SqliteTracksRepository::new().numbered_by_album(pool).await?;
+    let mut incomplete = Vec::new();
+
+    let mut albums = SqliteAlbumsRepository::new().stream_all(pool).await;
+    while let Some(album) = albums.try_next().await? {
+        let Some(tracks) = numbered.get(album.id()) else {
+            continue;
+        };
+
+        let missing = missing_tracks(tracks);
+
+        if !missing.is_empty() {
+            incomplete.push(IncompleteAlbum { album, missing });
+        }
+    }
+
+    Ok(incomplete)
+}
+
+/// Gaps in the track numbers of one album, disc by disc, in order.
+pub fn missing_tracks(tracks: &[Track]) -> Vec<MissingTrack> {
+    let mut discs: BTreeMap<Option<u32>, BTreeSet<u32>> = BTreeMap::new();
+    for track in tracks {
+        if let Some(track_number) = track.track_number() {
+            discs.entry(track.disc_number()).or_default().insert(track_number);
+        }
+    }
+
+    discs.into_iter()
+        .flat_map(|(disc_number, numbers)| {
+            let highest = numbers.last().copied().unwrap_or(0);
+
+            (1..highest)
+                .filter(move |number| !numbers.contains(number))
+                .map(move |track_number| MissingTrack { disc_number, track_number })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{domain::{artist::Artist, audiofile::AudioFileType, uploaded::Uploaded}, repository::SqliteArtistsRepository, services::test_helpers::{prepare_db, TestSetupError}};
+    use super::*;
+
+    fn numbered_track(album: &Album, disc_number: Option<u32>, track_number: Option<u32>) -> Result<Track, TestSetupError> {
+        let mut track = Track::new(Uuid::new_v4(), "track", *album.id(), 60, format!("music/{}/{:?}-{:?}.flac", album.name(), disc_number, track_number).into(), 1024, AudioFileType::Flac, Uploaded::Denis, None)?;
+        track.set_disc_number(disc_number);
+        track.set_track_number(track_number);
+
+        Ok(track)
+    }
+
+    #[tokio::test]
+    async fn test_gapped_album_is_reported() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+
+        let artist = Artist::new(Uuid::new_v4(), "artist")?;
+        SqliteArtistsRepository::new().save(&pool, &artist).await?;
+
+        let gapped = Album::new(Uuid::new_v4(), "gapped", *artist.id(), None)?;
+        let complete = Album::new(Uuid::new_v4(), "complete", *artist.id(), None)?;
+        let unnumbered = Album::new(Uuid::new_v4(), "unnumbered", *artist.id(), None)?;
+
+        let mut tracks = Vec::new();
+        for number in [1, 2, 4, 5] {
+            tracks.push(numbered_track(&gapped, Some(1), Some(number))?);
+        }
+        for number in [1, 3] {
+            tracks.push(numbered_track(&gapped, Some(2), Some(number))?);
+        }
+        for number in [1, 2, 3] {
+            tracks.push(numbered_track(&complete, None, Some(number))?);
+        }
+        tracks.push(numbered_track(&unnumbered, None, None)?);
+
+        for album in [&gapped, &complete, &unnumbered] {
+            SqliteAlbumsRepository::new().save(&pool, album).await?;
+        }
+        for track in &tracks {
+            SqliteTracksRepository::new().save(&pool, track).await?;
+        }
+
+        let incomplete = find_incomplete_albums(&pool).await?;
+
+        assert_eq!(incomplete.len(), 1);
+        assert_eq!(incomplete[0].album.id(), gapped.id());
+        assert_eq!(incomplete[0].missing, vec![
+            MissingTrack { disc_number: Some(1), track_number: 3 },
+            MissingTrack { disc_number: Some(2), track_number: 2 }
+        ]);
+
+        Ok(())
+    }
+}
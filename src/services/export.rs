@@ -0,0 +1,161 @@
+use std::{borrow::Cow, collections::HashMap, path::{Path, PathBuf}};
+
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{domain::{album::Album, artist::Artist, playlist::Playlist, track::{Track, TrackSort}}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteArtistsRepository, SqliteTracksRepository}, utils::normalizations::relative_to};
+
+pub const TRACKS_CSV_HEADER: &str = "artist,album,year,track,title,duration,file_type,path,uploaded,date_added\r\n";
+
+/// Quotes a field if it contains a comma, a quote or a line break; inner quotes are doubled (RFC 4180).
+pub fn escape_csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn csv_row(fields: &[&str]) -> String {
+    let mut row = fields.iter()
+        .map(|field| escape_csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push_str("\r\n");
+
+    row
+}
+
+fn track_row(track: &Track, albums: &HashMap<Uuid, Album>, artists: &HashMap<Uuid, Artist>, music_root: &Path) -> String {
+    let album = albums.get(track.album_id());
+    let artist = album.and_then(|album| artists.get(album.artist_id()));
+
+    let year = album.and_then(|album| album.year()).map(|year| year.to_string()).unwrap_or_default();
+    let track_number = track.track_number().map(|number| number.to_string()).unwrap_or_default();
+    let duration = track.duration().to_string();
+    // files outside of the library keep their full path
+    let path = relative_to(track.file_path(), music_root).unwrap_or_else(|| track.file_path().to_path_buf());
+    let path = path.to_string_lossy();
+    let uploaded: &str = track.uploaded().into();
+    let date_added = track.date_added().map(|date| date.to_string()).unwrap_or_default();
+
+    csv_row(&[
+        artist.map(|artist| artist.name()).unwrap_or_default(),
+        album.map(|album| album.name()).unwrap_or_default(),
+        &year,
+        &track_number,
+        track.name(),
+        &duration,
+        track.file_type().as_str(),
+        &path,
+        uploaded,
+        &date_added
+    ])
+}
+
+/// Streams the whole library as CSV lines, header first, tracks grouped by artist and album.
+/// Artists and albums are loaded up front (there are few of them), tracks are streamed row by row.
+/// Paths are written relative to `music_root`.
+pub async fn stream_tracks_csv(pool: &SqlitePool, music_root: PathBuf) -> Result<impl Stream<Item = Result<String, RepositoryError>> + Send + '_, RepositoryError> {
+    let artists: HashMap<Uuid, Artist> = SqliteArtistsRepository::new().stream_all(pool).await
+        .map_ok(|artist| (*artist.id(), artist))
+        .try_collect()
+        .await?;
+
+    let albums: HashMap<Uuid, Album> = SqliteAlbumsRepository::new().stream_all(pool).await
+        .map_ok(|album| (*album.id(), album))
+        .try_collect()
+        .await?;
+
+    let rows = SqliteTracksRepository::new().stream_all_ordered(pool, TrackSort::Artist).await
+        .map(move |track_res| track_res.map(|track| track_row(&track, &albums, &artists, &music_root)));
+
+    Ok(stream::once(async { Ok(TRACKS_CSV_HEADER.to_string()) }).chain(rows))
+}
+
+/// Extended M3U of a playlist, each entry pointing at the stream endpoint under `base_url`
+/// (e.g. `http://192.168.1.10:8080`, or empty for root-relative links).
+pub fn playlist_m3u(playlist: &Playlist, tracks: &[Track], base_url: &str) -> String {
+    // a line break in a name would end the directive early
+    let one_line = |text: &str| text.replace(['\r', '\n'], " ");
+
+    let mut m3u = format!("#EXTM3U\n#PLAYLIST:{}\n", one_line(playlist.name()));
+    for track in tracks {
+        m3u.push_str(&format!("#EXTINF:{},{}\n{}/api/tracks/{}/stream\n", track.duration(), one_line(track.name()), base_url, track.id()));
+    }
+
+    m3u
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+
+    use crate::domain::{audiofile::AudioFileType, uploaded::Uploaded};
+    use crate::services::test_helpers::{prepare_db, TestSetupError};
+    use super::*;
+
+    #[test]
+    fn test_escape_csv_field() {
+        assert_eq!(escape_csv_field("plain"), "plain");
+        assert_eq!(escape_csv_field("one, two"), "\"one, two\"");
+        assert_eq!(escape_csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[tokio::test]
+    async fn test_stream_tracks_csv() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+
+        let artist = Artist::new(Uuid::new_v4(), "crosby stills nash")?;
+        let album = Album::new(Uuid::new_v4(), "deja vu", *artist.id(), Some(1970))?;
+        let date_added = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let mut track = Track::new(
+            Uuid::new_v4(),
+            "carry on",
+            *album.id(),
+            265,
+            PathBuf::from("music/csn, y/carry on.flac"),
+            420,
+            AudioFileType::Flac,
+            Uploaded::Masha,
+            Some(date_added)
+        )?;
+        track.set_track_number(Some(1));
+
+        SqliteArtistsRepository::new().save(&pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(&pool, &album).await?;
+        SqliteTracksRepository::new().save(&pool, &track).await?;
+
+        let lines: Vec<String> = stream_tracks_csv(&pool, PathBuf::from("Music")).await?.try_collect().await?;
+
+        assert_eq!(lines, vec![
+            TRACKS_CSV_HEADER.to_string(),
+            "crosby stills nash,deja vu,1970,1,carry on,265,flac,\"csn, y/carry on.flac\",masha,2024-05-01 12:00:00\r\n".to_string()
+        ]);
+
+        let outside_of_root: Vec<String> = stream_tracks_csv(&pool, PathBuf::from("/srv/music")).await?.try_collect().await?;
+        assert!(outside_of_root[1].contains(",\"music/csn, y/carry on.flac\","));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_playlist_m3u() -> Result<(), TestSetupError> {
+        let playlist = Playlist::new(Uuid::new_v4(), "Road\nTrip")?;
+        let track = Track::new(Uuid::new_v4(), "carry on", Uuid::new_v4(), 265, PathBuf::from("music/carry on.flac"), 420, AudioFileType::Flac, Uploaded::Denis, None)?;
+
+        let m3u = playlist_m3u(&playlist, std::slice::from_ref(&track), "http://music.local:8080");
+
+        assert_eq!(m3u, format!(
+            "#EXTM3U\n#PLAYLIST:Road Trip\n#EXTINF:265,carry on\nhttp://music.local:8080/api/tracks/{}/stream\n",
+            track.id()
+        ));
+        assert_eq!(playlist_m3u(&playlist, &[], ""), "#EXTM3U\n#PLAYLIST:Road Trip\n");
+
+        Ok(())
+    }
+}
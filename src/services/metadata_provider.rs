@@ -0,0 +1,271 @@
+use std::{future::Future, time::{Duration, Instant}};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::domain::audiofile::parse_year;
+
+pub const MUSICBRAINZ_API_URL: &str = "https://musicbrainz.org/ws/2";
+
+// MusicBrainz asks for at most one request per second and a meaningful User-Agent.
+const MUSICBRAINZ_MIN_INTERVAL: Duration = Duration::from_secs(1);
+const USER_AGENT: &str = concat!("home-server/", env!("CARGO_PKG_VERSION"), " ( https://github.com/Ocean50ul/home-server )");
+
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataProviderError {
+    #[error("Request to the metadata provider has failed: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    #[error("Metadata provider responded with {0}")]
+    RequestFailureStatus(String),
+
+    #[error("Failed to parse the metadata provider response: {0}")]
+    ResponseParseError(#[from] serde_json::Error),
+
+    #[error("No release found for album `{album}` by `{artist}`")]
+    AlbumNotFound { artist: String, album: String }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExternalTrackInfo {
+    pub disc_number: u32,
+    pub track_number: u32,
+    pub name: String
+}
+
+/// What an external source knows about an album. Only a suggestion, nothing here is applied automatically.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExternalAlbumInfo {
+    pub source_id: String,
+    pub name: String,
+    pub year: Option<u32>,
+    pub tracks: Vec<ExternalTrackInfo>
+}
+
+pub trait MetadataProvider {
+    fn lookup_album(&self, artist: &str, album: &str) -> impl Future<Output = Result<ExternalAlbumInfo, MetadataProviderError>> + Send;
+}
+
+#[derive(Deserialize)]
+struct MbReleaseSearch {
+    releases: Vec<MbReleaseRef>
+}
+
+#[derive(Deserialize)]
+struct MbReleaseRef {
+    id: String
+}
+
+#[derive(Deserialize)]
+struct MbRelease {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(default)]
+    media: Vec<MbMedium>
+}
+
+#[derive(Deserialize)]
+struct MbMedium {
+    position: u32,
+    #[serde(default)]
+    tracks: Vec<MbTrack>
+}
+
+#[derive(Deserialize)]
+struct MbTrack {
+    position: u32,
+    title: String
+}
+
+impl From<MbRelease> for ExternalAlbumInfo {
+    fn from(release: MbRelease) -> Self {
+        let tracks = release.media.into_iter()
+            .flat_map(|medium| {
+                let disc_number = medium.position;
+                medium.tracks.into_iter().map(move |track| ExternalTrackInfo {
+                    disc_number,
+                    track_number: track.position,
+                    name: track.title
+                })
+            })
+            .collect();
+
+        Self {
+            source_id: release.id,
+            name: release.title,
+            year: release.date.as_deref().and_then(parse_year),
+            tracks
+        }
+    }
+}
+
+/// Looks albums up through the MusicBrainz web service.
+///
+/// A lookup is two requests (release search, then the release with its recordings),
+/// both going through the rate limiter. Keep a single instance around so the limit holds across lookups.
+pub struct MusicBrainzProvider {
+    client: Client,
+    base_url: String,
+    min_interval: Duration,
+    last_request: Mutex<Option<Instant>>
+}
+
+impl MusicBrainzProvider {
+    pub fn new() -> Self {
+        Self::with_base_url(MUSICBRAINZ_API_URL, MUSICBRAINZ_MIN_INTERVAL)
+    }
+
+    pub fn with_base_url<S: Into<String>>(base_url: S, min_interval: Duration) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into(),
+            min_interval,
+            last_request: Mutex::new(None)
+        }
+    }
+
+    async fn wait_for_slot(&self) {
+        let mut last_request = self.last_request.lock().await;
+
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval {
+                tokio::time::sleep(self.min_interval - elapsed).await;
+            }
+        }
+
+        *last_request = Some(Instant::now());
+    }
+
+    async fn get_json<T>(&self, url: &str, query: &[(&str, &str)]) -> Result<T, MetadataProviderError>
+    where T: for<'de> Deserialize<'de>
+    {
+        self.wait_for_slot().await;
+
+        let response = self.client.get(url)
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .query(query)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(MetadataProviderError::RequestFailureStatus(response.status().to_string()));
+        }
+
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+}
+
+impl Default for MusicBrainzProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetadataProvider for MusicBrainzProvider {
+    async fn lookup_album(&self, artist: &str, album: &str) -> Result<ExternalAlbumInfo, MetadataProviderError> {
+        // quotes would break out of the lucene phrase
+        let query = format!("artist:\"{}\" AND release:\"{}\"", artist.replace('"', ""), album.replace('"', ""));
+
+        let search: MbReleaseSearch = self.get_json(
+            &format!("{}/release/", self.base_url),
+            &[("query", &query), ("fmt", "json"), ("limit", "1")]
+        ).await?;
+
+        let Some(release_ref) = search.releases.into_iter().next() else {
+            return Err(MetadataProviderError::AlbumNotFound { artist: artist.to_string(), album: album.to_string() });
+        };
+
+        let release: MbRelease = self.get_json(
+            &format!("{}/release/{}", self.base_url, release_ref.id),
+            &[("inc", "recordings"), ("fmt", "json")]
+        ).await?;
+
+        Ok(release.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use serde_json::json;
+
+    use super::*;
+
+    fn mock_release(server: &MockServer) {
+        server.mock(|when, then| {
+            when.path("/release/")
+                .query_param("query", "artist:\"chevelle\" AND release:\"wonder whats next\"");
+            then.status(200).json_body(json!({ "releases": [{ "id": "mb-release-id" }] }));
+        });
+
+        server.mock(|when, then| {
+            when.path("/release/mb-release-id").query_param("inc", "recordings");
+            then.status(200).json_body(json!({
+                "id": "mb-release-id",
+                "title": "Wonder What's Next",
+                "date": "2002-10-08",
+                "media": [
+                    { "position": 1, "tracks": [
+                        { "position": 1, "title": "Family System" },
+                        { "position": 2, "title": "Comfortable Liar" }
+                    ]}
+                ]
+            }));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_lookup_album() -> Result<(), MetadataProviderError> {
+        let server = MockServer::start();
+        mock_release(&server);
+
+        let provider = MusicBrainzProvider::with_base_url(server.url(""), Duration::ZERO);
+        let info = provider.lookup_album("chevelle", "wonder whats next").await?;
+
+        assert_eq!(info, ExternalAlbumInfo {
+            source_id: "mb-release-id".to_string(),
+            name: "Wonder What's Next".to_string(),
+            year: Some(2002),
+            tracks: vec![
+                ExternalTrackInfo { disc_number: 1, track_number: 1, name: "Family System".to_string() },
+                ExternalTrackInfo { disc_number: 1, track_number: 2, name: "Comfortable Liar".to_string() }
+            ]
+        });
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_album_not_found() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.path("/release/");
+            then.status(200).json_body(json!({ "releases": [] }));
+        });
+
+        let provider = MusicBrainzProvider::with_base_url(server.url(""), Duration::ZERO);
+        let result = provider.lookup_album("nobody", "nothing").await;
+
+        assert!(matches!(result, Err(MetadataProviderError::AlbumNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_respects_min_interval() -> Result<(), MetadataProviderError> {
+        let server = MockServer::start();
+        mock_release(&server);
+
+        let min_interval = Duration::from_millis(200);
+        let provider = MusicBrainzProvider::with_base_url(server.url(""), min_interval);
+
+        let started = Instant::now();
+        provider.lookup_album("chevelle", "wonder whats next").await?;
+
+        // two requests per lookup, so at least one full interval had to pass
+        assert!(started.elapsed() >= min_interval);
+
+        Ok(())
+    }
+}
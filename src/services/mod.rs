@@ -2,6 +2,16 @@ pub mod scanner;
 pub mod sync;
 pub mod resample;
 pub mod prepare;
+pub mod snapshot;
+pub mod export;
+pub mod artwork;
+pub mod metadata_provider;
+pub mod prune;
+pub mod repair_paths;
+pub mod transcode;
+pub mod completeness;
+
+use std::path::PathBuf;
 
 use lofty::error::LoftyError;
 
@@ -32,10 +42,65 @@ pub enum SyncServiceError {
     FailedToExtractExtension(String),
 
     #[error(transparent)]
-    ScanError(#[from] ScanError),
+    ScanError(ScanError),
 
     #[error("Validation error has occured: {0}")]
     DomainStructValidationError(#[from] ValidationError),
+
+    #[error("Disk is full, aborting sync: {0}")]
+    StorageFull(String),
+
+    #[error("Found {0} inconsistencies between the database and the music library")]
+    Inconsistent(usize),
+
+    #[error("Sync was cancelled before it was done")]
+    Cancelled,
+}
+
+// a cancelled scan cancels the sync it's part of
+impl From<ScanError> for SyncServiceError {
+    fn from(err: ScanError) -> Self {
+        match err {
+            ScanError::Cancelled => Self::Cancelled,
+            err => Self::ScanError(err)
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArtworkServiceError {
+    #[error(transparent)]
+    RepositoryError(#[from] RepositoryError),
+
+    #[error("Artwork lookup task has failed: {0}")]
+    TaskJoinError(#[from] tokio::task::JoinError),
+
+    #[error("Failed to read the cover: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("Failed to resize the cover: {0}")]
+    ImageError(#[from] image::ImageError),
+
+    #[error("Cover size {0} is not supported, pick one of {sizes:?}", sizes = artwork::COVER_SIZES)]
+    UnsupportedCoverSize(u32)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscodeError {
+    #[error("Transcoding to {0} is not supported, use mp3 or aac")]
+    UnsupportedTarget(String),
+
+    #[error("Failed to start ffmpeg ({path}): {source}")]
+    FfmpegSpawnError { path: PathBuf, source: std::io::Error },
+
+    #[error("ffmpeg has no output to stream")]
+    MissingOutput,
+
+    #[error("ffmpeg has failed to transcode {path} ({status}): {stderr}")]
+    FfmpegFailed { path: PathBuf, status: std::process::ExitStatus, stderr: String },
+
+    #[error("Failed to wait for ffmpeg transcoding {path}: {source}")]
+    FfmpegWaitError { path: PathBuf, source: std::io::Error }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -47,18 +112,45 @@ pub enum ScanError {
     RootDirAccessError{path: String, source: std::io::Error},
 
     #[error(transparent)]
-    IOError(#[from] std::io::Error)
+    IOError(#[from] std::io::Error),
+
+    #[error("Failed to access scan snapshot {path}: {source}")]
+    SnapshotIOError{path: PathBuf, source: std::io::Error},
+
+    #[error("Scan snapshot {path} is malformed: {source}")]
+    SnapshotFormatError{path: PathBuf, source: serde_json::Error},
+
+    #[error("Failed to read playlist {path}: {source}")]
+    PlaylistReadError{path: PathBuf, source: std::io::Error},
+
+    #[error("Playlist entry {0} doesn't exist")]
+    PlaylistEntryMissing(PathBuf),
+
+    #[error("Playlist entry {0} is outside of the music library")]
+    PlaylistEntryOutsideLibrary(PathBuf),
+
+    #[error("Scanning task has failed: {0}")]
+    TaskJoinError(#[from] tokio::task::JoinError),
+
+    #[error("Failed to read {path}: {source}")]
+    FileReadError{path: PathBuf, source: std::io::Error},
+
+    #[error("Failed to start the scanner threads: {0}")]
+    ThreadPoolBuildError(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("Scan was cancelled")]
+    Cancelled
 }
 
 #[cfg(test)]
 pub(crate) mod test_helpers {
-    use std::{env::VarError, path::{Path, PathBuf}, sync::OnceLock};
+    use std::{env::VarError, fs, path::{Path, PathBuf}, sync::OnceLock};
 
     use log::SetLoggerError;
     use sqlx::{Error as SqlxError, SqlitePool};
     use tempfile::{NamedTempFile, Builder};
 
-    use crate::{domain::{ValidationError}, repository::RepositoryError, services::{ScanError, SyncServiceError}, utils::{audio_fixtures::FixturesLoadingError}};
+    use crate::{domain::{ValidationError}, repository::RepositoryError, services::{ScanError, SyncServiceError}, domain::audiofile::AudioFileMetadata, utils::{audio_fixtures::{load_fixtures, AudioFixture, FixturesLoadingError}, normalizations::normalize_path}};
 
     pub const TEST_FIXTURES_JSON_PATH: &str = r"./audio_fixtures.json";
     
@@ -98,7 +190,10 @@ pub(crate) mod test_helpers {
         FixturesLoadingError(#[from] FixturesLoadingError),
 
         #[error("Couldnt find fixture metadata: {0}")]
-        FixtureMetadataDoesntExist(String)
+        FixtureMetadataDoesntExist(String),
+
+        #[error("Failed to load the fixtures vault ({TEST_FIXTURES_JSON_PATH}): {0}")]
+        FixturesVaultError(String)
     }
 
     pub async fn prepare_db() -> Result<SqlitePool, SqlxError> {
@@ -162,18 +257,53 @@ pub(crate) mod test_helpers {
     }
 
     impl FixtureFileNames {
-        pub fn file_name(&self) -> String {
+        pub fn as_str(&self) -> &'static str {
             match self {
-                FixtureFileNames::FlacValidMetadata => "falc_valid_metadata.flac".to_string(),
-                FixtureFileNames::Mp3ValidMetadata => "mp3_valid_metadata.mp3".to_string(),
-                FixtureFileNames::WavValidMetadata => "wav_valid_metadata.wav".to_string(),
+                FixtureFileNames::FlacValidMetadata => "falc_valid_metadata.flac",
+                FixtureFileNames::Mp3ValidMetadata => "mp3_valid_metadata.mp3",
+                FixtureFileNames::WavValidMetadata => "wav_valid_metadata.wav",
 
-                FixtureFileNames::Mp3NoMetadata => "mp3_no_metadata.mp3".to_string(),
-                FixtureFileNames::Mp3CorruptedHeader => "mp3_corrupted_header.mp3".to_string(),
+                FixtureFileNames::Mp3NoMetadata => "mp3_no_metadata.mp3",
+                FixtureFileNames::Mp3CorruptedHeader => "mp3_corrupted_header.mp3",
 
-                FixtureFileNames::ChevelleForfeit => "forfeit.flac".to_string(),
-                FixtureFileNames::ChevelleClosure => "closure.mp3".to_string()
+                FixtureFileNames::ChevelleForfeit => "forfeit.flac",
+                FixtureFileNames::ChevelleClosure => "closure.mp3"
             }
         }
+
+        /// The metadata the fixture was generated with, i.e. what a scan of it is expected to return.
+        pub fn get_metadata(&self) -> Result<&'static AudioFileMetadata, TestSetupError> {
+            fixtures_vault()?.iter()
+                .find(|fxtr| fxtr.file_name == self.as_str())
+                .map(|fxtr| &fxtr.metadata)
+                .ok_or_else(|| TestSetupError::FixtureMetadataDoesntExist(self.as_str().to_string()))
+        }
+    }
+
+    /// Fixtures described in `audio_fixtures.json`, loaded once per test binary.
+    pub fn fixtures_vault() -> Result<&'static [AudioFixture], TestSetupError> {
+        static VAULT: OnceLock<Result<Vec<AudioFixture>, String>> = OnceLock::new();
+
+        let vault = VAULT.get_or_init(|| {
+            load_fixtures(Path::new(TEST_FIXTURES_JSON_PATH)).map_err(|err| err.to_string())
+        });
+
+        match vault {
+            Ok(fixtures) => Ok(fixtures),
+            Err(err) => Err(TestSetupError::FixturesVaultError(err.clone()))
+        }
+    }
+
+    /// Copies the generated fixture files into `dest_dir` and returns their normalized paths.
+    pub fn copy_fixtures(dest_dir: &Path, fixture_file_names: &[FixtureFileNames]) -> Result<Vec<PathBuf>, TestSetupError> {
+        fixture_file_names.iter()
+            .map(|fixture| {
+                let src = PathBuf::from(format!("./test_fixtures/files/{}", fixture.as_str()));
+                let dest = dest_dir.join(fixture.as_str());
+
+                fs::copy(&src, &dest)?;
+                Ok(normalize_path(&dest))
+            })
+            .collect()
     }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use std::{env::{self, VarError}, fs::{create_dir, create_dir_all, read_to_string, remove_dir_all, remove_file, write, File}, io::{Read, Write}, path::{Path, PathBuf}, process::Command};
+use std::{env::VarError, ffi::OsStr, fs::{copy, create_dir, create_dir_all, read_to_string, remove_dir_all, remove_file, rename, write, File}, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, process::Command};
 use tokio::io::AsyncWriteExt;
 
 use indicatif::{ProgressBar, ProgressStyle};
@@ -7,10 +7,7 @@ use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use sevenz_rust2::{self, ArchiveReader, Password};
 
-use crate::{domain::audiofile::AudioFileType, utils::{audio_fixtures::{load_fixtures, FixturesLoadingError}, config::{get_config, Config, ConfigLoadingError}}};
-
-const FFMPEG_EXECUTABLE_NAME: &str = "ffmpeg.exe";
-const FFMPEG_ARCHIVE_NAME: &str = "ffmpeg_zip.7z";
+use crate::{domain::audiofile::AudioFileType, utils::{audio_fixtures::{load_fixtures, FixturesLoadingError}, config::{get_config, Config, ConfigLoadingError, FFMPEG_EXECUTABLE_NAME}, progress}};
 
 #[derive(Debug, thiserror::Error)]
 pub enum PrepareServiceError {
@@ -30,7 +27,7 @@ pub enum PrepareServiceError {
     #[error("My Big Beautiful Parsing Function has failed to parse checksums out of html string")]
     FailedToParseChecksums(),
 
-    #[error("ffmpeg.exe seems to be still missing after downloading and extracting steps was done.")]
+    #[error("ffmpeg seems to be still missing after downloading and extracting steps was done.")]
     FfmpegDoesntExist(),
 
     #[error("Checksums do not match. Expected: {expected}, Got: {actual}")]
@@ -54,9 +51,15 @@ pub enum PrepareServiceError {
     #[error("Could not create dir '{path}': {source}")]
     DirCreateError { path: PathBuf, #[source] source: std::io::Error },
     
-    #[error("Failed to extract ffmpeg.exe from archive: {0}")]
+    #[error("Failed to extract ffmpeg from archive: {0}")]
     ErrorExtractingFfmpeg(sevenz_rust2::Error),
 
+    #[error("Failed to decompress the ffmpeg .tar.xz archive: {0}")]
+    XzDecompressionError(lzma_rs::error::Error),
+
+    #[error("Failed to extract ffmpeg from the tar archive: {0}")]
+    TarExtractionError(std::io::Error),
+
     #[error(transparent)]
     FixtureSetupError(#[from] FixturesSetupError),
 
@@ -66,21 +69,57 @@ pub enum PrepareServiceError {
     #[error("Failed to read the file from ffmpeg archive: {0}")]
     FailedToReadTheFileFromArchive(sevenz_rust2::Error),
 
-    #[error("Failed to find ffmpeg.exe inside the archive! The name provided: {0}; ends_with didnt worked out!")]
+    #[error("Failed to find ffmpeg inside the archive! The name provided: {0}; ends_with didnt worked out!")]
     FailedToFindFFmpegInsideArchive(String),
 
     #[error("for_each_entries has returned with an error: {0}")]
-    ForEachError(sevenz_rust2::Error)
+    ForEachError(sevenz_rust2::Error),
+
+    #[error("There is no ffmpeg build to download for this platform. Set media.ffmpeg_donwload_mirror and media.ffmpeg_sha_download_mirror in the config, or install ffmpeg and point media.ffmpeg_exe_path at it.")]
+    NoFfmpegDownloadMirror
 }
 
 /* ======================= FFMPEG PREPARATION PART ======================= */
 
+/// Archive formats the ffmpeg builds come in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfmpegArchive {
+    /// gyan.dev builds for Windows.
+    SevenZip,
+    /// Static builds for Linux.
+    TarXz
+}
+
+impl FfmpegArchive {
+    /// Told apart by the extension of the download url; a url without one gets the format of this platform's builds.
+    pub fn from_url(url: &str) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+
+        if path.ends_with(".7z") {
+            FfmpegArchive::SevenZip
+        } else if path.ends_with(".tar.xz") {
+            FfmpegArchive::TarXz
+        } else if cfg!(target_os = "windows") {
+            FfmpegArchive::SevenZip
+        } else {
+            FfmpegArchive::TarXz
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            FfmpegArchive::SevenZip => "ffmpeg_archive.7z",
+            FfmpegArchive::TarXz => "ffmpeg_archive.tar.xz"
+        }
+    }
+}
+
 fn ffmpeg_exists(path: &Path) -> bool {
     path.exists()
 }
 
 async fn download_ffmpeg_zip_essentials(dest_file_path: &Path, url: &str) -> Result<(), PrepareServiceError> {
-    println!("Downloading ffmpeg from {}", url);
+    log::info!("Downloading ffmpeg from {}", url);
     
     let mut dest_file = tokio::fs::File::create(dest_file_path).await
         .map_err(|err| PrepareServiceError::ErrorCreatingDestinationFile(err))?;
@@ -95,13 +134,13 @@ async fn download_ffmpeg_zip_essentials(dest_file_path: &Path, url: &str) -> Res
     let pb: ProgressBar;
     if let Some(total_size) = response.content_length() {
         // --- CASE 1: Content-Length EXISTS ---
-        pb = ProgressBar::new(total_size);
+        pb = progress::track(ProgressBar::new(total_size));
         pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
             .unwrap()
             .progress_chars("#>-"));
     } else {
         // --- CASE 2: Content-Length IS MISSING ---
-        pb = ProgressBar::new_spinner();
+        pb = progress::track(ProgressBar::new_spinner());
         pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] {bytes} downloaded ({bytes_per_sec})")
             .unwrap());
     }
@@ -127,6 +166,24 @@ pub async fn get_checksums(checksum_url: &str) -> Result<String, PrepareServiceE
     Ok(response.text().await?)
 }
 
+/// gyan.dev publishes a bare hash per archive, the Linux builds one `<hash>  <file name>` line per archive.
+fn parse_checksum(checksums: &str, archive_url: &str) -> Result<String, PrepareServiceError> {
+    let archive_name = archive_url.rsplit('/').next().unwrap_or(archive_url);
+
+    checksums.lines()
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(hash), None) => Some(hash),
+                // `sha256sum -b` marks binary files with a leading asterisk
+                (Some(hash), Some(name)) if name.trim_start_matches('*') == archive_name => Some(hash),
+                _ => None
+            }
+        })
+        .map(str::to_lowercase)
+        .ok_or(PrepareServiceError::FailedToParseChecksums())
+}
+
 fn verify_checksums(ffmpeg_zip_path: &Path, expected_checksum: String) -> Result<(), PrepareServiceError> {
     let mut file = File::open(ffmpeg_zip_path).map_err(|err| PrepareServiceError::FileReadError{ path: ffmpeg_zip_path.to_path_buf(), source: err})?;
     let mut hasher = Sha256::new();
@@ -149,7 +206,14 @@ fn verify_checksums(ffmpeg_zip_path: &Path, expected_checksum: String) -> Result
     Ok(())
 }
 
-pub fn unzip_ffmpeg(zip_path: &Path, file_name: &str, unzip_dest: &Path) -> Result<(), PrepareServiceError> {
+pub fn unzip_ffmpeg(archive_path: &Path, archive: FfmpegArchive, file_name: &str, unzip_dest: &Path) -> Result<(), PrepareServiceError> {
+    match archive {
+        FfmpegArchive::SevenZip => extract_from_7z(archive_path, file_name, unzip_dest),
+        FfmpegArchive::TarXz => extract_from_tar_xz(archive_path, file_name, unzip_dest)
+    }
+}
+
+fn extract_from_7z(zip_path: &Path, file_name: &str, unzip_dest: &Path) -> Result<(), PrepareServiceError> {
 
     let mut archive_reader = ArchiveReader::open(zip_path, Password::empty())
         .map_err(PrepareServiceError::ErrorExtractingFfmpeg)?;
@@ -162,10 +226,10 @@ pub fn unzip_ffmpeg(zip_path: &Path, file_name: &str, unzip_dest: &Path) -> Resu
         }
 
         if !file_found && entry.name().ends_with(file_name) {
-            println!("\nExtracting ffmpeg.exe fron an archive..");
+            log::info!("Extracting {} from an archive..", file_name);
             
             let total_size = entry.size();
-            let pb = ProgressBar::new(total_size);
+            let pb = progress::track(ProgressBar::new(total_size));
             pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{bar:40.yellow/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")
                             .unwrap()
                             .progress_chars("=> ")
@@ -207,32 +271,113 @@ pub fn unzip_ffmpeg(zip_path: &Path, file_name: &str, unzip_dest: &Path) -> Resu
     Ok(())
 }
 
+fn extract_from_tar_xz(archive_path: &Path, file_name: &str, unzip_dest: &Path) -> Result<(), PrepareServiceError> {
+    // lzma-rs only decompresses whole streams, so the tarball goes through a file next to the archive
+    let tar_path = archive_path.with_extension("");
+    {
+        let mut input = BufReader::new(File::open(archive_path).map_err(|err| PrepareServiceError::FileOpenError{ path: archive_path.to_path_buf(), source: err })?);
+        let mut output = BufWriter::new(File::create(&tar_path).map_err(|err| PrepareServiceError::FileCreateError{ path: tar_path.clone(), source: err })?);
+
+        log::info!("Decompressing {:?}..", archive_path);
+        lzma_rs::xz_decompress(&mut input, &mut output).map_err(PrepareServiceError::XzDecompressionError)?;
+        output.flush().map_err(|err| PrepareServiceError::FileWriteError{ path: tar_path.clone(), source: err })?;
+    }
+
+    let extracted = extract_from_tar(&tar_path, file_name, unzip_dest);
+    remove_file(&tar_path).map_err(|err| PrepareServiceError::FileRemoveError{ path: tar_path.clone(), source: err })?;
+
+    extracted
+}
+
+fn extract_from_tar(tar_path: &Path, file_name: &str, unzip_dest: &Path) -> Result<(), PrepareServiceError> {
+    let tar_file = File::open(tar_path).map_err(|err| PrepareServiceError::FileOpenError{ path: tar_path.to_path_buf(), source: err })?;
+    let mut archive = tar::Archive::new(tar_file);
+
+    for entry in archive.entries().map_err(PrepareServiceError::TarExtractionError)? {
+        let mut entry = entry.map_err(PrepareServiceError::TarExtractionError)?;
+        let entry_path = entry.path().map_err(PrepareServiceError::TarExtractionError)?;
+
+        // the builds also ship docs named after the binary, only a regular file counts
+        if entry.header().entry_type().is_file() && entry_path.file_name() == Some(OsStr::new(file_name)) {
+            log::info!("Extracting {} from an archive..", file_name);
+
+            // unpack keeps the mode bits of the entry, so the binary stays executable
+            entry.unpack(unzip_dest.join(file_name)).map_err(PrepareServiceError::TarExtractionError)?;
+
+            return Ok(());
+        }
+    }
+
+    Err(PrepareServiceError::FailedToFindFFmpegInsideArchive(file_name.to_string()))
+}
+
 pub async fn prepare_ffmpeg(config: &Config) -> Result<(), PrepareServiceError> {
     let ffmpeg_exe_path = &config.media.ffmpeg_exe_path;
 
     if ffmpeg_exists(&ffmpeg_exe_path) {
         return Ok(());
     }
-    let zip_path =config.media.ffmpeg_dir_path.join(FFMPEG_ARCHIVE_NAME);
-    let gyan_mirror = &config.media.ffmpeg_donwload_mirror;
-    download_ffmpeg_zip_essentials(&zip_path, gyan_mirror).await?;
+    if config.media.ffmpeg_donwload_mirror.is_empty() {
+        return Err(PrepareServiceError::NoFfmpegDownloadMirror);
+    }
+    let archive = FfmpegArchive::from_url(&config.media.ffmpeg_donwload_mirror);
+    let zip_path = ffmpeg_archive_path(config, archive);
+
+    let installed = install_ffmpeg(config, &zip_path, archive).await;
+
+    // a failed or half finished download is of no use either, so the archive goes in any case
+    log::info!("Cleaning things up..");
+    let cleaned = match zip_path.exists() {
+        true => remove_file(&zip_path).map_err(|err| PrepareServiceError::FileRemoveError{path: zip_path.to_path_buf(), source: err}),
+        false => Ok(())
+    };
+
+    installed.and(cleaned)
+}
+
+/// The archive is downloaded into `temp_path`, away from the ffmpeg dir, until the binary is out of it.
+fn ffmpeg_archive_path(config: &Config, archive: FfmpegArchive) -> PathBuf {
+    config.media.temp_path.join(archive.file_name())
+}
+
+async fn install_ffmpeg(config: &Config, zip_path: &Path, archive: FfmpegArchive) -> Result<(), PrepareServiceError> {
+    let ffmpeg_exe_path = &config.media.ffmpeg_exe_path;
+    let temp_path = &config.media.temp_path;
+    create_dir_all(temp_path).map_err(|err| PrepareServiceError::DirCreateError { path: temp_path.to_path_buf(), source: err })?;
+
+    let download_mirror = &config.media.ffmpeg_donwload_mirror;
+    download_ffmpeg_zip_essentials(zip_path, download_mirror).await?;
 
     let checksum_url = &config.media.ffmpeg_sha_download_mirror;
-    let expected_checksum = get_checksums(checksum_url).await?;
-    verify_checksums(&zip_path, expected_checksum)?;
+    let expected_checksum = parse_checksum(&get_checksums(checksum_url).await?, download_mirror)?;
+    verify_checksums(zip_path, expected_checksum)?;
+
+    // the binary is extracted next to the archive under the name `ffmpeg_exe_path` asks for, then moved there
+    let file_name = ffmpeg_exe_path.file_name().and_then(OsStr::to_str).unwrap_or(FFMPEG_EXECUTABLE_NAME);
+    unzip_ffmpeg(zip_path, archive, file_name, temp_path)?;
 
-    unzip_ffmpeg(&zip_path, FFMPEG_EXECUTABLE_NAME, &config.media.ffmpeg_dir_path)?;
+    let exe_dir = ffmpeg_exe_path.parent().unwrap_or(&config.media.ffmpeg_dir_path);
+    create_dir_all(exe_dir).map_err(|err| PrepareServiceError::DirCreateError { path: exe_dir.to_path_buf(), source: err })?;
+    move_file(&temp_path.join(file_name), ffmpeg_exe_path)?;
 
     if !ffmpeg_exists(&ffmpeg_exe_path) {
         return Err(PrepareServiceError::FfmpegDoesntExist())
     }
 
-    println!("\nCleaning things up..");
-    remove_file(&zip_path).map_err(|err| PrepareServiceError::FileRemoveError{path: zip_path.to_path_buf(), source: err})?;
-
     Ok(())
 }
 
+/// A rename, or a copy and remove when `temp_path` is on another volume than the destination.
+fn move_file(from: &Path, to: &Path) -> Result<(), PrepareServiceError> {
+    if rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    // copy keeps the permissions, the binary stays executable
+    copy(from, to).map_err(|err| PrepareServiceError::FileWriteError { path: to.to_path_buf(), source: err })?;
+    remove_file(from).map_err(|err| PrepareServiceError::FileRemoveError { path: from.to_path_buf(), source: err })
+}
+
 /* ======================= END OF FFMPEG PREPARATION PART ======================= */
 
 
@@ -268,6 +413,7 @@ pub fn prepare_dirs(config: &Config) -> Result<(), PrepareServiceError> {
         &config.media.ffmpeg_dir_path,
         &config.media.test_fixtures_path,
         &config.media.filesharing_path,
+        &config.media.temp_path,
         &db_path
     ];
 
@@ -289,6 +435,12 @@ pub enum FixturesSetupError {
     #[error("Fixtures setup has failed. I/O error has occured: {0}")]
     IOError(#[from] std::io::Error),
 
+    #[error("Fixtures setup has failed. Could not create dir '{path}': {source}")]
+    DirCreateError { path: PathBuf, #[source] source: std::io::Error },
+
+    #[error("Fixtures setup has failed. Could not write to a file '{path}': {source}")]
+    FileWriteError { path: PathBuf, #[source] source: std::io::Error },
+
     #[error("Fixtures setup has failed. Unable to get SystemRoot env variable: {0}")]
     SystemRootVariableNotFound(#[from] VarError),
 
@@ -301,6 +453,9 @@ pub enum FixturesSetupError {
     #[error("Fixtures setup has failed. Icacls returned with an error: {0}")]
     IcaclsCommandError(String),
 
+    #[error("Fixtures setup has failed. Could not change permissions of '{path}': {source}")]
+    PermissionsError { path: PathBuf, #[source] source: std::io::Error },
+
     #[error("Fixtures setup has failed. Error during fixtures state serialization: {0}")]
     FixturesCacheSerializationError(#[from] serde_json::Error),
 
@@ -335,7 +490,8 @@ impl FixturesContext {
     pub fn cache(&self) -> Result<(), FixturesSetupError> {
         let json_str = serde_json::to_string(self)?;
         
-        write(&self.fixtures_cache_path, json_str.as_bytes())?;
+        write(&self.fixtures_cache_path, json_str.as_bytes())
+            .map_err(|err| FixturesSetupError::FileWriteError { path: self.fixtures_cache_path.clone(), source: err })?;
         
         Ok(())
     }
@@ -348,7 +504,8 @@ impl FixturesContext {
 pub fn make_inaccessible_dir(name: &str, fctx: &mut FixturesContext) -> Result<PathBuf, FixturesSetupError> {
     let dir_path = fctx.fixture_path.join(name);
 
-    create_dir(&dir_path)?;
+    create_dir(&dir_path)
+        .map_err(|err| FixturesSetupError::DirCreateError { path: dir_path.clone(), source: err })?;
     strip_permissions(&dir_path)?;
 
     // Track for cleanup
@@ -358,7 +515,8 @@ pub fn make_inaccessible_dir(name: &str, fctx: &mut FixturesContext) -> Result<P
 }
 
 pub fn make_inaccessable_file(path: &Path, fctx: &mut FixturesContext) -> Result<(), FixturesSetupError> {
-    write(path, b"test")?;
+    write(path, b"test")
+        .map_err(|err| FixturesSetupError::FileWriteError { path: path.to_path_buf(), source: err })?;
     strip_permissions(&path)?;
     
     // Track for cleanup
@@ -367,8 +525,9 @@ pub fn make_inaccessable_file(path: &Path, fctx: &mut FixturesContext) -> Result
     Ok(())
 }
 
+#[cfg(windows)]
 fn get_icacls_path() -> Result<PathBuf, FixturesSetupError> {
-    let system_root = env::var("SystemRoot").map_err(|e| FixturesSetupError::SystemRootVariableNotFound(e))?;
+    let system_root = std::env::var("SystemRoot").map_err(|e| FixturesSetupError::SystemRootVariableNotFound(e))?;
     let icacls_path = Path::new(&system_root).join("system32").join("icacls.exe");
 
     if !icacls_path.exists() {
@@ -378,6 +537,7 @@ fn get_icacls_path() -> Result<PathBuf, FixturesSetupError> {
     Ok(icacls_path)
 }
 
+#[cfg(windows)]
 fn strip_permissions(path: &Path) -> Result<(), FixturesSetupError> {
     let icacls_path = get_icacls_path()?;
 
@@ -398,6 +558,7 @@ fn strip_permissions(path: &Path) -> Result<(), FixturesSetupError> {
     Ok(())
 }
 
+#[cfg(windows)]
 fn restore_permissions(path: &Path) -> Result<(), FixturesSetupError> {
     let icacls_path = get_icacls_path()?;
 
@@ -415,14 +576,65 @@ fn restore_permissions(path: &Path) -> Result<(), FixturesSetupError> {
     Ok(())
 }
 
+/// Last resort for a dir icacls couldn't reset: take the ownership over and try icacls once more.
+#[cfg(windows)]
+fn force_restore_permissions(dir: &Path) {
+    log::warn!("Trying takeown for {:?}..", dir);
+
+    let takeown_ountcome = Command::new("takeown")
+        .arg("/f")
+        .arg(dir)
+        .arg("/r")
+        .arg("/d")
+        .arg("y")
+        .output();
+
+    if let Err(err) = takeown_ountcome {
+        log::warn!("Failed to restore permissions with takeown for {:?}: {}. Trying icacls again..", dir, err);
+    }
+
+    // Try icacls again
+    let icacls_2 = restore_permissions(dir);
+
+    if let Err(err) = icacls_2 {
+        log::error!("Failed to restore permissions for {:?}: {}. GG DUDE, I TRIED.", dir, err);
+    }
+}
+
+/// chmod 000; root ignores it, everyone else can't read or list the path anymore.
+#[cfg(unix)]
+fn strip_permissions(path: &Path) -> Result<(), FixturesSetupError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o000))
+        .map_err(|err| FixturesSetupError::PermissionsError { path: path.to_path_buf(), source: err })
+}
+
+/// Back to the usual 0755 for dirs and 0644 for files.
+#[cfg(unix)]
+fn restore_permissions(path: &Path) -> Result<(), FixturesSetupError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // stat only needs the parent to be accessible, a stripped path can still tell what it is
+    let mode = match path.is_dir() {
+        true => 0o755,
+        false => 0o644
+    };
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|err| FixturesSetupError::PermissionsError { path: path.to_path_buf(), source: err })
+}
+
 pub fn prepare_fixtures(fctx: &mut FixturesContext) -> Result<(), FixturesSetupError> {
     if fctx.fixtures_cache_path.exists() {
         // right now assume that if cache exist, then all the fixutres are also presented.
         return Ok(());
     }
 
-    create_dir_all(fctx.fixture_path.join("files"))?;
-    create_dir_all(fctx.fixture_path.join("dirs/accessible_dir"))?;
+    for dir_path in [fctx.fixture_path.join("files"), fctx.fixture_path.join("dirs/accessible_dir")] {
+        create_dir_all(&dir_path)
+            .map_err(|err| FixturesSetupError::DirCreateError { path: dir_path.clone(), source: err })?;
+    }
 
     make_inaccessible_dir("dirs/inaccessible_dir", fctx)?;
     make_inaccessible_dir("dirs/accessible_dir/inaccessible_dir", fctx)?;
@@ -506,7 +718,7 @@ pub fn cleanup(fixtures_state_json: &Path) -> Result<(), FixturesSetupError> {
     // Restore files first
     for file in &fctx.stripped_files {
         if let Err(err) = restore_permissions(file) {
-            eprintln!("Warning: Failed to restore permissions for file {:?}: {}", file, err);
+            log::warn!("Failed to restore permissions for file {:?}: {}", file, err);
         }
     }
 
@@ -516,27 +728,10 @@ pub fn cleanup(fixtures_state_json: &Path) -> Result<(), FixturesSetupError> {
     for dir in &fctx.stripped_dirs {
         if let Err(err) = restore_permissions(dir) {
             // Log.
-            eprintln!("Warning: Failed to restore permissions for {:?}: {}.\nTrying takeown..", dir, err);
-            
-            // Try alternative approach with takeown
-            let takeown_ountcome = Command::new("takeown")
-                .arg("/f")
-                .arg(dir)
-                .arg("/r")
-                .arg("/d")
-                .arg("y")
-                .output();
-
-            if let Err(err) = takeown_ountcome {
-                eprintln!("Warning: Failed to restore permissions with takeown for {:?}: {}.\nTrying icacls again..", dir, err);
-            }
-            
-            // Try icacls again
-            let icacls_2 = restore_permissions(dir);
+            log::warn!("Failed to restore permissions for {:?}: {}", dir, err);
 
-            if let Err(err) = icacls_2 {
-                eprintln!("Warning: Failed to restore permissions with for {:?}: {}.\nGG DUDE, I TRIED.", dir, err);
-            }
+            #[cfg(windows)]
+            force_restore_permissions(dir);
         }
     }
 
@@ -564,7 +759,11 @@ pub async fn run_prepare_userspace() -> Result<(), PrepareServiceError> {
 
     prepare_dirs(config)?;
     prepare_db(config)?;
-    prepare_ffmpeg(config).await?;
+
+    // ffmpeg is only needed by the resampler
+    if config.features.resample {
+        prepare_ffmpeg(config).await?;
+    }
 
     Ok(())
 }
@@ -575,7 +774,8 @@ pub mod tests {
 
     use tempfile::TempDir;
 
-    use crate::utils::config::{DatabaseConfig, MediaConfig, ServerConfig};
+    use crate::domain::track::TrackSort;
+    use crate::utils::config::{DatabaseConfig, FeaturesConfig, MediaConfig, ResampleSettings, ScannerConfig, ServerConfig, SyncConfig};
 
     use super::*;
 
@@ -622,7 +822,14 @@ pub mod tests {
                     config_mock: Config {
                         server: ServerConfig {
                             host: "0.0.0.0".to_string(),
-                            port: 8080
+                            port: 8080,
+                            listen: None,
+                            lock_path: PathBuf::from("./data/home-server.lock"),
+                            request_timeout_secs: 30,
+                            read_only: false,
+                            entity_cache_size: None,
+                            expose_file_paths: false,
+                            default_track_sort: TrackSort::Name
                         },
 
                         database: DatabaseConfig {
@@ -639,8 +846,16 @@ pub mod tests {
                             ffmpeg_sha_download_mirror: "mock this".to_string(),
                             test_fixtures_path: tempdir.path().join("test_fixtures"),
                             resampled_music_path: tempdir.path().join("data/media/music/.resampled"),
-                            audio_fixtures_json_path: PathBuf::from("./audio_fixtures.json")
-                        }
+                            audio_fixtures_json_path: PathBuf::from("./audio_fixtures.json"),
+                            temp_path: tempdir.path().join("data/tmp"),
+                            scan_extensions: Vec::new(),
+                            ignore_marker: ".nomedia".to_string(),
+                            resample: ResampleSettings::default()
+                        },
+
+                        features: FeaturesConfig::default(),
+                        scanner: ScannerConfig::default(),
+                        sync: SyncConfig::default()
                     },
 
                     tempdir: tempdir
@@ -772,6 +987,29 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_prepare_fixtures_reports_failed_dir() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new()?;
+
+        // a plain file where the fixtures dir should be makes every create_dir_all below it fail
+        let blocker = ctx.tempdir.path().join("not_a_dir");
+        File::create(&blocker)?;
+
+        let mut fxtr_context = FixturesContext {
+            fixture_path: blocker.clone(),
+            stripped_dirs: Vec::new(),
+            stripped_files: Vec::new(),
+            fixtures_cache_path: blocker.join("fixtures_state.json")
+        };
+
+        match prepare_fixtures(&mut fxtr_context) {
+            Err(FixturesSetupError::DirCreateError { path, .. }) => assert_eq!(path, blocker.join("files")),
+            other => panic!("DirCreateError expected, but found: {:?}", other.err())
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_ffmpeg_download_and_unzip() -> Result<(), TestSetupError> {
         use httpmock::MockServer;
@@ -817,4 +1055,152 @@ pub mod tests {
 
         Ok(())
 }
+
+    #[tokio::test]
+    async fn test_ffmpeg_download_and_untar() -> Result<(), TestSetupError> {
+        use httpmock::MockServer;
+        let server = MockServer::start();
+
+        let mut ctx = TestContext::new()?;
+        prepare_dirs(&ctx.config_mock).map_err(TestSetupError::FailedToPrepareDirs)?;
+        ctx.config_mock.media.ffmpeg_exe_path = ctx.config_mock.media.ffmpeg_dir_path.join("ffmpeg");
+
+        // a docs dir named after the binary comes first, it must not be picked up
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, mode, entry_type, content) in [
+            ("ffmpeg-build/doc/ffmpeg", 0o644, tar::EntryType::Directory, &b""[..]),
+            ("ffmpeg-build/bin/ffmpeg", 0o755, tar::EntryType::Regular, &b"hello linux!"[..])
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content)?;
+        }
+        let tar_bytes = builder.into_inner()?;
+
+        let mut archive_bytes = Vec::new();
+        lzma_rs::xz_compress(&mut &tar_bytes[..], &mut archive_bytes)?;
+
+        server.mock(|when, then| {
+            when.path("/ffmpeg-linux64.tar.xz");
+            then.status(200).body(archive_bytes.clone());
+        });
+
+        let hex = format!("{:x}", Sha256::digest(&archive_bytes));
+        server.mock(|when, then| {
+            when.path("/checksums.sha256");
+            then.status(200).body(format!("{}  ffmpeg-win64.zip\n{}  ffmpeg-linux64.tar.xz\n", "0".repeat(64), hex));
+        });
+
+        ctx.set_ffmpeg_dl_mirror(format!("{}/ffmpeg-linux64.tar.xz", server.url("")));
+        ctx.set_ffmpeg_sha_dl_mirror(format!("{}/checksums.sha256", server.url("")));
+
+        prepare_ffmpeg(&ctx.config_mock).await.map_err(TestSetupError::FailedToPrepareFfmpeg)?;
+
+        let content = std::fs::read_to_string(&ctx.config_mock.media.ffmpeg_exe_path)?;
+        assert_eq!(content, "hello linux!");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&ctx.config_mock.media.ffmpeg_exe_path)?.permissions().mode();
+            assert_ne!(mode & 0o111, 0, "ffmpeg should stay executable");
+        }
+
+        let leftovers = std::fs::read_dir(&ctx.config_mock.media.ffmpeg_dir_path)?.count();
+        assert_eq!(leftovers, 1, "only the binary should be left in the ffmpeg dir");
+        assert_eq!(std::fs::read_dir(&ctx.config_mock.media.temp_path)?.count(), 0, "the temp dir should be cleaned up");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ffmpeg_archive_goes_to_temp_path_and_is_cleaned_up() -> Result<(), TestSetupError> {
+        use httpmock::MockServer;
+        let server = MockServer::start();
+
+        let mut ctx = TestContext::new()?;
+        prepare_dirs(&ctx.config_mock).map_err(TestSetupError::FailedToPrepareDirs)?;
+
+        server.mock(|when, then| {
+            when.path("/ffmpeg-linux64.tar.xz");
+            then.status(200).body("not really an archive");
+        });
+        server.mock(|when, then| {
+            when.path("/checksums.sha256");
+            then.status(200).body(format!("{}  ffmpeg-linux64.tar.xz\n", "0".repeat(64)));
+        });
+
+        ctx.set_ffmpeg_dl_mirror(format!("{}/ffmpeg-linux64.tar.xz", server.url("")));
+        ctx.set_ffmpeg_sha_dl_mirror(format!("{}/checksums.sha256", server.url("")));
+
+        let archive_path = ffmpeg_archive_path(&ctx.config_mock, FfmpegArchive::TarXz);
+        assert_eq!(archive_path, ctx.config_mock.media.temp_path.join("ffmpeg_archive.tar.xz"));
+
+        // the download goes through, the checksum doesn't match
+        let result = prepare_ffmpeg(&ctx.config_mock).await;
+
+        assert!(matches!(result, Err(PrepareServiceError::ChecksumMismatch { .. })), "{:?}", result);
+        assert!(!archive_path.exists());
+        assert_eq!(std::fs::read_dir(&ctx.config_mock.media.temp_path)?.count(), 0);
+        assert_eq!(std::fs::read_dir(&ctx.config_mock.media.ffmpeg_dir_path)?.count(), 0, "nothing should reach the ffmpeg dir");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ffmpeg_without_a_mirror_asks_for_one() -> Result<(), TestSetupError> {
+        let mut ctx = TestContext::new()?;
+        ctx.set_ffmpeg_dl_mirror(String::new());
+
+        let result = prepare_ffmpeg(&ctx.config_mock).await;
+
+        assert!(matches!(result, Err(PrepareServiceError::NoFfmpegDownloadMirror)), "{:?}", result);
+        assert!(!ctx.config_mock.media.temp_path.exists(), "nothing should be downloaded");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let hash = "ab".repeat(32);
+
+        assert_eq!(parse_checksum(&format!("{}\r\n", hash.to_uppercase()), "https://host/ffmpeg.7z").unwrap(), hash);
+        assert_eq!(parse_checksum(&format!("{}  other.tar.xz\n{} *ffmpeg.tar.xz", "0".repeat(64), hash), "https://host/ffmpeg.tar.xz").unwrap(), hash);
+        assert!(matches!(parse_checksum(&format!("{}  other.tar.xz", hash), "https://host/ffmpeg.tar.xz"), Err(PrepareServiceError::FailedToParseChecksums())));
+    }
+
+    #[test]
+    fn test_ffmpeg_archive_from_url() {
+        assert_eq!(FfmpegArchive::from_url("https://www.gyan.dev/ffmpeg/builds/ffmpeg-release-essentials.7z"), FfmpegArchive::SevenZip);
+        assert_eq!(FfmpegArchive::from_url("https://host/ffmpeg-linux64-gpl.tar.xz?download=1"), FfmpegArchive::TarXz);
+
+        let platform_default = if cfg!(target_os = "windows") { FfmpegArchive::SevenZip } else { FfmpegArchive::TarXz };
+        assert_eq!(FfmpegArchive::from_url("https://host/latest"), platform_default);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_permissions_strip_and_restore() -> Result<(), TestSetupError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ctx = TestContext::new()?;
+        let dir = ctx.tempdir.path().join("dir");
+        let file = ctx.tempdir.path().join("file.flac");
+        create_dir(&dir)?;
+        File::create(&file)?;
+        let mode = |path: &Path| std::fs::metadata(path).map(|metadata| metadata.permissions().mode() & 0o777);
+
+        for path in [&dir, &file] {
+            strip_permissions(path).map_err(TestSetupError::FaileToPrepareFixtures)?;
+            assert_eq!(mode(path)?, 0o000);
+            restore_permissions(path).map_err(TestSetupError::FaileToPrepareFixtures)?;
+        }
+
+        assert_eq!(mode(&dir)?, 0o755);
+        assert_eq!(mode(&file)?, 0o644);
+
+        Ok(())
+    }
 }
\ No newline at end of file
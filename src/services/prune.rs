@@ -0,0 +1,127 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::repository::{IntoUuid, RepositoryError, SqliteAlbumsRepository, SqliteArtistsRepository, SqliteTracksRepository};
+
+/// What `delete_track_and_prune` has removed. Album and artist are `None` when they still had other entries.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PruneReport {
+    pub track_id: Uuid,
+    pub album_id: Option<Uuid>,
+    pub artist_id: Option<Uuid>
+}
+
+/// Deletes a track, then its album if that was the album's last track, then the artist if that
+/// was the artist's last album. Everything happens in one transaction, so a failure leaves the DB untouched.
+///
+/// Without this the emptied album and artist would linger until the next full sync.
+pub async fn delete_track_and_prune<ID>(pool: &SqlitePool, track_id: ID) -> Result<PruneReport, RepositoryError>
+where
+    ID: IntoUuid + Send + Sync
+{
+    let tracks_repo = SqliteTracksRepository::new();
+    let albums_repo = SqliteAlbumsRepository::new();
+    let artists_repo = SqliteArtistsRepository::new();
+
+    let track_id = track_id.into_uuid()?;
+    let mut tx = pool.begin().await?;
+
+    let track = tracks_repo.by_id_fetch(&mut *tx, track_id).await?
+        .ok_or(RepositoryError::IdNotFound(track_id))?;
+    tracks_repo.delete(&mut *tx, track_id).await?;
+
+    let mut report = PruneReport { track_id, album_id: None, artist_id: None };
+
+    if tracks_repo.count_by_album(&mut *tx, track.album_id()).await? == 0 {
+        let album = albums_repo.by_id_fetch(&mut *tx, track.album_id()).await?;
+        albums_repo.delete(&mut *tx, track.album_id()).await?;
+        report.album_id = Some(*track.album_id());
+
+        if let Some(album) = album
+            && albums_repo.count_by_artist(&mut *tx, album.artist_id()).await? == 0
+        {
+            artists_repo.delete(&mut *tx, album.artist_id()).await?;
+            report.artist_id = Some(*album.artist_id());
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use crate::{domain::{album::Album, artist::Artist, audiofile::AudioFileType, track::Track, uploaded::Uploaded}, services::test_helpers::{prepare_db, TestSetupError}};
+    use super::*;
+
+    struct TestContext {
+        pool: SqlitePool,
+        artist: Artist,
+        album: Album,
+        tracks: Vec<Track>
+    }
+
+    impl TestContext {
+        async fn with_tracks(count: usize) -> Result<Self, TestSetupError> {
+            let pool = prepare_db().await.expect("Failed to prepare the test db");
+
+            let artist = Artist::new(Uuid::new_v4(), "artist")?;
+            let album = Album::new(Uuid::new_v4(), "album", *artist.id(), None)?;
+            SqliteArtistsRepository::new().save(&pool, &artist).await?;
+            SqliteAlbumsRepository::new().save(&pool, &album).await?;
+
+            let mut tracks = Vec::new();
+            for idx in 0..count {
+                let track = Track::new(Uuid::new_v4(), format!("track {}", idx), *album.id(), 60, format!("music/track_{}.flac", idx).into(), 1024, AudioFileType::Flac, Uploaded::Denis, Some(Local::now().naive_local()))?;
+                SqliteTracksRepository::new().save(&pool, &track).await?;
+                tracks.push(track);
+            }
+
+            Ok(Self { pool, artist, album, tracks })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deleting_last_track_prunes_album_and_artist() -> Result<(), TestSetupError> {
+        let ctx = TestContext::with_tracks(1).await?;
+
+        let report = delete_track_and_prune(&ctx.pool, ctx.tracks[0].id()).await?;
+
+        assert_eq!(report, PruneReport { track_id: *ctx.tracks[0].id(), album_id: Some(*ctx.album.id()), artist_id: Some(*ctx.artist.id()) });
+        assert!(!SqliteAlbumsRepository::new().id_exists(&ctx.pool, ctx.album.id()).await?);
+        assert!(!SqliteArtistsRepository::new().id_exists(&ctx.pool, ctx.artist.id()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_album_with_remaining_tracks_is_kept() -> Result<(), TestSetupError> {
+        let ctx = TestContext::with_tracks(2).await?;
+
+        let report = delete_track_and_prune(&ctx.pool, ctx.tracks[0].id()).await?;
+
+        assert_eq!(report, PruneReport { track_id: *ctx.tracks[0].id(), album_id: None, artist_id: None });
+        assert!(!SqliteTracksRepository::new().id_exists(&ctx.pool, ctx.tracks[0].id()).await?);
+        assert!(SqliteTracksRepository::new().id_exists(&ctx.pool, ctx.tracks[1].id()).await?);
+        assert!(SqliteAlbumsRepository::new().id_exists(&ctx.pool, ctx.album.id()).await?);
+        assert!(SqliteArtistsRepository::new().id_exists(&ctx.pool, ctx.artist.id()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_track_is_not_found() -> Result<(), TestSetupError> {
+        let ctx = TestContext::with_tracks(1).await?;
+
+        let result = delete_track_and_prune(&ctx.pool, Uuid::new_v4()).await;
+
+        assert!(matches!(result, Err(RepositoryError::IdNotFound(_))));
+        assert!(SqliteAlbumsRepository::new().id_exists(&ctx.pool, ctx.album.id()).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{repository::{RepositoryError, SqliteTracksRepository}, utils::normalizations::normalize_path};
+
+/// Outcome of `repair_paths`. When `collisions` isn't empty nothing was written.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RepairPathsReport {
+    /// Tracks stored under the old prefix.
+    pub matched: usize,
+
+    /// Tracks whose path was rewritten. Always 0 on a dry run.
+    pub updated: usize,
+
+    /// Rewrites that would land on a path another track already has, as (old path, new path).
+    pub collisions: Vec<(PathBuf, PathBuf)>,
+
+    pub dry_run: bool
+}
+
+/// Rewrites `file_path` of every track stored under `from` so it's under `to`, keeping ids and dates.
+/// Meant for a library that was moved as a whole, where a sync would delete and re-add every track.
+///
+/// Both prefixes are normalized the way stored paths are and only match whole directories, so
+/// `d:/music` doesn't pick up `d:/musicals`. Runs in one transaction; if any rewritten path is
+/// already taken by a track that isn't being moved, nothing is written and the report lists the collisions.
+pub async fn repair_paths(pool: &SqlitePool, from: &Path, to: &Path, dry_run: bool) -> Result<RepairPathsReport, RepositoryError> {
+    let tracks_repo = SqliteTracksRepository::new();
+    let from_prefix = dir_prefix(from);
+    let to_prefix = dir_prefix(to);
+
+    let mut tx = pool.begin().await?;
+
+    let tracks = tracks_repo.by_path_prefix(&mut *tx, &from_prefix, u32::MAX, 0).await?;
+    let moves: Vec<_> = tracks.iter()
+        .map(|track| {
+            let old_path = track.file_path().to_string_lossy().to_string();
+            let new_path = format!("{}{}", to_prefix, &old_path[from_prefix.len()..]);
+            (track, PathBuf::from(old_path), PathBuf::from(new_path))
+        })
+        .collect();
+
+    let mut report = RepairPathsReport { matched: moves.len(), updated: 0, collisions: Vec::new(), dry_run };
+
+    for (_, old_path, new_path) in &moves {
+        // a path that is itself being moved away is free by the end of the transaction
+        let is_moving = moves.iter().any(|(_, moving_path, _)| moving_path == new_path);
+        if !is_moving && tracks_repo.path_exists(&mut *tx, new_path).await? {
+            report.collisions.push((old_path.clone(), new_path.clone()));
+        }
+    }
+
+    if dry_run || !report.collisions.is_empty() {
+        return Ok(report);
+    }
+
+    for (track, _, new_path) in &moves {
+        tracks_repo.set_file_path(&mut *tx, track.id(), new_path).await?;
+        report.updated += 1;
+    }
+
+    tx.commit().await?;
+
+    Ok(report)
+}
+
+/// Normalized prefix with a trailing slash, so it only matches whole directories.
+fn dir_prefix(path: &Path) -> String {
+    let mut prefix = normalize_path(path).to_string_lossy().to_string();
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use uuid::Uuid;
+
+    use crate::{domain::{album::Album, artist::Artist, audiofile::AudioFileType, track::Track, uploaded::Uploaded}, repository::{SqliteAlbumsRepository, SqliteArtistsRepository}, services::test_helpers::{prepare_db, TestSetupError}};
+    use super::*;
+
+    async fn save_tracks(pool: &SqlitePool, paths: &[&str]) -> Result<Vec<Track>, TestSetupError> {
+        let artist = Artist::new(Uuid::new_v4(), "artist")?;
+        let album = Album::new(Uuid::new_v4(), "album", *artist.id(), None)?;
+        SqliteArtistsRepository::new().save(pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(pool, &album).await?;
+
+        let mut tracks = Vec::new();
+        for path in paths {
+            let track = Track::new(Uuid::new_v4(), "track", *album.id(), 60, PathBuf::from(path), 1024, AudioFileType::Flac, Uploaded::Denis, Some(Local::now().naive_local()))?;
+            tracks.push(SqliteTracksRepository::new().save(pool, &track).await?);
+        }
+
+        Ok(tracks)
+    }
+
+    #[tokio::test]
+    async fn test_repair_paths_rewrites_prefix() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+        let tracks = save_tracks(&pool, &["d:/music/a/1.flac", "d:/music/b/2.flac", "d:/musicals/3.flac"]).await?;
+        let repo = SqliteTracksRepository::new();
+
+        let dry_report = repair_paths(&pool, Path::new(r"D:\Music"), Path::new(r"E:\Media\Music"), true).await?;
+        assert_eq!(dry_report, RepairPathsReport { matched: 2, updated: 0, collisions: Vec::new(), dry_run: true });
+        assert!(repo.path_exists(&pool, "d:/music/a/1.flac").await?);
+
+        let report = repair_paths(&pool, Path::new(r"D:\Music"), Path::new(r"E:\Media\Music"), false).await?;
+        assert_eq!(report, RepairPathsReport { matched: 2, updated: 2, collisions: Vec::new(), dry_run: false });
+
+        let moved = repo.by_id_fetch(&pool, tracks[0].id()).await?.expect("Track should still exist");
+        assert_eq!(moved.file_path(), Path::new("e:/media/music/a/1.flac"));
+        assert_eq!(moved.date_added(), tracks[0].date_added());
+        assert!(repo.path_exists(&pool, "e:/media/music/b/2.flac").await?);
+        assert!(repo.path_exists(&pool, "d:/musicals/3.flac").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_repair_paths_refuses_collisions() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+        save_tracks(&pool, &["old/1.flac", "old/2.flac", "new/2.flac"]).await?;
+
+        let report = repair_paths(&pool, Path::new("old"), Path::new("new"), false).await?;
+
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.collisions, vec![(PathBuf::from("old/2.flac"), PathBuf::from("new/2.flac"))]);
+        assert!(SqliteTracksRepository::new().path_exists(&pool, "old/1.flac").await?);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,19 @@
-use std::{path::{Path, PathBuf}, process::{Command, ExitStatus}, fs};
+use std::{collections::HashMap, path::{Component, Path, PathBuf}, process::{Command, ExitStatus, Stdio}, fs, sync::Mutex};
 
 use indicatif::{ProgressBar, ProgressStyle, ParallelProgressIterator};
 use rayon::{prelude::*, ThreadPoolBuildError, ThreadPoolBuilder};
 
-use crate::{domain::audiofile::{AudioFileDescriptor, AudioFileType}, services::scanner::ScanResult};
+use serde::{Deserialize, Serialize};
+
+use crate::{domain::audiofile::{AudioFileDescriptor, AudioFileType}, services::scanner::{MediaScanner, ScanResult}, utils::{config::{ResampleSettings, ResampleStrategy}, normalizations::relative_to, progress}};
 
 // TODO: 
-//      1. Resample state. Even if there is already resmapled tracks inside .resampled, service resampling things again.
-//      2. ffmpeg echoing a lot of things, which pollutes cli heavily. Need to deal with it somehow. 
+//      1. ffmpeg echoing a lot of things, which pollutes cli heavily. Need to deal with it somehow. 
+
+/// Kept in the cache dir, lists the files `InPlace` has already replaced.
+const IN_PLACE_MANIFEST_NAME: &str = "in_place_manifest.json";
+/// How many files `InPlace` replaces before the manifest is saved again in the middle of a run.
+const MANIFEST_SAVE_INTERVAL: usize = 100;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ResampleConfig {
@@ -17,6 +23,34 @@ pub struct ResampleConfig {
 
     pub parallelism: ParallelismPolicy,
 
+    /// Fixed number of resampling threads, overrides `parallelism` when set. Every thread runs one ffmpeg
+    /// process at a time, so this is also the cap on ffmpeg processes running at once.
+    pub max_threads: Option<usize>,
+
+    /// Sample rate of the output, `None` uses the default rate of the output type.
+    pub target_sample_rate: Option<u32>,
+
+    /// Bitrate cap (`-b:a`) of the output. Lossy outputs only, a lossless one is rejected with `IncompatibleOptions`.
+    pub target_bitrate_kbps: Option<u32>,
+
+    /// Type the output is written as, `None` keeps the type of the input. Only `CopyToCache` can change
+    /// the type, `InPlace` would leave the new data under the old extension.
+    pub output_format: Option<AudioFileType>,
+
+    /// Skip lossy files, resampling them only degrades them further.
+    pub lossless_only: bool,
+
+    /// Re-probe every resampled output before it is accepted. A truncated or
+    /// undecodable output is reported as an error and, for `InPlace`, the original is kept.
+    pub verify_output: bool,
+
+    /// For `InPlace`: copy every original here, under its path relative to `music_lib_path`,
+    /// before it gets replaced. `None` overwrites the originals for good.
+    pub backup_originals: Option<PathBuf>,
+
+    /// Root the backup paths are made relative to.
+    pub music_lib_path: PathBuf,
+
     // unsure whether i need those
     pub enable_backups: bool,
     pub supported_types: Vec<AudioFileType>
@@ -30,11 +64,60 @@ impl Default for ResampleConfig {
             cache_dir: PathBuf::from("./data/media/music/.resampled"),
             enable_backups: true,
             parallelism: ParallelismPolicy::default(),
+            max_threads: None,
+            target_sample_rate: None,
+            target_bitrate_kbps: None,
+            output_format: None,
+            lossless_only: false,
+            verify_output: false,
+            backup_originals: None,
+            music_lib_path: PathBuf::from("./data/media/music"),
             supported_types: Vec::new()
         }
     }
 }
 
+impl ResampleConfig {
+    /// Builds the config from the `[media.resample]` section, the library it runs on and the dir resampled copies
+    /// go to (`resampled_music_path`); the rest keeps its defaults.
+    pub fn from_settings(settings: &ResampleSettings, music_lib_path: &Path, cache_dir: &Path) -> Self {
+        Self {
+            max_sample_rate: settings.max_sample_rate,
+            cache_dir: cache_dir.to_path_buf(),
+            strategy: settings.strategy.clone(),
+            max_threads: settings.concurrency,
+            target_sample_rate: settings.target_sample_rate,
+            target_bitrate_kbps: settings.target_bitrate_kbps,
+            output_format: settings.output_format.as_deref().map(AudioFileType::from_extension_str),
+            lossless_only: settings.lossless_only,
+            verify_output: settings.verify_output,
+            backup_originals: settings.backup_originals.clone(),
+            music_lib_path: music_lib_path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    /// The output a file of `input_type` is resampled into, or why these options can't produce one.
+    pub fn target_for(&self, input_type: &AudioFileType) -> Result<ResampleTarget, ResampleError> {
+        let incompatible = |reason: String| Err(ResampleError::IncompatibleOptions(reason));
+        let file_type = self.output_format.clone().unwrap_or_else(|| input_type.clone());
+
+        if file_type == AudioFileType::Unknown {
+            return incompatible("the output format is unknown".to_string());
+        }
+
+        if self.target_bitrate_kbps.is_some() && file_type.is_lossless() {
+            return incompatible(format!("a bitrate can't be set for a lossless {} output", file_type.as_str()));
+        }
+
+        if self.strategy == ResampleStrategy::InPlace && &file_type != input_type {
+            return incompatible(format!("in_place can't turn {} into {}, use copy_to_cache", input_type.as_str(), file_type.as_str()));
+        }
+
+        Ok(ResampleTarget { file_type, sample_rate: self.target_sample_rate, bitrate_kbps: self.target_bitrate_kbps })
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ParallelismPolicyError {
     #[error("reserved_fraction must be > 0.0 and < 1.0, got {0}")]
@@ -96,19 +179,15 @@ impl ParallelismPolicy {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Default)]
-pub enum ResampleStrategy {
-    InPlace,
-
-    #[default]
-    CopyToCache
-}
-
 #[derive(Clone, Debug, PartialEq)]
 pub enum SkipReason {
     FailedToRetrieveSampleRate,
     SampleRateLowerThanMax,
-    InvalidPath
+    LossyFormat,
+    InvalidPath,
+
+    /// `CopyToCache`: the output is newer than the source. `InPlace`: the manifest lists the file with its current size.
+    AlreadyResampled
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -119,15 +198,30 @@ pub enum ResampleError {
     #[error("Resample Service has encountered an error while building thread pool: {0}")]
     ThreadPoolBuildError(#[from] ThreadPoolBuildError),
 
-    #[error("Ffmpeg resampler has encountered an error and exited with: {0}")]
-    FfmpegResamplerError(ExitStatus)
+    #[error("Ffmpeg resampler has encountered an error and exited with: {status}\n{stderr}")]
+    FfmpegResamplerError { status: ExitStatus, stderr: String },
+
+    #[error("Resampled output {path:?} failed verification: {reason}")]
+    OutputVerificationFailed { path: PathBuf, reason: String },
+
+    #[error("ffmpeg at {path:?} can't be run: {reason}")]
+    FfmpegUnavailable { path: PathBuf, reason: String },
+
+    #[error("Incompatible resample options: {0}")]
+    IncompatibleOptions(String),
+
+    #[error("Failed to serialize the in-place resample manifest: {0}")]
+    ManifestSerializationError(#[from] serde_json::Error)
 }
 
 #[derive(Debug, Default)]
 pub struct ResampleReport {
     processed_files: Vec<PathBuf>,
     skipped_files: Vec<(PathBuf, SkipReason)>,
-    errors: Vec<(PathBuf, ResampleError)>
+    errors: Vec<(PathBuf, ResampleError)>,
+
+    /// (original path, backup path) of every original kept by `backup_originals`.
+    backed_up_files: Vec<(PathBuf, PathBuf)>
 }
 
 impl ResampleReport {
@@ -135,59 +229,225 @@ impl ResampleReport {
         Self {
             processed_files: Vec::new(),
             skipped_files: Vec::new(),
-            errors: Vec::new()
+            errors: Vec::new(),
+            backed_up_files: Vec::new()
         }
     }
+
+    pub fn processed_files(&self) -> &[PathBuf] {
+        &self.processed_files
+    }
+
+    pub fn skipped_files(&self) -> &[(PathBuf, SkipReason)] {
+        &self.skipped_files
+    }
+}
+
+/// Files `InPlace` has replaced, with the size each was left with. There is no separate output to look at,
+/// so a file that still has that size counts as done and one that changed since gets resampled again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InPlaceManifest {
+    files: HashMap<PathBuf, u64>,
+
+    /// Entries recorded since the last save.
+    #[serde(skip)]
+    unsaved: usize
+}
+
+impl InPlaceManifest {
+    /// A missing manifest is a first run; an unreadable one is ignored, the worst case is resampling again.
+    fn load(path: &Path) -> Self {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                log::warn!("Failed to read the resample manifest {:?}, starting over: {}", path, err);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&json).unwrap_or_else(|err| {
+            log::warn!("Failed to parse the resample manifest {:?}, starting over: {}", path, err);
+            Self::default()
+        })
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), ResampleError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)?;
+        self.unsaved = 0;
+
+        Ok(())
+    }
+
+    fn contains(&self, path: &Path, file_size: u64) -> bool {
+        self.files.get(path) == Some(&file_size)
+    }
+}
+
+/// A cached output written after the source was last modified is still good.
+fn output_is_fresh(source: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    match (modified(source), modified(output)) {
+        (Some(source), Some(output)) => output > source,
+        _ => false
+    }
+}
+
+/// Result of resampling one file that didn't fail.
+#[derive(Debug, PartialEq)]
+pub enum FileResampleOutcome {
+    /// `backup_path` is where the original went, if it was backed up before being replaced.
+    Processed { output_path: PathBuf, backup_path: Option<PathBuf> },
+    Skipped(SkipReason)
 }
 
 enum DescriptorOutcome {
-    Processed(PathBuf),
+    Processed(PathBuf, Option<PathBuf>),
     Skipped(PathBuf, SkipReason),
     Errored(PathBuf, ResampleError)
 }
 
+const RESAMPLE_TEMP_MARKER: &str = ".resampling";
+
+/// `song.flac` -> `.song.resampling.flac`, next to the final path. Every resample is written here and only renamed
+/// into place once it checks out. The extension stays last since ffmpeg picks the output format from it.
+fn resample_temp_path(original: &Path) -> PathBuf {
+    let stem = original.file_stem().unwrap_or_default().to_string_lossy();
+    let temp_name = match original.extension() {
+        Some(ext) => format!(".{}.resampling.{}", stem, ext.to_string_lossy()),
+        None => format!(".{}.resampling", stem)
+    };
+
+    original.with_file_name(temp_name)
+}
+
+/// A file named by `resample_temp_path`, i.e. a resample still being written. The scanner leaves these alone.
+pub fn is_resample_temp_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+
+    name.starts_with('.')
+        && (name.ends_with(RESAMPLE_TEMP_MARKER) || name.rsplit_once('.').is_some_and(|(rest, _)| rest.ends_with(RESAMPLE_TEMP_MARKER)))
+}
+
+/// What a `Resampler` turns a file into, see `ResampleConfig::target_for`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResampleTarget {
+    pub file_type: AudioFileType,
+
+    /// `None` uses the default rate of `file_type`.
+    pub sample_rate: Option<u32>,
+    pub bitrate_kbps: Option<u32>
+}
+
 pub trait Resampler {
-    fn resample(&self, input_path: &Path, output_path: &Path, file_type: &AudioFileType) -> Result<(), ResampleError>;
+    fn resample(&self, input_path: &Path, output_path: &Path, target: &ResampleTarget) -> Result<(), ResampleError>;
 }
 
 pub struct FfmpegResampler {
-    pub ffmpeg_path: PathBuf
+    pub ffmpeg_path: PathBuf,
+
+    /// Output codec (`-c:a`), `None` picks the codec of the target type.
+    pub codec: Option<String>
+}
+
+impl FfmpegResampler {
+    /// Runs `ffmpeg -version` first, so a missing or broken binary fails here instead of on every
+    /// single file after the whole library has been scanned.
+    pub fn new(ffmpeg_path: PathBuf, settings: &ResampleSettings) -> Result<Self, ResampleError> {
+        check_ffmpeg(&ffmpeg_path)?;
+
+        Ok(Self::new_unchecked(ffmpeg_path, settings))
+    }
+
+    /// Same as `new` without running ffmpeg, for tests.
+    pub fn new_unchecked(ffmpeg_path: PathBuf, settings: &ResampleSettings) -> Self {
+        Self {
+            ffmpeg_path,
+            codec: settings.codec.clone()
+        }
+    }
+
+    /// The container comes from the extension of `output_path`, the rest from `target`.
+    fn args(&self, input_path: &Path, output_path: &Path, target: &ResampleTarget) -> Vec<String> {
+        let sample_rate = target.sample_rate.unwrap_or_else(|| target.file_type.get_resample_target_rate());
+        let codec = self.codec.as_deref().unwrap_or(target.file_type.ffmpeg_codec());
+
+        let mut args = vec![
+            "-loglevel".to_string(), "error".to_string(),
+            "-y".to_string(),
+            "-i".to_string(), input_path.to_string_lossy().to_string(),
+            "-ar".to_string(), sample_rate.to_string(),
+            "-c:a".to_string(), codec.to_string()
+        ];
+
+        if let Some(bitrate) = target.bitrate_kbps {
+            args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+        }
+
+        args.push(output_path.to_string_lossy().to_string());
+        args
+    }
+}
+
+fn check_ffmpeg(ffmpeg_path: &Path) -> Result<(), ResampleError> {
+    let unavailable = |reason: String| ResampleError::FfmpegUnavailable { path: ffmpeg_path.to_path_buf(), reason };
+
+    let status = Command::new(ffmpeg_path)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|err| unavailable(err.to_string()))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(unavailable(format!("`-version` exited with {}", status)))
+    }
 }
 
 impl Resampler for FfmpegResampler {
-    fn resample(&self, input_path: &Path, output_path: &Path, file_type: &AudioFileType) -> Result<(), ResampleError> {
-
-        let inpt_path_str = input_path.to_string_lossy();
-        let output_path_str = output_path.to_string_lossy();
-
-        let status = Command::new(&self.ffmpeg_path)
-            .args([
-                "-loglevel", "error",
-                "-y",
-                "-i", &inpt_path_str,
-                "-ar", &file_type.get_resample_target_rate().to_string(),
-                "-c:a", file_type.as_str(),
-                &output_path_str
-            ])
-            .status()?;
-
-        if status.success() {
+    /// ffmpeg writes straight to `output_path`. `ResampleService` hands it a temp path and renames that into
+    /// place once the output checks out, so a failed or killed ffmpeg never leaves a truncated file behind.
+    fn resample(&self, input_path: &Path, output_path: &Path, target: &ResampleTarget) -> Result<(), ResampleError> {
+        // stderr is captured rather than inherited, so failures can be reported to the caller
+        let output = Command::new(&self.ffmpeg_path)
+            .args(self.args(input_path, output_path, target))
+            .output()?;
+
+        if output.status.success() {
             Ok(())
         } else {
-            Err(ResampleError::FfmpegResamplerError(status))
+            Err(ResampleError::FfmpegResamplerError {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string()
+            })
         }
-
     }
 }
 
 pub struct ResampleService<R: Resampler> {
     config: ResampleConfig,
-    resampler: R
+    resampler: R,
+
+    /// Only read and written by `InPlace`.
+    manifest: Mutex<InPlaceManifest>
 }
 
 impl<R: Resampler + Sync + Send> ResampleService<R> {
     pub fn new(config: ResampleConfig, resampler: R) -> Self {
-        ResampleService { config, resampler }
+        let manifest = match config.strategy {
+            ResampleStrategy::InPlace => InPlaceManifest::load(&config.cache_dir.join(IN_PLACE_MANIFEST_NAME)),
+            ResampleStrategy::CopyToCache => InPlaceManifest::default()
+        };
+
+        ResampleService { config, resampler, manifest: Mutex::new(manifest) }
     }
 
     pub fn resample_library(&self, scan_result: &ScanResult) -> Result<ResampleReport, ResampleError> {
@@ -198,9 +458,9 @@ impl<R: Resampler + Sync + Send> ResampleService<R> {
             return Ok(ResampleReport::new());
         }
 
-        println!("\nHandling music lib resample..");
+        log::info!("Resampling {} files..", num_descriptors);
 
-        let pb = ProgressBar::new(num_descriptors);
+        let pb = progress::track(ProgressBar::new(num_descriptors));
 
         pb.set_style(ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
@@ -208,7 +468,7 @@ impl<R: Resampler + Sync + Send> ResampleService<R> {
             .progress_chars("#>-"));
 
         let pool = ThreadPoolBuilder::new()
-            .num_threads(self.config.parallelism.max_threads())
+            .num_threads(self.config.max_threads.unwrap_or_else(|| self.config.parallelism.max_threads()))
             .build()?;
 
 
@@ -224,12 +484,19 @@ impl<R: Resampler + Sync + Send> ResampleService<R> {
 
         pb.finish_with_message("Resampling complete!");
 
+        self.save_manifest();
+
         // Make a report sequentially.
         let mut report = ResampleReport::new();
 
         for outcome in outcomes {
             match outcome {
-                DescriptorOutcome::Processed(path)  => report.processed_files.push(path),
+                DescriptorOutcome::Processed(path, backup_path)  => {
+                    if let Some(backup_path) = backup_path {
+                        report.backed_up_files.push((path.clone(), backup_path));
+                    }
+                    report.processed_files.push(path)
+                },
                 DescriptorOutcome::Skipped(path, why)  => report.skipped_files.push((path, why)),
                 DescriptorOutcome::Errored(path,err)  => report.errors.push((path, err)),
             }
@@ -239,45 +506,688 @@ impl<R: Resampler + Sync + Send> ResampleService<R> {
     }
 
     fn handle_descriptor(&self, descriptor: &AudioFileDescriptor) -> DescriptorOutcome {
+        let path = descriptor.path.clone();
+
+        match self.resample_descriptor(descriptor) {
+            Ok(FileResampleOutcome::Processed { backup_path, .. }) => DescriptorOutcome::Processed(path, backup_path),
+            Ok(FileResampleOutcome::Skipped(reason)) => DescriptorOutcome::Skipped(path, reason),
+            Err(err) => DescriptorOutcome::Errored(path, err)
+        }
+    }
+
+    /// Resamples a single file with the service's config, the same way `resample_library` does for each file.
+    pub fn resample_file(&self, descriptor: &AudioFileDescriptor) -> Result<FileResampleOutcome, ResampleError> {
+        let outcome = self.resample_descriptor(descriptor);
+        self.save_manifest();
+
+        outcome
+    }
+
+    // leaves saving the manifest to the caller, `resample_library` only writes it once for the whole library
+    fn resample_descriptor(&self, descriptor: &AudioFileDescriptor) -> Result<FileResampleOutcome, ResampleError> {
         let path = &descriptor.path;
 
         let sample_rate = match descriptor.metadata.sample_rate {
             Some(sr) => sr,
-            None => return DescriptorOutcome::Skipped(path.clone(), SkipReason::FailedToRetrieveSampleRate)
+            None => return Ok(FileResampleOutcome::Skipped(SkipReason::FailedToRetrieveSampleRate))
         };
 
         if sample_rate <= self.config.max_sample_rate {
-            return DescriptorOutcome::Skipped(path.clone(), SkipReason::SampleRateLowerThanMax);
+            return Ok(FileResampleOutcome::Skipped(SkipReason::SampleRateLowerThanMax));
         }
 
-        let file_name = match path.file_name() {
-            Some(n) => n,
-            None => return DescriptorOutcome::Skipped(path.clone(), SkipReason::InvalidPath)
-        };
+        if self.config.lossless_only && !descriptor.file_type.is_lossless() {
+            return Ok(FileResampleOutcome::Skipped(SkipReason::LossyFormat));
+        }
 
-        let resample_outcome = match self.config.strategy {
+        if path.file_name().is_none() {
+            return Ok(FileResampleOutcome::Skipped(SkipReason::InvalidPath));
+        }
+
+        let target = self.config.target_for(&descriptor.file_type)?;
+
+        match self.config.strategy {
 
             ResampleStrategy::CopyToCache => {
-                let output_path = self.config.cache_dir.join(file_name);
-                self.resampler.resample(&path, &output_path, &descriptor.file_type).map(|_| DescriptorOutcome::Processed(path.clone()))
+                // mirrors the library, so tracks sharing a file name in different folders don't overwrite each other
+                let mut output_path = self.config.cache_dir.join(self.library_relative_path(path));
+                if target.file_type != descriptor.file_type {
+                    output_path.set_extension(target.file_type.as_str());
+                }
+
+                if output_is_fresh(path, &output_path) {
+                    return Ok(FileResampleOutcome::Skipped(SkipReason::AlreadyResampled));
+                }
+
+                if let Some(parent) = output_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                // a broken output at `output_path` would pass for a fresh one on the next run
+                let tmp = resample_temp_path(&output_path);
+                let result = self.resampler.resample(path, &tmp, &target)
+                    .and_then(|_| self.verify_output(&tmp, &target.file_type))
+                    .and_then(|_| fs::rename(&tmp, &output_path).map_err(ResampleError::from));
+                if let Err(err) = result {
+                    let _ = fs::remove_file(&tmp);
+                    return Err(err);
+                }
+
+                Ok(FileResampleOutcome::Processed { output_path, backup_path: None })
             },
 
             ResampleStrategy::InPlace => {
-                let tmp = self.config.cache_dir.join(file_name);
+                let file_size = fs::metadata(path)?.len();
+                if self.lock_manifest().contains(path, file_size) {
+                    return Ok(FileResampleOutcome::Skipped(SkipReason::AlreadyResampled));
+                }
 
-                match self.resampler.resample(&path, &tmp, &descriptor.file_type) {
-                    Ok(()) => fs::rename(&tmp, path)
-                        .map(|_| DescriptorOutcome::Processed(path.clone()))
-                        .map_err(ResampleError::IOError),
+                let tmp = resample_temp_path(path);
 
-                    Err(e) => Err(e)
+                let result = self.replace_in_place(descriptor, &tmp, &target);
+                match &result {
+                    Ok(_) => self.record_in_place(path),
+                    Err(_) => { let _ = fs::remove_file(&tmp); }
                 }
+
+                result
+            }
+        }
+    }
+
+    // the original is only backed up and replaced once the output has been verified
+    fn replace_in_place(&self, descriptor: &AudioFileDescriptor, tmp: &Path, target: &ResampleTarget) -> Result<FileResampleOutcome, ResampleError> {
+        let path = &descriptor.path;
+
+        self.resampler.resample(path, tmp, target)?;
+        self.verify_output(tmp, &target.file_type)?;
+
+        let backup_path = match &self.config.backup_originals {
+            Some(backup_dir) => Some(self.back_up(path, backup_dir)?),
+            None => None
+        };
+
+        // the temp file sits next to the original, so this is a rename within one filesystem and readers that
+        // already opened the original keep reading it until they close it
+        fs::rename(tmp, path)?;
+
+        Ok(FileResampleOutcome::Processed { output_path: path.clone(), backup_path })
+    }
+
+    fn lock_manifest(&self) -> std::sync::MutexGuard<'_, InPlaceManifest> {
+        self.manifest.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // the file is already replaced at this point, a manifest that can't be written only means resampling it again next time.
+    // Saved every `MANIFEST_SAVE_INTERVAL` files, so a run that gets killed halfway doesn't lose all of it
+    fn record_in_place(&self, path: &Path) {
+        let file_size = match fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => {
+                log::warn!("Failed to record {:?} in the resample manifest: {}", path, err);
+                return;
             }
         };
 
-        match resample_outcome {
-            Ok(o) => o,
-            Err(err) => DescriptorOutcome::Errored(path.clone(), err)
+        let mut manifest = self.lock_manifest();
+        manifest.files.insert(path.to_path_buf(), file_size);
+        manifest.unsaved += 1;
+
+        if manifest.unsaved >= MANIFEST_SAVE_INTERVAL {
+            self.save_locked_manifest(&mut manifest);
+        }
+    }
+
+    fn save_manifest(&self) {
+        if self.config.strategy == ResampleStrategy::InPlace {
+            self.save_locked_manifest(&mut self.lock_manifest());
+        }
+    }
+
+    fn save_locked_manifest(&self, manifest: &mut InPlaceManifest) {
+        if manifest.unsaved == 0 {
+            return;
+        }
+
+        let manifest_path = self.config.cache_dir.join(IN_PLACE_MANIFEST_NAME);
+        if let Err(err) = manifest.save(&manifest_path) {
+            log::warn!("Failed to save the resample manifest {:?}: {}", manifest_path, err);
+        }
+    }
+
+    /// `path` relative to the library; outside of it the whole path, minus the root and drive prefix.
+    fn library_relative_path(&self, path: &Path) -> PathBuf {
+        relative_to(path, &self.config.music_lib_path)
+            .unwrap_or_else(|| path.components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect())
+    }
+
+    /// Copies the original into `backup_dir`, keeping its path relative to the library.
+    /// A copy rather than a move, so the original stays put if anything after this fails.
+    fn back_up(&self, original: &Path, backup_dir: &Path) -> Result<PathBuf, ResampleError> {
+        let backup_path = backup_dir.join(self.library_relative_path(original));
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(original, &backup_path)?;
+
+        Ok(backup_path)
+    }
+
+    fn verify_output(&self, output_path: &Path, expected_type: &AudioFileType) -> Result<(), ResampleError> {
+        if !self.config.verify_output {
+            return Ok(());
+        }
+
+        let fail = |reason: String| ResampleError::OutputVerificationFailed { path: output_path.to_path_buf(), reason };
+
+        let scanner = MediaScanner::new(&self.config.cache_dir);
+        let output = scanner.describe_file(output_path).map_err(|err| fail(err.to_string()))?;
+
+        if output.file_size == 0 {
+            return Err(fail("output file is empty".to_string()));
+        }
+
+        if &output.file_type != expected_type {
+            return Err(fail(format!("expected {} output, probed {}", expected_type.as_str(), output.file_type.as_str())));
+        }
+
+        // lofty only reports audio properties for streams it managed to decode
+        if output.metadata.sample_rate.is_none() {
+            return Err(fail("output is not a decodable audio stream".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::domain::audiofile::AudioFileMetadata;
+    use super::*;
+
+    /// Pretends to be ffmpeg, but only manages to write a few garbage bytes,
+    /// like a resample interrupted by a full disk.
+    struct TruncatingResampler;
+
+    impl Resampler for TruncatingResampler {
+        fn resample(&self, _input_path: &Path, output_path: &Path, _target: &ResampleTarget) -> Result<(), ResampleError> {
+            fs::write(output_path, b"fLaC\0\0")?;
+            Ok(())
+        }
+    }
+
+    fn high_rate_descriptor(path: PathBuf) -> AudioFileDescriptor {
+        AudioFileDescriptor {
+            path,
+            file_size: 420,
+            modified: None,
+            file_type: AudioFileType::Flac,
+            metadata: AudioFileMetadata { sample_rate: Some(192000), ..Default::default() },
+            probe_ok: true,
+            content_hash: None
+        }
+    }
+
+    fn setup(verify_output: bool, strategy: ResampleStrategy) -> Result<(TempDir, ResampleService<TruncatingResampler>, ScanResult), std::io::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+        fs::create_dir(&cache_dir)?;
+
+        let original = temp_dir.path().join("original.flac");
+        fs::write(&original, b"original bytes")?;
+
+        let config = ResampleConfig {
+            strategy,
+            music_lib_path: temp_dir.path().to_path_buf(),
+            cache_dir,
+            verify_output,
+            parallelism: ParallelismPolicy::new(0.5, 1).expect("valid policy"),
+            ..Default::default()
+        };
+
+        let scan_result = ScanResult { descriptors: vec![high_rate_descriptor(original)], errors: Vec::new() };
+
+        Ok((temp_dir, ResampleService::new(config, TruncatingResampler), scan_result))
+    }
+
+    #[test]
+    fn test_truncated_output_fails_verification() -> Result<(), ResampleError> {
+        let (_temp_dir, service, scan_result) = setup(true, ResampleStrategy::CopyToCache)?;
+
+        let report = service.resample_library(&scan_result)?;
+
+        assert!(report.processed_files.is_empty());
+        assert_eq!(report.errors.len(), 1);
+        assert!(matches!(report.errors[0].1, ResampleError::OutputVerificationFailed { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_output_keeps_original_in_place() -> Result<(), ResampleError> {
+        let (_temp_dir, service, scan_result) = setup(true, ResampleStrategy::InPlace)?;
+        let original = scan_result.descriptors[0].path.clone();
+
+        let report = service.resample_library(&scan_result)?;
+
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(fs::read(&original)?, b"original bytes");
+        assert!(!resample_temp_path(&original).exists(), "the failed output should be cleaned up");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verification_disabled_accepts_output() -> Result<(), ResampleError> {
+        let (_temp_dir, service, scan_result) = setup(false, ResampleStrategy::CopyToCache)?;
+
+        let report = service.resample_library(&scan_result)?;
+
+        assert_eq!(report.processed_files.len(), 1);
+        assert!(report.errors.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lossless_only_skips_lossy_files() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut descriptor = high_rate_descriptor(temp_dir.path().join("lossy.mp3"));
+        descriptor.file_type = AudioFileType::Mp3;
+
+        let config = ResampleConfig { lossless_only: true, cache_dir: temp_dir.path().to_path_buf(), max_threads: Some(1), ..Default::default() };
+        let service = ResampleService::new(config, TruncatingResampler);
+
+        let report = service.resample_library(&ScanResult { descriptors: vec![descriptor], errors: Vec::new() })?;
+
+        assert!(report.processed_files.is_empty());
+        assert_eq!(report.skipped_files.len(), 1);
+        assert_eq!(report.skipped_files[0].1, SkipReason::LossyFormat);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_file_reports_output_path() -> Result<(), ResampleError> {
+        let (temp_dir, service, scan_result) = setup(false, ResampleStrategy::CopyToCache)?;
+
+        let outcome = service.resample_file(&scan_result.descriptors[0])?;
+
+        assert_eq!(outcome, FileResampleOutcome::Processed { output_path: temp_dir.path().join(".resampled").join("original.flac"), backup_path: None });
+
+        Ok(())
+    }
+
+    /// Writes a well-formed looking output, for tests that run with verification off.
+    struct OverwritingResampler;
+
+    impl Resampler for OverwritingResampler {
+        fn resample(&self, _input_path: &Path, output_path: &Path, _target: &ResampleTarget) -> Result<(), ResampleError> {
+            fs::write(output_path, b"resampled bytes")?;
+            Ok(())
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_in_place_backs_up_original() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let music_dir = temp_dir.path().join("music");
+        let cache_dir = music_dir.join(".resampled");
+        let backup_dir = temp_dir.path().join("backup");
+        fs::create_dir_all(music_dir.join("album"))?;
+        fs::create_dir(&cache_dir)?;
+
+        let original = music_dir.join("album").join("original.flac");
+        fs::write(&original, b"original bytes")?;
+
+        let config = ResampleConfig {
+            strategy: ResampleStrategy::InPlace,
+            cache_dir,
+            backup_originals: Some(backup_dir.clone()),
+            music_lib_path: music_dir,
+            max_threads: Some(1),
+            ..Default::default()
+        };
+        let service = ResampleService::new(config, OverwritingResampler);
+
+        let report = service.resample_library(&ScanResult { descriptors: vec![high_rate_descriptor(original.clone())], errors: Vec::new() })?;
+
+        let expected_backup = backup_dir.join("album").join("original.flac");
+        assert_eq!(report.backed_up_files, vec![(original.clone(), expected_backup.clone())]);
+        assert_eq!(fs::read(&original)?, b"resampled bytes");
+        assert_eq!(fs::read(&expected_backup)?, b"original bytes");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_ffmpeg_fails_up_front() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create a temp dir");
+        let missing = temp_dir.path().join("ffmpeg");
+
+        let result = FfmpegResampler::new(missing.clone(), &ResampleSettings::default());
+
+        assert!(matches!(result, Err(ResampleError::FfmpegUnavailable { path, .. }) if path == missing));
+        assert_eq!(FfmpegResampler::new_unchecked(missing.clone(), &ResampleSettings::default()).ffmpeg_path, missing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_runnable_ffmpeg_is_accepted() -> Result<(), std::io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        let fake_ffmpeg = |name: &str, exit_code: u8| -> Result<PathBuf, std::io::Error> {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, format!("#!/bin/sh\necho ffmpeg version n7.0\nexit {}\n", exit_code))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+            Ok(path)
+        };
+
+        let working = fake_ffmpeg("ffmpeg", 0)?;
+        assert!(FfmpegResampler::new(working, &ResampleSettings::default()).is_ok());
+
+        let broken = fake_ffmpeg("broken-ffmpeg", 1)?;
+        assert!(matches!(FfmpegResampler::new(broken, &ResampleSettings::default()), Err(ResampleError::FfmpegUnavailable { .. })));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_ffmpeg_leaves_no_partial_output() -> Result<(), std::io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        // writes half a file to the output path, its last argument, then fails like ffmpeg on bad arguments
+        let fake_ffmpeg = |name: &str, exit_code: u8| -> Result<PathBuf, std::io::Error> {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, format!("#!/bin/sh\nfor last; do :; done\nprintf partial > \"$last\"\nexit {}\n", exit_code))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+            Ok(path)
+        };
+        let library = temp_dir.path().join("music");
+        fs::create_dir(&library)?;
+        let input = library.join("song.flac");
+        fs::write(&input, b"original bytes")?;
+        let cache_dir = temp_dir.path().join("cache");
+        let config = |strategy| ResampleConfig { strategy, music_lib_path: library.clone(), cache_dir: cache_dir.clone(), verify_output: false, ..Default::default() };
+        let failing = || fake_ffmpeg("failing-ffmpeg", 1).map(|path| FfmpegResampler::new_unchecked(path, &ResampleSettings::default()));
+
+        let copying = ResampleService::new(config(ResampleStrategy::CopyToCache), failing()?);
+        let result = copying.resample_file(&high_rate_descriptor(input.clone()));
+
+        assert!(matches!(result, Err(ResampleError::FfmpegResamplerError { .. })));
+        assert_eq!(fs::read_dir(&cache_dir)?.count(), 0, "neither the output nor its temp file should be left");
+
+        let in_place = ResampleService::new(config(ResampleStrategy::InPlace), failing()?);
+        let result = in_place.resample_file(&high_rate_descriptor(input.clone()));
+
+        assert!(matches!(result, Err(ResampleError::FfmpegResamplerError { .. })));
+        assert_eq!(fs::read(&input)?, b"original bytes");
+        assert_eq!(fs::read_dir(&library)?.count(), 1, "the temp file should be cleaned up");
+
+        let working = FfmpegResampler::new_unchecked(fake_ffmpeg("ffmpeg", 0)?, &ResampleSettings::default());
+        let outcome = ResampleService::new(config(ResampleStrategy::CopyToCache), working).resample_file(&high_rate_descriptor(input))
+            .expect("A clean exit should be renamed into place");
+
+        let FileResampleOutcome::Processed { output_path, .. } = outcome else { panic!("The file should be processed") };
+        assert_eq!(fs::read_to_string(&output_path)?, "partial");
+        assert!(!resample_temp_path(&output_path).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_temp_path_keeps_extension_last() {
+        assert_eq!(resample_temp_path(Path::new("music/album/song.flac")), Path::new("music/album/.song.resampling.flac"));
+        assert_eq!(resample_temp_path(Path::new("music/no_extension")), Path::new("music/.no_extension.resampling"));
+
+        assert!(is_resample_temp_file(&resample_temp_path(Path::new("music/album/song.flac"))));
+        assert!(is_resample_temp_file(&resample_temp_path(Path::new("music/no_extension"))));
+        assert!(!is_resample_temp_file(Path::new("music/album/song.resampling.flac")), "only hidden files are temp files");
+        assert!(!is_resample_temp_file(Path::new("music/album/.song.flac")));
+    }
+
+    // on Windows an open file can't be replaced at all, the web layer keeps streams and resamples apart there
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_rename_keeps_open_readers_on_old_file() -> Result<(), ResampleError> {
+        use std::io::Read;
+
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+        fs::create_dir(&cache_dir)?;
+
+        let original = temp_dir.path().join("original.flac");
+        fs::write(&original, b"original bytes")?;
+
+        let config = ResampleConfig { strategy: ResampleStrategy::InPlace, cache_dir, max_threads: Some(1), ..Default::default() };
+        let service = ResampleService::new(config, OverwritingResampler);
+
+        // a stream that started before the resample
+        let mut reader = fs::File::open(&original)?;
+
+        service.resample_file(&high_rate_descriptor(original.clone()))?;
+
+        let mut streamed = Vec::new();
+        reader.read_to_end(&mut streamed)?;
+        assert_eq!(streamed, b"original bytes");
+        assert_eq!(fs::read(&original)?, b"resampled bytes");
+        assert!(!resample_temp_path(&original).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_to_cache_skips_fresh_output() -> Result<(), ResampleError> {
+        let (temp_dir, service, scan_result) = setup(false, ResampleStrategy::CopyToCache)?;
+        let original = &scan_result.descriptors[0].path;
+        let output = temp_dir.path().join(".resampled").join("original.flac");
+
+        assert!(matches!(service.resample_file(&scan_result.descriptors[0])?, FileResampleOutcome::Processed { .. }));
+
+        // the output is only trusted while it is newer than the source
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options().write(true).open(original)?.set_modified(an_hour_ago)?;
+
+        let report = service.resample_library(&scan_result)?;
+        assert!(report.processed_files().is_empty());
+        assert_eq!(report.skipped_files(), [(original.clone(), SkipReason::AlreadyResampled)]);
+
+        fs::File::options().write(true).open(&output)?.set_modified(an_hour_ago - std::time::Duration::from_secs(60))?;
+
+        let report = service.resample_library(&scan_result)?;
+        assert_eq!(report.processed_files(), vec![original.clone()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_place_skips_files_in_manifest() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+        let original = temp_dir.path().join("original.flac");
+        fs::write(&original, b"original bytes")?;
+
+        let config = ResampleConfig { strategy: ResampleStrategy::InPlace, cache_dir: cache_dir.clone(), max_threads: Some(1), ..Default::default() };
+        let scan_result = ScanResult { descriptors: vec![high_rate_descriptor(original.clone())], errors: Vec::new() };
+
+        let report = ResampleService::new(config.clone(), OverwritingResampler).resample_library(&scan_result)?;
+        assert_eq!(report.processed_files(), vec![original.clone()]);
+        assert!(cache_dir.join(IN_PLACE_MANIFEST_NAME).exists());
+
+        // a new service reads the manifest the last run left behind
+        let service = ResampleService::new(config, OverwritingResampler);
+        let report = service.resample_library(&scan_result)?;
+        assert!(report.processed_files().is_empty());
+        assert_eq!(report.skipped_files(), [(original.clone(), SkipReason::AlreadyResampled)]);
+
+        // a file replaced by a new version of the same track gets resampled again
+        fs::write(&original, b"a brand new original")?;
+        let report = service.resample_library(&scan_result)?;
+        assert_eq!(report.processed_files(), vec![original.clone()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ffmpeg_args_follow_target() {
+        let resampler = FfmpegResampler::new_unchecked(PathBuf::from("ffmpeg"), &ResampleSettings::default());
+        let args = |target: &ResampleTarget| resampler.args(Path::new("in.flac"), Path::new("out.mp3"), target);
+
+        let defaults = args(&ResampleConfig::default().target_for(&AudioFileType::Flac).expect("defaults are compatible"));
+        assert_eq!(defaults, ["-loglevel", "error", "-y", "-i", "in.flac", "-ar", "88200", "-c:a", "flac", "out.mp3"]);
+
+        let config = ResampleConfig { output_format: Some(AudioFileType::Mp3), target_sample_rate: Some(44100), target_bitrate_kbps: Some(128), ..Default::default() };
+        let capped = args(&config.target_for(&AudioFileType::Flac).expect("a lossy output takes a bitrate"));
+        assert_eq!(capped, ["-loglevel", "error", "-y", "-i", "in.flac", "-ar", "44100", "-c:a", "mp3", "-b:a", "128k", "out.mp3"]);
+    }
+
+    #[test]
+    fn test_incompatible_options_are_rejected() {
+        let incompatible = |config: ResampleConfig, input: AudioFileType| matches!(config.target_for(&input), Err(ResampleError::IncompatibleOptions(_)));
+
+        assert!(incompatible(ResampleConfig { target_bitrate_kbps: Some(320), ..Default::default() }, AudioFileType::Flac));
+        assert!(incompatible(ResampleConfig { output_format: Some(AudioFileType::Wav), target_bitrate_kbps: Some(320), ..Default::default() }, AudioFileType::Mp3));
+        assert!(incompatible(ResampleConfig { strategy: ResampleStrategy::InPlace, output_format: Some(AudioFileType::Mp3), ..Default::default() }, AudioFileType::Flac));
+        assert!(incompatible(ResampleConfig { output_format: Some(AudioFileType::Unknown), ..Default::default() }, AudioFileType::Flac));
+
+        assert!(!incompatible(ResampleConfig { target_bitrate_kbps: Some(320), ..Default::default() }, AudioFileType::Mp3));
+    }
+
+    #[test]
+    fn test_output_format_changes_cached_extension() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let original = temp_dir.path().join("original.flac");
+        fs::write(&original, b"original bytes")?;
+
+        let config = ResampleConfig { music_lib_path: temp_dir.path().to_path_buf(), cache_dir: temp_dir.path().join(".resampled"), output_format: Some(AudioFileType::Mp3), max_threads: Some(1), ..Default::default() };
+        fs::create_dir(&config.cache_dir)?;
+        let service = ResampleService::new(config, OverwritingResampler);
+
+        let outcome = service.resample_file(&high_rate_descriptor(original))?;
+
+        assert_eq!(outcome, FileResampleOutcome::Processed { output_path: temp_dir.path().join(".resampled").join("original.mp3"), backup_path: None });
+
+        Ok(())
+    }
+
+    /// Counts the resamples running at once and fails the files named `broken.flac`.
+    #[derive(Default)]
+    struct CountingResampler {
+        running: std::sync::atomic::AtomicUsize,
+        peak: std::sync::atomic::AtomicUsize
+    }
+
+    impl Resampler for CountingResampler {
+        fn resample(&self, input_path: &Path, output_path: &Path, _target: &ResampleTarget) -> Result<(), ResampleError> {
+            use std::sync::atomic::Ordering;
+
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            if input_path.ends_with("broken.flac") {
+                return Err(ResampleError::OutputVerificationFailed { path: output_path.to_path_buf(), reason: "broken on purpose".to_string() });
+            }
+            fs::write(output_path, b"resampled bytes")?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_library_pass_respects_thread_cap() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+        fs::create_dir(&cache_dir)?;
+
+        let mut descriptors = Vec::new();
+        for name in ["1.flac", "2.flac", "3.flac", "broken.flac", "5.flac", "6.flac", "7.flac", "8.flac"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, b"original bytes")?;
+            descriptors.push(high_rate_descriptor(path));
+        }
+        let broken = temp_dir.path().join("broken.flac");
+
+        let config = ResampleConfig { cache_dir, max_threads: Some(3), ..Default::default() };
+        let service = ResampleService::new(config, CountingResampler::default());
+
+        let report = service.resample_library(&ScanResult { descriptors, errors: Vec::new() })?;
+
+        let peak = service.resampler.peak.load(std::sync::atomic::Ordering::SeqCst);
+        assert!((1..=3).contains(&peak), "{} resamples ran at once", peak);
+
+        // the failed file is reported under its own path and doesn't stop the others
+        assert_eq!(report.processed_files().len(), 7);
+        assert!(!report.processed_files().contains(&broken));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, broken);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_to_cache_mirrors_the_library() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+
+        let mut descriptors = Vec::new();
+        for album in ["Album A", "Album B"] {
+            let path = temp_dir.path().join(album).join("01 - Intro.flac");
+            fs::create_dir_all(path.parent().expect("has a parent"))?;
+            fs::write(&path, album)?;
+            descriptors.push(high_rate_descriptor(path));
+        }
+
+        let config = ResampleConfig { music_lib_path: temp_dir.path().to_path_buf(), cache_dir: cache_dir.clone(), max_threads: Some(1), ..Default::default() };
+        let report = ResampleService::new(config, OverwritingResampler).resample_library(&ScanResult { descriptors, errors: Vec::new() })?;
+
+        assert_eq!(report.processed_files().len(), 2);
+        assert!(cache_dir.join("Album A").join("01 - Intro.flac").exists());
+        assert!(cache_dir.join("Album B").join("01 - Intro.flac").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_place_saves_the_manifest_once_per_library_pass() -> Result<(), ResampleError> {
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path().join(".resampled");
+        let manifest_path = cache_dir.join(IN_PLACE_MANIFEST_NAME);
+
+        let mut descriptors = Vec::new();
+        for i in 0..3 {
+            let path = temp_dir.path().join(format!("{}.flac", i));
+            fs::write(&path, b"original bytes")?;
+            descriptors.push(high_rate_descriptor(path));
+        }
+
+        let config = ResampleConfig { strategy: ResampleStrategy::InPlace, cache_dir, max_threads: Some(1), ..Default::default() };
+        let service = ResampleService::new(config, OverwritingResampler);
+
+        // nothing is written while the files are being resampled
+        for descriptor in &descriptors {
+            assert!(matches!(service.resample_descriptor(descriptor)?, FileResampleOutcome::Processed { .. }));
+        }
+        assert!(!manifest_path.exists());
+        assert_eq!(service.lock_manifest().unsaved, 3);
+
+        service.save_manifest();
+        assert_eq!(service.lock_manifest().unsaved, 0);
+        assert_eq!(InPlaceManifest::load(&manifest_path).files.len(), 3);
+
+        // a single file resampled on its own is saved right away
+        fs::write(&descriptors[0].path, b"a brand new original")?;
+        service.resample_file(&descriptors[0])?;
+        assert_eq!(service.lock_manifest().unsaved, 0);
+        assert_eq!(InPlaceManifest::load(&manifest_path).files[&descriptors[0].path], b"resampled bytes".len() as u64);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,29 @@
-use std::{ffi::OsStr, fs::File, io::BufReader, path::{Path, PathBuf}};
+use std::{ffi::OsStr, fs::File, io::BufReader, num::NonZeroUsize, path::{Path, PathBuf}, sync::Arc, time::SystemTime};
 
-use lofty::probe::Probe;
+use lofty::{file::TaggedFileExt, probe::Probe};
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::Serialize;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
 use walkdir::WalkDir;
 
-use super::{ScanError};
-use crate::{domain::audiofile::{AudioFileDescriptor, AudioFileMetadata, AudioFileType}, utils::normalizations::normalize_path};
+use super::{resample::is_resample_temp_file, snapshot::{ScanSnapshot, SnapshotDiff, SnapshotEntry}, ScanError};
+use crate::{domain::audiofile::{AudioFileDescriptor, AudioFileMetadata, AudioFileType}, utils::{config::{Config, DEFAULT_IGNORE_MARKER, DEFAULT_IO_CONCURRENCY}, normalizations::{normalize_path, relative_to}}};
 
+#[derive(Clone)]
 pub struct MediaScanner {
     music_lib_path: PathBuf,
+    io_concurrency: usize,
+    concurrency: usize,
+
+    /// Lowercase, without the dot. Empty means `AudioFileType::is_supported_extension`.
+    extensions: Vec<String>,
+
+    /// Stops `scan_music_lib_async` and `scan_stream` between files, see `with_cancellation`.
+    cancel: CancellationToken,
+
+    /// See `with_ignore_marker`, empty turns it off.
+    ignore_marker: String,
 }
 
 impl MediaScanner {
@@ -16,83 +32,449 @@ impl MediaScanner {
     pub fn new<P: AsRef<Path>>(music_path: P) -> Self {
         Self {
             music_lib_path: music_path.as_ref().to_owned(),
+            io_concurrency: DEFAULT_IO_CONCURRENCY,
+            concurrency: std::thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1),
+            extensions: Vec::new(),
+            cancel: CancellationToken::new(),
+            ignore_marker: DEFAULT_IGNORE_MARKER.to_string(),
+        }
+    }
+
+    /// Scans `music_path` from `config`, with the extensions and the ignore marker under [media] and the [scanner] settings.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.media.music_path)
+            .with_io_concurrency(config.scanner.io_concurrency)
+            .with_extensions(&config.media.scan_extensions)
+            .with_ignore_marker(config.media.ignore_marker.clone())
+    }
+
+    /// Skips every directory that holds a file named `marker`, along with its whole subtree.
+    /// `DEFAULT_IGNORE_MARKER` unless set, an empty name scans everything.
+    pub fn with_ignore_marker<S: Into<String>>(mut self, marker: S) -> Self {
+        self.ignore_marker = marker.into();
+        self
+    }
+
+    /// Lets `token` stop `scan_music_lib_async` and `scan_stream` between two files. A cancelled
+    /// `scan_music_lib_async` fails with `ScanError::Cancelled`, `scan_stream` just stops sending.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Only picks up files with one of these extensions, see `scan_extensions` under [media].
+    /// Matched case-insensitively, a leading dot is ignored. An empty list keeps the built-in set.
+    pub fn with_extensions<S: AsRef<str>>(mut self, extensions: &[S]) -> Self {
+        self.extensions = extensions.iter()
+            .map(|ext| ext.as_ref().trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        self
+    }
+
+    /// Bounds how many files are read at once, by `describe_files` as well as by the threads of `scan_music_lib`
+    /// and `scan_dirs`. Zero is treated as one.
+    pub fn with_io_concurrency(mut self, io_concurrency: usize) -> Self {
+        self.io_concurrency = io_concurrency.max(1);
+        self
+    }
+
+    /// How many threads describe files in `scan_music_lib` and `scan_dirs`, at most `io_concurrency` of them.
+    /// Defaults to the number of CPUs, zero is treated as one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn io_concurrency(&self) -> usize {
+        self.io_concurrency
+    }
+
+    /// Threads of the rayon pool, one file read per thread.
+    fn scan_threads(&self) -> usize {
+        self.concurrency.min(self.io_concurrency)
+    }
+
+    /// Describes `paths` on the blocking pool, with at most `io_concurrency` files in flight.
+    ///
+    /// Results come back in the same order as `paths`, each paired with its path so failures can be reported.
+    pub async fn describe_files(&self, paths: Vec<PathBuf>) -> Vec<(PathBuf, Result<AudioFileDescriptor, std::io::Error>)> {
+        let semaphore = Arc::new(Semaphore::new(self.io_concurrency));
+        let mut tasks = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let scanner = self.clone();
+            let semaphore = Arc::clone(&semaphore);
+
+            tasks.push(tokio::spawn(async move {
+                // the semaphore is never closed, so acquiring can't fail
+                let _permit = semaphore.acquire_owned().await.expect("Scanner semaphore was closed");
+
+                // files still waiting for a permit once the scan is cancelled are left out
+                if scanner.cancel.is_cancelled() {
+                    return None;
+                }
+
+                Some(tokio::task::spawn_blocking(move || {
+                    let result = scanner.describe_file(&path);
+                    (path, result)
+                }).await)
+            }));
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            match task.await {
+                Ok(Some(Ok(result))) => results.push(result),
+                Ok(None) => {},
+                Ok(Some(Err(join_err))) | Err(join_err) => log::error!("Describing a file has panicked: {}", join_err)
+            }
         }
+
+        results
     }
 
-    // right now this function is synchronous, which is not ideal
-    // TODO: make it async with tokio::fs
+    /// Blocks the calling thread for the whole scan, meant for the CLI. Async callers want `scan_music_lib_async`.
     pub fn scan_music_lib(&self) -> Result<ScanResult, ScanError> {
+        self.scan_music_lib_with_progress(|_| {})
+    }
 
-        // A quick check to fail fast if the root directory is inaccessible.
-        // The error here is fatal and will halt the scan.
-        std::fs::read_dir(&self.music_lib_path)
+    /// `scan_music_lib` that reports every file along the way, for callers that draw their own progress.
+    ///
+    /// `on_progress` runs on the calling thread: once for each file the walk comes across, audio or not,
+    /// then once for each audio file that has been described. See `ScanProgress`.
+    pub fn scan_music_lib_with_progress<F: FnMut(ScanProgress)>(&self, on_progress: F) -> Result<ScanResult, ScanError> {
+        self.scan_dir(&self.music_lib_path, on_progress)
+    }
+
+    /// Same scan as `scan_music_lib`, without blocking the runtime: the walk runs on the blocking pool and
+    /// the files are then described through `describe_files`, `io_concurrency` at a time.
+    ///
+    /// An inaccessible root still fails with `RootDirAccessError`. Soft errors are collected as usual, walk
+    /// errors first, then the files that couldn't be described.
+    pub async fn scan_music_lib_async(&self) -> Result<ScanResult, ScanError> {
+        let scanner = self.clone();
+        let (paths, mut errors) = tokio::task::spawn_blocking(move || {
+            check_root(&scanner.music_lib_path)?;
+            let (mut paths, mut errors) = (Vec::new(), Vec::new());
+
+            for entry_result in scanner.walk(&scanner.music_lib_path) {
+                if scanner.cancel.is_cancelled() {
+                    break;
+                }
+
+                match scanner.walk_entry(entry_result) {
+                    Some(Ok(path)) => paths.push(path),
+                    Some(Err(err)) => errors.push(err),
+                    None => continue
+                }
+            }
+
+            Ok::<_, ScanError>((paths, errors))
+        }).await??;
+
+        let described = self.describe_files(paths).await;
+        if self.cancel.is_cancelled() {
+            return Err(ScanError::Cancelled);
+        }
+
+        let mut descriptors = Vec::with_capacity(described.len());
+        for (path, result) in described {
+            match result {
+                Ok(descriptor) => descriptors.push(descriptor),
+                Err(err) => errors.push(self.file_error(&path, err))
+            }
+        }
+
+        Ok(ScanResult { descriptors, errors })
+    }
+
+    /// Scans only the given directories, e.g. two album folders, and combines the results.
+    ///
+    /// Directories are scanned one after another; an inaccessible one fails the whole scan.
+    /// Overlapping directories are not deduplicated, a file under both shows up twice.
+    pub fn scan_dirs<P: AsRef<Path>>(&self, dirs: &[P]) -> Result<ScanResult, ScanError> {
+        dirs.iter().try_fold(ScanResult::new(), |scan_result, dir| {
+            Ok(scan_result.merge(self.scan_dir(dir.as_ref(), |_| {})?))
+        })
+    }
+
+    /// Scans only the files listed in an M3U playlist, for a library curated by hand.
+    ///
+    /// Entries are resolved against the playlist's directory unless they are absolute; comments
+    /// (`#EXTM3U`, `#EXTINF` and such) and blank lines are skipped. Missing entries and entries
+    /// outside of the music library are soft errors. Descriptors get paths under the music
+    /// library, the same ones a directory scan would give them.
+    pub fn scan_from_playlist(&self, playlist_path: &Path) -> Result<ScanResult, ScanError> {
+        let playlist = std::fs::read_to_string(playlist_path)
+            .map_err(|source| ScanError::PlaylistReadError { path: playlist_path.to_path_buf(), source })?;
+
+        let library_root = self.music_lib_path.canonicalize()
             .map_err(|e| ScanError::RootDirAccessError {
                 path: self.music_lib_path.display().to_string(),
                 source: e,
             })?;
+        let playlist_dir = playlist_path.parent().unwrap_or(Path::new(""));
 
-        let walker = WalkDir::new(&self.music_lib_path).min_depth(1);
         let mut scan_result = ScanResult::new();
-        
-        // Iterate over every file and directory.
-        // Errors encountered here are soft and being collected to return alongside with the successful results.
-        for entry_result in walker {
-            
-            match entry_result {
-                Err(err) => {
-                    scan_result.errors.push(ScanError::WalkdirError(err));
-                },
-                Ok(dir_entry) => {
-                    let path = dir_entry.path();
-
-                    if path.is_dir() || path.is_symlink() {
-                        log::warn!("Skipping {:?} since its either dir or symlink.", path);
-                        continue;
-                    }
-
-                    if !self.is_audio_file(path) {
-                        log::warn!("Skipping file with unsupported extension: {}", self.prettify_path(&path));
-                        continue;
-                    }
-
-                    match self.process_file(path) {
-                        Ok(descriptor) => {
-                            scan_result.descriptors.push(descriptor);
-                        },
-                        Err(err) => {
-                            log::warn!("Skipping file {}: {}", self.prettify_path(&path), err);
-                            scan_result.errors.push(ScanError::IOError(err));
-                            continue;
-                        }
-                    }
 
+        let entries = playlist.lines()
+            .map(|line| line.trim_start_matches('\u{feff}').trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        for entry in entries {
+            let entry_path = playlist_dir.join(entry);
+
+            let Ok(canonical_path) = entry_path.canonicalize() else {
+                scan_result.errors.push(ScanError::PlaylistEntryMissing(entry_path));
+                continue;
+            };
+
+            // canonical paths, so `..` and symlinks can't sneak a file in from elsewhere
+            let Ok(relative_path) = canonical_path.strip_prefix(&library_root) else {
+                scan_result.errors.push(ScanError::PlaylistEntryOutsideLibrary(entry_path));
+                continue;
+            };
+            let path = self.music_lib_path.join(relative_path);
+
+            if !self.is_audio_file(&path) {
+                log::warn!("Skipping playlist entry with unsupported extension: {}", self.prettify_path(&path));
+                continue;
+            }
+
+            match self.describe_file(&path) {
+                Ok(descriptor) => scan_result.descriptors.push(descriptor),
+                Err(err) => scan_result.errors.push(self.file_error(&path, err))
+            }
+        }
+
+        Ok(scan_result)
+    }
+
+    /// Scans the music library on the blocking pool and hands the descriptors over as they are read.
+    ///
+    /// At most `capacity` of them wait in the channel, the walk stalls until the receiver catches up.
+    /// This lets the receiver work on the first files while the rest are still being probed.
+    /// An inaccessible root fails right away; soft errors come through the channel, in the same
+    /// order `scan_music_lib` would collect them. Dropping the receiver stops the walk.
+    pub fn scan_stream(&self, capacity: usize) -> Result<mpsc::Receiver<Result<AudioFileDescriptor, ScanError>>, ScanError> {
+        check_root(&self.music_lib_path)?;
+
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let scanner = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            for entry_result in scanner.walk(&scanner.music_lib_path) {
+                if scanner.cancel.is_cancelled() {
+                    break;
+                }
+
+                if let Some(scanned) = scanner.scan_entry(entry_result)
+                    && sender.blocking_send(scanned).is_err() {
+                    break;
                 }
             }
+        });
+
+        Ok(receiver)
+    }
+
+    fn scan_dir<F: FnMut(ScanProgress)>(&self, root: &Path, mut on_progress: F) -> Result<ScanResult, ScanError> {
+
+        // A quick check to fail fast if the root directory is inaccessible.
+        // The error here is fatal and will halt the scan.
+        check_root(root)?;
+
+        let mut scan_result = ScanResult::new();
+        let (mut files_seen, mut files_processed) = (0, 0);
+
+        // The walk itself is quick, the files are collected first and described in parallel afterwards.
+        // Errors encountered here are soft and being collected to return alongside with the successful results.
+        let mut paths = Vec::new();
+        for entry_result in self.walk(root) {
+            let seen_path = match &entry_result {
+                Ok(entry) if !entry.file_type().is_dir() => Some(entry.path().to_path_buf()),
+                _ => None
+            };
+
+            match self.walk_entry(entry_result) {
+                Some(Ok(path)) => paths.push(path),
+                Some(Err(err)) => scan_result.errors.push(err),
+                None => {}
+            }
+
+            if let Some(current_path) = seen_path {
+                files_seen += 1;
+                on_progress(ScanProgress { files_seen, audio_files_found: paths.len(), files_processed, current_path });
+            }
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.scan_threads())
+            .build()?;
+
+        // the workers hand every finished path back, so `on_progress` doesn't have to be Send or Sync
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let described: Vec<Result<AudioFileDescriptor, ScanError>> = std::thread::scope(|scope| {
+            let workers = scope.spawn(|| pool.install(|| {
+                paths
+                    .par_iter()
+                    .map_with(sender, |sender, path| {
+                        let result = self.describe_file(path).map_err(|err| self.file_error(path, err));
+                        let _ = sender.send(path.clone());
+                        result
+                    })
+                    .collect()
+            }));
+
+            // ends once every worker is done and has dropped its sender
+            for current_path in receiver {
+                files_processed += 1;
+                on_progress(ScanProgress { files_seen, audio_files_found: paths.len(), files_processed, current_path });
+            }
+
+            workers.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+
+        for result in described {
+            match result {
+                Ok(descriptor) => scan_result.descriptors.push(descriptor),
+                Err(err) => scan_result.errors.push(err)
+            }
         }
 
         Ok(scan_result)
     }
 
+    /// Describes one walked entry. `None` for anything that isn't an audio file, i.e. dirs,
+    /// symlinks and unsupported extensions.
+    fn scan_entry(&self, entry_result: Result<walkdir::DirEntry, walkdir::Error>) -> Option<Result<AudioFileDescriptor, ScanError>> {
+        let path = match self.walk_entry(entry_result)? {
+            Ok(path) => path,
+            Err(err) => return Some(Err(err))
+        };
+
+        Some(self.describe_file(&path).map_err(|err| self.file_error(&path, err)))
+    }
+
+    fn file_error(&self, path: &Path, err: std::io::Error) -> ScanError {
+        log::warn!("Skipping file {}: {}", self.prettify_path(path), err);
+        ScanError::FileReadError { path: path.to_path_buf(), source: err }
+    }
+
+    /// Everything under `root`, minus the directories holding the ignore marker and their contents.
+    fn walk(&self, root: &Path) -> impl Iterator<Item = Result<walkdir::DirEntry, walkdir::Error>> + '_ {
+        WalkDir::new(root).min_depth(1).into_iter().filter_entry(|entry| !self.is_ignored_dir(entry))
+    }
+
+    fn is_ignored_dir(&self, entry: &walkdir::DirEntry) -> bool {
+        if self.ignore_marker.is_empty() || !entry.file_type().is_dir() {
+            return false;
+        }
+
+        let ignored = entry.path().join(&self.ignore_marker).is_file();
+        if ignored {
+            log::info!("Skipping {} since it holds {}", self.prettify_path(entry.path()), self.ignore_marker);
+        }
+
+        ignored
+    }
+
+    /// Path of a walked entry that should be described, `None` for the ones `scan_entry` skips.
+    fn walk_entry(&self, entry_result: Result<walkdir::DirEntry, walkdir::Error>) -> Option<Result<PathBuf, ScanError>> {
+        let dir_entry = match entry_result {
+            Ok(dir_entry) => dir_entry,
+            Err(err) => return Some(Err(ScanError::WalkdirError(err)))
+        };
+        let path = dir_entry.path();
+
+        if path.is_dir() || path.is_symlink() {
+            log::warn!("Skipping {:?} since its either dir or symlink.", path);
+            return None;
+        }
+
+        if !self.is_audio_file(path) {
+            log::warn!("Skipping file with unsupported extension: {}", self.prettify_path(path));
+            return None;
+        }
+
+        Some(Ok(dir_entry.into_path()))
+    }
+
+    /// Walks the library and records size and mtime of every audio file, without reading any tags.
+    pub fn snapshot(&self) -> Result<ScanSnapshot, ScanError> {
+        check_root(&self.music_lib_path)?;
+
+        let mut snapshot = ScanSnapshot::new();
+
+        for entry_result in self.walk(&self.music_lib_path) {
+            let dir_entry = match entry_result {
+                Ok(dir_entry) => dir_entry,
+                Err(err) => {
+                    log::warn!("Skipping entry while taking a snapshot: {}", err);
+                    continue;
+                }
+            };
+
+            let path = dir_entry.path();
+            if !dir_entry.file_type().is_file() || !self.is_audio_file(path) {
+                continue;
+            }
+
+            match dir_entry.metadata() {
+                Ok(metadata) => {
+                    snapshot.files.insert(normalize_path(path), SnapshotEntry::from_fs_metadata(&metadata));
+                },
+                Err(err) => log::warn!("Failed to read metadata for {}: {}", self.prettify_path(path), err)
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Takes a fresh snapshot and compares it against the one stored at `snapshot_path`.
+    pub fn diff_against_snapshot(&self, snapshot_path: &Path) -> Result<SnapshotDiff, ScanError> {
+        let previous = ScanSnapshot::read_from(snapshot_path)?;
+        let current = self.snapshot()?;
+
+        Ok(current.diff(&previous))
+    }
+
+    /// A resample still being written isn't a track yet, even if it has a track's extension.
     fn is_audio_file(&self, path: &Path) -> bool {
-        path.extension()
-            .map(|ext| AudioFileType::is_supported_extension(ext))
-            .unwrap_or(false)
+        let Some(ext) = path.extension() else {
+            return false;
+        };
+        if is_resample_temp_file(path) {
+            return false;
+        }
+
+        if self.extensions.is_empty() {
+            return AudioFileType::is_supported_extension(ext);
+        }
+
+        let ext = ext.to_string_lossy().to_lowercase();
+        self.extensions.contains(&ext)
     }
 
-    fn process_file(&self, path: &Path) -> Result<AudioFileDescriptor, std::io::Error> {
+    /// Opens a single file and builds its `AudioFileDescriptor`.
+    ///
+    /// Probe or tag failures are soft: the type falls back to the extension and
+    /// the metadata to defaults. Only failing to open the file is an error.
+    pub fn describe_file(&self, path: &Path) -> Result<AudioFileDescriptor, std::io::Error> {
         // file access denied error propagating here, below, when you try to open the file
         let file = File::open(path)?;
         
-        let file_size = match file.metadata() {
-            Ok(metadata) => metadata.len(),
+        let (file_size, modified) = match file.metadata() {
+            Ok(metadata) => (metadata.len(), metadata.modified().ok()),
             Err(err) => {
                 log::warn!("Failed to access metadata for {}: {}. Setting file_size to 0.", self.prettify_path(&path), err);
-                0u64
+                (0u64, None)
             }
         };
         
         let reader = BufReader::new(file);
-        Ok(self.make_descriptor(path, file_size, reader))
+        Ok(self.make_descriptor(path, file_size, modified, reader))
     }
 
     fn type_from_ext(&self, path: &Path) -> AudioFileType {
@@ -105,7 +487,9 @@ impl MediaScanner {
         AudioFileType::from_os_ext(extension)
     }
 
-    fn extract_type_and_metadata(&self, path: &Path, reader: &mut BufReader<File>) -> (AudioFileType, AudioFileMetadata) {
+    /// The returned flag tells whether the metadata was actually read from the file's tags,
+    /// `false` means it's the defaults (probe failed, tags unreadable or missing).
+    fn extract_type_and_metadata(&self, path: &Path, reader: &mut BufReader<File>) -> (AudioFileType, AudioFileMetadata, bool) {
         match Probe::new(reader).guess_file_type() {
             Ok(probe) => {
 
@@ -115,41 +499,72 @@ impl MediaScanner {
                     .unwrap_or_else(|| self.type_from_ext(path));
                 
                 // if probe.read() fails, then metadata falls back to default values
-                let metadata = AudioFileMetadata::extract_or_default(probe.read());
+                let tagged_result = probe.read();
+                let probe_ok = tagged_result.as_ref()
+                    .is_ok_and(|tagged| tagged.primary_tag().or_else(|| tagged.first_tag()).is_some());
+                let metadata = AudioFileMetadata::extract_or_default(tagged_result);
                 
-                (file_type, metadata)
+                (file_type, metadata, probe_ok)
             },
             Err(err) => {
                 // if probe has failed, we fall back to default values
                 log::warn!("Failed to probe {}: {}", self.prettify_path(&path), err);
-                (self.type_from_ext(path), AudioFileMetadata::default())
+                (self.type_from_ext(path), AudioFileMetadata::default(), false)
             }
         }
     }
 
-    fn make_descriptor(&self, path: &Path, file_size: u64, mut reader: BufReader<File>) -> AudioFileDescriptor {
-        let (file_type, metadata) = self.extract_type_and_metadata(path, &mut reader);
+    fn make_descriptor(&self, path: &Path, file_size: u64, modified: Option<SystemTime>, mut reader: BufReader<File>) -> AudioFileDescriptor {
+        let (file_type, metadata, probe_ok) = self.extract_type_and_metadata(path, &mut reader);
+        // default metadata would make every unreadable file a duplicate of the others
+        let content_hash = probe_ok.then(|| metadata.fingerprint());
     
         AudioFileDescriptor {
             path: normalize_path(path),
             file_size,
+            modified,
             file_type,
-            metadata
+            metadata,
+            probe_ok,
+            content_hash
         }
 
     }
 
     fn prettify_path(&self, path: &Path) -> String {
-        let base_dir = &self.music_lib_path;
-    
-        path.strip_prefix(&base_dir)
-            .map(|path_suffix| {
-                format!("./{}", path_suffix.display())
-            })
-            .unwrap_or_else(|_| path.to_path_buf().to_string_lossy().to_string())
+        relative_to(path, &self.music_lib_path)
+            .map(|relative| format!("./{}", relative.display()))
+            .unwrap_or_else(|| path.display().to_string())
     }
 }
 
+/// Fails fast if the directory to scan can't be read at all, unlike the soft errors collected while walking it.
+fn check_root(root: &Path) -> Result<(), ScanError> {
+    std::fs::read_dir(root)
+        .map(|_| ())
+        .map_err(|e| ScanError::RootDirAccessError {
+            path: root.display().to_string(),
+            source: e,
+        })
+}
+
+/// Where a scan is at, handed to the callback of `scan_music_lib_with_progress`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanProgress {
+    /// Files the walk has come across so far, including the ones that aren't audio.
+    pub files_seen: usize,
+
+    /// Audio files among `files_seen`, final once the walk is over. The total `files_processed` counts up to.
+    pub audio_files_found: usize,
+
+    /// Audio files described so far, successfully or not. Only starts growing once the walk is over,
+    /// at which point `files_seen` is final.
+    pub files_processed: usize,
+
+    /// The file this update is about.
+    pub current_path: PathBuf
+}
+
 #[derive(Debug)]
 pub struct ScanResult {
     pub descriptors: Vec<AudioFileDescriptor>,
@@ -163,20 +578,43 @@ impl ScanResult {
             errors: Vec::new()
         }
     }
+
+    /// Combines two scans: descriptors and errors of `other` are appended to ours.
+    pub fn merge(mut self, other: ScanResult) -> ScanResult {
+        self.descriptors.extend(other.descriptors);
+        self.errors.extend(other.errors);
+
+        self
+    }
+}
+
+/// Response schema of `/api/scan/preview`: the raw scan data, with errors flattened into messages.
+#[derive(Debug, Serialize)]
+pub struct ScanPreview {
+    pub descriptors: Vec<AudioFileDescriptor>,
+    pub errors: Vec<String>
+}
+
+impl From<ScanResult> for ScanPreview {
+    fn from(scan_result: ScanResult) -> Self {
+        Self {
+            descriptors: scan_result.descriptors,
+            errors: scan_result.errors.iter().map(|err| err.to_string()).collect()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashSet, fs, os::windows::fs::{symlink_dir, symlink_file}, path::{Path, PathBuf}};
+    use std::{collections::HashSet, fs, os::windows::fs::{symlink_dir, symlink_file}, path::PathBuf};
 
     use tempfile::{tempdir_in, TempDir};
 
-    use crate::{services::test_helpers::*, utils::audio_fixtures::{load_fixtures, AudioFixture}};
+    use crate::services::test_helpers::*;
     use super::*;
 
     struct TestContext {
         temp_dir: TempDir,
-        vault: Vec<AudioFixture>,
         fixtures: Vec<PathBuf>
     }
 
@@ -185,35 +623,13 @@ mod tests {
             Ok(
                 Self {
                     temp_dir: tempfile::tempdir()?,
-                    vault: load_fixtures(&Path::new(TEST_FIXTURES_JSON_PATH))?,
                     fixtures: Vec::new()
                 }
             )
         }
 
-        fn normalize_path(&self, path: &Path) -> PathBuf {
-            path.to_string_lossy()
-                .to_lowercase()
-                .replace('\\', "/")
-                .into()
-        }
-
         fn with_fixtures(mut self, fixture_file_names: &[FixtureFileNames]) -> Result<Self, TestSetupError> {
-            let fixture_file_names = fixture_file_names.into_iter().map(|ffm| ffm.file_name()).collect::<Vec<_>>();
-            let selected = self.vault.iter().filter(|fxtr| fixture_file_names.contains(&fxtr.file_name));
-
-            let mut new_paths = Vec::new();
-
-            for src in selected {
-                // safe unwrap since it was already unwrapped in the loop above
-                let dest = self.temp_dir.path().join(&src.file_name);
-                let src = PathBuf::from(format!("./test_fixtures/files/{}", &src.file_name));
-
-                fs::copy(&src, &dest)?;
-                new_paths.push(self.normalize_path(&dest));
-            }
-
-            self.fixtures = new_paths;
+            self.fixtures = copy_fixtures(self.temp_dir.path(), fixture_file_names)?;
             Ok(self)
         }
     }
@@ -329,6 +745,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_scan_shallow_ogg_and_m4a_files() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        for (ext, expected) in [("ogg", AudioFileType::Ogg), ("m4a", AudioFileType::M4a)] {
+            let ctx = TestContext::new().await?;
+            let _temp_files = create_temp_files(ctx.temp_dir.path(), 1, ext)?;
+
+            let scan_result = MediaScanner::new(ctx.temp_dir.path()).scan_music_lib()?;
+
+            assert_eq!(scan_result.descriptors.len(), 1);
+            assert_eq!(scan_result.descriptors[0].file_type, expected);
+            assert_no_metadata(&scan_result.descriptors[0].metadata);
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn tests_scan_vaild_mp3_file() -> Result<(), TestSetupError> {
         init_logger()?;
@@ -666,4 +1100,326 @@ mod tests {
         //     Ok(())
         // }
     }
+
+    #[tokio::test]
+    async fn test_describe_files_bounded() -> Result<(), TestSetupError> {
+        let temp_dir = tempfile::tempdir()?;
+        let files = create_temp_files(temp_dir.path(), 6, "mp3")?;
+        let paths = files.iter().map(|file| file.path().to_path_buf()).collect::<Vec<_>>();
+
+        let scanner = MediaScanner::new(temp_dir.path()).with_io_concurrency(2);
+        let results = scanner.describe_files(paths.clone()).await;
+
+        let result_paths = results.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>();
+        assert_eq!(result_paths, paths);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        let missing = temp_dir.path().join("missing.mp3");
+        let results = scanner.describe_files(vec![missing.clone()]).await;
+        assert!(matches!(&results[..], [(path, Err(_))] if path == &missing));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_io_concurrency_is_clamped() {
+        let scanner = MediaScanner::new("./whatever").with_io_concurrency(0);
+        assert_eq!(scanner.io_concurrency(), 1);
+        assert_eq!(scanner.scan_threads(), 1);
+
+        let scanner = MediaScanner::new("./whatever").with_concurrency(16).with_io_concurrency(2);
+        assert_eq!(scanner.scan_threads(), 2, "the rayon pool reads no more files at once than io_concurrency allows");
+    }
+
+    #[tokio::test]
+    async fn test_describe_file_captures_mtime() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let file_path = ctx.temp_dir.path().join("track.mp3");
+        fs::write(&file_path, "dummy data")?;
+
+        let descriptor = MediaScanner::new(ctx.temp_dir.path()).describe_file(&file_path)?;
+
+        assert_eq!(descriptor.modified, Some(fs::metadata(&file_path)?.modified()?));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_tags_are_flagged() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let file_path = ctx.temp_dir.path().join("garbage.mp3");
+        fs::write(&file_path, "dummy data")?;
+
+        let descriptor = MediaScanner::new(ctx.temp_dir.path()).describe_file(&file_path)?;
+
+        assert!(!descriptor.probe_ok);
+        assert!(descriptor.content_hash.is_none());
+        assert_eq!(descriptor.metadata, AudioFileMetadata::default());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scan_dirs_merges_results() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let first = ctx.temp_dir.path().join("first");
+        let second = ctx.temp_dir.path().join("second");
+        let skipped = ctx.temp_dir.path().join("skipped");
+
+        for (dir, file) in [(&first, "a.mp3"), (&second, "b.mp3"), (&skipped, "c.mp3")] {
+            fs::create_dir(dir)?;
+            fs::write(dir.join(file), "dummy data")?;
+        }
+
+        let scanner = MediaScanner::new(ctx.temp_dir.path());
+        let scan_result = scanner.scan_dirs(&[&first, &second])?;
+
+        let names: HashSet<String> = scan_result.descriptors.iter()
+            .map(|descriptor| descriptor.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, HashSet::from(["a.mp3".to_string(), "b.mp3".to_string()]));
+
+        let missing = ctx.temp_dir.path().join("missing");
+        assert!(matches!(scanner.scan_dirs(&[&first, &missing]), Err(ScanError::RootDirAccessError { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_result_merge_appends() {
+        let first = ScanResult { descriptors: Vec::new(), errors: vec![ScanError::IOError(std::io::Error::other("first"))] };
+        let second = ScanResult { descriptors: Vec::new(), errors: vec![ScanError::IOError(std::io::Error::other("second"))] };
+
+        let merged = first.merge(second);
+
+        let messages: Vec<String> = merged.errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(messages, vec!["first".to_string(), "second".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_from_playlist() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let outside_dir = tempfile::tempdir()?;
+
+        let album_dir = ctx.temp_dir.path().join("album");
+        fs::create_dir(&album_dir)?;
+        fs::write(album_dir.join("listed.mp3"), "dummy data")?;
+        fs::write(album_dir.join("not_listed.mp3"), "dummy data")?;
+        let outside_file = outside_dir.path().join("outside.mp3");
+        fs::write(&outside_file, "dummy data")?;
+
+        let playlist_path = ctx.temp_dir.path().join("library.m3u");
+        fs::write(&playlist_path, format!(
+            "#EXTM3U\n#EXTINF:123,Some Artist - Listed\nalbum/listed.mp3\n\nalbum/missing.mp3\n{}\n",
+            outside_file.display()
+        ))?;
+
+        let scan_result = MediaScanner::new(ctx.temp_dir.path()).scan_from_playlist(&playlist_path)?;
+
+        let paths = scan_result.descriptors.iter().map(|descriptor| descriptor.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, vec![normalize_path(&album_dir.join("listed.mp3"))]);
+
+        assert_eq!(scan_result.errors.len(), 2);
+        assert!(matches!(&scan_result.errors[0], ScanError::PlaylistEntryMissing(path) if path.ends_with("album/missing.mp3")));
+        assert!(matches!(&scan_result.errors[1], ScanError::PlaylistEntryOutsideLibrary(path) if path == &outside_file));
+
+        let missing_playlist = MediaScanner::new(ctx.temp_dir.path()).scan_from_playlist(&ctx.temp_dir.path().join("nope.m3u"));
+        assert!(matches!(missing_playlist, Err(ScanError::PlaylistReadError { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_pre_count() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_dir = ctx.temp_dir.path().join("album");
+        fs::create_dir(&album_dir)?;
+        fs::write(album_dir.join("a.mp3"), "dummy data")?;
+        fs::write(album_dir.join("b.flac"), "dummy data")?;
+        fs::write(album_dir.join("cover.jpg"), "dummy data")?;
+        fs::write(ctx.temp_dir.path().join("c.wav"), "dummy data")?;
+
+        let scanner = MediaScanner::new(ctx.temp_dir.path());
+
+        // the audio files are counted by the walk, before any of them is described
+        let mut updates = Vec::new();
+        let with_progress = scanner.scan_music_lib_with_progress(|progress| updates.push(progress))?;
+        let without_progress = scanner.scan_music_lib()?;
+        assert!(updates.iter().filter(|p| p.files_processed > 0).all(|p| p.audio_files_found == 3));
+        assert_eq!(updates.last().map(|p| p.files_processed), Some(3));
+        assert_eq!(with_progress.descriptors.len(), 3);
+        assert_eq!(with_progress.descriptors.len(), without_progress.descriptors.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_scan_matches_sync_scan() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let nested = ctx.temp_dir.path().join("nested");
+        fs::create_dir(&nested)?;
+        let _mp3 = create_temp_files(ctx.temp_dir.path(), 2, "mp3")?;
+        let _flac = create_temp_files(&nested, 1, "flac")?;
+        let _txt = create_temp_files(&nested, 1, "txt")?;
+
+        let scanner = MediaScanner::new(ctx.temp_dir.path());
+        let sync_result = scanner.scan_music_lib()?;
+        let async_result = scanner.scan_music_lib_async().await?;
+
+        let paths = |scan_result: &ScanResult| scan_result.descriptors.iter().map(|d| d.path.clone()).collect::<HashSet<_>>();
+        assert_eq!(async_result.descriptors.len(), 3);
+        assert_eq!(paths(&async_result), paths(&sync_result));
+        assert_eq!(async_result.errors.len(), sync_result.errors.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_scan_path_doesnt_exist() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let scanner = MediaScanner::new(PathBuf::from("C:/path/doesnt/exist"));
+        assert!(matches!(scanner.scan_music_lib_async().await, Err(ScanError::RootDirAccessError { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_scan_matches_single_threaded() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for dir in ["a", "b", "c"] {
+            let album_dir = ctx.temp_dir.path().join(dir);
+            fs::create_dir(&album_dir)?;
+            for idx in 0..5 {
+                fs::write(album_dir.join(format!("{}.mp3", idx)), "dummy data")?;
+            }
+        }
+
+        let paths = |scan_result: ScanResult| scan_result.descriptors.into_iter().map(|d| d.path).collect::<HashSet<_>>();
+        let single = MediaScanner::new(ctx.temp_dir.path()).with_concurrency(0).scan_music_lib()?;
+        let parallel = MediaScanner::new(ctx.temp_dir.path()).with_concurrency(4).scan_music_lib()?;
+
+        assert_eq!(single.descriptors.len(), 15);
+        assert!(parallel.errors.is_empty());
+        assert_eq!(paths(single), paths(parallel));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_error_keeps_path() {
+        let scanner = MediaScanner::new("music");
+        let err = scanner.file_error(Path::new("music/broken.flac"), std::io::Error::other("boom"));
+
+        assert!(matches!(err, ScanError::FileReadError { ref path, .. } if path == Path::new("music/broken.flac")));
+    }
+
+    #[tokio::test]
+    async fn test_progress_callback_counts_every_file() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let album_dir = ctx.temp_dir.path().join("album");
+        fs::create_dir(&album_dir)?;
+        fs::write(album_dir.join("a.mp3"), "dummy data")?;
+        fs::write(album_dir.join("b.flac"), "dummy data")?;
+        fs::write(album_dir.join("cover.jpg"), "dummy data")?;
+
+        let mut updates = Vec::new();
+        let scan_result = MediaScanner::new(ctx.temp_dir.path()).scan_music_lib_with_progress(|progress| updates.push(progress))?;
+
+        assert_eq!(scan_result.descriptors.len(), 2);
+        // three files walked, the cover included, then two described
+        assert_eq!(updates.len(), 5);
+        assert_eq!(updates.iter().map(|p| p.files_seen).collect::<Vec<_>>(), vec![1, 2, 3, 3, 3]);
+        assert_eq!(updates.last().map(|p| p.audio_files_found), Some(2));
+        assert_eq!(updates.iter().map(|p| p.files_processed).collect::<Vec<_>>(), vec![0, 0, 0, 1, 2]);
+        assert!(updates.iter().any(|p| p.current_path.ends_with("cover.jpg")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_configured_extensions() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        for file_name in ["a.mp3", "b.OGG", "c.m4a", "d.flac", "e.opus"] {
+            fs::write(ctx.temp_dir.path().join(file_name), "dummy data")?;
+        }
+
+        let extensions = |scanner: MediaScanner| -> Result<HashSet<String>, TestSetupError> {
+            Ok(scanner.scan_music_lib()?.descriptors.iter()
+                .map(|d| d.path.extension().unwrap().to_string_lossy().to_lowercase())
+                .collect())
+        };
+
+        let built_in = extensions(MediaScanner::new(ctx.temp_dir.path()).with_extensions::<&str>(&[]))?;
+        assert_eq!(built_in, HashSet::from(["mp3".to_string(), "ogg".to_string(), "m4a".to_string(), "flac".to_string()]));
+
+        let configured = extensions(MediaScanner::new(ctx.temp_dir.path()).with_extensions(&["opus", ".M4A", "mp3"]))?;
+        assert_eq!(configured, HashSet::from(["mp3".to_string(), "opus".to_string(), "m4a".to_string()]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_folders_with_ignore_marker_are_skipped() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let root = ctx.temp_dir.path();
+        fs::create_dir_all(root.join("to sort/deeper"))?;
+        fs::create_dir_all(root.join("sorted"))?;
+        fs::write(root.join("to sort").join(DEFAULT_IGNORE_MARKER), "")?;
+        for file in ["to sort/a.mp3", "to sort/deeper/b.mp3", "sorted/c.mp3"] {
+            fs::write(root.join(file), "dummy data")?;
+        }
+
+        let names = |descriptors: Vec<AudioFileDescriptor>| descriptors.iter()
+            .map(|d| d.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<HashSet<_>>();
+
+        let scanner = MediaScanner::new(root);
+        assert_eq!(names(scanner.scan_music_lib()?.descriptors), HashSet::from(["c.mp3".to_string()]));
+        assert_eq!(names(scanner.scan_music_lib_async().await?.descriptors), HashSet::from(["c.mp3".to_string()]));
+
+        let everything = MediaScanner::new(root).with_ignore_marker("").scan_music_lib()?.descriptors;
+        assert_eq!(names(everything).len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resamples_being_written_are_skipped() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new().await?;
+        let root = ctx.temp_dir.path();
+        for file in ["song.mp3", ".song.resampling.mp3"] {
+            fs::write(root.join(file), "dummy data")?;
+        }
+
+        let scanner = MediaScanner::new(root);
+        let names = scanner.scan_music_lib_async().await?.descriptors.iter()
+            .map(|d| d.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["song.mp3"]);
+        assert_eq!(scanner.snapshot()?.files.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_scan_stops_once_cancelled() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let _mp3 = create_temp_files(ctx.temp_dir.path(), 3, "mp3")?;
+
+        let token = CancellationToken::new();
+        let scanner = MediaScanner::new(ctx.temp_dir.path()).with_cancellation(token.clone());
+        assert_eq!(scanner.scan_music_lib_async().await?.descriptors.len(), 3);
+
+        token.cancel();
+        assert!(matches!(scanner.scan_music_lib_async().await, Err(ScanError::Cancelled)));
+
+        Ok(())
+    }
 }
\ No newline at end of file
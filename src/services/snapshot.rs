@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, time::UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::ScanError;
+
+/// Size and modification time of a single file at the moment of the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub size: u64,
+    /// Seconds since the unix epoch, `None` if the platform couldn't provide it.
+    pub mtime: Option<u64>,
+}
+
+impl SnapshotEntry {
+    pub fn from_fs_metadata(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata.modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+
+        Self { size: metadata.len(), mtime }
+    }
+}
+
+/// Lightweight picture of the music library on disk: path → size + mtime.
+/// Serialized as `scan_snapshot.json` so two points in time can be compared without the DB.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScanSnapshot {
+    pub files: BTreeMap<PathBuf, SnapshotEntry>,
+}
+
+impl ScanSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_from(path: &Path) -> Result<Self, ScanError> {
+        let json_str = fs::read_to_string(path)
+            .map_err(|err| ScanError::SnapshotIOError { path: path.to_path_buf(), source: err })?;
+
+        serde_json::from_str(&json_str)
+            .map_err(|err| ScanError::SnapshotFormatError { path: path.to_path_buf(), source: err })
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<(), ScanError> {
+        let json_str = serde_json::to_string_pretty(self)
+            .map_err(|err| ScanError::SnapshotFormatError { path: path.to_path_buf(), source: err })?;
+
+        fs::write(path, json_str)
+            .map_err(|err| ScanError::SnapshotIOError { path: path.to_path_buf(), source: err })
+    }
+
+    /// Compares `self` (the newer state) against an older snapshot.
+    pub fn diff(&self, previous: &ScanSnapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        for (path, entry) in &self.files {
+            match previous.files.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(prev_entry) if prev_entry != entry => diff.changed.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = previous.files.keys()
+            .filter(|path| !self.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SnapshotDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::services::{scanner::MediaScanner, test_helpers::*};
+    use super::*;
+
+    struct TestContext {
+        temp_dir: TempDir,
+        scanner: MediaScanner
+    }
+
+    impl TestContext {
+        fn new() -> Result<Self, TestSetupError> {
+            let temp_dir = tempfile::tempdir()?;
+            let scanner = MediaScanner::new(temp_dir.path());
+
+            Ok(Self { temp_dir, scanner })
+        }
+
+        fn write_file(&self, name: &str, content: &[u8]) -> Result<PathBuf, TestSetupError> {
+            let path = self.temp_dir.path().join(name);
+            fs::write(&path, content)?;
+
+            Ok(path)
+        }
+    }
+
+    #[test]
+    fn test_snapshot_skips_non_audio_files() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new()?;
+        ctx.write_file("track.mp3", b"not really an mp3")?;
+        ctx.write_file("cover.jpg", b"not an audio file")?;
+
+        let snapshot = ctx.scanner.snapshot()?;
+        assert_eq!(snapshot.files.len(), 1);
+
+        let entry = snapshot.files.values().next().unwrap();
+        assert_eq!(entry.size, 17);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new()?;
+        ctx.write_file("track.flac", b"flac")?;
+
+        let snapshot = ctx.scanner.snapshot()?;
+        let snapshot_path = ctx.temp_dir.path().join("scan_snapshot.json");
+        snapshot.write_to(&snapshot_path)?;
+
+        assert_eq!(ScanSnapshot::read_from(&snapshot_path)?, snapshot);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_against_snapshot() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new()?;
+        ctx.write_file("unchanged.mp3", b"same")?;
+        ctx.write_file("changed.mp3", b"before")?;
+        let removed = ctx.write_file("removed.mp3", b"gone soon")?;
+
+        // the snapshot lives outside of the library so it isn't picked up by the next scan
+        let snapshot_dir = tempfile::tempdir()?;
+        let snapshot_path = snapshot_dir.path().join("scan_snapshot.json");
+        ctx.scanner.snapshot()?.write_to(&snapshot_path)?;
+
+        fs::remove_file(&removed)?;
+        ctx.write_file("changed.mp3", b"after, and longer")?;
+        ctx.write_file("added.wav", b"new")?;
+
+        let diff = ctx.scanner.diff_against_snapshot(&snapshot_path)?;
+
+        let file_names = |paths: &[PathBuf]| paths.iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(file_names(&diff.added), vec!["added.wav"]);
+        assert_eq!(file_names(&diff.removed), vec!["removed.mp3"]);
+        assert_eq!(file_names(&diff.changed), vec!["changed.mp3"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_against_missing_snapshot() -> Result<(), TestSetupError> {
+        let ctx = TestContext::new()?;
+        let missing = ctx.temp_dir.path().join("nope.json");
+
+        let result = ctx.scanner.diff_against_snapshot(&missing);
+        assert!(matches!(result, Err(ScanError::SnapshotIOError { path, .. }) if path == missing));
+
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
-use std::{collections::{HashMap, HashSet}, path::PathBuf};
+use std::{borrow::Cow, collections::{HashMap, HashSet}, fmt, future::ready, path::{Path, PathBuf}, time::{Duration, Instant}};
 
-use chrono::{Local, NaiveDateTime};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use futures::TryStreamExt;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
-use crate::{domain::{album::Album, artist::Artist, audiofile::AudioFileDescriptor, track::Track, uploaded::Uploaded, BatchDeleteReport, BatchSaveReport}, repository::{SqliteAlbumsRepository, SqliteArtistsRepository, SqliteTracksRepository}, services::scanner::MediaScanner};
+use crate::{utils::{config::{Config, DEFAULT_IGNORE_MARKER}, normalizations::{normalize_name, relative_to}}, domain::{album::Album, artist::Artist, audiofile::AudioFileDescriptor, track::Track, uploaded::Uploaded, BatchDeleteReport, BatchSaveReport, ValidationError}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteArtistsRepository, SqliteTracksRepository}, services::scanner::MediaScanner};
 use super::SyncServiceError;
 
 /// Manages the synchronization between a music library on disk and the
@@ -21,7 +23,14 @@ pub struct MusicLibSyncService<'a> {
 
     pool: &'a SqlitePool,
     music_lib_path: PathBuf,
-    db_cache: DatabaseCache
+    strategy: SyncStrategy,
+    db_cache: DatabaseCache,
+    batch_commit_size: Option<usize>,
+    dominant_artist_threshold: Option<u8>,
+    scan_pipeline_capacity: Option<usize>,
+    scan_extensions: Vec<String>,
+    ignore_marker: String,
+    cancel: CancellationToken
 }
 
 impl<'a> MusicLibSyncService<'a> {
@@ -40,11 +49,26 @@ impl<'a> MusicLibSyncService<'a> {
     /// Returns an error if the database cannot be accessed or if there is an
     /// issue during the initial caching.
     pub async fn new(pool: &'a SqlitePool, music_lib_path: PathBuf) -> Result<Self, SyncServiceError> {
+        Self::new_with_strategy(pool, music_lib_path, SyncStrategy::CacheAll).await
+    }
+
+    /// Same as `new`, with the library path and the settings under [media] and [sync] taken from `config`.
+    pub async fn from_config(pool: &'a SqlitePool, config: &Config) -> Result<Self, SyncServiceError> {
+        Ok(Self::new(pool, config.media.music_path.clone()).await?
+            .with_batch_commit_size(config.sync.batch_commit_size)
+            .with_dominant_artist_threshold(config.sync.dominant_artist_threshold)
+            .with_scan_pipeline(config.sync.scan_pipeline_capacity)
+            .with_scan_extensions(config.media.scan_extensions.clone())
+            .with_ignore_marker(config.media.ignore_marker.clone()))
+    }
+
+    /// Same as `new`, with the given `SyncStrategy` deciding how much of the database is cached.
+    pub async fn new_with_strategy(pool: &'a SqlitePool, music_lib_path: PathBuf, strategy: SyncStrategy) -> Result<Self, SyncServiceError> {
         let artists_repo = SqliteArtistsRepository::new();
         let albums_repo = SqliteAlbumsRepository::new();
         let tracks_repo = SqliteTracksRepository::new();
 
-        let db_cache = MusicLibSyncService::cache_db(pool, &artists_repo, &albums_repo, &tracks_repo).await?;
+        let db_cache = MusicLibSyncService::cache_db(pool, &artists_repo, &albums_repo, &tracks_repo, strategy).await?;
 
         Ok(
             Self {
@@ -53,18 +77,88 @@ impl<'a> MusicLibSyncService<'a> {
                 tracks_repo,
                 pool,
                 music_lib_path,
-                db_cache
+                strategy,
+                db_cache,
+                batch_commit_size: None,
+                dominant_artist_threshold: None,
+                scan_pipeline_capacity: None,
+                scan_extensions: Vec::new(),
+                ignore_marker: DEFAULT_IGNORE_MARKER.to_string(),
+                cancel: CancellationToken::new()
             }
         )
     }
 
-    /// Performs a full, atomic synchronization of the music library.
+    /// Commits added tracks every `batch_commit_size` rows instead of in one transaction.
+    ///
+    /// This gives up atomicity: deletions, artists and albums are committed first, then tracks
+    /// in chunks. If the sync fails halfway, everything committed so far stays in the database
+    /// and only the current chunk is rolled back. Running the sync again picks up where it
+    /// stopped, since already stored tracks are no longer new. In exchange the write lock is
+    /// held only for one chunk at a time, so readers aren't blocked during a long first sync.
+    ///
+    /// `None` keeps the default single transaction. A size of 0 is treated as 1.
+    pub fn with_batch_commit_size(mut self, batch_commit_size: Option<usize>) -> Self {
+        self.batch_commit_size = batch_commit_size.map(|size| size.max(1));
+        self
+    }
+
+    /// Keeps albums with per-track artist credits ("feat." and such) together.
+    ///
+    /// New tracks sharing an album name and directory, but tagged with different artists, are
+    /// attributed to one artist: the most common one if it has more than `threshold` percent
+    /// of the tracks, otherwise "Various Artists". Tracks already in the DB are not regrouped.
+    ///
+    /// `None` (the default) keeps every track under its own artist tag.
+    pub fn with_dominant_artist_threshold(mut self, threshold: Option<u8>) -> Self {
+        self.dominant_artist_threshold = threshold.map(|percent| percent.min(100));
+        self
+    }
+
+    /// Sorts scanned files into new and already stored ones while the scan is still running.
+    ///
+    /// The scanner probes files on the blocking pool and passes them over a channel holding at
+    /// most `capacity` descriptors, each one is checked against the database state as it comes.
+    /// The outcome is the same either way, only the time spent differs. On 5,000 tagged WAVs (release
+    /// build, one CPU, warm page cache, capacity 256) a sync with nothing to change took ~197 ms instead
+    /// of ~240 ms, and a first import ~388 ms instead of ~428 ms: the scan overlaps the state checks.
+    /// Cold reads off a spinning disk weren't measured.
+    ///
+    /// `None` (the default) scans the whole library first. A capacity of 0 is treated as 1.
+    pub fn with_scan_pipeline(mut self, capacity: Option<usize>) -> Self {
+        self.scan_pipeline_capacity = capacity.map(|capacity| capacity.max(1));
+        self
+    }
+
+    /// Extensions of the files to sync, see `MediaScanner::with_extensions`. Empty (the default) keeps the built-in set.
+    pub fn with_scan_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.scan_extensions = extensions;
+        self
+    }
+
+    /// Folders the scan leaves out, see `MediaScanner::with_ignore_marker`.
+    pub fn with_ignore_marker(mut self, marker: String) -> Self {
+        self.ignore_marker = marker;
+        self
+    }
+
+    /// Lets `token` stop the sync: the scan between files, and the changes before each commit. A cancelled
+    /// sync fails with `SyncServiceError::Cancelled` and rolls back its open transaction, so only batches
+    /// committed before the cancellation stay in the database, see `with_batch_commit_size`.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Performs a full synchronization of the music library, atomic unless
+    /// batched commits were enabled with `with_batch_commit_size`.
     ///
     /// This method executes the complete synchronization workflow:
     /// 1. Scans the filesystem for all supported audio files.
     /// 2. Compares the file list against the cached database state.
-    /// 3. Computes a set of additions (new files) and deletions (missing files).
-    /// 4. Applies all database changes within a single transaction.
+    /// 3. Computes a set of additions (new files), updates (re-tagged files) and deletions (missing files).
+    /// 4. Applies all database changes within a single transaction, or in several
+    ///    when batched commits are enabled.
     ///
     /// On success, it returns a `SyncServiceReport` detailing all the changes made.
     ///
@@ -72,49 +166,277 @@ impl<'a> MusicLibSyncService<'a> {
     ///
     /// Returns an error if the filesystem cannot be scanned or if the database
     /// transaction fails. The database will be rolled back to its original state
-    /// in case of a transaction error.
-    pub async fn synchronize(&self) -> Result<SyncServiceReport, SyncServiceError> {
+    /// in case of a transaction error. With batched commits only the failing batch
+    /// is rolled back, see `with_batch_commit_size`.
+    ///
+    /// After the changes are applied the cache is rebuilt, so the same instance can be synchronized again.
+    /// A cancelled sync skips the rebuild.
+    pub async fn synchronize(&mut self) -> Result<SyncServiceReport, SyncServiceError> {
+        let started = Instant::now();
+        let plan = self.plan().await?;
+        let mut report = SyncServiceReport::new(Local::now().naive_local());
+        report.files_scanned = plan.timings.files_scanned;
+        report.scan_duration = plan.timings.scan_duration;
+        report.diff_duration = plan.timings.diff_duration;
+        report.skipped_files = plan.changes.skipped.clone();
+
+        let commit_started = Instant::now();
+        let applied = match self.batch_commit_size {
+            Some(batch_size) => self.apply_in_batches(&mut report, &plan.changes, batch_size).await,
+            None => self.apply_atomically(&mut report, &plan.changes).await
+        };
+        report.commit_duration = commit_started.elapsed();
+
+        // batched commits may have landed before a failure, so the cache is refreshed either way,
+        // except after a cancel: the token stays cancelled, so this instance won't sync again anyway.
+        // The apply error is the one that gets returned, a failed refresh on top of it is only logged
+        match applied {
+            Ok(()) => self.refresh_cache().await?,
+            Err(SyncServiceError::Cancelled) => return Err(SyncServiceError::Cancelled),
+            Err(err) => {
+                if let Err(refresh_err) = self.refresh_cache().await {
+                    log::error!("Failed to refresh the sync cache after a failed sync: {}", refresh_err);
+                }
+                return Err(err);
+            }
+        }
+
+        report.total_duration = started.elapsed();
+        Ok(report)
+    }
+
+    /// Scans the library and works out what `synchronize` would change, without touching the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the filesystem cannot be scanned or the changes can't be worked out,
+    /// e.g. a file with tags that don't make a valid track.
+    pub async fn plan(&self) -> Result<SyncPlan, SyncServiceError> {
         // Scan the filesystem to get the current, actual state of the music library.
-        let scanner = MediaScanner::new(&self.music_lib_path);
-        let scan_result = scanner.scan_music_lib()?;
+        let started = Instant::now();
+        let scanner = MediaScanner::new(&self.music_lib_path)
+            .with_extensions(&self.scan_extensions)
+            .with_ignore_marker(self.ignore_marker.clone())
+            .with_cancellation(self.cancel.clone());
+        let (mut library, errors) = match self.scan_pipeline_capacity {
+            Some(capacity) => {
+                let mut receiver = scanner.scan_stream(capacity)?;
+                let (mut library, mut errors) = (ScannedLibrary::new(), 0);
+
+                while let Some(scanned) = receiver.recv().await {
+                    match scanned {
+                        Ok(descriptor) => library.push(&self.db_cache, descriptor),
+                        Err(_) => errors += 1
+                    }
+                }
+                self.check_cancelled()?;
+
+                (library, errors)
+            },
+            None => {
+                let scan_result = scanner.scan_music_lib_async().await?;
+                let mut library = ScannedLibrary::new();
+                scan_result.descriptors.into_iter().for_each(|descriptor| library.push(&self.db_cache, descriptor));
+
+                (library, scan_result.errors.len())
+            }
+        };
+        let scan_duration = started.elapsed();
+        log_duration("scan", scan_duration, format_args!("files={} errors={}", library.paths.len(), errors));
+
+        let diff_started = Instant::now();
+        if self.strategy == SyncStrategy::Streaming {
+            let started = Instant::now();
+            self.split_stored(&mut library).await?;
+            log_phase("lookup", started, format_args!("stored={} new={}", library.synced.len(), library.unsynced.len()));
+        }
 
         // Calculate the difference between the filesystem and our cached database state.
-        let (additions, deletions) = self.difference(&scan_result.descriptors).await?;
+        let started = Instant::now();
+        let changes = self.difference(&library).await?;
+        log_phase("diff", started, format_args!(
+            "new_tracks={} new_albums={} new_artists={} deleted_tracks={} deleted_albums={} deleted_artists={} moved_tracks={} updated_tracks={}",
+            changes.additions.tracks.len(), changes.additions.albums.len(), changes.additions.artists.len(),
+            changes.deletions.track_ids.len(), changes.deletions.album_ids.len(), changes.deletions.artist_ids.len(),
+            changes.moves.len(), changes.updates.len()
+        ));
+
+        let timings = PlanTimings { files_scanned: library.paths.len(), scan_duration, diff_duration: diff_started.elapsed() };
+        Ok(SyncPlan::new(changes, timings))
+    }
+
+    /// What changed on disk since the last sync, as counts and paths relative to the library. Built on
+    /// `plan`, so these are exactly the changes `synchronize` would make.
+    ///
+    /// # Errors
+    ///
+    /// Same as `plan`.
+    pub async fn diff(&self) -> Result<SyncDiff, SyncServiceError> {
+        Ok(SyncDiff::new(self.plan().await?, &self.music_lib_path))
+    }
+
+    /// Compares the database against the library and reports what's out of place, without touching
+    /// the database. On top of what `diff` finds, it lists the albums and artists that are empty
+    /// already, which a sync leaves alone since it only prunes what loses its last track during that sync.
+    ///
+    /// # Errors
+    ///
+    /// Same as `plan`, or a `RepositoryError` if the empty albums and artists can't be listed.
+    pub async fn verify(&self) -> Result<VerifyReport, SyncServiceError> {
+        let diff = self.diff().await?;
+        let empty_albums = self.albums_repo.empty_albums(self.pool).await?;
+        let empty_artists = self.artists_repo.empty_artists(self.pool).await?;
+
+        Ok(VerifyReport {
+            missing_files: diff.missing_tracks,
+            untracked_files: diff.new_tracks,
+            moved_files: diff.moved_tracks,
+            stale_tags: diff.updated_tracks,
+            orphaned_album_ids: diff.orphaned_album_ids,
+            orphaned_artist_ids: diff.orphaned_artist_ids,
+            empty_album_ids: empty_albums.iter().map(|album| *album.id()).collect(),
+            empty_artist_ids: empty_artists.iter().map(|artist| *artist.id()).collect()
+        })
+    }
 
+    async fn apply_atomically(&self, report: &mut SyncServiceReport, changes: &PendingChanges) -> Result<(), SyncServiceError> {
         let mut tx = self.pool.begin().await?;
-        let mut report = SyncServiceReport::new(Local::now().naive_local());
-        
-        // Apply deletions first.
-        if !deletions.is_empty() {
-            report.deleted_tracks = self.tracks_repo.batch_delete(&mut *tx, &deletions.track_ids).await?;
-            report.deleted_albums = self.albums_repo.batch_delete(&mut *tx, &deletions.album_ids).await?;
-            report.deleted_artists = self.artists_repo.batch_delete(&mut *tx, &deletions.artist_ids).await?;
+
+        self.apply_entity_changes(&mut tx, report, changes).await?;
+
+        let started = Instant::now();
+        report.added_tracks = self.tracks_repo.batch_save_iter(&mut *tx, &changes.additions.tracks).await?;
+        abort_if_storage_full(&report.added_tracks)?;
+        log_phase("add_tracks", started, format_args!("rows={}", changes.additions.tracks.len()));
+
+        self.check_cancelled()?;
+        let started = Instant::now();
+        tx.commit().await?;
+        report.committed_batches = 1;
+        log_phase("commit", started, format_args!("transactions=1"));
+
+        Ok(())
+    }
+
+    async fn apply_in_batches(&self, report: &mut SyncServiceReport, changes: &PendingChanges, batch_size: usize) -> Result<(), SyncServiceError> {
+        // Everything but the added tracks goes in first, added tracks reference the new artists and albums.
+        // They're few compared to tracks, so one transaction is fine.
+        let mut tx = self.pool.begin().await?;
+
+        self.apply_entity_changes(&mut tx, report, changes).await?;
+
+        self.check_cancelled()?;
+        let started = Instant::now();
+        tx.commit().await?;
+        report.committed_batches += 1;
+        log_phase("commit", started, format_args!("transactions=1"));
+
+        let tracks = changes.additions.tracks.iter().collect::<Vec<&Track>>();
+
+        // chunks interleave adding and committing, so both are summed up over all of them
+        let (mut adding, mut committing) = (Duration::ZERO, Duration::ZERO);
+        for chunk in tracks.chunks(batch_size) {
+            let mut tx = self.pool.begin().await?;
+
+            let started = Instant::now();
+            let chunk_report = self.tracks_repo.batch_save(&mut *tx, chunk).await?;
+            abort_if_storage_full(&chunk_report)?;
+            adding += started.elapsed();
+
+            self.check_cancelled()?;
+            let started = Instant::now();
+            tx.commit().await?;
+            committing += started.elapsed();
+
+            report.committed_batches += 1;
+            report.added_tracks.outcomes.extend(chunk_report.outcomes);
         }
+        log_duration("add_tracks", adding, format_args!("rows={}", tracks.len()));
+        log_duration("commit_tracks", committing, format_args!("transactions={}", tracks.len().div_ceil(batch_size)));
 
-        // Then apply additions.
-        if !additions.is_empty() {
-            report.added_artists = self.artists_repo.batch_save(&mut *tx, &additions.artists.values().collect::<Vec<&Artist>>()).await?;
-            report.added_albums = self.albums_repo.batch_save(&mut *tx, &additions.albums.values().collect::<Vec<&Album>>()).await?;
-            report.added_tracks = self.tracks_repo.batch_save(&mut *tx, &additions.tracks.iter().collect::<Vec<&Track>>()).await?;
+        Ok(())
+    }
+
+    /// Fails with `Cancelled` once the token from `with_cancellation` was tripped.
+    fn check_cancelled(&self) -> Result<(), SyncServiceError> {
+        match self.cancel.is_cancelled() {
+            true => Err(SyncServiceError::Cancelled),
+            false => Ok(())
         }
+    }
 
-        tx.commit().await?;
-        
-        Ok(report)
+    /// Applies all changes except for the added tracks. New artists and albums go in first, so moved and
+    /// re-tagged tracks can point to them, and deletions last, once no track references the orphaned rows.
+    async fn apply_entity_changes(&self, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, report: &mut SyncServiceReport, changes: &PendingChanges) -> Result<(), SyncServiceError> {
+        let (additions, deletions) = (&changes.additions, &changes.deletions);
+
+        let started = Instant::now();
+        report.added_artists = self.artists_repo.batch_save_iter(tx, additions.artists.values()).await?;
+        abort_if_storage_full(&report.added_artists)?;
+
+        report.added_albums = self.albums_repo.batch_save_iter(tx, additions.albums.values()).await?;
+        abort_if_storage_full(&report.added_albums)?;
+        log_phase("add", started, format_args!("rows={}", additions.artists.len() + additions.albums.len()));
+
+        let started = Instant::now();
+        for (track_id, new_path) in &changes.moves {
+            self.tracks_repo.set_file_path(&mut **tx, *track_id, new_path).await?;
+            report.moved_tracks.push((*track_id, new_path.clone()));
+        }
+        log_phase("move", started, format_args!("rows={}", changes.moves.len()));
+
+        let started = Instant::now();
+        for update in &changes.updates {
+            report.updated_tracks.push(self.tracks_repo.update(&mut **tx, &update.track).await?);
+        }
+        log_phase("update", started, format_args!("rows={}", changes.updates.len()));
+
+        let started = Instant::now();
+        if !deletions.is_empty() {
+            report.deleted_tracks = self.tracks_repo.batch_delete(tx, &deletions.track_ids).await?;
+            report.deleted_albums = self.albums_repo.batch_delete(tx, &deletions.album_ids).await?;
+            report.deleted_artists = self.artists_repo.batch_delete(tx, &deletions.artist_ids).await?;
+        }
+        log_phase("delete", started, format_args!("rows={}", deletions.len()));
+
+        Ok(())
     }
 
-    async fn cache_db(pool: &'a SqlitePool, artists_repo: &SqliteArtistsRepository, albums_repo: &SqliteAlbumsRepository, tracks_repo: &SqliteTracksRepository) -> Result<DatabaseCache, SyncServiceError> {
+    /// Re-reads the database state into the cache.
+    ///
+    /// Needed whenever the database could have changed since the cache was built,
+    /// either by a previous `synchronize` or by somebody else writing into it.
+    pub async fn refresh_cache(&mut self) -> Result<(), SyncServiceError> {
+        self.db_cache = MusicLibSyncService::cache_db(self.pool, &self.artists_repo, &self.albums_repo, &self.tracks_repo, self.strategy).await?;
 
-        // Fetching all the data from a DB. Memory intensive and obviously wont fit really large DBs.
-        let tracks: HashMap<PathBuf, Track> = tracks_repo.stream_all(pool).await.try_collect::<Vec<_>>().await?
-            .into_iter()
-            .map(|t| (t.file_path().to_owned(), t))
-            .collect();
+        Ok(())
+    }
+
+    async fn cache_db(pool: &'a SqlitePool, artists_repo: &SqliteArtistsRepository, albums_repo: &SqliteAlbumsRepository, tracks_repo: &SqliteTracksRepository, strategy: SyncStrategy) -> Result<DatabaseCache, SyncServiceError> {
+        let started = Instant::now();
+
+        // Fetching all the data from a DB. Memory intensive and obviously wont fit really large DBs,
+        // that's what the streaming strategy is for: it leaves the tracks out.
+        let tracks: HashMap<PathBuf, Track> = match strategy {
+            SyncStrategy::CacheAll => tracks_repo.stream_all(pool).await.try_collect::<Vec<_>>().await?
+                .into_iter()
+                .map(|t| (t.file_path().to_owned(), t))
+                .collect(),
+            SyncStrategy::Streaming => HashMap::new()
+        };
         
-        let artists = artists_repo.stream_all(pool).await.try_collect::<Vec<_>>().await?
-            .into_iter()
-            .map(|a| (a.name().to_owned(), a))
-            .collect();
+        // Tags only carry the name, so same-named artists can't be told apart here: new tracks
+        // go to the one with the lowest id, so every sync picks the same one, and the rest is left alone.
+        let mut artists: HashMap<String, Artist> = HashMap::new();
+        for artist in artists_repo.stream_all_ordered(pool).await.try_collect::<Vec<_>>().await? {
+            match artists.get(artist.name()) {
+                Some(kept) => log::warn!(
+                    "More than one artist is named \"{}\" ({} and {}). New tracks with this artist tag are added to {}.",
+                    artist.name(), kept.id(), artist.id(), kept.id()
+                ),
+                None => { artists.insert(artist.name().to_owned(), artist); }
+            }
+        }
             
         let albums: HashMap<(String, Uuid), Album> = albums_repo.stream_all(pool).await.try_collect::<Vec<_>>().await?
             .into_iter()
@@ -122,16 +444,8 @@ impl<'a> MusicLibSyncService<'a> {
             .collect();
 
         // Creating fast lookup tables:
-        let mut album_to_track_ids: HashMap<Uuid, Vec<Uuid>> = HashMap::new();      // ablum_id -> Vec<track_id>
         let mut artist_to_album_ids: HashMap<Uuid, Vec<Uuid>> = HashMap::new();     // artist_id -> Vec<album_id> of Albums that has given artist_id
 
-        for track in tracks.values() {
-            album_to_track_ids
-                .entry(*track.album_id())
-                .or_default()
-                .push(*track.id())
-        }
-
         for album in albums.values() {
             // Index albums by their artist for artist-level lookups.
             artist_to_album_ids
@@ -140,7 +454,69 @@ impl<'a> MusicLibSyncService<'a> {
                 .push(*album.id());
         }
         
-        Ok(DatabaseCache { tracks, albums, artists, album_to_track_ids, artist_to_album_ids })
+        log_phase("cache_build", started, format_args!("tracks={} albums={} artists={}", tracks.len(), albums.len(), artists.len()));
+
+        Ok(DatabaseCache { tracks, albums, artists, artist_to_album_ids })
+    }
+
+    /// Moves the scanned files that are already stored from `unsynced` to `synced`, asking the
+    /// database one chunk at a time. Only needed by the streaming strategy, the cached tracks
+    /// sort them right away otherwise.
+    async fn split_stored(&self, library: &mut ScannedLibrary) -> Result<(), SyncServiceError> {
+        let mut scanned = std::mem::take(&mut library.unsynced).into_iter().peekable();
+
+        while scanned.peek().is_some() {
+            let chunk = scanned.by_ref().take(STREAMING_CHUNK_SIZE).collect::<Vec<_>>();
+            let paths = chunk.iter().map(|file| &file.path).collect::<Vec<_>>();
+            let stored = self.tracks_repo.paths_exist(self.pool, &paths).await?;
+
+            for file in chunk {
+                if stored.contains(&file.path) {
+                    library.synced.push(file);
+                } else {
+                    library.unsynced.push(file);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stored tracks whose file is no longer in the library. The streaming strategy goes through
+    /// the tracks table row by row, keeping only these.
+    async fn missing_tracks(&self, library: &ScannedLibrary) -> Result<Vec<Track>, SyncServiceError> {
+        let missing = match self.strategy {
+            SyncStrategy::CacheAll => self.db_cache.tracks.values()
+                .filter(|track| !library.paths.contains(track.file_path()))
+                .cloned()
+                .collect(),
+            SyncStrategy::Streaming => self.tracks_repo.stream_all(self.pool).await
+                .try_filter(|track| ready(!library.paths.contains(track.file_path())))
+                .try_collect()
+                .await?
+        };
+
+        Ok(missing)
+    }
+
+    /// Stored tracks of the given files, borrowed from the cache or fetched a chunk at a time.
+    async fn stored_tracks(&self, files: &[&AudioFileDescriptor]) -> Result<HashMap<PathBuf, Cow<'_, Track>>, SyncServiceError> {
+        let mut stored = HashMap::new();
+
+        match self.strategy {
+            SyncStrategy::CacheAll => for file in files {
+                if let Some(track) = self.db_cache.tracks.get(&file.path) {
+                    stored.insert(file.path.clone(), Cow::Borrowed(track));
+                }
+            },
+            SyncStrategy::Streaming => for chunk in files.chunks(STREAMING_CHUNK_SIZE) {
+                let paths = chunk.iter().map(|file| &file.path).collect::<Vec<_>>();
+                let fetched = self.tracks_repo.by_paths_map(self.pool, &paths).await?;
+                stored.extend(fetched.into_iter().map(|(path, track)| (path, Cow::Owned(track))));
+            }
+        }
+
+        Ok(stored)
     }
 
     fn resolve_artist_id(&self, new_files: &mut PendingAdditions, artist_name: &str) -> Result<Uuid, SyncServiceError> {
@@ -166,7 +542,14 @@ impl<'a> MusicLibSyncService<'a> {
             *album.id()
         } else {
             let new_id = Uuid::new_v4();
-            let new_album = Album::new(new_id, alb_name.to_string(), art_id, alb_year)?;
+            // tag years are often garbage, one of those shouldn't keep the album out
+            let new_album = match Album::new(new_id, alb_name, art_id, alb_year) {
+                Err(ValidationError::YearOutOfRange(year)) => {
+                    log::warn!("Album '{}' is tagged with year {}, storing it without one", alb_name, year);
+                    Album::new(new_id, alb_name, art_id, None)?
+                },
+                album => album?
+            };
             new_files.add_album(new_album);
 
             new_id
@@ -175,20 +558,34 @@ impl<'a> MusicLibSyncService<'a> {
         Ok(id)
     }
 
-    async fn find_new_files(&self, music_lib_files: &Vec<AudioFileDescriptor>) -> Result<PendingAdditions, SyncServiceError> {
+    async fn find_new_files(&self, unsynced: &[AudioFileDescriptor]) -> Result<PendingAdditions, SyncServiceError> {
         let mut new_files = PendingAdditions::new();
 
-        for file in music_lib_files {
-            if self.db_cache.tracks.contains_key(&file.path) {
-                continue;
-            }
+        let unsynced_files: Vec<&AudioFileDescriptor> = unsynced.iter().collect();
 
-            let art_id = self.resolve_artist_id(&mut new_files, &file.metadata.artist_name)?;
+        // First pass: settle on one artist per album, second pass: build the entities.
+        let album_artists = match self.dominant_artist_threshold {
+            Some(threshold) => group_album_artists(&unsynced_files, threshold),
+            None => HashMap::new()
+        };
+
+        for file in unsynced_files {
+            let artist_name = album_artists.get(&album_group_key(file))
+                .map(String::as_str)
+                .unwrap_or(&file.metadata.artist_name);
+
+            let art_id = self.resolve_artist_id(&mut new_files, artist_name)?;
             let alb_id = self.resolve_album_id(&mut new_files, &file.metadata.album_name, art_id, file.metadata.album_year)?;
             let default_uploaded = Uploaded::Denis;
             let default_date = Some(Local::now().naive_local());
 
-            let new_track = Track::new(Uuid::new_v4(), file.metadata.track_name.to_owned(), alb_id, file.metadata.track_duration, file.path.clone(), file.file_size, file.file_type.clone(), default_uploaded, default_date)?;
+            let mut new_track = Track::new(Uuid::new_v4(), file.metadata.track_name.to_owned(), alb_id, file.metadata.track_duration, file.path.clone(), file.file_size, file.file_type.clone(), default_uploaded, default_date)?;
+            new_track.set_disc_number(file.metadata.disc_number);
+            new_track.set_track_number(file.metadata.track_number);
+            new_track.set_probe_ok(file.probe_ok);
+            new_track.set_content_hash(file.content_hash.clone());
+            new_track.set_genre(file.metadata.genre.clone());
+            new_track.set_file_mtime(file.modified.map(|modified| DateTime::<Utc>::from(modified).naive_utc()));
             new_files.add_track(new_track);
 
         }
@@ -196,36 +593,176 @@ impl<'a> MusicLibSyncService<'a> {
         Ok(new_files)
     }
 
-    async fn find_orphaned_entities(&self, music_lib_files: &Vec<AudioFileDescriptor>) -> Result<PendingDeletions, SyncServiceError> {
+    /// Pairs tracks whose file is gone with new files that look like the same file somewhere else: same size and
+    /// the same track, album and artist names. Only files with readable tags are considered, and a signature shared
+    /// by more than one gone track or more than one new file is left alone, those stay a delete plus an add.
+    ///
+    /// Returns (id of the stored track, path it moved to).
+    fn find_moved_tracks(&self, library: &ScannedLibrary, missing: &[Track]) -> Vec<(Uuid, PathBuf)> {
+        type Signature = (u64, String, String, String);
+
+        let album_names = self.album_and_artist_names();
+
+        let mut gone: HashMap<Signature, Vec<Uuid>> = HashMap::new();
+        for track in missing.iter().filter(|track| track.probe_ok()) {
+            let Some((album_name, artist_name)) = album_names.get(track.album_id()) else { continue };
+
+            let signature = (track.file_size(), track.name().to_string(), album_name.to_string(), artist_name.to_string());
+            gone.entry(signature).or_default().push(*track.id());
+        }
+
+        if gone.is_empty() {
+            return Vec::new();
+        }
+
+        let mut appeared: HashMap<Signature, Vec<&PathBuf>> = HashMap::new();
+        for file in library.unsynced.iter().filter(|file| file.probe_ok) {
+            let signature = (
+                file.file_size,
+                normalize_name(&file.metadata.track_name),
+                normalize_name(&file.metadata.album_name),
+                normalize_name(&file.metadata.artist_name)
+            );
+            appeared.entry(signature).or_default().push(&file.path);
+        }
+
+        gone.into_iter()
+            .filter_map(|(signature, track_ids)| match (track_ids.as_slice(), appeared.get(&signature).map(Vec::as_slice)) {
+                ([track_id], Some([new_path])) => Some((*track_id, (*new_path).clone())),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Compares the tags of files already in the DB with the stored track, album and artist, and returns the
+    /// tracks whose tags changed, as they should be stored. A track tagged with another album or artist is
+    /// pointed to the matching album, new artists and albums are added to `new_files`.
+    ///
+    /// Files whose tags couldn't be read are skipped, their default metadata would overwrite the stored one.
+    async fn find_modified_tracks(&self, synced: &[AudioFileDescriptor], new_files: &mut PendingAdditions) -> Result<Vec<TrackUpdate>, SyncServiceError> {
+        let album_names = self.album_and_artist_names();
+        let synced_files: Vec<&AudioFileDescriptor> = synced.iter().filter(|file| file.probe_ok).collect();
+
+        // Grouped albums are stored under their dominant artist rather than the track's own tag,
+        // that alone is not a change.
+        let album_artists = match self.dominant_artist_threshold {
+            Some(threshold) => group_album_artists(&synced_files, threshold),
+            None => HashMap::new()
+        };
+
+        let mut updates = Vec::new();
+        for chunk in synced_files.chunks(STREAMING_CHUNK_SIZE) {
+            let stored_tracks = self.stored_tracks(chunk).await?;
+
+            for file in chunk {
+                let Some(stored) = stored_tracks.get(&file.path) else { continue };
+                let Some((album_name, artist_name)) = album_names.get(stored.album_id()) else { continue };
+
+                if let Some(track) = self.retagged_track(stored, file, album_name, artist_name, &album_artists, new_files)? {
+                    updates.push(TrackUpdate { previous_album_id: *stored.album_id(), track });
+                }
+            }
+        }
+
+        Ok(updates)
+    }
+
+    /// The stored track as it should be after the tags of `file` changed, `None` if they didn't.
+    fn retagged_track(&self, stored: &Track, file: &AudioFileDescriptor, album_name: &str, artist_name: &str, album_artists: &HashMap<(String, Option<PathBuf>), String>, new_files: &mut PendingAdditions) -> Result<Option<Track>, SyncServiceError> {
+        let grouped_artist = album_artists.get(&album_group_key(file)).map(String::as_str);
+        let same_album = album_name == file.metadata.album_name
+            && (artist_name == file.metadata.artist_name || grouped_artist == Some(artist_name));
+
+        let unchanged = same_album
+            && stored.name() == file.metadata.track_name
+            && stored.duration() == file.metadata.track_duration
+            && stored.disc_number() == file.metadata.disc_number
+            && stored.track_number() == file.metadata.track_number
+            && stored.genre() == file.metadata.genre.as_deref()
+            // tracks synced before the hash was kept get it on their next sync
+            && stored.content_hash() == file.content_hash.as_deref();
+        if unchanged {
+            return Ok(None);
+        }
+
+        let alb_id = if same_album {
+            *stored.album_id()
+        } else {
+            let art_id = self.resolve_artist_id(new_files, grouped_artist.unwrap_or(&file.metadata.artist_name))?;
+            self.resolve_album_id(new_files, &file.metadata.album_name, art_id, file.metadata.album_year)?
+        };
+
+        let mut updated = Track::new(*stored.id(), file.metadata.track_name.to_owned(), alb_id, file.metadata.track_duration, stored.file_path().clone(), file.file_size, file.file_type.clone(), *stored.uploaded(), *stored.date_added())?;
+        updated.set_disc_number(file.metadata.disc_number);
+        updated.set_track_number(file.metadata.track_number);
+        updated.set_probe_ok(file.probe_ok);
+        updated.set_content_hash(file.content_hash.clone());
+        updated.set_genre(file.metadata.genre.clone());
+        updated.set_file_mtime(file.modified.map(|modified| DateTime::<Utc>::from(modified).naive_utc()));
+
+        Ok(Some(updated))
+    }
+
+    /// album_id -> (album name, artist name) of the cached albums.
+    fn album_and_artist_names(&self) -> HashMap<Uuid, (&str, &str)> {
+        let artist_names: HashMap<Uuid, &str> = self.db_cache.artists.values()
+            .map(|artist| (*artist.id(), artist.name()))
+            .collect();
+
+        self.db_cache.albums.values()
+            .filter_map(|album| artist_names.get(album.artist_id()).map(|artist_name| (*album.id(), (album.name(), *artist_name))))
+            .collect()
+    }
+
+    /// Finds the tracks whose files are missing, and the albums and artists left without tracks once
+    /// those are deleted and the re-tagged tracks in `updates` point to their new album. Albums and
+    /// artists picked up again by `additions` or `updates` are kept.
+    ///
+    /// Whether an album is left empty is decided by counting its stored tracks, so it works the same
+    /// whether the tracks are cached or not.
+    async fn find_orphaned_entities(&self, missing: &[Track], moved: &HashSet<Uuid>, additions: &PendingAdditions, updates: &[TrackUpdate]) -> Result<PendingDeletions, SyncServiceError> {
 
         fn is_subset<T: Eq + std::hash::Hash>(subset: &[T], superset: &HashSet<&T>) -> bool {
             subset.iter().all(|item| superset.contains(item))
         }
     
         let mut deletions = PendingDeletions::new();
-        let music_lib_paths: HashSet<PathBuf> = music_lib_files.iter().map(|fd| fd.path.clone()).collect();
+        let mut tracks_leaving: HashMap<Uuid, u64> = HashMap::new();     // album_id -> tracks leaving it
         
-        // 1. Find all tracks whose files are missing.
-        for db_track in self.db_cache.tracks.values() {
-            if !music_lib_paths.contains(db_track.file_path()) {
-                deletions.track_ids.push(*db_track.id());
-            }
+        // 1. Find all tracks whose files are missing, and didn't just move.
+        for db_track in missing.iter().filter(|track| !moved.contains(track.id())) {
+            deletions.track_ids.push(*db_track.id());
+            *tracks_leaving.entry(*db_track.album_id()).or_default() += 1;
         }
         
-        let tracks_to_be_deleted = deletions.track_ids.iter().collect::<HashSet<_>>();
+        let mut albums_in_use = additions.tracks.iter().map(|track| *track.album_id()).collect::<HashSet<_>>();
+        for update in updates {
+            albums_in_use.insert(*update.track.album_id());
+
+            if update.previous_album_id != *update.track.album_id() {
+                *tracks_leaving.entry(update.previous_album_id).or_default() += 1;
+            }
+        }
     
         // 2. Find all orphaned albums.
-        for (album_id, track_ids) in &self.db_cache.album_to_track_ids {
-            if track_ids.is_empty() || is_subset(track_ids, &tracks_to_be_deleted) {
+        let album_ids = tracks_leaving.keys().copied().collect::<Vec<_>>();
+        let mut stored_counts = HashMap::new();
+        for chunk in album_ids.chunks(STREAMING_CHUNK_SIZE) {
+            stored_counts.extend(self.tracks_repo.count_by_albums(self.pool, chunk).await?);
+        }
+
+        for (album_id, leaving) in &tracks_leaving {
+            if !albums_in_use.contains(album_id) && stored_counts.get(album_id).copied().unwrap_or(0) <= *leaving {
                 deletions.album_ids.push(*album_id);
             }
         }
     
         let albums_to_be_deleted = deletions.album_ids.iter().collect::<HashSet<_>>();
+        let artists_in_use = additions.albums.values().map(|album| *album.artist_id()).collect::<HashSet<_>>();
     
         // 3. Find all orphaned artists.
         for (artist_id, album_ids) in &self.db_cache.artist_to_album_ids {
-            if album_ids.is_empty() || is_subset(album_ids, &albums_to_be_deleted) {
+            if !artists_in_use.contains(artist_id) && (album_ids.is_empty() || is_subset(album_ids, &albums_to_be_deleted)) {
                 deletions.artist_ids.push(*artist_id);
             }
         }
@@ -233,11 +770,107 @@ impl<'a> MusicLibSyncService<'a> {
         Ok(deletions)
     }
 
-    async fn difference(&self, music_lib_files: &Vec<AudioFileDescriptor>) -> Result<(PendingAdditions, PendingDeletions), SyncServiceError> {
-        let additions = self.find_new_files(music_lib_files).await?;
-        let deletions = self.find_orphaned_entities(music_lib_files).await?;
+    async fn difference(&self, library: &ScannedLibrary) -> Result<PendingChanges, SyncServiceError> {
+        let missing = self.missing_tracks(library).await?;
+        let moves = self.find_moved_tracks(library, &missing);
+        let moved_ids = moves.iter().map(|(track_id, _)| *track_id).collect::<HashSet<_>>();
+        let moved_paths = moves.iter().map(|(_, new_path)| new_path).collect::<HashSet<_>>();
+
+        let unsynced = library.unsynced.iter()
+            .filter(|file| !moved_paths.contains(&file.path))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut additions = self.find_new_files(&unsynced).await?;
+        let updates = self.find_modified_tracks(&library.synced, &mut additions).await?;
+        let deletions = self.find_orphaned_entities(&missing, &moved_ids, &additions, &updates).await?;
+
+        Ok(PendingChanges { additions, deletions, moves, updates, missing, skipped: library.skipped.clone() })
+    }
+}
+
+const VARIOUS_ARTISTS: &str = "various artists";
+
+/// Rows the streaming strategy looks up per query, well below SQLite's limit on bound parameters.
+const STREAMING_CHUNK_SIZE: usize = 500;
+
+/// How `MusicLibSyncService` finds out what is already stored, see `MusicLibSyncService::new_with_strategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// Loads every track, album and artist when the service is created. The fastest, but the
+    /// memory it takes grows with the library.
+    #[default]
+    CacheAll,
+    /// Loads only albums and artists, a small fraction of the rows. Scanned files are looked up
+    /// in the tracks table in chunks, and of the stored tracks only those whose file is gone are
+    /// kept in memory. Slower, but fits libraries too large to cache.
+    Streaming
+}
+
+/// Phases taking longer than this are logged at info, the rest at debug.
+const SLOW_PHASE: Duration = Duration::from_secs(1);
+
+/// Logs how long a sync phase took, as `sync phase=<name> took_ms=<ms> <counts>`, so a slow sync
+/// can be pinned down to a phase and the amount of rows it went through.
+fn log_phase(phase: &str, started: Instant, details: fmt::Arguments) {
+    log_duration(phase, started.elapsed(), details);
+}
+
+fn log_duration(phase: &str, took: Duration, details: fmt::Arguments) {
+    let level = if took >= SLOW_PHASE { log::Level::Info } else { log::Level::Debug };
+    log::log!(level, "sync phase={} took_ms={} {}", phase, took.as_millis(), details);
+}
+
+/// Album name plus the directory it lives in, so two different "Greatest Hits" don't end up grouped together.
+fn album_group_key(file: &AudioFileDescriptor) -> (String, Option<PathBuf>) {
+    (file.metadata.album_name.clone(), file.path.parent().map(|dir| dir.to_path_buf()))
+}
+
+/// Picks the artist for every album whose tracks are tagged with more than one artist.
+/// Albums with a single artist are left out, their tracks keep their own tag.
+fn group_album_artists(files: &[&AudioFileDescriptor], threshold: u8) -> HashMap<(String, Option<PathBuf>), String> {
+    let mut artist_counts: HashMap<(String, Option<PathBuf>), HashMap<&str, usize>> = HashMap::new();
+
+    for file in files {
+        *artist_counts
+            .entry(album_group_key(file))
+            .or_default()
+            .entry(&file.metadata.artist_name)
+            .or_default() += 1;
+    }
+
+    artist_counts.into_iter()
+        .filter(|(_, counts)| counts.len() > 1)
+        .map(|(album_key, counts)| {
+            let total: usize = counts.values().sum();
+
+            // ties go to the alphabetically first artist, so repeated syncs agree
+            let (dominant, dominant_count) = counts.into_iter()
+                .max_by(|(a_name, a_count), (b_name, b_count)| a_count.cmp(b_count).then(b_name.cmp(a_name)))
+                .expect("Albums in this map have at least two artists");
+
+            let artist = if dominant_count * 100 > total * threshold as usize {
+                dominant.to_string()
+            } else {
+                VARIOUS_ARTISTS.to_string()
+            };
+
+            (album_key, artist)
+        })
+        .collect()
+}
 
-        Ok((additions, deletions))
+/// A full disk fails every following insert as well, so there is no point in collecting
+/// per-row errors: the transaction is dropped (rolled back) and the sync stops.
+fn abort_if_storage_full(report: &BatchSaveReport) -> Result<(), SyncServiceError> {
+    let storage_full = report.outcomes.iter().find_map(|outcome| match &outcome.result {
+        Err(RepositoryError::StorageFull(message)) => Some(message.clone()),
+        _ => None
+    });
+
+    match storage_full {
+        Some(message) => Err(SyncServiceError::StorageFull(message)),
+        None => Ok(())
     }
 }
 
@@ -251,6 +884,30 @@ pub struct SyncServiceReport {
     pub added_albums: BatchSaveReport,
     pub added_artists: BatchSaveReport,
 
+    /// Tracks whose file was found under a new path, (track id, new path). They keep their id and date added.
+    pub moved_tracks: Vec<(Uuid, PathBuf)>,
+
+    /// Tracks whose file was re-tagged, as stored after the update.
+    pub updated_tracks: Vec<Track>,
+
+    /// Audio files left alone because their size couldn't be read, a later sync picks them up.
+    pub skipped_files: Vec<PathBuf>,
+
+    /// Transactions committed by the sync: 1 for a regular sync, more with batched commits.
+    pub committed_batches: usize,
+
+    /// Audio files found in the library.
+    pub files_scanned: usize,
+
+    /// Walking the library and reading the tags.
+    pub scan_duration: Duration,
+    /// Working out the changes, including the lookups of the streaming strategy.
+    pub diff_duration: Duration,
+    /// Writing the changes, over all transactions.
+    pub commit_duration: Duration,
+    /// The whole `synchronize`, cache refresh included.
+    pub total_duration: Duration,
+
     pub timestamp: NaiveDateTime,
 }
 
@@ -265,83 +922,397 @@ impl SyncServiceReport {
             added_albums: BatchSaveReport::new(),
             added_artists: BatchSaveReport::new(),
 
+            moved_tracks: Vec::new(),
+            updated_tracks: Vec::new(),
+            skipped_files: Vec::new(),
+
+            committed_batches: 0,
+
+            files_scanned: 0,
+            scan_duration: Duration::ZERO,
+            diff_duration: Duration::ZERO,
+            commit_duration: Duration::ZERO,
+            total_duration: Duration::ZERO,
+
             timestamp
         }
     }
-}
 
-#[derive(Debug)]
-struct PendingAdditions {
-    artists: HashMap<String, Artist>,           // (artist_name) -> Artist
-    albums: HashMap<(String, Uuid), Album>,     // (album_name, artist_id) -> Album
-    tracks: HashSet<Track>
+    /// Rows added, deleted, moved or updated, over tracks, albums and artists. Failed rows are left out, see `failures`.
+    pub fn changes(&self) -> usize {
+        self.added_tracks.successful_ids().len() + self.added_albums.successful_ids().len() + self.added_artists.successful_ids().len()
+            + self.deleted_tracks.deleted_ids.len() + self.deleted_albums.deleted_ids.len() + self.deleted_artists.deleted_ids.len()
+            + self.moved_tracks.len() + self.updated_tracks.len()
+    }
+
+    /// Rows that failed to be added or deleted, over tracks, albums and artists.
+    pub fn failures(&self) -> usize {
+        self.added_tracks.failed().len() + self.added_albums.failed().len() + self.added_artists.failed().len()
+            + self.deleted_tracks.failed.len() + self.deleted_albums.failed.len() + self.deleted_artists.failed.len()
+    }
 }
 
-impl PendingAdditions {
+/// `Scanned 40,123 files in 12.0s, diffed in 0.3s, committed 412 changes in 1.1s (13.6s in total)`,
+/// followed by `, 3 failed` when some rows didn't make it and `, 2 skipped` when some files were left alone.
+impl fmt::Display for SyncServiceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = |duration: Duration| format!("{:.1}s", duration.as_secs_f64());
+
+        write!(
+            f,
+            "Scanned {} files in {}, diffed in {}, committed {} changes in {} ({} in total)",
+            with_thousands_separators(self.files_scanned), secs(self.scan_duration), secs(self.diff_duration),
+            with_thousands_separators(self.changes()), secs(self.commit_duration), secs(self.total_duration)
+        )?;
 
-    fn new() -> Self {
-        Self {
-            artists: HashMap::new(),
-            albums: HashMap::new(),
-            tracks: HashSet::new()
+        if self.failures() > 0 {
+            write!(f, ", {} failed", with_thousands_separators(self.failures()))?;
+        }
+        match self.skipped_files.len() {
+            0 => Ok(()),
+            skipped => write!(f, ", {} skipped", with_thousands_separators(skipped))
         }
     }
+}
 
-    fn is_empty(&self) -> bool {
-        self.artists.is_empty() && self.albums.is_empty() && self.tracks.is_empty()
-    }
+/// `40123` -> `40,123`
+fn with_thousands_separators(count: usize) -> String {
+    let digits = count.to_string();
+    let mut separated = String::with_capacity(digits.len() + digits.len() / 3);
 
-    fn add_track(&mut self, track: Track) -> () {
-        if !self.tracks.contains(&track) {
-            self.tracks.insert(track);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            separated.push(',');
         }
+        separated.push(digit);
     }
 
-    fn add_album(&mut self, album: Album) -> () {
-        self.albums.entry((album.name().to_string(), *album.artist_id())).or_insert(album);
-    }
+    separated
+}
 
-    fn add_artist(&mut self, artist: Artist) -> () {
-        self.artists.entry(artist.name().to_string()).or_insert(artist);
-    }
+/// What a sync would change, as worked out by `MusicLibSyncService::plan`.
+#[derive(Debug)]
+pub struct SyncPlan {
+    pub additions: PlannedAdditions,
+    pub deletions: PlannedDeletions,
 
-    fn find_album(&self, album_name: &str, artist_id: Uuid) -> Option<&Album> {
-        self.albums.get(&(album_name.to_string(), artist_id))
-    }
+    /// Tracks whose file was found under a new path, (old path, new path).
+    pub moved_tracks: Vec<(PathBuf, PathBuf)>,
 
-    fn find_artist(&self, artist_name: &str) -> Option<&Artist> {
-        self.artists.get(&artist_name.to_string())
-    }
+    /// Paths of the tracks whose tags changed.
+    pub updated_tracks: Vec<PathBuf>,
+
+    timings: PlanTimings,
+
+    changes: PendingChanges
 }
 
+/// How long `plan` took, carried over into the `SyncServiceReport`.
 #[derive(Debug)]
-struct PendingDeletions {
-    track_ids: Vec<Uuid>,
-    album_ids: Vec<Uuid>,
-    artist_ids: Vec<Uuid>
+struct PlanTimings {
+    files_scanned: usize,
+    scan_duration: Duration,
+    diff_duration: Duration
 }
 
-impl PendingDeletions {
-    fn new() -> Self {
-        Self {
-            track_ids: Vec::new(),
-            album_ids: Vec::new(),
-            artist_ids: Vec::new()
-        }
-    }
+/// New rows, tracks by path and albums and artists by name.
+#[derive(Debug)]
+pub struct PlannedAdditions {
+    pub tracks: Vec<PathBuf>,
+    pub albums: Vec<String>,
+    pub artists: Vec<String>
+}
 
-    fn is_empty(&self) -> bool {
-        self.track_ids.is_empty() && self.album_ids.is_empty() && self.artist_ids.is_empty()
-    }
+/// Rows left without a file, or without tracks.
+#[derive(Debug)]
+pub struct PlannedDeletions {
+    /// (track id, path of the missing file)
+    pub tracks: Vec<(Uuid, PathBuf)>,
+    pub album_ids: Vec<Uuid>,
+    pub artist_ids: Vec<Uuid>
 }
 
-struct DatabaseCache {
-    tracks: HashMap<PathBuf, Track>,                // PathBuf -> Track
-    albums: HashMap<(String, Uuid), Album>,         // (album_name, artist_id) -> Album
-    artists: HashMap<String, Artist>,               // artist_name -> Artist
+impl SyncPlan {
+    fn new(changes: PendingChanges, timings: PlanTimings) -> Self {
+        let stored_paths: HashMap<&Uuid, &PathBuf> = changes.missing.iter()
+            .map(|track| (track.id(), track.file_path()))
+            .collect();
+
+        let mut additions = PlannedAdditions {
+            tracks: changes.additions.tracks.iter().map(|track| track.file_path().clone()).collect(),
+            albums: changes.additions.albums.values().map(|album| album.name().to_string()).collect(),
+            artists: changes.additions.artists.keys().cloned().collect()
+        };
+        additions.tracks.sort();
+        additions.albums.sort();
+        additions.artists.sort();
+
+        let mut deletions = PlannedDeletions {
+            tracks: changes.deletions.track_ids.iter()
+                .filter_map(|track_id| stored_paths.get(track_id).map(|path| (*track_id, (*path).clone())))
+                .collect(),
+            album_ids: changes.deletions.album_ids.clone(),
+            artist_ids: changes.deletions.artist_ids.clone()
+        };
+        deletions.tracks.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+        let mut moved_tracks: Vec<(PathBuf, PathBuf)> = changes.moves.iter()
+            .filter_map(|(track_id, new_path)| stored_paths.get(track_id).map(|old_path| ((*old_path).clone(), new_path.clone())))
+            .collect();
+        moved_tracks.sort();
+
+        let mut updated_tracks: Vec<PathBuf> = changes.updates.iter().map(|update| update.track.file_path().clone()).collect();
+        updated_tracks.sort();
+
+        Self { additions, deletions, moved_tracks, updated_tracks, timings, changes }
+    }
+
+    /// `true` if the database is already in sync with the library.
+    pub fn is_empty(&self) -> bool {
+        self.additions.tracks.is_empty() && self.additions.albums.is_empty() && self.additions.artists.is_empty()
+            && self.deletions.tracks.is_empty() && self.deletions.album_ids.is_empty() && self.deletions.artist_ids.is_empty()
+            && self.moved_tracks.is_empty() && self.updated_tracks.is_empty()
+    }
+}
+
+/// Scans the library with the settings from `config`, the same ones a sync uses, and works out how it
+/// differs from the database, without changing anything.
+///
+/// # Errors
+///
+/// Same as `MusicLibSyncService::from_config` and `MusicLibSyncService::plan`.
+pub async fn compute_diff(pool: &SqlitePool, config: &Config) -> Result<SyncDiff, SyncServiceError> {
+    MusicLibSyncService::from_config(pool, config).await?.diff().await
+}
+
+/// How the library differs from the database, as found by `MusicLibSyncService::diff`. Paths are relative
+/// to the library, the way `TrackDto` has them; one outside of it is kept whole.
+#[derive(Debug, Serialize)]
+pub struct SyncDiff {
+    pub counts: SyncDiffCounts,
+
+    /// Audio files no track points to yet.
+    pub new_tracks: Vec<PathBuf>,
+
+    /// Tracks whose file is gone, (track id, stored path). Files that were only moved are in `moved_tracks`.
+    pub missing_tracks: Vec<(Uuid, PathBuf)>,
+
+    /// Tracks whose file was found under a new path, (stored path, new path).
+    pub moved_tracks: Vec<(PathBuf, PathBuf)>,
+
+    /// Paths of the tracks whose tags changed.
+    pub updated_tracks: Vec<PathBuf>,
+
+    /// Albums and artists with no tracks left once the missing ones are gone.
+    pub orphaned_album_ids: Vec<Uuid>,
+    pub orphaned_artist_ids: Vec<Uuid>
+}
+
+/// The sizes of the `SyncDiff` lists, for when the paths themselves aren't needed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SyncDiffCounts {
+    pub new_tracks: usize,
+    pub missing_tracks: usize,
+    pub moved_tracks: usize,
+    pub updated_tracks: usize,
+    pub orphaned_albums: usize,
+    pub orphaned_artists: usize
+}
+
+impl SyncDiff {
+    fn new(plan: SyncPlan, music_root: &Path) -> Self {
+        let relative = |path: PathBuf| relative_to(&path, music_root).unwrap_or(path);
+
+        let counts = SyncDiffCounts {
+            new_tracks: plan.additions.tracks.len(),
+            missing_tracks: plan.deletions.tracks.len(),
+            moved_tracks: plan.moved_tracks.len(),
+            updated_tracks: plan.updated_tracks.len(),
+            orphaned_albums: plan.deletions.album_ids.len(),
+            orphaned_artists: plan.deletions.artist_ids.len()
+        };
+
+        Self {
+            counts,
+            new_tracks: plan.additions.tracks.into_iter().map(relative).collect(),
+            missing_tracks: plan.deletions.tracks.into_iter().map(|(id, path)| (id, relative(path))).collect(),
+            moved_tracks: plan.moved_tracks.into_iter().map(|(old, new)| (relative(old), relative(new))).collect(),
+            updated_tracks: plan.updated_tracks.into_iter().map(relative).collect(),
+            orphaned_album_ids: plan.deletions.album_ids,
+            orphaned_artist_ids: plan.deletions.artist_ids
+        }
+    }
+}
+
+/// Where the database and the library disagree, as found by `MusicLibSyncService::verify`.
+/// Paths are relative to the library, as in `SyncDiff`.
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// Tracks whose file is gone, (track id, stored path). Files that were only moved are in `moved_files`.
+    pub missing_files: Vec<(Uuid, PathBuf)>,
+
+    /// Audio files in the library no track points to.
+    pub untracked_files: Vec<PathBuf>,
+
+    /// Tracks whose file was found under a new path, (stored path, new path).
+    pub moved_files: Vec<(PathBuf, PathBuf)>,
+
+    /// Paths of the tracks whose tags changed since they were stored.
+    pub stale_tags: Vec<PathBuf>,
+
+    /// Albums and artists with no tracks left once the missing ones are gone.
+    pub orphaned_album_ids: Vec<Uuid>,
+    pub orphaned_artist_ids: Vec<Uuid>,
+
+    /// Albums and artists that have no tracks already, see `empty_albums` and `empty_artists` of the repositories.
+    pub empty_album_ids: Vec<Uuid>,
+    pub empty_artist_ids: Vec<Uuid>
+}
+
+impl VerifyReport {
+    /// How many problems were found, over all categories.
+    pub fn inconsistencies(&self) -> usize {
+        self.missing_files.len() + self.untracked_files.len() + self.moved_files.len() + self.stale_tags.len()
+            + self.orphaned_album_ids.len() + self.orphaned_artist_ids.len()
+            + self.empty_album_ids.len() + self.empty_artist_ids.len()
+    }
+
+    /// `true` if the database matches the library.
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies() == 0
+    }
+}
+
+/// The scanned library boiled down to what the diff needs, filled one descriptor at a time
+/// so it works the same for a buffered and a pipelined scan.
+struct ScannedLibrary {
+    /// Every audio file on disk.
+    paths: HashSet<PathBuf>,
+    /// Files not in the database yet, in scan order.
+    unsynced: Vec<AudioFileDescriptor>,
+    /// Files already in the database, checked for changed tags.
+    synced: Vec<AudioFileDescriptor>,
+    /// Files whose size couldn't be read, neither added nor updated this time.
+    skipped: Vec<PathBuf>
+}
+
+impl ScannedLibrary {
+    fn new() -> Self {
+        Self { paths: HashSet::new(), unsynced: Vec::new(), synced: Vec::new(), skipped: Vec::new() }
+    }
+
+    /// A file the scanner couldn't stat comes with a size of 0, which no track may have. It still counts
+    /// as present, so a stored track isn't deleted over it, and the next sync gets another go at it.
+    fn push(&mut self, db_cache: &DatabaseCache, descriptor: AudioFileDescriptor) {
+        self.paths.insert(descriptor.path.clone());
+
+        if descriptor.file_size == 0 {
+            log::warn!("Skipping {}, its size couldn't be read.", descriptor.path.display());
+            self.skipped.push(descriptor.path);
+            return;
+        }
+
+        if db_cache.tracks.contains_key(&descriptor.path) {
+            self.synced.push(descriptor);
+        } else {
+            self.unsynced.push(descriptor);
+        }
+    }
+}
+
+/// Everything `difference` found, applied together by `synchronize`.
+#[derive(Debug)]
+struct PendingChanges {
+    additions: PendingAdditions,
+    deletions: PendingDeletions,
+    /// (id of the stored track, path it moved to)
+    moves: Vec<(Uuid, PathBuf)>,
+    updates: Vec<TrackUpdate>,
+    /// Stored tracks whose file is gone, the moved ones included.
+    missing: Vec<Track>,
+    /// See `ScannedLibrary::skipped`.
+    skipped: Vec<PathBuf>
+}
+
+/// A re-tagged track, as it should be stored.
+#[derive(Debug)]
+struct TrackUpdate {
+    /// Album the track is stored under now, it may be left empty by the update.
+    previous_album_id: Uuid,
+    track: Track
+}
+
+#[derive(Debug)]
+struct PendingAdditions {
+    artists: HashMap<String, Artist>,           // (artist_name) -> Artist
+    albums: HashMap<(String, Uuid), Album>,     // (album_name, artist_id) -> Album
+    tracks: HashSet<Track>
+}
+
+impl PendingAdditions {
+
+    fn new() -> Self {
+        Self {
+            artists: HashMap::new(),
+            albums: HashMap::new(),
+            tracks: HashSet::new()
+        }
+    }
+
+    fn add_track(&mut self, track: Track) -> () {
+        if !self.tracks.contains(&track) {
+            self.tracks.insert(track);
+        }
+    }
+
+    fn add_album(&mut self, album: Album) -> () {
+        self.albums.entry((album.name().to_string(), *album.artist_id())).or_insert(album);
+    }
+
+    fn add_artist(&mut self, artist: Artist) -> () {
+        self.artists.entry(artist.name().to_string()).or_insert(artist);
+    }
+
+    fn find_album(&self, album_name: &str, artist_id: Uuid) -> Option<&Album> {
+        self.albums.get(&(album_name.to_string(), artist_id))
+    }
+
+    fn find_artist(&self, artist_name: &str) -> Option<&Artist> {
+        self.artists.get(&artist_name.to_string())
+    }
+}
+
+#[derive(Debug)]
+struct PendingDeletions {
+    track_ids: Vec<Uuid>,
+    album_ids: Vec<Uuid>,
+    artist_ids: Vec<Uuid>
+}
+
+impl PendingDeletions {
+    fn new() -> Self {
+        Self {
+            track_ids: Vec::new(),
+            album_ids: Vec::new(),
+            artist_ids: Vec::new()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.track_ids.is_empty() && self.album_ids.is_empty() && self.artist_ids.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.track_ids.len() + self.album_ids.len() + self.artist_ids.len()
+    }
+}
+
+struct DatabaseCache {
+    tracks: HashMap<PathBuf, Track>,                // PathBuf -> Track, empty with SyncStrategy::Streaming
+    albums: HashMap<(String, Uuid), Album>,         // (album_name, artist_id) -> Album
+    artists: HashMap<String, Artist>,               // artist_name -> Artist
 
     // lookup tables
-    album_to_track_ids: HashMap<Uuid, Vec<Uuid>>,   // ablum_id -> Vec<track_id> of Tracks that has given album_id
     artist_to_album_ids: HashMap<Uuid, Vec<Uuid>>,  // artist_id -> Vec<album_id> of Albums that has given artist_id
 }
 
@@ -352,7 +1323,7 @@ pub mod tests {
     use tempfile::TempDir;
 
     use super::*;
-    use crate::{domain::audiofile::{AudioFileMetadata, AudioFileType}, services::test_helpers::*, utils::{audio_fixtures::{load_fixtures, AudioFixture}, normalizations::{normalize_path}}};
+    use crate::{domain::{audiofile::{AudioFileMetadata, AudioFileType}, BatchSaveOutcome}, services::test_helpers::*};
 
     struct TestContext {
         pool: SqlitePool,
@@ -360,7 +1331,6 @@ pub mod tests {
         alb_repo: SqliteAlbumsRepository,
         art_repo: SqliteArtistsRepository,
         temp_dir: TempDir,
-        vault: Vec<AudioFixture>,
         fixtures: Vec<PathBuf>
     }
 
@@ -373,36 +1343,15 @@ pub mod tests {
                     alb_repo: SqliteAlbumsRepository::new(),
                     art_repo: SqliteArtistsRepository::new(),
                     temp_dir: tempfile::tempdir()?,
-                    vault: load_fixtures(&Path::new(TEST_FIXTURES_JSON_PATH))?,
                     fixtures: Vec::new()
                 }
             )
         }
 
         fn with_fixtures(mut self, fixture_file_names: &[FixtureFileNames]) -> Result<Self, TestSetupError> {
-            let fixture_file_names = fixture_file_names.into_iter().map(|ffm| ffm.file_name()).collect::<Vec<_>>();
-            let selected = self.vault.iter().filter(|fxtr| fixture_file_names.contains(&fxtr.file_name));
-
-            let mut new_paths = Vec::new();
-
-            for src in selected {
-                // safe unwrap since it was already unwrapped in the loop above
-                let dest = self.temp_dir.path().join(&src.file_name);
-                let src = PathBuf::from(format!("./test_fixtures/files/{}", &src.file_name));
-
-                fs::copy(&src, &dest)?;
-                new_paths.push(normalize_path(&dest));
-            }
-
-            self.fixtures = new_paths;
+            self.fixtures = copy_fixtures(self.temp_dir.path(), fixture_file_names)?;
             Ok(self)
         }
-
-        fn get_metadata(&self, fixture_name: FixtureFileNames) -> Result<&AudioFileMetadata, TestSetupError> {
-            self.vault.iter().find(|fxtr| fxtr.file_name == fixture_name.file_name())
-                .ok_or(TestSetupError::FixtureMetadataDoesntExist(fixture_name.file_name()))
-                .map(|fxtr| &fxtr.metadata)
-        }
     }
 
     #[tokio::test]
@@ -411,7 +1360,7 @@ pub mod tests {
 
         // Creating ctx with tempdir that has one audiofiles in it
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::ChevelleClosure])?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
@@ -427,7 +1376,7 @@ pub mod tests {
             &closure_metadata.track_name,
             *wonder_whats_next.id(),
             closure_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.as_str()),
             420,
             AudioFileType::Mp3,
             Uploaded::Denis,
@@ -440,7 +1389,7 @@ pub mod tests {
         // Expected behavior - sync service does nothing.
 
         // Create sync service and run it
-        let sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = sync_service.synchronize().await?;
 
         // Assert that report has nothing.
@@ -473,7 +1422,7 @@ pub mod tests {
         init_logger()?;
 
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::FlacValidMetadata])?;
-        let synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = synch_service.synchronize().await?;
 
         // Assert nothing was deleted. Since DB was empty, nothing else could be done besides checking the report.
@@ -520,15 +1469,15 @@ pub mod tests {
 
         // Creating ctx with tempdir that has two audiofiles in it
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::ChevelleClosure, FixtureFileNames::ChevelleForfeit])?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
-        let forfeit_metadata = ctx.get_metadata(FixtureFileNames::ChevelleForfeit)?;
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
+        let forfeit_metadata = FixtureFileNames::ChevelleForfeit.get_metadata()?;
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
         ctx.art_repo.save(&ctx.pool, &chevelle).await?;
 
         // Adding new album and two tracks, and generating a report.
-        let synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = synch_service.synchronize().await?;
 
         // Asserting that report has new album and new tracks added.
@@ -565,8 +1514,8 @@ pub mod tests {
 
         // Creating ctx with tempdir that has two audiofiles in it
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::ChevelleClosure, FixtureFileNames::ChevelleForfeit])?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
-        let forfeit_metadata = ctx.get_metadata(FixtureFileNames::ChevelleForfeit)?;
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
+        let forfeit_metadata = FixtureFileNames::ChevelleForfeit.get_metadata()?;
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
@@ -577,7 +1526,7 @@ pub mod tests {
         ctx.alb_repo.save(&ctx.pool, &wonder_whats_next).await?;
 
         // Adding two new tracks to existing album with existing artist.
-        let synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut synch_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = synch_service.synchronize().await?;
 
         // Asserting that the report is valid.
@@ -611,8 +1560,8 @@ pub mod tests {
 
         // Creating ctx with tempdir that has one audiofiles in it
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::ChevelleClosure])?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
-        let forfeit_metadata = ctx.get_metadata(FixtureFileNames::ChevelleForfeit)?; // <- this track has no audifile associated with it
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
+        let forfeit_metadata = FixtureFileNames::ChevelleForfeit.get_metadata()?; // <- this track has no audifile associated with it
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
@@ -628,7 +1577,7 @@ pub mod tests {
             &closure_metadata.track_name,
             *wonder_whats_next.id(),
             closure_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.as_str()),
             420,
             AudioFileType::Flac,
             Uploaded::Denis,
@@ -640,7 +1589,7 @@ pub mod tests {
             &forfeit_metadata.track_name,
             *wonder_whats_next.id(),
             forfeit_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleForfeit.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleForfeit.as_str()),
             420,
             AudioFileType::Flac,
             Uploaded::Denis,
@@ -655,7 +1604,7 @@ pub mod tests {
         // - flac_valid_metadata2.flac has associated row inside the DB, but the file is missing.
 
         // Create sync service and run it
-        let sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = sync_service.synchronize().await?;
 
         // Assert that report has only one thing in it: deleted one track entry from a DB
@@ -690,8 +1639,8 @@ pub mod tests {
 
         // Creating ctx with tempdir that has one audiofiles in it
         let ctx = TestContext::new().await?.with_fixtures(&[FixtureFileNames::ChevelleClosure])?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
-        let forfeit_metadata = ctx.get_metadata(FixtureFileNames::ChevelleForfeit)?;
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
+        let forfeit_metadata = FixtureFileNames::ChevelleForfeit.get_metadata()?;
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
@@ -709,7 +1658,7 @@ pub mod tests {
             &closure_metadata.track_name,
             *wonder_whats_next.id(),
             closure_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.as_str()),
             420,
             AudioFileType::Flac,
             Uploaded::Denis,
@@ -721,7 +1670,7 @@ pub mod tests {
             &forfeit_metadata.track_name,
             *should_be_deleted.id(),
             forfeit_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleForfeit.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleForfeit.as_str()),
             420,
             AudioFileType::Flac,
             Uploaded::Denis,
@@ -735,7 +1684,7 @@ pub mod tests {
         // Expected behavior: delete row from a DB without corresponding audiofile and then cascade deletion of the album 'should_be_deleted'
 
         // Create sync service and run it
-        let sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = sync_service.synchronize().await?;
 
         // Assert that report has exactly two things deleted: one track and one album.
@@ -772,7 +1721,7 @@ pub mod tests {
 
         // Create ctx with empty tempdir.
         let ctx = TestContext::new().await?;
-        let closure_metadata = ctx.get_metadata(FixtureFileNames::ChevelleClosure)?;
+        let closure_metadata = FixtureFileNames::ChevelleClosure.get_metadata()?;
 
         // Create New Artist and add it to the DB.
         let chevelle = Artist::new(Uuid::new_v4(), &closure_metadata.artist_name)?;
@@ -788,7 +1737,7 @@ pub mod tests {
             &closure_metadata.track_name,
             *wonder_whats_next.id(),
             closure_metadata.track_duration,
-            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.file_name()),
+            ctx.temp_dir.path().join(FixtureFileNames::ChevelleClosure.as_str()),
             420,
             AudioFileType::Flac,
             Uploaded::Denis,
@@ -801,7 +1750,7 @@ pub mod tests {
         // Expected behavior: delete orphaned album and cascade delete artist.
 
         // Create sync service and run it
-        let sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
         let report = sync_service.synchronize().await?;
 
         // Assert that report has exactly two things deleted: one album and one artist.
@@ -815,4 +1764,732 @@ pub mod tests {
 
         Ok(())
     }
+
+    /// A valid PCM wav with `secs` seconds of silence and a RIFF INFO title,
+    /// so the sync can be exercised without the generated fixtures.
+    fn write_silent_wav(path: &Path, title: &str, secs: u32) -> Result<(), TestSetupError> {
+        write_tagged_wav(path, &[(b"INAM", title)], secs)
+    }
+
+    fn write_tagged_wav(path: &Path, tags: &[(&[u8; 4], &str)], secs: u32) -> Result<(), TestSetupError> {
+        let sample_rate: u32 = 8000;
+        let data_len = sample_rate * 2 * secs;
+
+        // INFO strings are nul terminated and chunks are padded to an even size
+        let mut info = b"INFO".to_vec();
+        for (id, value) in tags {
+            let mut value_bytes = value.as_bytes().to_vec();
+            value_bytes.push(0);
+            info.extend_from_slice(*id);
+            info.extend_from_slice(&(value_bytes.len() as u32).to_le_bytes());
+            if value_bytes.len() % 2 == 1 {
+                value_bytes.push(0);
+            }
+            info.extend_from_slice(&value_bytes);
+        }
+        let list_len = info.len() as u32;
+
+        let mut bytes = Vec::with_capacity(44 + data_len as usize);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + 8 + list_len + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());               // PCM
+        bytes.extend_from_slice(&1u16.to_le_bytes());               // mono
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());  // byte rate
+        bytes.extend_from_slice(&2u16.to_le_bytes());               // block align
+        bytes.extend_from_slice(&16u16.to_le_bytes());              // bits per sample
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&list_len.to_le_bytes());
+        bytes.extend_from_slice(&info);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes.resize(bytes.len() + data_len as usize, 0);
+
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_second_sync_is_no_op() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        write_silent_wav(&ctx.temp_dir.path().join("first.wav"), "first", 1)?;
+        write_silent_wav(&ctx.temp_dir.path().join("second.wav"), "second", 2)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+
+        let first_report = sync_service.synchronize().await?;
+        assert_eq!(first_report.added_tracks.outcomes.len(), 2);
+        assert!(first_report.added_tracks.outcomes.iter().all(|outcome| outcome.result.is_ok()));
+
+        // with a stale cache this would try to insert both tracks again and fail on the unique path
+        let second_report = sync_service.synchronize().await?;
+        assert!(second_report.added_artists.outcomes.is_empty());
+        assert!(second_report.added_albums.outcomes.is_empty());
+        assert!(second_report.added_tracks.outcomes.is_empty());
+        assert!(second_report.deleted_tracks.deleted_ids.is_empty());
+        assert!(second_report.deleted_albums.deleted_ids.is_empty());
+        assert!(second_report.deleted_artists.deleted_ids.is_empty());
+
+        let tracks_in_db = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(tracks_in_db.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_commits_tracks_in_batches() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for (idx, title) in ["one", "two", "three", "four", "five"].iter().enumerate() {
+            write_silent_wav(&ctx.temp_dir.path().join(format!("{}.wav", idx)), title, 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_batch_commit_size(Some(2));
+        let report = sync_service.synchronize().await?;
+
+        // artists and albums, then tracks as 2 + 2 + 1
+        assert_eq!(report.committed_batches, 4);
+        assert_eq!(report.added_tracks.outcomes.len(), 5);
+        assert!(report.added_tracks.outcomes.iter().all(|outcome| outcome.result.is_ok()));
+
+        let tracks_in_db = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(tracks_in_db.len(), 5);
+
+        let second_report = sync_service.synchronize().await?;
+        assert!(second_report.added_tracks.outcomes.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_report_has_phase_timings() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        write_silent_wav(&ctx.temp_dir.path().join("first.wav"), "first", 1)?;
+        write_silent_wav(&ctx.temp_dir.path().join("second.wav"), "second", 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let report = sync_service.synchronize().await?;
+
+        assert_eq!(report.files_scanned, 2);
+        assert!(report.changes() >= 2);
+        assert!(report.total_duration > Duration::ZERO);
+        assert!(report.total_duration >= report.scan_duration + report.diff_duration + report.commit_duration);
+
+        let summary = report.to_string();
+        assert!(summary.starts_with("Scanned 2 files in "), "{}", summary);
+        assert!(summary.contains(&format!("committed {} changes", report.changes())), "{}", summary);
+
+        assert_eq!(with_thousands_separators(0), "0");
+        assert_eq!(with_thousands_separators(412), "412");
+        assert_eq!(with_thousands_separators(40123), "40,123");
+        assert_eq!(with_thousands_separators(1234567), "1,234,567");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_stores_file_mtime() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let file_path = ctx.temp_dir.path().join("dated.wav");
+        write_silent_wav(&file_path, "dated", 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+
+        let expected = DateTime::<Utc>::from(fs::metadata(&file_path)?.modified()?).naive_utc();
+        let tracks_in_db = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+
+        assert_eq!(tracks_in_db.len(), 1);
+        assert_eq!(tracks_in_db[0].file_mtime(), &Some(expected));
+
+        Ok(())
+    }
+
+    async fn album_artist_names(ctx: &TestContext) -> Result<HashMap<String, String>, TestSetupError> {
+        let artists: HashMap<Uuid, String> = ctx.art_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?
+            .into_iter()
+            .map(|artist| (*artist.id(), artist.name().to_string()))
+            .collect();
+
+        Ok(ctx.alb_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?
+            .into_iter()
+            .map(|album| (album.name().to_string(), artists[album.artist_id()].clone()))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_groups_featuring_credits_under_dominant_artist() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let album_dir = ctx.temp_dir.path().join("mixed");
+        fs::create_dir(&album_dir)?;
+
+        let credits = [("one", "Chevelle"), ("two", "Chevelle"), ("three", "Chevelle"), ("four", "Chevelle feat. Someone")];
+        for (title, artist) in credits {
+            write_tagged_wav(&album_dir.join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Mixed")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_dominant_artist_threshold(Some(60));
+        sync_service.synchronize().await?;
+
+        let albums = album_artist_names(&ctx).await?;
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums["mixed"], "chevelle");
+
+        let artists = ctx.art_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(artists.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_falls_back_to_various_artists() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let album_dir = ctx.temp_dir.path().join("compilation");
+        fs::create_dir(&album_dir)?;
+
+        let credits = [("one", "Chevelle"), ("two", "Deftones"), ("three", "Tool")];
+        for (title, artist) in credits {
+            write_tagged_wav(&album_dir.join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Compilation")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_dominant_artist_threshold(Some(60));
+        sync_service.synchronize().await?;
+
+        let albums = album_artist_names(&ctx).await?;
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums["compilation"], VARIOUS_ARTISTS);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_without_threshold_keeps_track_artists() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let credits = [("one", "Chevelle"), ("two", "Deftones")];
+        for (title, artist) in credits {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Split")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+
+        let artists = ctx.art_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(artists.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_pipelined_scan_matches_buffered() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let credits = [("one", "Chevelle", "Closure"), ("two", "Chevelle", "Closure"), ("three", "Deftones", "Diamond Eyes"), ("four", "Tool", "Lateralus")];
+        for (title, artist, album) in credits {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", album)], 1)?;
+        }
+        fs::write(ctx.temp_dir.path().join("cover.jpg"), "not audio")?;
+
+        let buffered_pool = prepare_db().await.expect("Failed to prepare the test db");
+        let mut buffered = MusicLibSyncService::new(&buffered_pool, ctx.temp_dir.path().to_path_buf()).await?;
+        buffered.synchronize().await?;
+
+        // a capacity of one makes the scanner wait for the diff after every file
+        let mut pipelined = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_scan_pipeline(Some(1));
+        let report = pipelined.synchronize().await?;
+        assert_eq!(report.added_tracks.outcomes.len(), 4);
+        assert_eq!(report.added_albums.outcomes.len(), 3);
+        assert_eq!(report.added_artists.outcomes.len(), 3);
+
+        let track_paths = |tracks: Vec<Track>| tracks.into_iter().map(|track| track.file_path().clone()).collect::<HashSet<_>>();
+        let pipelined_tracks = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        let buffered_tracks = ctx.trk_repo.stream_all(&buffered_pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(track_paths(pipelined_tracks), track_paths(buffered_tracks));
+
+        let pipelined_albums = album_artist_names(&ctx).await?;
+        let buffered_ctx = TestContext { pool: buffered_pool, ..TestContext::new().await? };
+        assert_eq!(pipelined_albums, album_artist_names(&buffered_ctx).await?);
+
+        // deletions are found from the streamed paths too
+        fs::remove_file(ctx.temp_dir.path().join("four.wav"))?;
+        let report = pipelined.synchronize().await?;
+        assert_eq!(report.deleted_tracks.deleted_ids.len(), 1);
+        assert_eq!(report.deleted_albums.deleted_ids.len(), 1);
+        assert_eq!(report.deleted_artists.deleted_ids.len(), 1);
+        assert!(report.added_tracks.outcomes.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_detects_moved_track() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let old_path = ctx.temp_dir.path().join("one.wav");
+        write_tagged_wav(&old_path, &[(b"INAM", "one"), (b"IART", "Chevelle"), (b"IPRD", "Closure")], 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+        let before = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(before.len(), 1);
+
+        let new_dir = ctx.temp_dir.path().join("closure");
+        fs::create_dir(&new_dir)?;
+        fs::rename(&old_path, new_dir.join("01 one.wav"))?;
+
+        let report = sync_service.synchronize().await?;
+        assert_eq!(report.moved_tracks.len(), 1);
+        assert_eq!(report.moved_tracks[0].0, *before[0].id());
+        assert!(report.added_tracks.outcomes.is_empty());
+        assert!(report.deleted_tracks.deleted_ids.is_empty());
+        assert!(report.deleted_albums.deleted_ids.is_empty());
+
+        let after = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].id(), before[0].id());
+        assert_eq!(after[0].date_added(), before[0].date_added());
+        assert_eq!(after[0].file_path(), &report.moved_tracks[0].1);
+        assert!(after[0].file_path().ends_with("01 one.wav"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_ambiguous_move_falls_back_to_delete_and_add() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let tags: &[(&[u8; 4], &str)] = &[(b"INAM", "one"), (b"IART", "Chevelle"), (b"IPRD", "Closure")];
+        write_tagged_wav(&ctx.temp_dir.path().join("a.wav"), tags, 1)?;
+        write_tagged_wav(&ctx.temp_dir.path().join("b.wav"), tags, 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+
+        fs::rename(ctx.temp_dir.path().join("a.wav"), ctx.temp_dir.path().join("c.wav"))?;
+        fs::rename(ctx.temp_dir.path().join("b.wav"), ctx.temp_dir.path().join("d.wav"))?;
+
+        let report = sync_service.synchronize().await?;
+        assert!(report.moved_tracks.is_empty());
+        assert_eq!(report.deleted_tracks.deleted_ids.len(), 2);
+        assert_eq!(report.added_tracks.outcomes.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_updates_retagged_track_name() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let path = ctx.temp_dir.path().join("one.wav");
+        write_tagged_wav(&path, &[(b"INAM", "one"), (b"IART", "Chevelle"), (b"IPRD", "Closure")], 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+        let before = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+
+        write_tagged_wav(&path, &[(b"INAM", "uno"), (b"IART", "Chevelle"), (b"IPRD", "Closure")], 1)?;
+
+        let report = sync_service.synchronize().await?;
+        assert_eq!(report.updated_tracks.len(), 1);
+        assert!(report.added_tracks.outcomes.is_empty());
+        assert!(report.deleted_tracks.deleted_ids.is_empty());
+        assert!(report.added_albums.outcomes.is_empty());
+        assert!(report.deleted_albums.deleted_ids.is_empty());
+
+        let after = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].id(), before[0].id());
+        assert_eq!(after[0].name(), "uno");
+        assert_eq!(after[0].album_id(), before[0].album_id());
+        assert_eq!(after[0].date_added(), before[0].date_added());
+
+        let report = sync_service.synchronize().await?;
+        assert!(report.updated_tracks.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_moves_retagged_track_to_new_album_and_artist() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let path = ctx.temp_dir.path().join("one.wav");
+        write_tagged_wav(&path, &[(b"INAM", "one"), (b"IART", "Chevelle"), (b"IPRD", "Closure")], 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+        let old_album = ctx.alb_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.remove(0);
+
+        write_tagged_wav(&path, &[(b"INAM", "one"), (b"IART", "Deftones"), (b"IPRD", "Diamond Eyes")], 1)?;
+
+        let report = sync_service.synchronize().await?;
+        assert_eq!(report.updated_tracks.len(), 1);
+        assert_eq!(report.added_albums.outcomes.len(), 1);
+        assert_eq!(report.added_artists.outcomes.len(), 1);
+        assert_eq!(report.deleted_albums.deleted_ids, vec![*old_album.id()]);
+        assert_eq!(report.deleted_artists.deleted_ids, vec![*old_album.artist_id()]);
+        assert!(report.deleted_tracks.deleted_ids.is_empty());
+
+        let albums = album_artist_names(&ctx).await?;
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums["diamond eyes"], "deftones");
+
+        let tracks = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(tracks[0].album_id(), report.updated_tracks[0].album_id());
+        assert_ne!(tracks[0].album_id(), old_album.id());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_grouped_album_is_not_updated() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let credits = [("one", "Chevelle"), ("two", "Chevelle"), ("three", "Chevelle feat. Someone")];
+        for (title, artist) in credits {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Mixed")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_dominant_artist_threshold(Some(60));
+        sync_service.synchronize().await?;
+
+        let report = sync_service.synchronize().await?;
+        assert!(report.updated_tracks.is_empty());
+        assert!(report.deleted_albums.deleted_ids.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_plan_leaves_db_untouched() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for (title, artist) in [("one", "Chevelle"), ("two", "Deftones")] {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Split")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let plan = sync_service.plan().await?;
+        assert_eq!(plan.additions.tracks.len(), 2);
+        assert_eq!(plan.additions.albums.len(), 2);
+        assert_eq!(plan.additions.artists, vec!["chevelle".to_string(), "deftones".to_string()]);
+        assert!(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.is_empty());
+
+        let report = sync_service.synchronize().await?;
+        assert_eq!(report.added_tracks.outcomes.len(), plan.additions.tracks.len());
+
+        fs::remove_file(ctx.temp_dir.path().join("two.wav"))?;
+        let plan = sync_service.plan().await?;
+        assert!(plan.additions.tracks.is_empty());
+        assert_eq!(plan.deletions.tracks.len(), 1);
+        assert!(plan.deletions.tracks[0].1.ends_with("two.wav"));
+        assert_eq!(plan.deletions.album_ids.len(), 1);
+        assert_eq!(plan.deletions.artist_ids.len(), 1);
+        assert_eq!(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.len(), 2);
+
+        sync_service.synchronize().await?;
+        assert!(sync_service.plan().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_streaming_matches_cache_all() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let root = ctx.temp_dir.path();
+        let credits = [("one", "Chevelle", "Closure"), ("two", "Chevelle", "Closure"), ("three", "Deftones", "Diamond Eyes"), ("four", "Tool", "Lateralus")];
+        for (title, artist, album) in credits {
+            write_tagged_wav(&root.join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", album)], 1)?;
+        }
+
+        let cached_pool = prepare_db().await.expect("Failed to prepare the test db");
+        let cached_ctx = TestContext { pool: cached_pool, ..TestContext::new().await? };
+        let mut cached = MusicLibSyncService::new(&cached_ctx.pool, root.to_path_buf()).await?;
+        let mut streaming = MusicLibSyncService::new_with_strategy(&ctx.pool, root.to_path_buf(), SyncStrategy::Streaming).await?;
+
+        let (cached_report, streaming_report) = (cached.synchronize().await?, streaming.synchronize().await?);
+        assert_eq!(streaming_report.added_tracks.outcomes.len(), 4);
+        assert_eq!(streaming_report.added_albums.outcomes.len(), cached_report.added_albums.outcomes.len());
+        assert!(streaming.synchronize().await?.updated_tracks.is_empty());
+
+        // one of each: a deletion cascading to album and artist, a re-tag leaving an album empty, a move and an addition
+        fs::remove_file(root.join("four.wav"))?;
+        write_tagged_wav(&root.join("three.wav"), &[(b"INAM", "three"), (b"IART", "Deftones"), (b"IPRD", "Koi No Yokan")], 1)?;
+        fs::create_dir(root.join("closure"))?;
+        fs::rename(root.join("one.wav"), root.join("closure").join("one.wav"))?;
+        write_tagged_wav(&root.join("five.wav"), &[(b"INAM", "five"), (b"IART", "Karnivool"), (b"IPRD", "Sound Awake")], 1)?;
+
+        let (cached_report, streaming_report) = (cached.synchronize().await?, streaming.synchronize().await?);
+        for report in [&cached_report, &streaming_report] {
+            assert_eq!(report.added_tracks.outcomes.len(), 1);
+            assert_eq!(report.deleted_tracks.deleted_ids.len(), 1);
+            assert_eq!(report.moved_tracks.len(), 1);
+            assert_eq!(report.updated_tracks.len(), 1);
+            assert_eq!(report.added_albums.outcomes.len(), 2);
+            assert_eq!(report.deleted_albums.deleted_ids.len(), 2);
+            assert_eq!(report.added_artists.outcomes.len(), 1);
+            assert_eq!(report.deleted_artists.deleted_ids.len(), 1);
+        }
+
+        let track_paths = |tracks: Vec<Track>| tracks.into_iter().map(|track| track.file_path().clone()).collect::<HashSet<_>>();
+        let streaming_tracks = ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?;
+        let cached_tracks = ctx.trk_repo.stream_all(&cached_ctx.pool).await.try_collect::<Vec<_>>().await?;
+        assert_eq!(track_paths(streaming_tracks), track_paths(cached_tracks));
+        assert_eq!(album_artist_names(&ctx).await?, album_artist_names(&cached_ctx).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_verify_reports_inconsistencies() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for (title, artist) in [("one", "Chevelle"), ("two", "Deftones")] {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Split")], 1)?;
+        }
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        let report = sync_service.verify().await?;
+        assert_eq!(report.untracked_files.len(), 2);
+        assert_eq!(report.inconsistencies(), 2);
+
+        sync_service.synchronize().await?;
+        assert!(sync_service.verify().await?.is_consistent());
+
+        fs::remove_file(ctx.temp_dir.path().join("two.wav"))?;
+        let report = sync_service.verify().await?;
+        assert_eq!(report.missing_files.len(), 1);
+        assert!(report.missing_files[0].1.ends_with("two.wav"));
+        assert_eq!(report.orphaned_album_ids.len(), 1);
+        assert_eq!(report.orphaned_artist_ids.len(), 1);
+        assert!(report.untracked_files.is_empty());
+        assert_eq!(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.len(), 2);
+
+        // re-tagged on disk
+        write_tagged_wav(&ctx.temp_dir.path().join("one.wav"), &[(b"INAM", "one (remaster)"), (b"IART", "Chevelle"), (b"IPRD", "Split")], 1)?;
+        assert_eq!(sync_service.verify().await?.stale_tags.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_verify_reports_albums_and_artists_that_are_empty_already() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        write_tagged_wav(&ctx.temp_dir.path().join("one.wav"), &[(b"INAM", "one"), (b"IART", "Chevelle"), (b"IPRD", "Split")], 1)?;
+        let sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+
+        // left behind by a track deleted by hand, not by a file that went missing
+        let artist = Artist::new(Uuid::new_v4(), "Deftones")?;
+        let album = Album::new(Uuid::new_v4(), "Split", *artist.id(), None)?;
+        ctx.art_repo.save(&ctx.pool, &artist).await?;
+        ctx.alb_repo.save(&ctx.pool, &album).await?;
+
+        let report = sync_service.verify().await?;
+        assert_eq!(report.untracked_files.len(), 1);
+        assert!(report.orphaned_album_ids.is_empty());
+        assert_eq!(report.empty_album_ids, vec![*album.id()]);
+        assert_eq!(report.empty_artist_ids, vec![*artist.id()]);
+        assert_eq!(report.inconsistencies(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compute_diff_counts_changes_since_last_sync() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for (title, artist) in [("one", "Chevelle"), ("two", "Deftones")] {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", artist), (b"IPRD", "Split")], 1)?;
+        }
+        MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?.synchronize().await?;
+
+        fs::remove_file(ctx.temp_dir.path().join("two.wav"))?;
+        for title in ["three", "four"] {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", title)), &[(b"INAM", title), (b"IART", "Chevelle"), (b"IPRD", "Split")], 1)?;
+        }
+
+        let mut config: Config = toml::from_str(r#"
+            [server]
+            host = "127.0.0.1"
+            port = 9000
+
+            [database]
+            path = "./test.db"
+
+            [media]
+            music_path = "./music"
+            video_path = "./video"
+            filesharing_path = "./share"
+            ffmpeg_dir_path = "./ffmpeg_dir"
+            test_fixtures_path = "./fixtures"
+            resampled_music_path = "./resampled"
+            audio_fixtures_json_path = "./fixtures.json"
+        "#).expect("Config should parse");
+        config.media.music_path = ctx.temp_dir.path().to_path_buf();
+
+        let diff = compute_diff(&ctx.pool, &config).await?;
+        assert_eq!(diff.counts, SyncDiffCounts {
+            new_tracks: 2,
+            missing_tracks: 1,
+            moved_tracks: 0,
+            updated_tracks: 0,
+            orphaned_albums: 1,
+            orphaned_artists: 1
+        });
+        // relative to the library, like the paths of the tracks
+        let mut new_tracks = diff.new_tracks.clone();
+        new_tracks.sort();
+        assert_eq!(new_tracks, vec![PathBuf::from("four.wav"), PathBuf::from("three.wav")]);
+        assert_eq!(diff.missing_tracks[0].1, PathBuf::from("two.wav"));
+        assert_eq!(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_groups_copies_by_content_hash() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for file_name in ["the red.wav", "the red (copy).wav"] {
+            write_tagged_wav(&ctx.temp_dir.path().join(file_name), &[(b"INAM", "The Red"), (b"IART", "Chevelle"), (b"IPRD", "Wonder What's Next")], 1)?;
+        }
+        write_tagged_wav(&ctx.temp_dir.path().join("send the pain below.wav"), &[(b"INAM", "Send The Pain Below"), (b"IART", "Chevelle"), (b"IPRD", "Wonder What's Next")], 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+
+        let groups = ctx.trk_repo.find_duplicate_groups(&ctx.pool).await?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        assert!(groups[0].iter().all(|track| track.name() == "the red"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_service_stores_and_updates_genre() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        let tagged_path = ctx.temp_dir.path().join("tagged.wav");
+        write_tagged_wav(&tagged_path, &[(b"INAM", "Tagged"), (b"IART", "Chevelle"), (b"IPRD", "Genres"), (b"IGNR", "Alternative Metal")], 1)?;
+        write_tagged_wav(&ctx.temp_dir.path().join("untagged.wav"), &[(b"INAM", "Untagged"), (b"IART", "Chevelle"), (b"IPRD", "Genres")], 1)?;
+
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?;
+        sync_service.synchronize().await?;
+
+        let genres = |tracks: Vec<Track>| tracks.iter().map(|track| (track.name().to_string(), track.genre().map(str::to_string))).collect::<HashMap<_, _>>();
+        let stored = genres(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?);
+        assert_eq!(stored["tagged"].as_deref(), Some("Alternative Metal"));
+        assert_eq!(stored["untagged"], None);
+
+        write_tagged_wav(&tagged_path, &[(b"INAM", "Tagged"), (b"IART", "Chevelle"), (b"IPRD", "Genres"), (b"IGNR", "Hard Rock")], 1)?;
+        let report = sync_service.synchronize().await?;
+        assert_eq!(report.updated_tracks.len(), 1);
+        assert_eq!(report.updated_tracks[0].genre(), Some("Hard Rock"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sync_service_cancelled_mid_scan_commits_nothing() -> Result<(), TestSetupError> {
+        init_logger()?;
+
+        let ctx = TestContext::new().await?;
+        for i in 0..500 {
+            write_tagged_wav(&ctx.temp_dir.path().join(format!("{}.wav", i)), &[(b"INAM", &i.to_string()), (b"IART", "Chevelle"), (b"IPRD", "Many")], 1)?;
+        }
+
+        let token = CancellationToken::new();
+        let mut sync_service = MusicLibSyncService::new(&ctx.pool, ctx.temp_dir.path().to_path_buf()).await?
+            .with_cancellation(token.clone());
+
+        let canceller = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            token.cancel();
+        });
+        let result = sync_service.synchronize().await;
+        canceller.await.expect("Canceller should finish");
+
+        assert!(matches!(result, Err(SyncServiceError::Cancelled)), "Expected a cancelled sync, got {:?}", result.map(|report| report.added_tracks.outcomes.len()));
+        assert!(ctx.trk_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.is_empty());
+        assert!(ctx.art_repo.stream_all(&ctx.pool).await.try_collect::<Vec<_>>().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_report_counts_failed_rows_apart() {
+        let mut report = SyncServiceReport::new(Local::now().naive_local());
+        report.added_tracks.outcomes.push(BatchSaveOutcome { batch_index: 0, result: Ok(Uuid::new_v4()) });
+        report.added_tracks.outcomes.push(BatchSaveOutcome { batch_index: 1, result: Err(RepositoryError::UnknownError("disk full".to_string())) });
+        report.deleted_albums.failed.push((Uuid::new_v4(), RepositoryError::IdNotFound(Uuid::new_v4())));
+
+        assert_eq!(report.changes(), 1);
+        assert_eq!(report.failures(), 2);
+        assert!(report.to_string().ends_with("(0.0s in total), 2 failed"), "{}", report);
+
+        report.added_tracks.outcomes.pop();
+        report.deleted_albums.failed.clear();
+        assert!(report.to_string().ends_with("(0.0s in total)"), "{}", report);
+
+        report.skipped_files.push(PathBuf::from("music/unsized.flac"));
+        assert_eq!(report.changes(), 1);
+        assert!(report.to_string().ends_with("(0.0s in total), 1 skipped"), "{}", report);
+    }
+
+    #[test]
+    fn test_files_without_a_size_are_skipped() {
+        let db_cache = DatabaseCache { tracks: HashMap::new(), albums: HashMap::new(), artists: HashMap::new(), artist_to_album_ids: HashMap::new() };
+        let descriptor = |path: &str, file_size: u64| AudioFileDescriptor {
+            path: PathBuf::from(path),
+            file_size,
+            modified: None,
+            file_type: AudioFileType::Flac,
+            metadata: AudioFileMetadata::default(),
+            probe_ok: true,
+            content_hash: None
+        };
+
+        let mut library = ScannedLibrary::new();
+        library.push(&db_cache, descriptor("music/sized.flac", 1024));
+        library.push(&db_cache, descriptor("music/unsized.flac", 0));
+
+        assert_eq!(library.unsynced.iter().map(|file| file.path.clone()).collect::<Vec<_>>(), vec![PathBuf::from("music/sized.flac")]);
+        assert_eq!(library.skipped, vec![PathBuf::from("music/unsized.flac")]);
+        assert!(library.paths.contains(Path::new("music/unsized.flac")), "a skipped file is still on disk");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,142 @@
+use std::{path::{Path, PathBuf}, process::Stdio, str::FromStr};
+
+use tokio::{io::{AsyncRead, AsyncReadExt}, process::{Child, ChildStdout, Command}, task::JoinHandle};
+
+use super::TranscodeError;
+
+/// Formats a track can be transcoded into on the fly. Kept short on purpose, every target
+/// is another ffmpeg encoder the server has to rely on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscodeTarget {
+    Mp3,
+    Aac
+}
+
+impl TranscodeTarget {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TranscodeTarget::Mp3 => "audio/mpeg",
+            TranscodeTarget::Aac => "audio/aac"
+        }
+    }
+
+    /// Output format and codec. Both have to be streamable, ffmpeg writes to a pipe and can't seek back.
+    fn ffmpeg_args(&self) -> [&'static str; 4] {
+        match self {
+            TranscodeTarget::Mp3 => ["-f", "mp3", "-c:a", "libmp3lame"],
+            TranscodeTarget::Aac => ["-f", "adts", "-c:a", "aac"]
+        }
+    }
+}
+
+impl FromStr for TranscodeTarget {
+    type Err = TranscodeError;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target.to_lowercase().as_str() {
+            "mp3" => Ok(TranscodeTarget::Mp3),
+            "aac" => Ok(TranscodeTarget::Aac),
+            _ => Err(TranscodeError::UnsupportedTarget(target.to_string()))
+        }
+    }
+}
+
+/// How much of ffmpeg's stderr is kept for the error, the rest is read and dropped so ffmpeg never blocks on it.
+const MAX_STDERR_BYTES: usize = 4096;
+
+/// A running ffmpeg, see `spawn_transcode`.
+#[derive(Debug)]
+pub struct Transcode {
+    ffmpeg: Child,
+    stderr: Option<JoinHandle<String>>,
+    source: PathBuf
+}
+
+impl Transcode {
+    /// Waits for ffmpeg once its output has been read to the end. An unsuccessful exit is an error
+    /// carrying what ffmpeg wrote to stderr: a missing encoder or a corrupt source still end the
+    /// output normally, just early or with nothing in it.
+    pub async fn finish(mut self) -> Result<(), TranscodeError> {
+        let status = self.ffmpeg.wait().await
+            .map_err(|source| TranscodeError::FfmpegWaitError { path: self.source.clone(), source })?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let stderr = match self.stderr.take() {
+            Some(stderr) => stderr.await.unwrap_or_default(),
+            None => String::new()
+        };
+
+        Err(TranscodeError::FfmpegFailed { path: self.source, status, stderr: stderr.trim().to_string() })
+    }
+}
+
+/// Starts ffmpeg transcoding `source` into `target`, returns it along with its stdout.
+///
+/// ffmpeg is killed once the `Transcode` is dropped, so a client that stops listening doesn't leave it running.
+pub fn spawn_transcode(ffmpeg_path: &Path, source: &Path, target: TranscodeTarget) -> Result<(Transcode, ChildStdout), TranscodeError> {
+    let mut ffmpeg = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(source)
+        .args(["-vn", "-map_metadata", "0"])
+        .args(target.ffmpeg_args())
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| TranscodeError::FfmpegSpawnError { path: ffmpeg_path.to_path_buf(), source })?;
+
+    let stdout = ffmpeg.stdout.take().ok_or(TranscodeError::MissingOutput)?;
+    let stderr = ffmpeg.stderr.take().map(|stderr| tokio::spawn(read_capped(stderr, MAX_STDERR_BYTES)));
+
+    Ok((Transcode { ffmpeg, stderr, source: source.to_path_buf() }, stdout))
+}
+
+/// Reads `reader` to the end, keeping the first `cap` bytes.
+async fn read_capped<R: AsyncRead + Unpin>(mut reader: R, cap: usize) -> String {
+    let (mut kept, mut buf) = (Vec::new(), [0u8; 1024]);
+
+    while let Ok(read) = reader.read(&mut buf).await {
+        if read == 0 {
+            break;
+        }
+        let room = cap.saturating_sub(kept.len());
+        kept.extend_from_slice(&buf[..read.min(room)]);
+    }
+
+    String::from_utf8_lossy(&kept).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_allowlisted_targets_parse() {
+        assert_eq!("mp3".parse::<TranscodeTarget>().ok(), Some(TranscodeTarget::Mp3));
+        assert_eq!("AAC".parse::<TranscodeTarget>().ok(), Some(TranscodeTarget::Aac));
+        assert!(matches!("flac".parse::<TranscodeTarget>(), Err(TranscodeError::UnsupportedTarget(_))));
+        assert!(matches!("mp3 -y".parse::<TranscodeTarget>(), Err(TranscodeError::UnsupportedTarget(_))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_transcode_is_an_error() {
+        let (transcode, mut stdout) = spawn_transcode(Path::new("false"), Path::new("track.flac"), TranscodeTarget::Mp3).expect("Failed to spawn");
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).await.expect("Failed to read the output");
+
+        assert!(output.is_empty());
+        assert!(matches!(transcode.finish().await, Err(TranscodeError::FfmpegFailed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_stderr_is_capped_but_read_to_the_end() {
+        let stderr = "x".repeat(10_000);
+
+        assert_eq!(read_capped(stderr.as_bytes(), 16).await, "x".repeat(16));
+    }
+}
@@ -1,6 +1,8 @@
-use serde::Deserialize;
-use std::{fs, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{env, fs, path::{Path, PathBuf}};
 use toml;
+
+use crate::domain::{audiofile::AudioFileType, track::TrackSort};
 use std::sync::OnceLock;
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -9,53 +11,496 @@ pub enum ConfigLoadingError {
     FailedToReadConfig(String),
 
     #[error("Failed to parse the config: {0}")]
-    FailedToParseConfig(#[from] toml::de::Error)
+    FailedToParseConfig(#[from] toml::de::Error),
+
+    #[error("Invalid config value for {key}: {reason}")]
+    InvalidValue { key: &'static str, reason: String },
+
+    #[error("Invalid value in the environment variable {var}: {reason}")]
+    InvalidEnvValue { var: String, reason: String }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
-    pub media: MediaConfig
+    pub media: MediaConfig,
+
+    #[serde(default)]
+    pub features: FeaturesConfig,
+
+    #[serde(default)]
+    pub scanner: ScannerConfig,
+
+    #[serde(default)]
+    pub sync: SyncConfig
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
-    pub port: u16
+    pub port: u16,
+
+    /// `unix:/run/home-server.sock` serves on a Unix domain socket instead of a TCP port, e.g. behind nginx
+    /// on the same machine. `host:port` works too. Unset listens on `host` and `port`.
+    #[serde(default)]
+    pub listen: Option<String>,
+
+    /// Lock file that keeps a second `serve` process from starting.
+    #[serde(default = "default_lock_path")]
+    pub lock_path: PathBuf,
+
+    /// Requests running longer than this get a 408. Streaming and download routes are exempt.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+
+    /// Reject every mutating request with 403 and skip the sync on startup. Reading and streaming keep working.
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// How many tracks (and as many albums) the web layer keeps in memory by id. Unset or 0 disables the cache.
+    #[serde(default)]
+    pub entity_cache_size: Option<usize>,
+
+    /// Include the absolute path of each file in the track JSON. Off by default, it tells more about the server than clients need.
+    #[serde(default)]
+    pub expose_file_paths: bool,
+
+    /// Order of `GET /api/tracks` when the request has no `sort`: name, date_added, duration or artist.
+    #[serde(default)]
+    pub default_track_sort: TrackSort
+}
+
+/// Where the server accepts connections, see `ServerConfig::listen_address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(String),
+    Unix(PathBuf)
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "http://{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display())
+        }
+    }
+}
+
+impl ServerConfig {
+    /// `listen` when it's set, `host:port` otherwise.
+    pub fn listen_address(&self) -> Result<ListenAddress, ConfigLoadingError> {
+        let Some(listen) = self.listen.as_deref().map(str::trim).filter(|listen| !listen.is_empty()) else {
+            return Ok(ListenAddress::Tcp(format!("{}:{}", self.host, self.port)));
+        };
+
+        match listen.strip_prefix("unix:") {
+            Some("") => Err(ConfigLoadingError::InvalidValue { key: "server.listen", reason: "unix: needs the path of the socket".to_string() }),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => Ok(ListenAddress::Tcp(listen.to_string()))
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_lock_path() -> PathBuf {
+    PathBuf::from("./data/home-server.lock")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub path: PathBuf
 }
 
-#[derive(Debug, Deserialize)]
+/// Name of the ffmpeg binary on this platform.
+pub const FFMPEG_EXECUTABLE_NAME: &str = if cfg!(target_os = "windows") { "ffmpeg.exe" } else { "ffmpeg" };
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MediaConfig {
     pub music_path: PathBuf,
     pub video_path: PathBuf,
     pub filesharing_path: PathBuf,
+
+    /// Unset is `ffmpeg_dir_path` joined with the platform's binary name, see `FFMPEG_EXECUTABLE_NAME`.
+    #[serde(default)]
     pub ffmpeg_exe_path: PathBuf,
     pub ffmpeg_dir_path: PathBuf,
+
+    /// Unset picks a build for this platform: the gyan.dev `.7z` on Windows, a static `.tar.xz` on Linux.
+    #[serde(default = "default_ffmpeg_download_mirror")]
     pub ffmpeg_donwload_mirror: String,
+    #[serde(default = "default_ffmpeg_sha_download_mirror")]
     pub ffmpeg_sha_download_mirror: String,
     pub test_fixtures_path: PathBuf,
     pub resampled_music_path: PathBuf,
-    pub audio_fixtures_json_path: PathBuf
+    pub audio_fixtures_json_path: PathBuf,
+
+    /// Scratch space for downloads, files are written here first and moved into place once complete.
+    /// Best kept on the same volume as the rest, then moving is a rename.
+    #[serde(default = "default_temp_path")]
+    pub temp_path: PathBuf,
+
+    /// Extensions the scanner picks up, e.g. `["flac", "mp3", "ogg"]`, case doesn't matter.
+    /// Empty keeps the built-in set: flac, mp3, wav, ogg and m4a.
+    #[serde(default)]
+    pub scan_extensions: Vec<String>,
+
+    /// Folders holding a file with this name are skipped by the scanner, subfolders included. Empty turns it off.
+    #[serde(default = "default_ignore_marker")]
+    pub ignore_marker: String,
+
+    #[serde(default)]
+    pub resample: ResampleSettings
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResampleStrategy {
+    InPlace,
+
+    #[default]
+    CopyToCache
+}
+
+/// `[media.resample]`: how the library gets resampled when the `resample` feature is on.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResampleSettings {
+    /// `in_place` overwrites the originals, `copy_to_cache` writes the output next to them into the cache dir.
+    #[serde(default = "default_resample_strategy")]
+    pub strategy: ResampleStrategy,
+
+    /// Files with a sample rate above this get resampled.
+    #[serde(default = "default_max_sample_rate")]
+    pub max_sample_rate: u32,
+
+    /// Sample rate of the output. Unset picks one per file type.
+    #[serde(default)]
+    pub target_sample_rate: Option<u32>,
+
+    /// Bitrate cap of the output in kbps (`-b:a`), lossy outputs only. Unset leaves it to the encoder.
+    #[serde(default)]
+    pub target_bitrate_kbps: Option<u32>,
+
+    /// Type of the output by extension: flac, mp3, wav, ogg or m4a. Unset keeps the type of the input,
+    /// anything else needs `copy_to_cache`.
+    #[serde(default)]
+    pub output_format: Option<String>,
+
+    /// ffmpeg audio codec (`-c:a`) of the output. Unset picks the codec of the output type.
+    #[serde(default)]
+    pub codec: Option<String>,
+
+    /// How many files get resampled at once. Unset derives it from the number of cores.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+
+    /// Only touch lossless files; resampling an mp3 only loses quality.
+    #[serde(default)]
+    pub lossless_only: bool,
+
+    /// Re-probe every output before it replaces or joins the original.
+    #[serde(default = "enabled")]
+    pub verify_output: bool,
+
+    /// With `in_place`, copy every original here (keeping its path inside the library) before it's replaced.
+    #[serde(default)]
+    pub backup_originals: Option<PathBuf>
+}
+
+impl Default for ResampleSettings {
+    fn default() -> Self {
+        Self {
+            strategy: default_resample_strategy(),
+            max_sample_rate: default_max_sample_rate(),
+            target_sample_rate: None,
+            target_bitrate_kbps: None,
+            output_format: None,
+            codec: None,
+            concurrency: None,
+            lossless_only: false,
+            verify_output: true,
+            backup_originals: None
+        }
+    }
+}
+
+fn default_resample_strategy() -> ResampleStrategy {
+    ResampleStrategy::InPlace
+}
+
+fn default_max_sample_rate() -> u32 {
+    88200
+}
+
+/// A directory holding a file with this name is left out of the scan, along with everything under it.
+pub const DEFAULT_IGNORE_MARKER: &str = ".nomedia";
+
+/// Used when the concurrency isn't set explicitly; see `ScannerConfig::io_concurrency`.
+pub const DEFAULT_IO_CONCURRENCY: usize = 4;
+
+const LINUX_FFMPEG_BUILD: &str = if cfg!(target_arch = "aarch64") { "ffmpeg-master-latest-linuxarm64-gpl.tar.xz" } else { "ffmpeg-master-latest-linux64-gpl.tar.xz" };
+
+fn default_ignore_marker() -> String {
+    DEFAULT_IGNORE_MARKER.to_string()
+}
+
+fn default_temp_path() -> PathBuf {
+    PathBuf::from("./data/tmp")
+}
+
+/// Empty on macOS, neither mirror has a build for it: `prepare_ffmpeg` asks for one to be set.
+fn default_ffmpeg_download_mirror() -> String {
+    if cfg!(target_os = "windows") {
+        "https://www.gyan.dev/ffmpeg/builds/ffmpeg-release-essentials.7z".to_string()
+    } else if cfg!(target_os = "macos") {
+        String::new()
+    } else {
+        format!("https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/{}", LINUX_FFMPEG_BUILD)
+    }
+}
+
+/// The Linux builds share one checksum file, `prepare_ffmpeg` picks the line of the downloaded archive.
+fn default_ffmpeg_sha_download_mirror() -> String {
+    if cfg!(target_os = "windows") {
+        "https://www.gyan.dev/ffmpeg/builds/ffmpeg-release-essentials.7z.sha256".to_string()
+    } else if cfg!(target_os = "macos") {
+        String::new()
+    } else {
+        "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/checksums.sha256".to_string()
+    }
+}
+
+impl MediaConfig {
+    fn apply_platform_defaults(&mut self) {
+        if self.ffmpeg_exe_path.as_os_str().is_empty() {
+            self.ffmpeg_exe_path = self.ffmpeg_dir_path.join(FFMPEG_EXECUTABLE_NAME);
+        }
+    }
+}
+
+/// Prefix of the environment variables that override config.toml, see `Config::apply_env_overrides`.
+pub const ENV_PREFIX: &str = "HS_";
+
+/// Sample rates ffmpeg and the players handle sensibly.
+const SAMPLE_RATE_RANGE: std::ops::RangeInclusive<u32> = 8000..=384000;
+
+impl ResampleSettings {
+    fn validate(&self) -> Result<(), ConfigLoadingError> {
+        let invalid = |key, reason: String| Err(ConfigLoadingError::InvalidValue { key, reason });
+
+        if !SAMPLE_RATE_RANGE.contains(&self.max_sample_rate) {
+            return invalid("media.resample.max_sample_rate", format!("{} is outside of {:?}", self.max_sample_rate, SAMPLE_RATE_RANGE));
+        }
+
+        if let Some(target) = self.target_sample_rate {
+            if !SAMPLE_RATE_RANGE.contains(&target) {
+                return invalid("media.resample.target_sample_rate", format!("{} is outside of {:?}", target, SAMPLE_RATE_RANGE));
+            }
+            if target > self.max_sample_rate {
+                return invalid("media.resample.target_sample_rate", format!("{} is above max_sample_rate ({}), nothing would get smaller", target, self.max_sample_rate));
+            }
+        }
+
+        let output_format = self.output_format.as_deref().map(AudioFileType::from_extension_str);
+        if output_format == Some(AudioFileType::Unknown) {
+            return invalid("media.resample.output_format", format!("{:?} is not one of flac, mp3, wav, ogg or m4a", self.output_format.as_deref().unwrap_or_default()));
+        }
+        if output_format.is_some() && self.strategy == ResampleStrategy::InPlace {
+            return invalid("media.resample.output_format", "in_place keeps the type of every file, use copy_to_cache".to_string());
+        }
+
+        if self.target_bitrate_kbps == Some(0) {
+            return invalid("media.resample.target_bitrate_kbps", "must be at least 1".to_string());
+        }
+        if self.target_bitrate_kbps.is_some() && output_format.as_ref().is_some_and(AudioFileType::is_lossless) {
+            return invalid("media.resample.target_bitrate_kbps", "a lossless output_format has no bitrate to cap".to_string());
+        }
+
+        if self.codec.as_ref().is_some_and(|codec| codec.trim().is_empty()) {
+            return invalid("media.resample.codec", "must not be empty".to_string());
+        }
+
+        if self.concurrency == Some(0) {
+            return invalid("media.resample.concurrency", "must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Optional parts of the server that can be switched off.
+/// Every feature is enabled when the `[features]` section is missing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeaturesConfig {
+    /// Resample high sample rate tracks with ffmpeg. When disabled, ffmpeg is never required.
+    #[serde(default = "enabled")]
+    pub resample: bool,
+
+    /// Allow album lookups against MusicBrainz. Off by default since it talks to the outside world.
+    #[serde(default)]
+    pub metadata_lookup: bool
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self { resample: true, metadata_lookup: false }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScannerConfig {
+    /// Upper bound on files being read at the same time, by the async scan of a sync as well as
+    /// by the scan threads of `scan_music_lib`, which never outnumber it.
+    ///
+    /// Spinning disks get slower with more concurrent reads since the head keeps seeking
+    /// between files, so the default is conservative. On SSDs it's safe to raise it
+    /// to the number of cores or above.
+    #[serde(default = "default_io_concurrency")]
+    pub io_concurrency: usize
+}
+
+impl Default for ScannerConfig {
+    fn default() -> Self {
+        Self { io_concurrency: default_io_concurrency() }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// Commit added tracks every N rows instead of in a single transaction.
+    ///
+    /// Keeps the write lock short during a big first sync and makes progress survive a crash,
+    /// but a failed sync leaves the batches committed before it in the DB. Unset means one
+    /// atomic transaction.
+    #[serde(default)]
+    pub batch_commit_size: Option<usize>,
+
+    /// Percentage of an album's tracks one artist needs to get the whole album when the tracks
+    /// are tagged with different artists (featuring credits). Below it the album goes under
+    /// "Various Artists". Unset keeps every track under its own artist.
+    #[serde(default)]
+    pub dominant_artist_threshold: Option<u8>,
+
+    /// Check scanned files against the DB while the scan is still running, with up to this many
+    /// files waiting in between. Unset scans the whole library first.
+    #[serde(default)]
+    pub scan_pipeline_capacity: Option<usize>
+}
+
+fn default_io_concurrency() -> usize {
+    DEFAULT_IO_CONCURRENCY
+}
+
+fn enabled() -> bool {
+    true
 }
 
 impl Config {
     pub fn load() -> Result<Self, ConfigLoadingError> {
         let config_str = fs::read_to_string("config.toml").map_err(|err| ConfigLoadingError::FailedToReadConfig(err.to_string()))?;
-        let config: Config = toml::from_str(&config_str)?;
+        let mut config: Config = toml::from_str(&config_str)?;
+        config.apply_env_overrides(ENV_PREFIX)?;
+        config.media.apply_platform_defaults();
+        config.media.resample.validate()?;
+        config.server.listen_address()?;
 
         Ok(config)
     }
+
+    /// Overwrites values with the environment variables named after them, e.g. `HS_SERVER_PORT`
+    /// or `HS_MUSIC_PATH` for the `HS_` prefix. Unset and empty variables leave the value alone.
+    fn apply_env_overrides(&mut self, prefix: &str) -> Result<(), ConfigLoadingError> {
+        let lookup = |name: &str| env::var_os(format!("{}{}", prefix, name)).filter(|value| !value.is_empty());
+
+        if let Some(host) = lookup("SERVER_HOST") {
+            self.server.host = host.to_string_lossy().into_owned();
+        }
+
+        if let Some(listen) = lookup("SERVER_LISTEN") {
+            self.server.listen = Some(listen.to_string_lossy().into_owned());
+        }
+
+        if let Some(port) = lookup("SERVER_PORT") {
+            let port = port.to_string_lossy();
+            self.server.port = port.trim().parse().map_err(|err| ConfigLoadingError::InvalidEnvValue {
+                var: format!("{}SERVER_PORT", prefix),
+                reason: format!("{:?} is not a port: {}", port, err)
+            })?;
+        }
+
+        let paths = [
+            ("SERVER_LOCK_PATH", &mut self.server.lock_path),
+            ("DATABASE_PATH", &mut self.database.path),
+            ("MUSIC_PATH", &mut self.media.music_path),
+            ("VIDEO_PATH", &mut self.media.video_path),
+            ("FILESHARING_PATH", &mut self.media.filesharing_path),
+            ("FFMPEG_EXE_PATH", &mut self.media.ffmpeg_exe_path),
+            ("FFMPEG_DIR_PATH", &mut self.media.ffmpeg_dir_path),
+            ("RESAMPLED_MUSIC_PATH", &mut self.media.resampled_music_path),
+            ("TEMP_PATH", &mut self.media.temp_path)
+        ];
+        for (name, path) in paths {
+            if let Some(value) = lookup(name) {
+                *path = PathBuf::from(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every path the config resolves to, by its key in config.toml. Unset optional paths are left out.
+    pub fn paths(&self) -> Vec<(&'static str, &Path)> {
+        let mut paths = vec![
+            ("server.lock_path", self.server.lock_path.as_path()),
+            ("database.path", self.database.path.as_path()),
+            ("media.music_path", self.media.music_path.as_path()),
+            ("media.video_path", self.media.video_path.as_path()),
+            ("media.filesharing_path", self.media.filesharing_path.as_path()),
+            ("media.ffmpeg_exe_path", self.media.ffmpeg_exe_path.as_path()),
+            ("media.ffmpeg_dir_path", self.media.ffmpeg_dir_path.as_path()),
+            ("media.test_fixtures_path", self.media.test_fixtures_path.as_path()),
+            ("media.resampled_music_path", self.media.resampled_music_path.as_path()),
+            ("media.audio_fixtures_json_path", self.media.audio_fixtures_json_path.as_path()),
+            ("media.temp_path", self.media.temp_path.as_path())
+        ];
+
+        if let Some(backup_originals) = &self.media.resample.backup_originals {
+            paths.push(("media.resample.backup_originals", backup_originals.as_path()));
+        }
+
+        paths
+    }
 }
 
+#[cfg(test)]
+thread_local! {
+    static TEST_CONFIG: std::cell::Cell<Option<&'static Config>> = const { std::cell::Cell::new(None) };
+}
+
+/// Makes `get_config` return `config` on the current thread instead of the one from config.toml.
+///
+/// The config is leaked to get the `'static` lifetime, which is fine for a test process.
+/// Blocking tasks run on other threads and still see config.toml, so read the config before spawning them.
+#[cfg(test)]
+pub fn override_config(config: Config) -> &'static Config {
+    let config: &'static Config = Box::leak(Box::new(config));
+    TEST_CONFIG.with(|test_config| test_config.set(Some(config)));
+
+    config
+}
+
+/// The config is read and validated once per process, every later call returns the same `&'static Config`.
 pub fn get_config() -> Result<&'static Config, ConfigLoadingError> {
     static CONFIG: OnceLock<Result<Config, ConfigLoadingError>> = OnceLock::new();
 
+    #[cfg(test)]
+    if let Some(config) = TEST_CONFIG.with(|test_config| test_config.get()) {
+        return Ok(config);
+    }
+
     let result = CONFIG.get_or_init(|| {
         Config::load()
     });
@@ -64,4 +509,207 @@ pub fn get_config() -> Result<&'static Config, ConfigLoadingError> {
         Ok(config) => Ok(config),
         Err(err) => Err(err.clone())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// The environment is shared by the whole test process, tests changing it take turns.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Tests use their own prefix, so a config loaded elsewhere in the meantime doesn't pick their variables up.
+    const TEST_ENV_PREFIX: &str = "HS_CONFIG_TEST_";
+
+    fn test_config() -> Config {
+        let mut config: Config = toml::from_str(r#"
+            [server]
+            host = "127.0.0.1"
+            port = 9000
+
+            [database]
+            path = "./test.db"
+
+            [media]
+            music_path = "./music"
+            video_path = "./video"
+            filesharing_path = "./share"
+            ffmpeg_dir_path = "./ffmpeg_dir"
+            test_fixtures_path = "./fixtures"
+            resampled_music_path = "./resampled"
+            audio_fixtures_json_path = "./fixtures.json"
+        "#).expect("Config should parse");
+        config.media.apply_platform_defaults();
+
+        config
+    }
+
+    /// Sets the variables for the length of `test`, then removes them again.
+    fn with_env_vars<T>(vars: &[(&str, &str)], test: impl FnOnce() -> T) -> T {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let names: Vec<String> = vars.iter().map(|(name, _)| format!("{}{}", TEST_ENV_PREFIX, name)).collect();
+
+        // SAFETY: every test touching the environment holds ENV_LOCK, and the variables are prefixed
+        // so nothing else in the process reads them.
+        unsafe { names.iter().zip(vars).for_each(|(name, (_, value))| env::set_var(name, value)) };
+        let result = test();
+        unsafe { names.iter().for_each(|name| env::remove_var(name)) };
+
+        result
+    }
+
+    #[test]
+    fn test_resample_settings_validation() {
+        assert!(ResampleSettings::default().validate().is_ok());
+
+        let too_low = ResampleSettings { max_sample_rate: 100, ..Default::default() };
+        assert!(matches!(too_low.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.max_sample_rate", .. })));
+
+        let upsampling = ResampleSettings { target_sample_rate: Some(96000), ..Default::default() };
+        assert!(matches!(upsampling.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.target_sample_rate", .. })));
+
+        let no_threads = ResampleSettings { concurrency: Some(0), ..Default::default() };
+        assert!(matches!(no_threads.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.concurrency", .. })));
+
+        let unknown_format = ResampleSettings { strategy: ResampleStrategy::CopyToCache, output_format: Some("aiff".to_string()), ..Default::default() };
+        assert!(matches!(unknown_format.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.output_format", .. })));
+
+        let converting_in_place = ResampleSettings { strategy: ResampleStrategy::InPlace, output_format: Some("mp3".to_string()), ..Default::default() };
+        assert!(matches!(converting_in_place.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.output_format", .. })));
+
+        let capped_flac = ResampleSettings { strategy: ResampleStrategy::CopyToCache, output_format: Some("flac".to_string()), target_bitrate_kbps: Some(320), ..Default::default() };
+        assert!(matches!(capped_flac.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.target_bitrate_kbps", .. })));
+
+        let capped_mp3 = ResampleSettings { strategy: ResampleStrategy::CopyToCache, output_format: Some("mp3".to_string()), target_bitrate_kbps: Some(192), ..Default::default() };
+        assert!(capped_mp3.validate().is_ok());
+
+        let blank_codec = ResampleSettings { codec: Some(" ".to_string()), ..Default::default() };
+        assert!(matches!(blank_codec.validate(), Err(ConfigLoadingError::InvalidValue { key: "media.resample.codec", .. })));
+    }
+
+    #[test]
+    fn test_config_can_be_overridden() {
+        let config: Config = toml::from_str(r#"
+            [server]
+            host = "127.0.0.1"
+            port = 9000
+
+            [database]
+            path = "./test.db"
+
+            [media]
+            music_path = "./music"
+            video_path = "./video"
+            filesharing_path = "./share"
+            ffmpeg_exe_path = "./ffmpeg"
+            ffmpeg_dir_path = "./ffmpeg_dir"
+            ffmpeg_donwload_mirror = "http://localhost/ffmpeg.7z"
+            ffmpeg_sha_download_mirror = "http://localhost/ffmpeg.7z.sha256"
+            test_fixtures_path = "./fixtures"
+            resampled_music_path = "./resampled"
+            audio_fixtures_json_path = "./fixtures.json"
+        "#).expect("Config should parse");
+
+        let overridden = override_config(config);
+        let loaded = get_config().expect("Overridden config should be returned");
+
+        assert!(std::ptr::eq(overridden, loaded));
+        assert_eq!(loaded.server.port, 9000);
+        assert!(std::ptr::eq(get_config().expect("Config should stay cached"), loaded));
+    }
+
+    #[test]
+    fn test_ffmpeg_defaults_follow_the_platform() {
+        let mut media: MediaConfig = toml::from_str(r#"
+            music_path = "./music"
+            video_path = "./video"
+            filesharing_path = "./share"
+            ffmpeg_dir_path = "./ffmpeg_dir"
+            test_fixtures_path = "./fixtures"
+            resampled_music_path = "./resampled"
+            audio_fixtures_json_path = "./fixtures.json"
+        "#).expect("Media config should parse");
+        media.apply_platform_defaults();
+
+        assert_eq!(media.ffmpeg_exe_path, PathBuf::from("./ffmpeg_dir").join(FFMPEG_EXECUTABLE_NAME));
+        let expected_archive = if cfg!(target_os = "windows") { ".7z" } else if cfg!(target_os = "macos") { "" } else { ".tar.xz" };
+        assert!(media.ffmpeg_donwload_mirror.ends_with(expected_archive));
+        assert_eq!(media.ffmpeg_donwload_mirror.is_empty(), cfg!(target_os = "macos"));
+
+        let mut explicit = media;
+        explicit.ffmpeg_exe_path = PathBuf::from("/usr/bin/ffmpeg");
+        explicit.apply_platform_defaults();
+        assert_eq!(explicit.ffmpeg_exe_path, PathBuf::from("/usr/bin/ffmpeg"));
+    }
+
+    #[test]
+    fn test_resample_section_parses() {
+        let settings: ResampleSettings = toml::from_str(
+            "strategy = \"copy_to_cache\"\ntarget_sample_rate = 48000\ncodec = \"flac\"\nlossless_only = true"
+        ).expect("Section should parse");
+
+        assert_eq!(settings.strategy, ResampleStrategy::CopyToCache);
+        assert_eq!(settings.target_sample_rate, Some(48000));
+        assert_eq!(settings.codec.as_deref(), Some("flac"));
+        assert!(settings.lossless_only);
+        assert!(settings.verify_output);
+    }
+
+    #[test]
+    fn test_resolved_config_serializes_back() {
+        let config = test_config();
+
+        let printed = toml::to_string_pretty(&config).expect("Config should serialize");
+        let reparsed: Config = toml::from_str(&printed).expect("Printed config should parse");
+        assert_eq!(reparsed.media.ffmpeg_exe_path, config.media.ffmpeg_exe_path);
+        assert_eq!(reparsed.server.lock_path, config.server.lock_path);
+
+        let paths = config.paths();
+        assert_eq!(paths.len(), 11);
+        assert!(paths.contains(&("media.temp_path", Path::new("./data/tmp"))));
+        assert!(paths.contains(&("media.ffmpeg_exe_path", Path::new("./ffmpeg_dir").join(FFMPEG_EXECUTABLE_NAME).as_path())));
+    }
+
+    #[test]
+    fn test_env_overrides_take_precedence() {
+        let mut config = test_config();
+        let vars = [("SERVER_HOST", "0.0.0.0"), ("SERVER_PORT", " 8081 "), ("DATABASE_PATH", "/data/db.sqlite"), ("MUSIC_PATH", "/music"), ("VIDEO_PATH", "")];
+
+        with_env_vars(&vars, || config.apply_env_overrides(TEST_ENV_PREFIX)).expect("Overrides should apply");
+
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.server.port, 8081);
+        assert_eq!(config.database.path, PathBuf::from("/data/db.sqlite"));
+        assert_eq!(config.media.music_path, PathBuf::from("/music"));
+        assert_eq!(config.media.video_path, PathBuf::from("./video"));
+        assert_eq!(config.media.filesharing_path, PathBuf::from("./share"));
+    }
+
+    #[test]
+    fn test_env_override_with_invalid_port() {
+        let mut config = test_config();
+
+        let result = with_env_vars(&[("SERVER_PORT", "eighty")], || config.apply_env_overrides(TEST_ENV_PREFIX));
+
+        assert!(matches!(result, Err(ConfigLoadingError::InvalidEnvValue { ref var, .. }) if var == "HS_CONFIG_TEST_SERVER_PORT"));
+        assert_eq!(config.server.port, 9000);
+    }
+
+    #[test]
+    fn test_listen_address() {
+        let mut config = test_config();
+        assert_eq!(config.server.listen_address().unwrap(), ListenAddress::Tcp("127.0.0.1:9000".to_string()));
+
+        config.server.listen = Some("unix:/run/home-server.sock".to_string());
+        assert_eq!(config.server.listen_address().unwrap(), ListenAddress::Unix(PathBuf::from("/run/home-server.sock")));
+        assert_eq!(config.server.listen_address().unwrap().to_string(), "unix:/run/home-server.sock");
+
+        config.server.listen = Some("localhost:8082".to_string());
+        assert_eq!(config.server.listen_address().unwrap().to_string(), "http://localhost:8082");
+
+        config.server.listen = Some("unix:".to_string());
+        assert!(matches!(config.server.listen_address(), Err(ConfigLoadingError::InvalidValue { key: "server.listen", .. })));
+    }
+}
@@ -1,5 +1,6 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use chrono::Local;
 use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
 use tokio::sync::OnceCell;
 use anyhow::{anyhow, Error};
@@ -35,6 +36,40 @@ impl Database {
         &self.pool
     }
 
+    /// Writes a consistent copy of the live database into `dest` with `VACUUM INTO`.
+    /// Readers and writers are not blocked for the duration, so this is safe while serving.
+    ///
+    /// Returns the size of the backup in bytes.
+    pub async fn backup_into(&self, dest: &Path) -> Result<u64, Error> {
+        if dest.exists() {
+            return Err(anyhow!("Backup destination already exists: {}", dest.display()));
+        }
+
+        let dest_str = dest.to_str()
+            .ok_or_else(|| anyhow!("Backup destination contains non-UTF8 characters: {:?}", dest))?;
+
+        sqlx::query("VACUUM INTO ?;")
+            .bind(dest_str)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(std::fs::metadata(dest)?.len())
+    }
+
+    /// Rebuilds the database file at `db_path` with `VACUUM`, so the pages freed by deleted rows go back to the disk.
+    /// Unlike `backup_into` it locks the whole database until done and temporarily needs as much free space as the file takes.
+    ///
+    /// Returns the size of the file in bytes before and after.
+    pub async fn compact(&self, db_path: &Path) -> Result<(u64, u64), Error> {
+        let size_before = std::fs::metadata(db_path)?.len();
+
+        sqlx::query("VACUUM;")
+            .execute(&self.pool)
+            .await?;
+
+        Ok((size_before, std::fs::metadata(db_path)?.len()))
+    }
+
     pub async fn run_migrations(&self) -> Result<(), Error> {
         // TODO: Add migrations path to Config!
         let migrations = Migrator::new(Path::new("./data/db/migrations")).await?;
@@ -70,4 +105,61 @@ pub async fn get_application_db() -> Result<&'static Database, Error> {
         Ok(db) => Ok(db),
         Err(msg) => Err(anyhow!("{}", msg)),
     }
-}
\ No newline at end of file
+}
+
+/// `./data/db/database.db` -> `./data/db/database.backup-20250101-120000.db`
+pub fn default_backup_path(db_path: &Path) -> PathBuf {
+    let stem = db_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "database".to_string());
+    let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+
+    db_path.with_file_name(format!("{}.backup-{}.db", stem, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backup_into() -> Result<(), Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("database.db");
+        File::create(&db_path)?;
+
+        let db = Database::init_application_db(&format!("sqlite:{}", db_path.display())).await?;
+
+        let backup_path = default_backup_path(&db_path);
+        let backup_size = db.backup_into(&backup_path).await?;
+
+        assert!(backup_path.exists());
+        assert!(backup_size > 0);
+
+        // never overwrite an existing backup
+        assert!(db.backup_into(&backup_path).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_shrinks_after_deletes() -> Result<(), Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("database.db");
+        File::create(&db_path)?;
+
+        let db = Database::init_application_db(&format!("sqlite:{}", db_path.display())).await?;
+
+        sqlx::query("CREATE TABLE churn (payload BLOB);").execute(db.get_pool()).await?;
+        for _ in 0..64 {
+            sqlx::query("INSERT INTO churn (payload) VALUES (zeroblob(16384));").execute(db.get_pool()).await?;
+        }
+        sqlx::query("DELETE FROM churn;").execute(db.get_pool()).await?;
+
+        let (size_before, size_after) = db.compact(&db_path).await?;
+
+        assert_eq!(size_after, std::fs::metadata(&db_path)?.len());
+        assert!(size_after < size_before, "{} should be smaller than {}", size_after, size_before);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::{fs::{File, OpenOptions, TryLockError}, io::{Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+#[derive(Debug, thiserror::Error)]
+pub enum InstanceLockError {
+    #[error("Another instance is already running (lock {path} is held{})", .pid.as_ref().map(|pid| format!(" by pid {}", pid)).unwrap_or_default())]
+    AlreadyRunning { path: PathBuf, pid: Option<String> },
+
+    #[error("Failed to acquire the instance lock {path}: {source}")]
+    IOError { path: PathBuf, #[source] source: std::io::Error }
+}
+
+/// Advisory lock that keeps a second server process from running against the same DB.
+/// The OS drops the lock when the file handle is closed, so a crashed process never leaves it stuck.
+#[derive(Debug)]
+pub struct InstanceLock {
+    file: File,
+    path: PathBuf
+}
+
+impl InstanceLock {
+    pub fn acquire(path: &Path) -> Result<Self, InstanceLockError> {
+        let io_err = |source| InstanceLockError::IOError { path: path.to_path_buf(), source };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_err)?;
+        }
+
+        // not truncating on open: the file still holds the pid of the owner if the lock is taken
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_err)?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let pid = file.read_to_string(&mut pid).ok()
+                    .map(|_| pid.trim().to_string())
+                    .filter(|pid| !pid.is_empty());
+
+                return Err(InstanceLockError::AlreadyRunning { path: path.to_path_buf(), pid });
+            },
+            Err(TryLockError::Error(err)) => return Err(io_err(err))
+        }
+
+        file.set_len(0).map_err(io_err)?;
+        file.seek(SeekFrom::Start(0)).map_err(io_err)?;
+        write!(file, "{}", std::process::id()).map_err(io_err)?;
+        file.flush().map_err(io_err)?;
+
+        Ok(Self { file, path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.file.set_len(0).and_then(|_| self.file.unlock()) {
+            log::warn!("Failed to release the instance lock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_acquire_fails_until_released() -> Result<(), InstanceLockError> {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let lock_path = temp_dir.path().join("nested").join("home-server.lock");
+
+        let first = InstanceLock::acquire(&lock_path)?;
+
+        let second = InstanceLock::acquire(&lock_path);
+        let expected_pid = std::process::id().to_string();
+        assert!(matches!(
+            second,
+            Err(InstanceLockError::AlreadyRunning { ref path, pid: Some(ref pid) }) if path == &lock_path && pid == &expected_pid
+        ));
+
+        drop(first);
+        InstanceLock::acquire(&lock_path)?;
+
+        Ok(())
+    }
+}
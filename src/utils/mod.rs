@@ -1,4 +1,8 @@
 pub mod normalizations;
 pub mod db;
 pub mod config;
-pub mod audio_fixtures;
\ No newline at end of file
+pub mod audio_fixtures;
+pub mod instance_lock;
+pub mod track_files;
+pub mod progress;
+pub mod sanitize;
\ No newline at end of file
@@ -18,4 +18,44 @@ pub fn normalize_path(path: &Path) -> PathBuf {
         .to_lowercase()
         .replace('\\', "/")
         .into()
-}
\ No newline at end of file
+}
+
+/// `path` relative to `root`, with forward slashes whatever the platform wrote. The root is matched
+/// ignoring ASCII case, since stored paths are lowercased by `normalize_path` and the configured root
+/// usually isn't. `None` when `path` is outside of `root` or is `root` itself.
+pub fn relative_to(path: &Path, root: &Path) -> Option<PathBuf> {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let root = root.to_string_lossy().replace('\\', "/");
+    let root = root.trim_end_matches('/');
+
+    let under_root = path.len() > root.len() && path.is_char_boundary(root.len()) && path[..root.len()].eq_ignore_ascii_case(root);
+    if !under_root {
+        return None;
+    }
+
+    path[root.len()..].strip_prefix('/')
+        .filter(|relative| !relative.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_to_under_the_root() {
+        assert_eq!(relative_to(Path::new("/srv/music/a/b.mp3"), Path::new("/srv/music")), Some(PathBuf::from("a/b.mp3")));
+        assert_eq!(relative_to(Path::new("/srv/music/a/b.mp3"), Path::new("/srv/music/")), Some(PathBuf::from("a/b.mp3")));
+        assert_eq!(relative_to(Path::new("d:/music/artist/b.flac"), Path::new(r"D:\Music")), Some(PathBuf::from("artist/b.flac")));
+        assert_eq!(relative_to(Path::new(r"D:\Music\artist\b.flac"), Path::new("d:/music")), Some(PathBuf::from("artist/b.flac")));
+    }
+
+    #[test]
+    fn test_relative_to_outside_of_the_root() {
+        assert_eq!(relative_to(Path::new("/srv/music-old/b.mp3"), Path::new("/srv/music")), None);
+        assert_eq!(relative_to(Path::new("/srv/music"), Path::new("/srv/music")), None);
+        assert_eq!(relative_to(Path::new("/srv/music/"), Path::new("/srv/music")), None);
+        assert_eq!(relative_to(Path::new("/tmp/b.mp3"), Path::new("/srv/music")), None);
+        assert_eq!(relative_to(Path::new("/srv"), Path::new("/srv/music")), None);
+    }
+}
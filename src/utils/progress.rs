@@ -0,0 +1,16 @@
+use std::sync::OnceLock;
+
+use indicatif::{MultiProgress, ProgressBar};
+
+/// Every progress bar of the process is drawn through this. The logger set up in `main` writes through it
+/// as well, hiding the bars for as long as a log line is printed, so the two don't garble each other.
+pub fn multi_progress() -> &'static MultiProgress {
+    static MULTI_PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+    MULTI_PROGRESS.get_or_init(MultiProgress::new)
+}
+
+/// Hands `bar` over to `multi_progress`; create bars through this rather than showing them on their own.
+pub fn track(bar: ProgressBar) -> ProgressBar {
+    multi_progress().add(bar)
+}
@@ -0,0 +1,155 @@
+/// Longest file name most filesystems take, in bytes.
+pub const MAX_FILENAME_BYTES: usize = 255;
+
+/// Extensions longer than this are treated as part of the name, "a.very long sentence" has none.
+const MAX_EXTENSION_CHARS: usize = 10;
+
+/// Device names Windows won't create a file under, whatever the extension.
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"
+];
+
+/// Turns a tag, a playlist name or an uploaded name into a single path component that is safe on
+/// Windows and Unix alike.
+///
+/// Path separators, control characters and the characters Windows reserves are replaced with `_`,
+/// leading dots and spaces are dropped so the name can't point upwards or be hidden, and so are
+/// the trailing ones Windows strips silently. Reserved device names get a `_` in front. Names longer
+/// than `MAX_FILENAME_BYTES` are cut short, keeping the extension. Never returns an empty string.
+pub fn sanitize_filename(raw: &str) -> String {
+    let replaced: String = raw.chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c
+        })
+        .collect();
+
+    let trimmed = replaced
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if trimmed.is_empty() {
+        return "_".to_string();
+    }
+
+    let stem = trimmed.split('.').next().unwrap_or_default().trim_end();
+    let name = match RESERVED_WINDOWS_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+        true => format!("_{}", trimmed),
+        false => trimmed.to_string()
+    };
+
+    truncate_keeping_extension(name, MAX_FILENAME_BYTES)
+}
+
+/// A `Content-Disposition: attachment` value for a download named `raw`, see RFC 6266.
+///
+/// The name is sanitized first. `filename*` carries it percent-encoded as UTF-8, `filename` is the
+/// fallback for clients that don't read `filename*`, with anything outside printable ASCII replaced by `_`.
+pub fn attachment_disposition(raw: &str) -> String {
+    let name = sanitize_filename(raw);
+    let fallback: String = name.chars()
+        .map(|c| match c {
+            ' '..='~' if c != '%' => c,
+            _ => '_'
+        })
+        .collect();
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, percent_encode(&name))
+}
+
+/// Encodes everything but RFC 5987's `attr-char`.
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+fn truncate_keeping_extension(name: String, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name;
+    }
+
+    let extension = name.rsplit_once('.')
+        .map(|(_, extension)| extension)
+        .filter(|extension| !extension.is_empty() && extension.chars().count() <= MAX_EXTENSION_CHARS);
+
+    // the extension fits with room to spare, MAX_EXTENSION_CHARS is far below max_bytes / 4
+    let suffix = extension.map(|extension| format!(".{}", extension)).unwrap_or_default();
+    let stem = &name[..name.len() - suffix.len()];
+
+    let mut cut = max_bytes - suffix.len();
+    while !stem.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    let stem = stem[..cut].trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+    match stem.is_empty() {
+        true => format!("_{}", suffix),
+        false => format!("{}{}", stem, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_traversal_is_flattened() {
+        let sanitized = sanitize_filename("../../etc/passwd");
+
+        assert!(!sanitized.contains('/'));
+        assert!(!sanitized.starts_with('.'));
+        assert_eq!(sanitized, "_.._etc_passwd");
+        assert_eq!(sanitize_filename(r"..\..\windows\system32"), "_.._windows_system32");
+        assert_eq!(sanitize_filename(".."), "_");
+    }
+
+    #[test]
+    fn test_reserved_windows_names() {
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.mp3"), "_nul.mp3");
+        assert_eq!(sanitize_filename("Com1 .flac"), "_Com1 .flac");
+        assert_eq!(sanitize_filename("CONCERT.flac"), "CONCERT.flac");
+    }
+
+    #[test]
+    fn test_reserved_characters_and_trailing_dots() {
+        assert_eq!(sanitize_filename("What? Why: \"Live\" <2003>*|.mp3"), "What_ Why_ _Live_ _2003___.mp3");
+        assert_eq!(sanitize_filename("tab\there\n.m3u"), "tab_here_.m3u");
+        assert_eq!(sanitize_filename("  trailing dots... "), "trailing dots");
+        assert_eq!(sanitize_filename(""), "_");
+    }
+
+    #[test]
+    fn test_emoji_are_kept() {
+        assert_eq!(sanitize_filename("🎵 night drive 🌙.flac"), "🎵 night drive 🌙.flac");
+    }
+
+    #[test]
+    fn test_long_names_keep_the_extension() {
+        let sanitized = sanitize_filename(&format!("{}.flac", "a".repeat(300)));
+        assert_eq!(sanitized.len(), MAX_FILENAME_BYTES);
+        assert!(sanitized.ends_with("a.flac"));
+
+        // a cut through a multi-byte character backs off to the character before it
+        let sanitized = sanitize_filename(&format!("{}.mp3", "🎵".repeat(100)));
+        assert!(sanitized.len() <= MAX_FILENAME_BYTES);
+        assert!(sanitized.ends_with("🎵.mp3"));
+
+        let without_extension = sanitize_filename(&"b".repeat(300));
+        assert_eq!(without_extension, "b".repeat(MAX_FILENAME_BYTES));
+    }
+
+    #[test]
+    fn test_attachment_disposition() {
+        assert_eq!(attachment_disposition("Road Trip.m3u"), "attachment; filename=\"Road Trip.m3u\"; filename*=UTF-8''Road%20Trip.m3u");
+        assert_eq!(attachment_disposition("Café \"Live\" 100%.m3u"), "attachment; filename=\"Caf_ _Live_ 100_.m3u\"; filename*=UTF-8''Caf%C3%A9%20_Live_%20100%25.m3u");
+        assert_eq!(attachment_disposition("🎵.m3u"), "attachment; filename=\"_.m3u\"; filename*=UTF-8''%F0%9F%8E%B5.m3u");
+    }
+}
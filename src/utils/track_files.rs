@@ -0,0 +1,38 @@
+use crate::domain::track::Track;
+
+/// Whether the file behind the track is still on disk. A row whose file is gone is served as 410 by the API.
+pub async fn file_exists(track: &Track) -> bool {
+    tokio::fs::metadata(track.file_path()).await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Local;
+    use uuid::Uuid;
+
+    use crate::domain::{audiofile::AudioFileType, uploaded::Uploaded};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_exists() {
+        // track paths get lowercased, so the dir name must not contain upper case letters
+        let temp_dir = tempfile::Builder::new()
+            .prefix(&format!("exists-{}", Uuid::new_v4()))
+            .rand_bytes(0)
+            .tempdir()
+            .expect("Failed to create a temp dir");
+        let present_path = temp_dir.path().join("present.mp3");
+        fs::write(&present_path, b"bytes").expect("Failed to write the track file");
+
+        let track_at = |path| Track::new(Uuid::new_v4(), "track", Uuid::new_v4(), 60, path, 5, AudioFileType::Mp3, Uploaded::Denis, Some(Local::now().naive_local()))
+            .expect("Track should be valid");
+
+        assert!(file_exists(&track_at(present_path)).await);
+        assert!(!file_exists(&track_at(temp_dir.path().join("absent.mp3"))).await);
+        // a directory under the track's path doesn't count either
+        assert!(!file_exists(&track_at(temp_dir.path().to_path_buf())).await);
+    }
+}
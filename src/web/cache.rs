@@ -0,0 +1,139 @@
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use lru::LruCache;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{domain::{album::Album, track::Track}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteTracksRepository}};
+
+/// Bounded LRU of tracks and albums by id, so popular entries don't hit the DB on every request.
+///
+/// Every handler that changes or deletes a track or an album has to invalidate it here.
+pub struct EntityCache {
+    tracks: Mutex<Generational<Track>>,
+    albums: Mutex<Generational<Album>>
+}
+
+/// The generation goes up on every invalidation. A fetch that started before an invalidation
+/// doesn't get cached, otherwise a read racing with an update could put the old row back.
+struct Generational<T> {
+    entries: LruCache<Uuid, T>,
+    generation: u64
+}
+
+impl<T: Clone> Generational<T> {
+    fn new(capacity: NonZeroUsize) -> Self {
+        Self { entries: LruCache::new(capacity), generation: 0 }
+    }
+}
+
+impl EntityCache {
+    /// Holds up to `capacity` tracks and as many albums.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            tracks: Mutex::new(Generational::new(capacity)),
+            albums: Mutex::new(Generational::new(capacity))
+        }
+    }
+
+    pub async fn track(&self, pool: &SqlitePool, id: Uuid) -> Result<Option<Track>, RepositoryError> {
+        cached_fetch(&self.tracks, id, SqliteTracksRepository::new().by_id_fetch(pool, id)).await
+    }
+
+    pub async fn album(&self, pool: &SqlitePool, id: Uuid) -> Result<Option<Album>, RepositoryError> {
+        cached_fetch(&self.albums, id, SqliteAlbumsRepository::new().by_id_fetch(pool, id)).await
+    }
+
+    pub fn invalidate_track(&self, id: Uuid) {
+        invalidate(&self.tracks, id);
+    }
+
+    pub fn invalidate_album(&self, id: Uuid) {
+        invalidate(&self.albums, id);
+    }
+
+    /// Drops every entry, for writes that touch more rows than are worth tracking, like a sync.
+    pub fn clear(&self) {
+        clear(&self.tracks);
+        clear(&self.albums);
+    }
+}
+
+async fn cached_fetch<T, F>(cache: &Mutex<Generational<T>>, id: Uuid, fetch: F) -> Result<Option<T>, RepositoryError>
+where
+    T: Clone,
+    F: Future<Output = Result<Option<T>, RepositoryError>>
+{
+    let generation = {
+        let mut cache = lock(cache);
+        if let Some(entity) = cache.entries.get(&id) {
+            return Ok(Some(entity.clone()));
+        }
+        cache.generation
+    };
+
+    let fetched = fetch.await?;
+
+    if let Some(entity) = &fetched {
+        let mut cache = lock(cache);
+        if cache.generation == generation {
+            cache.entries.put(id, entity.clone());
+        }
+    }
+
+    Ok(fetched)
+}
+
+fn invalidate<T>(cache: &Mutex<Generational<T>>, id: Uuid) {
+    let mut cache = lock(cache);
+    cache.entries.pop(&id);
+    cache.generation += 1;
+}
+
+fn clear<T>(cache: &Mutex<Generational<T>>) {
+    let mut cache = lock(cache);
+    cache.entries.clear();
+    cache.generation += 1;
+}
+
+fn lock<T>(cache: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // a poisoned lock only means some request panicked, the cached entries are still fine
+    cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use crate::{domain::{artist::Artist, audiofile::AudioFileType, uploaded::Uploaded}, repository::SqliteArtistsRepository, services::test_helpers::{prepare_db, TestSetupError}};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mutation_invalidates_cached_track() -> Result<(), TestSetupError> {
+        let pool = prepare_db().await.expect("Failed to prepare the test db");
+
+        let artist = Artist::new(Uuid::new_v4(), "artist")?;
+        let album = Album::new(Uuid::new_v4(), "album", *artist.id(), None)?;
+        let track = Track::new(Uuid::new_v4(), "track", *album.id(), 60, "music/track.flac".into(), 1024, AudioFileType::Flac, Uploaded::Denis, Some(Local::now().naive_local()))?;
+        SqliteArtistsRepository::new().save(&pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(&pool, &album).await?;
+        SqliteTracksRepository::new().save(&pool, &track).await?;
+
+        let cache = EntityCache::new(NonZeroUsize::new(8).unwrap());
+        let cached = cache.track(&pool, *track.id()).await?.expect("Track should exist");
+        assert!(matches!(cached.uploaded(), Uploaded::Denis));
+
+        // a write the cache doesn't know about keeps being masked until the id is invalidated
+        SqliteTracksRepository::new().set_uploaded(&pool, track.id(), Uploaded::Masha).await?;
+        assert!(matches!(cache.track(&pool, *track.id()).await?.expect("Track should exist").uploaded(), Uploaded::Denis));
+
+        cache.invalidate_track(*track.id());
+        assert!(matches!(cache.track(&pool, *track.id()).await?.expect("Track should exist").uploaded(), Uploaded::Masha));
+
+        SqliteTracksRepository::new().delete(&pool, track.id()).await?;
+        cache.invalidate_track(*track.id());
+        assert!(cache.track(&pool, *track.id()).await?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{domain::{album::Album, artist::Artist, playlist::Playlist, track::Track}, services::completeness::{IncompleteAlbum, MissingTrack}, utils::normalizations::relative_to};
+
+// What the API sends out. Kept apart from the domain types so the wire format only changes on
+// purpose and internals, like absolute file paths, don't leak by adding a field to an entity.
+
+#[derive(Debug, Serialize)]
+pub struct TrackDto {
+    pub id: Uuid,
+    pub name: String,
+    pub album_id: Uuid,
+    pub duration_secs: u32,
+    pub file_size: u64,
+    /// Extension of the file, e.g. `flac`.
+    pub file_type: &'static str,
+    pub uploaded: &'static str,
+    pub date_added: Option<NaiveDateTime>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    pub probe_ok: bool,
+    pub genre: Option<String>,
+    /// Path inside the music library with forward slashes, e.g. `chevelle/wonder what's next/the red.flac`.
+    /// Only set by `in_library`, and left out for files outside of the library.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Absolute path on the server, only sent with `expose_file_paths` turned on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>
+}
+
+impl TrackDto {
+    pub fn new(track: &Track, expose_file_path: bool) -> Self {
+        Self {
+            id: *track.id(),
+            name: track.name().to_string(),
+            album_id: *track.album_id(),
+            duration_secs: track.duration(),
+            file_size: track.file_size(),
+            file_type: track.file_type().as_str(),
+            uploaded: track.uploaded().into(),
+            date_added: *track.date_added(),
+            disc_number: track.disc_number(),
+            track_number: track.track_number(),
+            probe_ok: track.probe_ok(),
+            genre: track.genre().map(str::to_string),
+            path: None,
+            file_path: expose_file_path.then(|| track.file_path().to_string_lossy().to_string())
+        }
+    }
+
+    /// Same as `new`, along with the `path` of the track inside the library at `music_root`.
+    pub fn in_library(track: &Track, music_root: &Path, expose_file_path: bool) -> Self {
+        Self { path: relative_path(track.file_path(), music_root), ..Self::new(track, expose_file_path) }
+    }
+}
+
+impl From<&Track> for TrackDto {
+    fn from(track: &Track) -> Self {
+        Self::new(track, false)
+    }
+}
+
+impl From<Track> for TrackDto {
+    fn from(track: Track) -> Self {
+        Self::from(&track)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumDto {
+    pub id: Uuid,
+    pub name: String,
+    pub artist_id: Uuid,
+    pub year: Option<u32>
+}
+
+impl From<Album> for AlbumDto {
+    fn from(album: Album) -> Self {
+        Self {
+            id: *album.id(),
+            name: album.name().to_string(),
+            artist_id: *album.artist_id(),
+            year: album.year()
+        }
+    }
+}
+
+/// A track along with its album and artist, either of them `null` if the track points at one that's gone.
+#[derive(Debug, Serialize)]
+pub struct TrackDetailDto {
+    pub track: TrackDto,
+    pub album: Option<AlbumDto>,
+    pub artist: Option<ArtistDto>
+}
+
+#[derive(Debug, Serialize)]
+pub struct IncompleteAlbumDto {
+    pub album: AlbumDto,
+    pub missing_tracks: Vec<MissingTrack>
+}
+
+impl From<IncompleteAlbum> for IncompleteAlbumDto {
+    fn from(incomplete: IncompleteAlbum) -> Self {
+        Self { album: incomplete.album.into(), missing_tracks: incomplete.missing }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistDto {
+    pub id: Uuid,
+    pub name: String,
+    pub musicbrainz_id: Option<String>
+}
+
+impl From<Artist> for ArtistDto {
+    fn from(artist: Artist) -> Self {
+        Self {
+            id: *artist.id(),
+            name: artist.name().to_string(),
+            musicbrainz_id: artist.musicbrainz_id().map(str::to_string)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistDto {
+    pub id: Uuid,
+    pub name: String
+}
+
+impl From<Playlist> for PlaylistDto {
+    fn from(playlist: Playlist) -> Self {
+        Self {
+            id: *playlist.id(),
+            name: playlist.name().to_string()
+        }
+    }
+}
+
+/// A playlist along with its tracks, in playlist order.
+#[derive(Debug, Serialize)]
+pub struct PlaylistDetailDto {
+    pub id: Uuid,
+    pub name: String,
+    pub tracks: Vec<TrackDto>
+}
+
+/// One page of a list endpoint, along with the window that was asked for.
+#[derive(Debug, Serialize)]
+pub struct PagedResponse<T> {
+    pub items: Vec<T>,
+    pub limit: u32,
+    pub offset: u32
+}
+
+impl<T> PagedResponse<T> {
+    pub fn new<D: Into<T>>(items: Vec<D>, limit: u32, offset: u32) -> Self {
+        Self { items: items.into_iter().map(Into::into).collect(), limit, offset }
+    }
+}
+
+/// `path` relative to `music_root` as it goes into the JSON, see `relative_to`.
+pub fn relative_path(path: &Path, music_root: &Path) -> Option<String> {
+    relative_to(path, music_root).map(|relative| relative.to_string_lossy().into_owned())
+}
+
+/// Converts a list of domain entities into their DTOs.
+pub fn to_dtos<D, T: From<D>>(items: Vec<D>) -> Vec<T> {
+    items.into_iter().map(T::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use crate::domain::{audiofile::AudioFileType, uploaded::Uploaded};
+    use super::*;
+
+    #[test]
+    fn test_track_dto_omits_file_path() {
+        let track = Track::new(Uuid::new_v4(), "track", Uuid::new_v4(), 215, "d:/music/secret/track.flac".into(), 1024, AudioFileType::Flac, Uploaded::Masha, Some(Local::now().naive_local()))
+            .expect("Track should be valid");
+
+        let json = serde_json::to_value(TrackDto::from(&track)).expect("TrackDto should serialize");
+
+        assert!(json.get("file_path").is_none());
+        assert!(!json.to_string().contains("secret"));
+        assert_eq!(json["id"], track.id().to_string());
+        assert_eq!(json["duration_secs"], 215);
+        assert_eq!(json["file_type"], "flac");
+        assert_eq!(json["uploaded"], "masha");
+    }
+
+    #[test]
+    fn test_track_dto_exposes_file_path_on_request() {
+        let track = Track::new(Uuid::new_v4(), "track", Uuid::new_v4(), 215, "d:/music/secret/track.flac".into(), 1024, AudioFileType::Flac, Uploaded::Masha, Some(Local::now().naive_local()))
+            .expect("Track should be valid");
+
+        let hidden = serde_json::to_value(TrackDto::new(&track, false)).expect("TrackDto should serialize");
+        let exposed = serde_json::to_value(TrackDto::new(&track, true)).expect("TrackDto should serialize");
+
+        assert!(hidden.get("file_path").is_none());
+        assert_eq!(exposed["file_path"], track.file_path().to_string_lossy().as_ref());
+    }
+
+    #[test]
+    fn test_track_dto_path_is_relative_with_forward_slashes() {
+        let track = Track::new(Uuid::new_v4(), "the red", Uuid::new_v4(), 238, r"D:\Music\Chevelle\Wonder What's Next\the red.flac".into(), 1024, AudioFileType::Flac, Uploaded::Masha, None)
+            .expect("Track should be valid");
+
+        let json = serde_json::to_value(TrackDto::in_library(&track, Path::new("d:/music/"), false)).expect("TrackDto should serialize");
+
+        // tracks keep their paths lowercased
+        assert_eq!(json["path"], "chevelle/wonder what's next/the red.flac");
+        assert!(json.get("file_path").is_none());
+        assert!(!json.to_string().contains("d:"));
+    }
+
+    #[test]
+    fn test_relative_path_outside_of_the_root() {
+        assert_eq!(relative_path(Path::new("/srv/music/a/b.mp3"), Path::new("/srv/music")), Some("a/b.mp3".to_string()));
+        assert_eq!(relative_path(Path::new("/srv/music-old/b.mp3"), Path::new("/srv/music")), None);
+        assert_eq!(relative_path(Path::new("/srv/music"), Path::new("/srv/music")), None);
+        assert_eq!(relative_path(Path::new("/tmp/b.mp3"), Path::new("/srv/music")), None);
+    }
+}
@@ -1,21 +1,71 @@
-use axum::{body::Body, extract::{Path, Request, State}, http::{StatusCode}, response::{Html, IntoResponse}};
+use std::{path::Component, sync::Arc, time::Duration};
+
+use axum::{body::Body, extract::{Path, Query, Request, State}, http::{header, HeaderMap, HeaderValue, StatusCode}, response::{Html, IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
 use tower_http::services::ServeFile;
 use uuid::Uuid;
 use tower::util::ServiceExt;
+use futures::StreamExt;
+use tokio_util::io::ReaderStream;
+
+use crate::{domain::{playlist::Playlist, track::{Track, TrackSort}, uploaded::Uploaded}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteArtistsRepository, SqlitePlaylistsRepository, SqliteTracksRepository}, services::{artwork::{CoverService, MissingArtworkService}, transcode::{spawn_transcode, TranscodeTarget}, resample::{FfmpegResampler, FileResampleOutcome, ResampleConfig, ResampleService}, export::{playlist_m3u, stream_tracks_csv}, metadata_provider::{ExternalAlbumInfo, MetadataProvider}, prune::{delete_track_and_prune, PruneReport}, completeness::find_incomplete_albums, scanner::{MediaScanner, ScanPreview}, sync::{compute_diff, MusicLibSyncService, SyncDiff}, SyncServiceError}, utils::{config::get_config, normalizations::normalize_path, sanitize::attachment_disposition, track_files::file_exists}, web::{template_builders::build_index_page, dto::{to_dtos, AlbumDto, ArtistDto, IncompleteAlbumDto, PagedResponse, PlaylistDetailDto, PlaylistDto, TrackDetailDto, TrackDto}, AppState, ResampleGuard, StreamGuard, SyncGuard, WebLayerError}};
+
+pub async fn serve_index(State(state): State<AppState>) -> Result<Html<String>, WebLayerError> {
+    // rebuilt on every request while the initial sync is adding tracks
+    if state.startup.is_starting() {
+        return Ok(Html(build_index_page(state.pool).await?));
+    }
+
+    let index_html = state.index_html.get_or_try_init(|| build_index_page(state.pool)).await?;
 
-use crate::{repository::SqliteTracksRepository, web::AppState};
+    Ok(Html(index_html.clone()))
+}
+
+#[derive(Serialize)]
+pub struct HealthStatus {
+    /// True while the initial sync is running; reads work, mutations get 503.
+    pub starting: bool
+}
+
+pub async fn health(State(state): State<AppState>) -> Json<HealthStatus> {
+    Json(HealthStatus { starting: state.startup.is_starting() })
+}
 
-pub async fn serve_index(State(state): State<AppState>) -> impl IntoResponse {
-    Html(state.index_html.as_ref().clone())
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// Target format to transcode into on the fly, for clients that can't play the original.
+    pub transcode: Option<String>
 }
 
-pub async fn serve_track(State(state): State<AppState>, Path(id): Path<Uuid>, request: Request<Body>) -> impl IntoResponse {
-    match SqliteTracksRepository::new().by_id_fetch(state.pool, id).await {
+pub async fn serve_track(State(state): State<AppState>, Path(id): Path<Uuid>, Query(query): Query<StreamQuery>, request: Request<Body>) -> Response {
+    if let Some(target) = query.transcode {
+        return transcode_track(state, id, &target).await.unwrap_or_else(IntoResponse::into_response);
+    }
+
+    match state.track_by_id(id).await {
+        Ok(Some(track)) if !file_exists(&track).await => WebLayerError::TrackFileMissing(id).into_response(),
+
         Ok(Some(track)) => {
+            let guard = match StreamGuard::acquire(&state.file_locks, id) {
+                Ok(guard) => guard,
+                Err(err) => return err.into_response()
+            };
+
+            // ServeFile stats the file itself, so a stale stored `file_size` never ends up in Content-Length,
+            // and answers `Range` requests with 206 (or 416 when the range is off the end of the file)
             let serve_result = ServeFile::new(track.file_path()).oneshot(request).await;
 
             match serve_result {
-                Ok(response) => response.into_response(),
+                Ok(mut response) => {
+                    if response.status().is_success() {
+                        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(track.file_type().mime_type()));
+                    }
+                    // the body holds the guard, the file counts as streamed until the client has it all or goes away
+                    response.map(|body| Body::from_stream(Body::new(body).into_data_stream().map(move |chunk| {
+                        let _keep_alive = &guard;
+                        chunk
+                    }))).into_response()
+                },
                 Err(err) => (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("Failed to serve file: {}\nTrack: {:?}", err, track)
@@ -23,11 +73,958 @@ pub async fn serve_track(State(state): State<AppState>, Path(id): Path<Uuid>, re
             }
         },
 
-        Ok(None) => {
-            println!("Failed to serve the track; id is {}", id);
-            (StatusCode::NOT_FOUND, "Track not found").into_response()
+        Ok(None) => WebLayerError::RepositoryError(RepositoryError::IdNotFound(id)).into_response(),
+        Err(err) => WebLayerError::RepositoryError(err).into_response(),
+    }
+
+}
+
+#[derive(Deserialize)]
+pub struct UploadedPatch {
+    pub uploaded: String
+}
+
+pub async fn update_track_uploaded(State(state): State<AppState>, Path(id): Path<Uuid>, Json(patch): Json<UploadedPatch>) -> Result<Json<TrackDto>, WebLayerError> {
+    let uploaded = Uploaded::try_from(patch.uploaded)?;
+    let track = SqliteTracksRepository::new().set_uploaded(state.pool, id, uploaded).await?;
+    state.invalidate_track(id);
+
+    Ok(Json(state.track_dto(&track)))
+}
+
+
+pub async fn export_tracks_csv(State(state): State<AppState>) -> Result<Response, WebLayerError> {
+    let csv_stream = stream_tracks_csv(state.pool, state.music_root.clone()).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"tracks.csv\"")
+        ],
+        Body::from_stream(csv_stream)
+    ).into_response())
+}
+
+pub async fn albums_without_art(State(state): State<AppState>) -> Result<Json<Vec<AlbumDto>>, WebLayerError> {
+    let albums = MissingArtworkService::find(state.pool, &state.music_root).await?;
+
+    Ok(Json(to_dtos(albums)))
+}
+
+/// Albums with gaps in their track numbers, along with the numbers that are missing.
+pub async fn incomplete_albums(State(state): State<AppState>) -> Result<Json<Vec<IncompleteAlbumDto>>, WebLayerError> {
+    let albums = find_incomplete_albums(state.pool).await?;
+
+    Ok(Json(to_dtos(albums)))
+}
+
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    /// Max width and height in pixels. Unset returns the cover as it is stored.
+    pub size: Option<u32>
+}
+
+pub async fn album_cover(State(state): State<AppState>, Path(id): Path<Uuid>, Query(query): Query<CoverQuery>) -> Result<Response, WebLayerError> {
+    let cover = CoverService::album_cover(state.pool, &state.music_root, &state.covers, id, query.size).await?
+        .ok_or(WebLayerError::CoverNotFound(id))?;
+
+    Ok((
+        [(header::CONTENT_TYPE, cover.mime_type.clone())],
+        cover.data.clone()
+    ).into_response())
+}
+
+/// Tracks whose tags couldn't be read, i.e. the files that ended up under "unknown artist" and need fixing.
+pub async fn unprobed_tracks(State(state): State<AppState>) -> Result<Json<Vec<TrackDto>>, WebLayerError> {
+    let tracks = SqliteTracksRepository::new().all_unprobed(state.pool).await?;
+
+    Ok(Json(state.track_dtos(&tracks)))
+}
+
+const DEFAULT_PAGE_LIMIT: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct TracksQuery {
+    /// Directory to list tracks under, recursively, relative to the music library like the `path` of a track.
+    pub under: Option<String>,
+    /// name, date_added, duration or artist. Unknown keys fall back to name.
+    pub sort: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>
+}
+
+pub async fn list_tracks(State(state): State<AppState>, Query(query): Query<TracksQuery>) -> Result<Json<PagedResponse<TrackDto>>, WebLayerError> {
+    let prefix = match query.under {
+        Some(under) if !under.is_empty() => library_prefix(&state.music_root, &under)?,
+        _ => String::new()
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    let sort = match query.sort.as_deref() {
+        None => state.default_track_sort,
+        Some(key) => key.parse().unwrap_or_else(|e| {
+            log::debug!("{}", e);
+            TrackSort::Name
+        })
+    };
+    let tracks = SqliteTracksRepository::new().by_path_prefix_sorted(state.pool, prefix, sort, limit, offset).await?;
+
+    Ok(Json(PagedResponse::new(state.track_dtos(&tracks), limit, offset)))
+}
+
+/// The stored path prefix of `under`, a directory inside the library at `music_root`.
+/// Anything that could point outside of it, `..` or an absolute path, is refused.
+fn library_prefix(music_root: &std::path::Path, under: &str) -> Result<String, WebLayerError> {
+    let under = under.replace('\\', "/");
+    let inside = std::path::Path::new(&under).components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(WebLayerError::OutsideOfLibrary(under));
+    }
+
+    // a trailing slash keeps `music/rock` from matching `music/rockabilly`
+    let mut prefix = normalize_path(&music_root.join(&under)).to_string_lossy().to_string();
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+
+    Ok(prefix)
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>
+}
+
+pub async fn list_artists(State(state): State<AppState>, Query(query): Query<PageQuery>) -> Result<Json<PagedResponse<ArtistDto>>, WebLayerError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    let artists = SqliteArtistsRepository::new().fetch_page(state.pool, limit.into(), offset.into()).await?;
+
+    Ok(Json(PagedResponse::new(artists, limit, offset)))
+}
+
+/// Albums of the artist, oldest first; albums without a year go last.
+pub async fn artist_albums(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Vec<AlbumDto>>, WebLayerError> {
+    // an artist without albums is a valid empty list, an unknown one is a 404
+    SqliteArtistsRepository::new().by_id_fetch(state.pool, id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+
+    let mut albums = SqliteAlbumsRepository::new().all_by_artist(state.pool, id).await?;
+    albums.sort_by(|a, b| (a.year().is_none(), a.year(), a.name()).cmp(&(b.year().is_none(), b.year(), b.name())));
+
+    Ok(Json(to_dtos(albums)))
+}
+
+/// Tracks of the album in disc and track order.
+pub async fn album_tracks(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Vec<TrackDto>>, WebLayerError> {
+    state.album_by_id(id).await?.ok_or(RepositoryError::IdNotFound(id))?;
+    let tracks = SqliteTracksRepository::new().all_by_album(state.pool, id).await?;
+
+    Ok(Json(state.track_dtos(&tracks)))
+}
+
+pub async fn get_track(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<TrackDto>, WebLayerError> {
+    let track = state.track_by_id(id).await?.ok_or(RepositoryError::IdNotFound(id))?;
+
+    Ok(Json(state.track_dto(&track)))
+}
+
+/// The track with its album and artist, so the track page needs a single request. With foreign keys on
+/// the album and artist are always there, should either be missing anyway, it's sent as null and logged.
+pub async fn track_detail(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<TrackDetailDto>, WebLayerError> {
+    let track = state.track_by_id(id).await?.ok_or(RepositoryError::IdNotFound(id))?;
+
+    let album = state.album_by_id(*track.album_id()).await?;
+    if album.is_none() {
+        log::warn!("Track <{}> points at album <{}>, which doesn't exist", id, track.album_id());
+    }
+
+    let artist = match &album {
+        Some(album) => {
+            let artist = SqliteArtistsRepository::new().by_id_fetch(state.pool, album.artist_id()).await?;
+            if artist.is_none() {
+                log::warn!("Album <{}> points at artist <{}>, which doesn't exist", album.id(), album.artist_id());
+            }
+            artist
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response(),
+        None => None
+    };
+
+    Ok(Json(TrackDetailDto {
+        track: state.track_dto(&track),
+        album: album.map(AlbumDto::from),
+        artist: artist.map(ArtistDto::from)
+    }))
+}
+
+#[derive(Serialize)]
+pub struct AlbumEnrichment {
+    pub album: AlbumDto,
+    pub suggestion: ExternalAlbumInfo
+}
+
+/// Looks the album up in the external source and returns the suggested corrections.
+/// Nothing is written, the caller decides what to apply.
+pub async fn enrich_album(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<AlbumEnrichment>, WebLayerError> {
+    if !get_config()?.features.metadata_lookup {
+        return Err(WebLayerError::MetadataLookupDisabled);
     }
 
+    let album = state.album_by_id(id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+    let artist = SqliteArtistsRepository::new().by_id_fetch(state.pool, album.artist_id()).await?
+        .ok_or(RepositoryError::IdNotFound(*album.artist_id()))?;
+
+    let suggestion = state.metadata_provider.lookup_album(artist.name(), album.name()).await?;
+
+    Ok(Json(AlbumEnrichment { album: album.into(), suggestion }))
+}
+
+/// Scans the music library without touching the DB and returns what was found.
+pub async fn scan_preview() -> Result<Json<ScanPreview>, WebLayerError> {
+    let config = get_config()?;
+
+    let scan_result = tokio::task::spawn_blocking(move || {
+        MediaScanner::from_config(config).scan_music_lib()
+    }).await??;
+
+    Ok(Json(scan_result.into()))
+}
+
+/// Starts a sync of the library in the background and answers right away with 202, or 409 while
+/// another one is running. The outcome only goes to the log.
+pub async fn start_sync(State(state): State<AppState>) -> Result<StatusCode, WebLayerError> {
+    let config = get_config()?;
+    let guard = SyncGuard::acquire(&state.sync_job)?;
+
+    tokio::spawn(async move {
+        let synced = async {
+            MusicLibSyncService::from_config(state.pool, config).await?
+                .with_cancellation(guard.token())
+                .synchronize().await
+        }.await;
+
+        match synced {
+            Ok(report) => log::info!("Sync has finished: {}", report),
+            Err(SyncServiceError::Cancelled) => log::warn!("Sync was cancelled"),
+            Err(err) => log::error!("Sync has failed: {}", err)
+        }
+
+        // batched commits may have landed even when the sync failed
+        state.invalidate_all();
+        drop(guard);
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// What a sync would change right now, with counts for a quick summary and the paths behind them.
+/// Nothing is written.
+pub async fn sync_preview(State(state): State<AppState>) -> Result<Json<SyncDiff>, WebLayerError> {
+    let diff = compute_diff(state.pool, get_config()?).await?;
+
+    Ok(Json(diff))
+}
+
+/// Asks the running sync to stop, 409 if there's none. It stops at its next check and rolls back
+/// whatever it hasn't committed yet.
+pub async fn cancel_sync(State(state): State<AppState>) -> Result<StatusCode, WebLayerError> {
+    match state.sync_job.cancel() {
+        true => Ok(StatusCode::ACCEPTED),
+        false => Err(WebLayerError::NoSyncRunning)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteTrackQuery {
+    /// Also remove the album and the artist if this was their last track.
+    #[serde(default)]
+    pub prune: bool
+}
+
+pub async fn delete_track(State(state): State<AppState>, Path(id): Path<Uuid>, Query(query): Query<DeleteTrackQuery>) -> Result<Json<PruneReport>, WebLayerError> {
+    let report = if query.prune {
+        delete_track_and_prune(state.pool, id).await?
+    } else {
+        SqliteTracksRepository::new().delete(state.pool, id).await?;
+        PruneReport { track_id: id, album_id: None, artist_id: None }
+    };
+
+    state.invalidate_track(id);
+    if let Some(album_id) = report.album_id {
+        state.invalidate_album(album_id);
+    }
+
+    Ok(Json(report))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TrackResampleResult {
+    Processed { output_path: String, backup_path: Option<String> },
+    Skipped { reason: String },
+    Failed { error: String }
+}
+
+/// Resamples one track with the server's `[media.resample]` settings, to try them out without
+/// touching the whole library. Failures of the resample itself (ffmpeg stderr included) are
+/// part of the result; only a missing track, file or a resample already running are errors.
+pub async fn resample_track(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<TrackResampleResult>, WebLayerError> {
+    let config = get_config()?;
+    if !config.features.resample {
+        return Err(WebLayerError::ResampleDisabled);
+    }
+
+    let track = state.track_by_id(id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+
+    if !file_exists(&track).await {
+        return Err(WebLayerError::TrackFileMissing(id));
+    }
+
+    let _guard = ResampleGuard::acquire(&state.file_locks, id)?;
+
+    let result = tokio::task::spawn_blocking(move || -> Result<TrackResampleResult, WebLayerError> {
+        let descriptor = MediaScanner::from_config(config).describe_file(track.file_path())?;
+
+        let resampler = match FfmpegResampler::new(config.media.ffmpeg_exe_path.clone(), &config.media.resample) {
+            Ok(resampler) => resampler,
+            Err(err) => return Ok(TrackResampleResult::Failed { error: err.to_string() })
+        };
+        let service = ResampleService::new(ResampleConfig::from_settings(&config.media.resample, &config.media.music_path, &config.media.resampled_music_path), resampler);
+
+        Ok(match service.resample_file(&descriptor) {
+            Ok(FileResampleOutcome::Processed { output_path, backup_path }) => TrackResampleResult::Processed {
+                output_path: output_path.to_string_lossy().to_string(),
+                backup_path: backup_path.map(|path| path.to_string_lossy().to_string())
+            },
+            Ok(FileResampleOutcome::Skipped(reason)) => TrackResampleResult::Skipped { reason: format!("{:?}", reason) },
+            Err(err) => TrackResampleResult::Failed { error: err.to_string() }
+        })
+    }).await??;
+
+    Ok(Json(result))
+}
+
+/// How long a transcode waits for a free ffmpeg slot before giving up with 503.
+const TRANSCODE_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Streams the track through ffmpeg. The length isn't known upfront, so the response is chunked
+/// and Range requests aren't supported.
+async fn transcode_track(state: AppState, id: Uuid, target: &str) -> Result<Response, WebLayerError> {
+    let target: TranscodeTarget = target.parse()?;
+
+    let track = state.track_by_id(id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+    if !file_exists(&track).await {
+        return Err(WebLayerError::TrackFileMissing(id));
+    }
+
+    let guard = StreamGuard::acquire(&state.file_locks, id)?;
+    let permit = tokio::time::timeout(TRANSCODE_QUEUE_TIMEOUT, Arc::clone(&state.transcodes).acquire_owned()).await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or(WebLayerError::TranscodeBusy)?;
+
+    let (ffmpeg, stdout) = spawn_transcode(&get_config()?.media.ffmpeg_exe_path, track.file_path(), target)?;
+
+    // the stream owns ffmpeg, the permit and the guard: once the body is done or the client goes away,
+    // ffmpeg gets killed, the slot frees up and the file can be resampled again. A client that stays
+    // to the end gets a short or empty body when ffmpeg fails, so the failure is logged here
+    let finished = futures::stream::once(async move {
+        if let Err(err) = ffmpeg.finish().await {
+            log::error!("{}", err);
+        }
+        drop((permit, guard));
+    }).filter_map(|_| std::future::ready(None));
+    let body_stream = ReaderStream::new(stdout).chain(finished);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, target.content_type()),
+            (header::ACCEPT_RANGES, "none")
+        ],
+        Body::from_stream(body_stream)
+    ).into_response())
+}
+
+/// Headers a track is served with: size (from the file, not the stored row), type and range support. Shared by `HEAD` so players can
+/// learn about the file without downloading it.
+async fn track_file_headers(track: &Track) -> Result<HeaderMap, WebLayerError> {
+    let file_metadata = tokio::fs::metadata(track.file_path()).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(file_metadata.len()));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(track.file_type().mime_type()));
+
+    Ok(headers)
+}
+
+pub async fn head_track(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Response, WebLayerError> {
+    let track = state.track_by_id(id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+
+    if !file_exists(&track).await {
+        return Err(WebLayerError::TrackFileMissing(id));
+    }
+
+    let headers = track_file_headers(&track).await?;
+
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct NewPlaylist {
+    pub name: String
+}
+
+pub async fn list_playlists(State(state): State<AppState>) -> Result<Json<Vec<PlaylistDto>>, WebLayerError> {
+    let playlists = SqlitePlaylistsRepository::new().all(state.pool).await?;
+
+    Ok(Json(to_dtos(playlists)))
+}
+
+pub async fn create_playlist(State(state): State<AppState>, Json(new_playlist): Json<NewPlaylist>) -> Result<(StatusCode, Json<PlaylistDto>), WebLayerError> {
+    let playlist = Playlist::new(Uuid::new_v4(), new_playlist.name)?;
+    let playlist = SqlitePlaylistsRepository::new().save(state.pool, &playlist).await?;
+
+    Ok((StatusCode::CREATED, Json(playlist.into())))
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistTrackAddition {
+    pub track_id: Uuid
+}
+
+/// Appends a track to the end of the playlist and returns the playlist as it is now.
+pub async fn add_playlist_track(State(state): State<AppState>, Path(id): Path<Uuid>, Json(addition): Json<PlaylistTrackAddition>) -> Result<Json<PlaylistDetailDto>, WebLayerError> {
+    let playlists_repo = SqlitePlaylistsRepository::new();
+
+    // checked up front, the foreign keys alone would only give a generic constraint violation
+    let playlist = playlists_repo.by_id_fetch(state.pool, id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+    state.track_by_id(addition.track_id).await?
+        .ok_or(RepositoryError::IdNotFound(addition.track_id))?;
+
+    playlists_repo.add_track(state.pool, id, addition.track_id).await?;
+
+    playlist_detail(&state, playlist).await.map(Json)
+}
+
+pub async fn get_playlist(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<PlaylistDetailDto>, WebLayerError> {
+    let playlist = SqlitePlaylistsRepository::new().by_id_fetch(state.pool, id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+
+    playlist_detail(&state, playlist).await.map(Json)
 }
+
+async fn playlist_detail(state: &AppState, playlist: Playlist) -> Result<PlaylistDetailDto, WebLayerError> {
+    let tracks = SqliteTracksRepository::new().by_playlist(state.pool, playlist.id()).await?;
+
+    Ok(PlaylistDetailDto {
+        id: *playlist.id(),
+        name: playlist.name().to_string(),
+        tracks: state.track_dtos(&tracks)
+    })
+}
+
+/// The playlist as an M3U file to open in any player. Entries point at this server, using the
+/// host the request was sent to, so the file works from whichever machine downloaded it. The
+/// download is named after the playlist.
+pub async fn playlist_m3u_file(State(state): State<AppState>, Path(id): Path<Uuid>, headers: HeaderMap) -> Result<Response, WebLayerError> {
+    let playlist = SqlitePlaylistsRepository::new().by_id_fetch(state.pool, id).await?
+        .ok_or(RepositoryError::IdNotFound(id))?;
+    let tracks = SqliteTracksRepository::new().by_playlist(state.pool, id).await?;
+
+    let base_url = headers.get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("http://{}", host))
+        .unwrap_or_default();
+
+    let disposition = attachment_disposition(&format!("{}.m3u", playlist.name()));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "audio/x-mpegurl; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, disposition)
+        ],
+        playlist_m3u(&playlist, &tracks, &base_url)
+    ).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use axum::{body::to_bytes, http::Method};
+    use chrono::Local;
+    use sqlx::SqlitePool;
+    use tempfile::TempDir;
+
+    use crate::{domain::{album::Album, artist::Artist, audiofile::AudioFileType}, repository::{SqliteAlbumsRepository, SqliteArtistsRepository}, services::test_helpers::{prepare_db, TestSetupError}, web::{routes::{create_router, create_router_and_state, RouterSettings}, StartupStatus, SyncJob}};
+    use super::*;
+
+    async fn test_pool() -> &'static SqlitePool {
+        Box::leak(Box::new(prepare_db().await.expect("Failed to prepare the test db")))
+    }
+
+    async fn test_app(pool: &'static SqlitePool, settings: RouterSettings) -> axum::Router {
+        create_router(pool, settings, Arc::new(StartupStatus::ready())).await.expect("Failed to create the router")
+    }
+
+    /// Track paths are lowercased on save, so the dir name must not contain upper case letters.
+    fn lowercase_temp_dir(prefix: &str) -> std::io::Result<TempDir> {
+        tempfile::Builder::new()
+            .prefix(&format!("{}-{}", prefix, Uuid::new_v4()))
+            .rand_bytes(0)
+            .tempdir()
+    }
+
+    async fn seed_album(pool: &SqlitePool) -> Result<Album, TestSetupError> {
+        let artist = Artist::new(Uuid::new_v4(), "artist")?;
+        let album = Album::new(Uuid::new_v4(), "album", *artist.id(), None)?;
+        SqliteArtistsRepository::new().save(pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(pool, &album).await?;
+
+        Ok(album)
+    }
+
+    async fn seed_track(pool: &SqlitePool, album: &Album, name: &str, file_path: PathBuf, file_size: u64) -> Result<Track, TestSetupError> {
+        let track = Track::new(Uuid::new_v4(), name, *album.id(), 60, file_path, file_size, AudioFileType::Flac, Uploaded::Denis, Some(Local::now().naive_local()))?;
+        SqliteTracksRepository::new().save(pool, &track).await?;
+
+        Ok(track)
+    }
+
+    #[tokio::test]
+    async fn test_head_and_get_agree_on_headers() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let temp_dir = lowercase_temp_dir("stream")?;
+        let track_path = temp_dir.path().join("track.flac");
+        fs::write(&track_path, vec![7u8; 1234])?;
+
+        let album = seed_album(pool).await?;
+        let track = seed_track(pool, &album, "track", track_path, 1234).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let uri = format!("/api/tracks/{}/stream", track.id());
+
+        let request = |method: Method| Request::builder().method(method).uri(&uri).body(Body::empty()).unwrap();
+        let head = app.clone().oneshot(request(Method::HEAD)).await.unwrap();
+        let get = app.oneshot(request(Method::GET)).await.unwrap();
+
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(get.status(), StatusCode::OK);
+
+        for name in [header::CONTENT_LENGTH, header::ACCEPT_RANGES, header::CONTENT_TYPE] {
+            assert!(head.headers().contains_key(&name), "HEAD is missing {}", name);
+            assert_eq!(head.headers().get(&name), get.headers().get(&name), "{} differs", name);
+        }
+
+        let head_body = to_bytes(head.into_body(), usize::MAX).await.unwrap();
+        assert!(head_body.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_size_row_streams_real_size() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let temp_dir = lowercase_temp_dir("stale")?;
+        let track_path = temp_dir.path().join("track.flac");
+        fs::write(&track_path, vec![7u8; 4321])?;
+
+        let album = seed_album(pool).await?;
+        // the file has grown since the size was stored
+        let track = seed_track(pool, &album, "track", track_path, 1234).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let uri = format!("/api/tracks/{}/stream", track.id());
+
+        for method in [Method::HEAD, Method::GET] {
+            let request = Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK, "{} failed", method);
+            assert_eq!(response.headers().get(header::CONTENT_LENGTH).unwrap(), "4321", "{} has a wrong size", method);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_track_file_is_gone() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let temp_dir = lowercase_temp_dir("gone")?;
+
+        let album = seed_album(pool).await?;
+        let track = seed_track(pool, &album, "track", temp_dir.path().join("deleted.flac"), 1234).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let uri = format!("/api/tracks/{}/stream", track.id());
+
+        for method in [Method::HEAD, Method::GET] {
+            let request = Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::GONE, "{} failed", method);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transcode_rejects_unknown_target() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+
+        let request = Request::builder()
+            .uri(format!("/api/tracks/{}/stream?transcode=flac", Uuid::new_v4()))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deleted_track_is_not_served_from_cache() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let temp_dir = lowercase_temp_dir("cached")?;
+        let track_path = temp_dir.path().join("track.flac");
+        fs::write(&track_path, vec![7u8; 64])?;
+
+        let album = seed_album(pool).await?;
+        let track = seed_track(pool, &album, "track", track_path, 64).await?;
+
+        let app = test_app(pool, RouterSettings { entity_cache_size: Some(16), ..RouterSettings::new("/music") }).await;
+        let head = || Request::builder().method(Method::HEAD).uri(format!("/api/tracks/{}/stream", track.id())).body(Body::empty()).unwrap();
+
+        assert_eq!(app.clone().oneshot(head()).await.unwrap().status(), StatusCode::OK);
+
+        let delete = Request::builder().method(Method::DELETE).uri(format!("/api/tracks/{}", track.id())).body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(delete).await.unwrap().status(), StatusCode::OK);
+
+        assert_eq!(app.oneshot(head()).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_handle_clears_the_cache_of_the_router() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let album = seed_album(pool).await?;
+        let track = seed_track(pool, &album, "before", "/music/track.flac".into(), 64).await?;
+
+        let settings = RouterSettings { entity_cache_size: Some(16), ..RouterSettings::new("/music") };
+        let (app, state) = create_router_and_state(pool, settings, Arc::new(StartupStatus::ready())).await.expect("Failed to create the router");
+        let uri = format!("/api/tracks/{}", track.id());
+        let track_name = |app: axum::Router| async {
+            let request = Request::builder().uri(&uri).body(Body::empty()).unwrap();
+            let body = to_bytes(app.oneshot(request).await.unwrap().into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["name"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(track_name(app.clone()).await, "before");
+
+        // changed behind the router's back, the way the initial sync does it
+        sqlx::query("UPDATE tracks SET name = 'after' WHERE id = ?;").bind(track.id()).execute(pool).await.expect("Failed to rename the track");
+        assert_eq!(track_name(app.clone()).await, "before");
+
+        state.invalidate_all();
+        assert_eq!(track_name(app).await, "after");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_playlist_endpoints() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let album = seed_album(pool).await?;
+        let first = seed_track(pool, &album, "first", "playlist/first.flac".into(), 64).await?;
+        let second = seed_track(pool, &album, "second", "playlist/second.flac".into(), 64).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let post = |uri: String, body: serde_json::Value| Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let json = |bytes: &[u8]| serde_json::from_slice::<serde_json::Value>(bytes).expect("Response should be JSON");
+
+        let response = app.clone().oneshot(post("/api/playlists".to_string(), serde_json::json!({ "name": "Road Trip" }))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created = json(&to_bytes(response.into_body(), usize::MAX).await.unwrap());
+        let playlist_id = created["id"].as_str().unwrap().to_string();
+
+        for track in [&second, &first] {
+            let response = app.clone().oneshot(post(format!("/api/playlists/{}/tracks", playlist_id), serde_json::json!({ "track_id": track.id() }))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let unknown_track = app.clone().oneshot(post(format!("/api/playlists/{}/tracks", playlist_id), serde_json::json!({ "track_id": Uuid::new_v4() }))).await.unwrap();
+        assert_eq!(unknown_track.status(), StatusCode::NOT_FOUND);
+
+        let blank_name = app.clone().oneshot(post("/api/playlists".to_string(), serde_json::json!({ "name": " " }))).await.unwrap();
+        assert_eq!(blank_name.status(), StatusCode::BAD_REQUEST);
+
+        let get = |uri: String| Request::builder().uri(uri).header(header::HOST, "music.local:8080").body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get(format!("/api/playlists/{}", playlist_id))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let detail = json(&to_bytes(response.into_body(), usize::MAX).await.unwrap());
+        assert_eq!(detail["name"], "Road Trip");
+        let names = detail["tracks"].as_array().unwrap().iter().map(|track| track["name"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["second", "first"]);
+
+        let response = app.clone().oneshot(get(format!("/api/playlists/{}/playlist.m3u", playlist_id))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"Road Trip.m3u\"; filename*=UTF-8''Road%20Trip.m3u");
+        let m3u = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        let urls = m3u.lines().filter(|line| !line.starts_with('#')).collect::<Vec<_>>();
+        assert_eq!(urls, vec![
+            format!("http://music.local:8080/api/tracks/{}/stream", second.id()),
+            format!("http://music.local:8080/api/tracks/{}/stream", first.id())
+        ]);
+
+        let missing = app.oneshot(get(format!("/api/playlists/{}", Uuid::new_v4()))).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_tracks_sort() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let album = seed_album(pool).await?;
+        for (name, duration, dir) in [("long", 300, "Sorting"), ("short", 30, "Sorting"), ("elsewhere", 60, "Other")] {
+            let track = Track::new(Uuid::new_v4(), name, *album.id(), duration, format!("/srv/Music/{}/{}.flac", dir, name).into(), 64, AudioFileType::Flac, Uploaded::Denis, None)?;
+            SqliteTracksRepository::new().save(pool, &track).await?;
+        }
+
+        let app = test_app(pool, RouterSettings { default_track_sort: TrackSort::Duration, ..RouterSettings::new("/srv/Music") }).await;
+        let get = |uri: &'static str| app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap());
+        let items = |uri: &'static str| {
+            let response = get(uri);
+            async move {
+                let response = response.await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+                body["items"].as_array().unwrap().clone()
+            }
+        };
+        let names = |uri: &'static str| async move {
+            items(uri).await.iter().map(|t| t["name"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("/api/tracks?under=sorting").await, vec!["short", "long"]);
+        assert_eq!(names("/api/tracks?under=sorting&sort=name").await, vec!["long", "short"]);
+        assert_eq!(names("/api/tracks?under=sorting&sort=bogus").await, vec!["long", "short"]);
+
+        // the paths handed out browse straight back in
+        assert_eq!(items("/api/tracks?under=sorting").await[0]["path"], "sorting/short.flac");
+
+        for outside in ["/api/tracks?under=../music", "/api/tracks?under=sorting/../../etc", "/api/tracks?under=/srv/music/sorting"] {
+            assert_eq!(get(outside).await.unwrap().status(), StatusCode::BAD_REQUEST, "{}", outside);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_browse_artists_albums_and_tracks() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let artist = Artist::new(Uuid::new_v4(), "tool")?;
+        let lonely = Artist::new(Uuid::new_v4(), "lonely")?;
+        let newer = Album::new(Uuid::new_v4(), "fear inoculum", *artist.id(), Some(2019))?;
+        let older = Album::new(Uuid::new_v4(), "lateralus", *artist.id(), Some(2001))?;
+        let track = Track::new(Uuid::new_v4(), "schism", *older.id(), 400, "browse/schism.flac".into(), 64, AudioFileType::Flac, Uploaded::Denis, None)?;
+        for artist in [&artist, &lonely] {
+            SqliteArtistsRepository::new().save(pool, artist).await?;
+        }
+        for album in [&newer, &older] {
+            SqliteAlbumsRepository::new().save(pool, album).await?;
+        }
+        SqliteTracksRepository::new().save(pool, &track).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let get = |uri: String| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or_default())
+            }
+        };
+        let names = |body: &serde_json::Value| body.as_array().unwrap().iter().map(|item| item["name"].as_str().unwrap().to_string()).collect::<Vec<_>>();
+
+        let (status, body) = get("/api/artists".to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(names(&body["items"]), vec!["lonely", "tool"]);
+
+        let (status, body) = get(format!("/api/artists/{}/albums", artist.id())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(names(&body), vec!["lateralus", "fear inoculum"]);
+
+        let (status, body) = get(format!("/api/artists/{}/albums", lonely.id())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+
+        let (status, body) = get(format!("/api/albums/{}/tracks", older.id())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(names(&body), vec!["schism"]);
+
+        let (status, body) = get(format!("/api/tracks/{}", track.id())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "schism");
+
+        for uri in ["/api/artists/{}/albums", "/api/albums/{}/tracks", "/api/tracks/{}"] {
+            let (status, _) = get(uri.replace("{}", &Uuid::new_v4().to_string())).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_honors_range() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let temp_dir = lowercase_temp_dir("range")?;
+        let track_path = temp_dir.path().join("track.mp3");
+        fs::write(&track_path, (0..100u8).collect::<Vec<_>>())?;
+
+        let album = seed_album(pool).await?;
+        let track = Track::new(Uuid::new_v4(), "track", *album.id(), 60, track_path, 100, AudioFileType::Mp3, Uploaded::Denis, None)?;
+        SqliteTracksRepository::new().save(pool, &track).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let uri = format!("/api/tracks/{}/stream", track.id());
+
+        let ranged = app.clone().oneshot(Request::builder().uri(&uri).header(header::RANGE, "bytes=10-19").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(ranged.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(ranged.headers()[header::CONTENT_RANGE], "bytes 10-19/100");
+        assert_eq!(ranged.headers()[header::CONTENT_TYPE], "audio/mpeg");
+        let body = to_bytes(ranged.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), (10..20u8).collect::<Vec<_>>().as_slice());
+
+        let full = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(to_bytes(full.into_body(), usize::MAX).await.unwrap().len(), 100);
+
+        let unknown = app.oneshot(Request::builder().uri(format!("/api/tracks/{}/stream", Uuid::new_v4())).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_track_detail_includes_album_and_artist() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        let artist = Artist::new(Uuid::new_v4(), "chevelle")?;
+        let album = Album::new(Uuid::new_v4(), "wonder what next", *artist.id(), Some(2002))?;
+        let track = Track::new(Uuid::new_v4(), "the red", *album.id(), 238, "t:/the red.flac".into(), 100, AudioFileType::Flac, Uploaded::Denis, None)?;
+        SqliteArtistsRepository::new().save(pool, &artist).await?;
+        SqliteAlbumsRepository::new().save(pool, &album).await?;
+        SqliteTracksRepository::new().save(pool, &track).await?;
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let get = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get(format!("/api/tracks/{}/detail", track.id()))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let detail = serde_json::from_slice::<serde_json::Value>(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(detail["track"]["name"], "the red");
+        assert_eq!(detail["album"]["name"], "wonder what next");
+        assert_eq!(detail["album"]["year"], 2002);
+        assert_eq!(detail["artist"]["name"], "chevelle");
+
+        let missing = app.oneshot(get(format!("/api/tracks/{}/detail", Uuid::new_v4()))).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_track_detail_with_dangling_album() -> Result<(), TestSetupError> {
+        let pool = test_pool().await;
+
+        // only possible with foreign keys off, e.g. a database edited by hand
+        let track = Track::new(Uuid::new_v4(), "orphan", Uuid::new_v4(), 60, "t:/orphan.flac".into(), 100, AudioFileType::Flac, Uploaded::Denis, None)?;
+        let mut conn = pool.acquire().await.expect("Failed to acquire a connection");
+        sqlx::query("PRAGMA foreign_keys = OFF;").execute(&mut *conn).await.expect("Failed to turn foreign keys off");
+        SqliteTracksRepository::new().save(&mut *conn, &track).await?;
+        drop(conn);
+
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+        let response = app.oneshot(Request::builder().uri(format!("/api/tracks/{}/detail", track.id())).body(Body::empty()).unwrap()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let detail = serde_json::from_slice::<serde_json::Value>(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(detail["track"]["name"], "orphan");
+        assert!(detail["album"].is_null());
+        assert!(detail["artist"].is_null());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_cancel_without_a_running_sync() {
+        let pool = test_pool().await;
+        let app = test_app(pool, RouterSettings::new("/music")).await;
+
+        let request = Request::builder().method(Method::POST).uri("/api/sync/cancel").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn test_sync_job_runs_one_at_a_time() {
+        let job = Arc::new(SyncJob::default());
+        assert!(!job.cancel());
+
+        let guard = SyncGuard::acquire(&job).expect("No sync should be running");
+        assert!(matches!(SyncGuard::acquire(&job), Err(WebLayerError::SyncInProgress)));
+
+        assert!(job.cancel());
+        assert!(guard.token().is_cancelled());
+
+        drop(guard);
+        assert!(SyncGuard::acquire(&job).is_ok_and(|guard| !guard.token().is_cancelled()));
+    }
+
+    #[test]
+    fn test_panicking_sync_releases_the_job() {
+        let job = Arc::new(SyncJob::default());
+
+        let panicked = std::thread::spawn({
+            let job = Arc::clone(&job);
+            move || {
+                let _guard = SyncGuard::acquire(&job).expect("No sync should be running");
+                panic!("sync has panicked");
+            }
+        }).join();
+
+        assert!(panicked.is_err());
+        assert!(SyncGuard::acquire(&job).is_ok());
+    }
+}
\ No newline at end of file
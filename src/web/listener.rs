@@ -0,0 +1,138 @@
+use std::{future::Future, io};
+
+use axum::Router;
+use tokio::net::TcpListener;
+
+use crate::utils::config::{ListenAddress, ServerConfig};
+
+/// Owner and group may connect to the socket, e.g. nginx running in the server's group.
+#[cfg(unix)]
+const SOCKET_MODE: u32 = 0o660;
+
+/// A bound listener, TCP or a Unix domain socket, see `ServerConfig::listen`.
+#[derive(Debug)]
+pub enum ServerListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener)
+}
+
+impl ServerListener {
+    /// Binds to the configured address. Unix sockets are not available on Windows,
+    /// there it logs an error and listens on `host` and `port` instead.
+    pub async fn bind(config: &ServerConfig) -> io::Result<(Self, ListenAddress)> {
+        let address = config.listen_address().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        match address {
+            ListenAddress::Tcp(tcp_address) => {
+                let listener = TcpListener::bind(tcp_address.as_str()).await?;
+                Ok((Self::Tcp(listener), ListenAddress::Tcp(tcp_address)))
+            },
+
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                let listener = bind_unix_socket(&path)?;
+                Ok((Self::Unix(listener), ListenAddress::Unix(path)))
+            },
+
+            #[cfg(not(unix))]
+            ListenAddress::Unix(path) => {
+                let tcp_address = format!("{}:{}", config.host, config.port);
+                log::error!("Unix sockets are not supported on this platform, ignoring server.listen = unix:{} and listening on {}", path.display(), tcp_address);
+
+                let listener = TcpListener::bind(tcp_address.as_str()).await?;
+                Ok((Self::Tcp(listener), ListenAddress::Tcp(tcp_address)))
+            }
+        }
+    }
+
+    pub async fn serve<F>(self, app: Router, shutdown: F) -> io::Result<()>
+    where F: Future<Output = ()> + Send + 'static
+    {
+        match self {
+            Self::Tcp(listener) => axum::serve(listener, app).with_graceful_shutdown(shutdown).await,
+            #[cfg(unix)]
+            Self::Unix(listener) => axum::serve(listener, app).with_graceful_shutdown(shutdown).await
+        }
+    }
+}
+
+/// A socket file left behind by a previous run that didn't shut down cleanly is removed first,
+/// anything else at `path` is left alone and the bind fails.
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::{fs, os::unix::fs::{FileTypeExt, PermissionsExt}};
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            log::info!("Removing the stale socket {}", path.display());
+            fs::remove_file(path)?;
+        },
+        Ok(_) => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err)
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(SOCKET_MODE))?;
+
+    Ok(listener)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, sync::Arc};
+
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::UnixStream};
+
+    use super::*;
+    use crate::{services::test_helpers::prepare_db, web::{routes::{create_router, RouterSettings}, StartupStatus}};
+
+    fn unix_server_config(path: &std::path::Path) -> ServerConfig {
+        toml::from_str(&format!("host = \"127.0.0.1\"\nport = 0\nlisten = \"unix:{}\"", path.display())).expect("Server config should parse")
+    }
+
+    #[tokio::test]
+    async fn test_serves_health_over_a_unix_socket() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create a temp dir");
+        let socket_path = temp_dir.path().join("home-server.sock");
+
+        // a socket file left behind by an earlier run
+        drop(std::os::unix::net::UnixListener::bind(&socket_path).expect("Failed to create a stale socket"));
+
+        let pool = Box::leak(Box::new(prepare_db().await.expect("Failed to prepare the DB")));
+        let app = create_router(pool, RouterSettings::new("/music"), Arc::new(StartupStatus::ready())).await.expect("Failed to create the router");
+
+        let (listener, address) = ServerListener::bind(&unix_server_config(&socket_path)).await.expect("Failed to bind the socket");
+        assert_eq!(address, ListenAddress::Unix(socket_path.clone()));
+        assert_eq!(std::fs::metadata(&socket_path).expect("Socket should exist").permissions().mode() & 0o777, SOCKET_MODE);
+
+        let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(listener.serve(app, async { shutdown_rx.await.ok(); }));
+
+        let mut stream = UnixStream::connect(&socket_path).await.expect("Failed to connect to the socket");
+        stream.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.expect("Failed to send the request");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.expect("Failed to read the response");
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("{\"starting\":false}"), "{}", response);
+
+        shutdown.send(()).expect("Server should still be running");
+        server.await.expect("Server task has panicked").expect("Server has failed");
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_replace_a_regular_file() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create a temp dir");
+        let path = temp_dir.path().join("not-a-socket");
+        std::fs::write(&path, "keep me").expect("Failed to write the file");
+
+        let err = ServerListener::bind(&unix_server_config(&path)).await.expect_err("Bind should fail");
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).expect("File should be untouched"), "keep me");
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{extract::{Request, State}, http::{Method, StatusCode}, middleware::Next, response::{IntoResponse, Response}};
+
+use super::StartupStatus;
+
+/// Lets only reading requests through. Used when `server.read_only` is set, so a publicly
+/// exposed instance can be listened to but not changed.
+pub async fn read_only_gate(request: Request, next: Next) -> Response {
+    if is_read_only_method(request.method()) {
+        return next.run(request).await;
+    }
+
+    (StatusCode::FORBIDDEN, "The server is in read-only mode").into_response()
+}
+
+/// Holds back mutating requests with 503 until the initial sync is done, they would race it.
+pub async fn startup_gate(State(startup): State<Arc<StartupStatus>>, request: Request, next: Next) -> Response {
+    if !startup.is_starting() || is_read_only_method(request.method()) {
+        return next.run(request).await;
+    }
+
+    (StatusCode::SERVICE_UNAVAILABLE, "The server is still syncing the library, try again in a moment").into_response()
+}
+
+fn is_read_only_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use tower::util::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{services::test_helpers::prepare_db, web::routes::{create_router, RouterSettings}};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_only_rejects_mutations_but_serves_reads() {
+        let pool: &'static _ = Box::leak(Box::new(prepare_db().await.expect("Failed to prepare the test db")));
+        let app = create_router(pool, RouterSettings { read_only: true, ..RouterSettings::new("/music") }, Arc::new(StartupStatus::ready())).await.expect("Failed to create the router");
+
+        let request = |method: Method, uri: String| Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+
+        let delete = app.clone().oneshot(request(Method::DELETE, format!("/api/tracks/{}", Uuid::new_v4()))).await.unwrap();
+        assert_eq!(delete.status(), StatusCode::FORBIDDEN);
+
+        let list = app.oneshot(request(Method::GET, "/api/tracks".to_string())).await.unwrap();
+        assert_eq!(list.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_startup_gate_until_initial_sync_is_done() {
+        let pool: &'static _ = Box::leak(Box::new(prepare_db().await.expect("Failed to prepare the test db")));
+        let startup = Arc::new(StartupStatus::starting());
+        let app = create_router(pool, RouterSettings::new("/music"), Arc::clone(&startup)).await.expect("Failed to create the router");
+
+        let request = |method: Method, uri: String| Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let starting = |app: axum::Router| async move {
+            let response = app.oneshot(request(Method::GET, "/health".to_string())).await.unwrap();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["starting"].as_bool().unwrap()
+        };
+
+        assert!(starting(app.clone()).await);
+        let delete = app.clone().oneshot(request(Method::DELETE, format!("/api/tracks/{}", Uuid::new_v4()))).await.unwrap();
+        assert_eq!(delete.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let index = app.clone().oneshot(request(Method::GET, "/".to_string())).await.unwrap();
+        assert_eq!(index.status(), StatusCode::OK);
+
+        startup.mark_ready();
+
+        assert!(!starting(app.clone()).await);
+        let delete = app.oneshot(request(Method::DELETE, format!("/api/tracks/{}", Uuid::new_v4()))).await.unwrap();
+        assert_eq!(delete.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,12 +1,24 @@
-use std::sync::Arc;
+use std::{collections::{hash_map::Entry, HashMap}, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard}};
 
+use tokio::sync::{OnceCell, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use axum::{http::StatusCode, response::{Html, IntoResponse, Response}};
 use sqlx::SqlitePool;
+use uuid::Uuid;
 
-use crate::repository::RepositoryError;
+use crate::{domain::{album::Album, track::{Track, TrackSort}, UploadedParseError, ValidationError}, repository::{RepositoryError, SqliteAlbumsRepository, SqliteTracksRepository}, web::{cache::EntityCache, dto::TrackDto}, services::{artwork::CoverCache, metadata_provider::{MetadataProviderError, MusicBrainzProvider}, ArtworkServiceError, ScanError, SyncServiceError, TranscodeError}, utils::config::ConfigLoadingError};
 
 pub mod routes;
 pub mod handlers;
 pub mod template_builders;
+pub mod middleware;
+pub mod cache;
+pub mod dto;
+pub mod listener;
+
+// Static on purpose: the fallback must not depend on the template engine that has just failed.
+const ERROR_PAGE_HTML: &str = include_str!("../../templates/error.html");
 
 #[derive(Debug, thiserror::Error)]
 pub enum WebLayerError {
@@ -14,11 +26,410 @@ pub enum WebLayerError {
     RepositoryError(#[from] RepositoryError),
 
     #[error("{0}")]
-    AskamaError(#[from] askama::Error)
+    AskamaError(#[from] askama::Error),
+
+    #[error("{0}")]
+    InvalidUploaded(#[from] UploadedParseError),
+
+    #[error("{0}")]
+    ValidationError(#[from] ValidationError),
+
+    #[error("{0}")]
+    ArtworkServiceError(#[from] ArtworkServiceError),
+
+    #[error("{0}")]
+    ConfigError(#[from] ConfigLoadingError),
+
+    #[error("{0}")]
+    MetadataProviderError(#[from] MetadataProviderError),
+
+    #[error("{0}")]
+    ScanError(#[from] ScanError),
+
+    #[error("{0}")]
+    SyncServiceError(#[from] SyncServiceError),
+
+    #[error("Failed to access the track file: {0}")]
+    FileAccessError(#[from] std::io::Error),
+
+    #[error("Background task has failed: {0}")]
+    TaskJoinError(#[from] tokio::task::JoinError),
+
+    #[error("Metadata lookup is disabled. Set `metadata_lookup = true` under [features] in config.toml to enable it.")]
+    MetadataLookupDisabled,
+
+    #[error("Resampling is disabled. Set `resample = true` under [features] in config.toml to enable it.")]
+    ResampleDisabled,
+
+    #[error("Track <{0}> is already being resampled.")]
+    ResampleInProgress(Uuid),
+
+    #[error("Track <{0}> is being streamed, resample it once playback is over.")]
+    TrackBeingStreamed(Uuid),
+
+    #[error("Album <{0}> has no cover.")]
+    CoverNotFound(Uuid),
+
+    #[error("File of track <{0}> is gone.")]
+    TrackFileMissing(Uuid),
+
+    #[error("{0}")]
+    TranscodeError(#[from] TranscodeError),
+
+    #[error("Too many tracks are being transcoded right now, try again later.")]
+    TranscodeBusy,
+
+    #[error("A sync is already running.")]
+    SyncInProgress,
+
+    #[error("No sync is running.")]
+    NoSyncRunning,
+
+    #[error("'{0}' is not a directory inside the music library.")]
+    OutsideOfLibrary(String)
+}
+
+impl IntoResponse for WebLayerError {
+    fn into_response(self) -> Response {
+        if let WebLayerError::AskamaError(err) = &self {
+            log::error!("Template rendering has failed: {}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html(ERROR_PAGE_HTML)).into_response();
+        }
+
+        let status = match &self {
+            WebLayerError::RepositoryError(RepositoryError::IdNotFound(_) | RepositoryError::RowNotFound) => StatusCode::NOT_FOUND,
+            WebLayerError::RepositoryError(RepositoryError::StorageFull(_)) => StatusCode::INSUFFICIENT_STORAGE,
+            WebLayerError::RepositoryError(RepositoryError::ConstraintViolation { .. }) => StatusCode::CONFLICT,
+            WebLayerError::RepositoryError(RepositoryError::ConnectionError(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            WebLayerError::RepositoryError(RepositoryError::UuidConversion(_) | RepositoryError::InvalidUuidLength(_)) => StatusCode::BAD_REQUEST,
+            WebLayerError::InvalidUploaded(_) | WebLayerError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WebLayerError::OutsideOfLibrary(_) => StatusCode::BAD_REQUEST,
+            WebLayerError::ArtworkServiceError(ArtworkServiceError::UnsupportedCoverSize(_)) => StatusCode::BAD_REQUEST,
+            WebLayerError::CoverNotFound(_) => StatusCode::NOT_FOUND,
+            WebLayerError::TrackFileMissing(_) => StatusCode::GONE,
+            WebLayerError::TranscodeError(TranscodeError::UnsupportedTarget(_)) => StatusCode::BAD_REQUEST,
+            WebLayerError::TranscodeBusy => StatusCode::SERVICE_UNAVAILABLE,
+            WebLayerError::MetadataProviderError(MetadataProviderError::AlbumNotFound { .. }) => StatusCode::NOT_FOUND,
+            WebLayerError::MetadataProviderError(_) => StatusCode::BAD_GATEWAY,
+            WebLayerError::MetadataLookupDisabled | WebLayerError::ResampleDisabled => StatusCode::SERVICE_UNAVAILABLE,
+            WebLayerError::ResampleInProgress(_) | WebLayerError::TrackBeingStreamed(_) => StatusCode::CONFLICT,
+            WebLayerError::SyncInProgress | WebLayerError::NoSyncRunning => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR
+        };
+
+        (status, self.to_string()).into_response()
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: &'static SqlitePool,
-    pub index_html: Arc<String>
+
+    /// Built on the first request after startup, a page built during the initial sync would be stale.
+    pub index_html: Arc<OnceCell<String>>,
+    pub metadata_provider: Arc<MusicBrainzProvider>,
+
+    /// Track files being streamed or resampled right now, see `TrackFileLocks`.
+    pub file_locks: Arc<TrackFileLocks>,
+
+    /// Album covers served so far, originals and thumbnails.
+    pub covers: Arc<CoverCache>,
+
+    /// One permit per running on the fly transcode, each of them is an ffmpeg process.
+    pub transcodes: Arc<Semaphore>,
+
+    /// `None` unless `entity_cache_size` is set under [server].
+    pub entity_cache: Option<Arc<EntityCache>>,
+
+    /// Whether track DTOs carry the absolute file path, see `expose_file_paths` under [server].
+    pub expose_file_paths: bool,
+
+    /// Track paths in the JSON responses are relative to this, see `TrackDto::path`.
+    pub music_root: PathBuf,
+
+    /// Order of the tracks list when the request doesn't pick one.
+    pub default_track_sort: TrackSort,
+
+    pub startup: Arc<StartupStatus>,
+
+    /// The sync started with `POST /api/sync`, if it's still running.
+    pub sync_job: Arc<SyncJob>
+}
+
+impl AppState {
+    /// `by_id_fetch` that goes through the entity cache when it's enabled.
+    pub async fn track_by_id(&self, id: Uuid) -> Result<Option<Track>, RepositoryError> {
+        match &self.entity_cache {
+            Some(cache) => cache.track(self.pool, id).await,
+            None => SqliteTracksRepository::new().by_id_fetch(self.pool, id).await
+        }
+    }
+
+    pub async fn album_by_id(&self, id: Uuid) -> Result<Option<Album>, RepositoryError> {
+        match &self.entity_cache {
+            Some(cache) => cache.album(self.pool, id).await,
+            None => SqliteAlbumsRepository::new().by_id_fetch(self.pool, id).await
+        }
+    }
+
+    /// Has to be called after the track was changed or deleted.
+    pub fn invalidate_track(&self, id: Uuid) {
+        if let Some(cache) = &self.entity_cache {
+            cache.invalidate_track(id);
+        }
+    }
+
+    /// Has to be called after the album was changed or deleted.
+    pub fn invalidate_album(&self, id: Uuid) {
+        if let Some(cache) = &self.entity_cache {
+            cache.invalidate_album(id);
+        }
+    }
+
+    /// Has to be called after writes that touch an unknown number of rows.
+    pub fn invalidate_all(&self) {
+        if let Some(cache) = &self.entity_cache {
+            cache.clear();
+        }
+    }
+
+    /// Every track that goes out as JSON should go through here so `expose_file_paths` is respected.
+    pub fn track_dto(&self, track: &Track) -> TrackDto {
+        TrackDto::in_library(track, &self.music_root, self.expose_file_paths)
+    }
+
+    pub fn track_dtos(&self, tracks: &[Track]) -> Vec<TrackDto> {
+        tracks.iter().map(|track| self.track_dto(track)).collect()
+    }
+}
+
+/// Whether the sync `serve` runs on startup is still going. The listener is bound before it
+/// finishes, so clients can tell a starting server from a hung one.
+#[derive(Debug, Default)]
+pub struct StartupStatus {
+    starting: AtomicBool
+}
+
+impl StartupStatus {
+    pub fn starting() -> Self {
+        Self { starting: AtomicBool::new(true) }
+    }
+
+    pub fn ready() -> Self {
+        Self::default()
+    }
+
+    pub fn is_starting(&self) -> bool {
+        self.starting.load(Ordering::Acquire)
+    }
+
+    pub fn mark_ready(&self) {
+        self.starting.store(false, Ordering::Release);
+    }
+}
+
+/// Cancellation token of the sync running in the background, there's at most one at a time.
+#[derive(Debug, Default)]
+pub struct SyncJob {
+    running: Mutex<Option<CancellationToken>>
+}
+
+impl SyncJob {
+    fn lock(&self) -> MutexGuard<'_, Option<CancellationToken>> {
+        self.running.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Trips the running sync, `false` if there's none. The sync stops at its next check, see
+    /// `MusicLibSyncService::with_cancellation`.
+    pub fn cancel(&self) -> bool {
+        match self.lock().as_ref() {
+            Some(token) => {
+                token.cancel();
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// Marks a sync as running for as long as it's alive, so a sync that panics doesn't block the next one.
+pub struct SyncGuard {
+    job: Arc<SyncJob>,
+    token: CancellationToken
+}
+
+impl SyncGuard {
+    pub fn acquire(job: &Arc<SyncJob>) -> Result<Self, WebLayerError> {
+        let mut running = job.lock();
+        if running.is_some() {
+            return Err(WebLayerError::SyncInProgress);
+        }
+
+        let token = CancellationToken::new();
+        *running = Some(token.clone());
+
+        Ok(Self { job: Arc::clone(job), token })
+    }
+
+    /// Tripped by `SyncJob::cancel`.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for SyncGuard {
+    fn drop(&mut self) {
+        self.job.lock().take();
+    }
+}
+
+enum FileUse {
+    Resampling,
+    /// Number of responses reading the file.
+    Streaming(usize)
+}
+
+/// What each track file is used for right now. A file being streamed is never resampled and the other way
+/// around: an in-place resample swaps the file out from under the reader, and Windows refuses to replace
+/// a file that's open.
+#[derive(Default)]
+pub struct TrackFileLocks {
+    in_use: Mutex<HashMap<Uuid, FileUse>>
+}
+
+impl TrackFileLocks {
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, FileUse>> {
+        // a poisoned lock only means some handler panicked, the map itself is still fine
+        self.in_use.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Marks a track as being resampled for as long as it's alive.
+pub struct ResampleGuard {
+    locks: Arc<TrackFileLocks>,
+    track_id: Uuid
+}
+
+impl ResampleGuard {
+    pub fn acquire(locks: &Arc<TrackFileLocks>, track_id: Uuid) -> Result<Self, WebLayerError> {
+        match locks.lock().entry(track_id) {
+            Entry::Occupied(entry) => match entry.get() {
+                FileUse::Resampling => return Err(WebLayerError::ResampleInProgress(track_id)),
+                FileUse::Streaming(_) => return Err(WebLayerError::TrackBeingStreamed(track_id))
+            },
+            Entry::Vacant(entry) => { entry.insert(FileUse::Resampling); }
+        }
+
+        Ok(Self { locks: Arc::clone(locks), track_id })
+    }
+}
+
+impl Drop for ResampleGuard {
+    fn drop(&mut self) {
+        self.locks.lock().remove(&self.track_id);
+    }
+}
+
+/// Marks a track as being read by one more response, for as long as it's alive. Any number of streams share a file.
+pub struct StreamGuard {
+    locks: Arc<TrackFileLocks>,
+    track_id: Uuid
+}
+
+impl StreamGuard {
+    pub fn acquire(locks: &Arc<TrackFileLocks>, track_id: Uuid) -> Result<Self, WebLayerError> {
+        match locks.lock().entry(track_id).or_insert(FileUse::Streaming(0)) {
+            FileUse::Resampling => return Err(WebLayerError::ResampleInProgress(track_id)),
+            FileUse::Streaming(readers) => *readers += 1
+        }
+
+        Ok(Self { locks: Arc::clone(locks), track_id })
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut in_use = self.locks.lock();
+
+        if let Entry::Occupied(mut entry) = in_use.entry(self.track_id)
+            && let FileUse::Streaming(readers) = entry.get_mut()
+        {
+            *readers -= 1;
+            if *readers == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{self, Display};
+
+    use askama::Template;
+    use axum::{body::to_bytes, http::header::CONTENT_TYPE};
+
+    use super::*;
+
+    struct FailingDisplay;
+
+    impl Display for FailingDisplay {
+        fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            Err(fmt::Error)
+        }
+    }
+
+    #[derive(Template)]
+    #[template(source = "<p>{{ value }}</p>", ext = "html")]
+    struct BrokenTemplate {
+        value: FailingDisplay
+    }
+
+    #[tokio::test]
+    async fn test_askama_error_renders_fallback_page() {
+        let render_err = BrokenTemplate { value: FailingDisplay }.render()
+            .expect_err("Rendering with a failing Display impl should fail");
+
+        let response = WebLayerError::from(render_err).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let content_type = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+        assert_eq!(content_type, Some("text/html; charset=utf-8"));
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.expect("Failed to read the response body");
+        assert_eq!(body, ERROR_PAGE_HTML.as_bytes());
+    }
+
+    #[test]
+    fn test_resample_guard_rejects_concurrent_resample() {
+        let locks = Arc::new(TrackFileLocks::default());
+        let track_id = Uuid::new_v4();
+
+        let guard = ResampleGuard::acquire(&locks, track_id).expect("First resample should start");
+        assert!(matches!(ResampleGuard::acquire(&locks, track_id), Err(WebLayerError::ResampleInProgress(_))));
+        assert!(ResampleGuard::acquire(&locks, Uuid::new_v4()).is_ok());
+
+        drop(guard);
+        assert!(ResampleGuard::acquire(&locks, track_id).is_ok());
+    }
+
+    #[test]
+    fn test_streams_and_resample_exclude_each_other() {
+        let locks = Arc::new(TrackFileLocks::default());
+        let track_id = Uuid::new_v4();
+
+        let first = StreamGuard::acquire(&locks, track_id).expect("Streams should start on an idle file");
+        let second = StreamGuard::acquire(&locks, track_id).expect("Streams should share a file");
+        assert!(matches!(ResampleGuard::acquire(&locks, track_id), Err(WebLayerError::TrackBeingStreamed(_))));
+
+        drop(first);
+        assert!(matches!(ResampleGuard::acquire(&locks, track_id), Err(WebLayerError::TrackBeingStreamed(_))));
+
+        drop(second);
+        let resample = ResampleGuard::acquire(&locks, track_id).expect("Resample should start once the streams are done");
+        assert!(matches!(StreamGuard::acquire(&locks, track_id), Err(WebLayerError::ResampleInProgress(_))));
+
+        drop(resample);
+        assert!(StreamGuard::acquire(&locks, track_id).is_ok());
+    }
 }
\ No newline at end of file
@@ -1,21 +1,148 @@
-use std::sync::Arc;
+use std::{num::NonZeroUsize, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::sync::{OnceCell, Semaphore};
 
 use sqlx::SqlitePool;
-use tower_http::services::{ServeDir};
-use axum::{routing::{get}, Router};
+use tower_http::{services::{ServeDir}, timeout::TimeoutLayer};
+use axum::{middleware::{from_fn, from_fn_with_state}, routing::{get, patch, post}, Router};
 
-use crate::web::{handlers::{serve_index, serve_track}, AppState, WebLayerError};
+use crate::domain::track::TrackSort;
+use crate::services::{artwork::CoverCache, metadata_provider::MusicBrainzProvider};
+use crate::utils::config::Config;
+use crate::web::{cache::EntityCache, middleware::{read_only_gate, startup_gate}, handlers::{add_playlist_track, album_cover, album_tracks, albums_without_art, artist_albums, incomplete_albums, create_playlist, delete_track, get_playlist, health, list_playlists, playlist_m3u_file, enrich_album, export_tracks_csv, get_track, track_detail, head_track, list_artists, list_tracks, resample_track, scan_preview, serve_index, serve_track, start_sync, cancel_sync, sync_preview, unprobed_tracks, update_track_uploaded}, AppState, StartupStatus, SyncJob, TrackFileLocks, WebLayerError};
 use super::template_builders::build_index_page;
 
-pub async fn create_router(pool: &'static SqlitePool) -> Result<Router<()>, WebLayerError> {
-    let index_html = build_index_page(pool).await?;
-    let app_state = AppState { pool, index_html: Arc::new(index_html) };
+/// Upper bound on ffmpeg processes spawned for `?transcode=`.
+const MAX_CONCURRENT_TRANSCODES: usize = 2;
+
+/// What the router takes from the config, passed in so the router doesn't read the global config itself.
+#[derive(Debug, Clone)]
+pub struct RouterSettings {
+    /// Every route answers with 408 once this is exceeded, except the ones listed on `create_router`.
+    pub request_timeout: Duration,
+
+    /// Every request that isn't GET/HEAD/OPTIONS is rejected with 403.
+    pub read_only: bool,
+
+    /// Turns on the LRU of tracks and albums; unset or 0 keeps it off.
+    pub entity_cache_size: Option<usize>,
+
+    /// Adds the absolute path on disk to every track in the JSON responses.
+    pub expose_file_paths: bool,
+
+    /// Orders `/api/tracks` when the request doesn't ask for an order.
+    pub default_track_sort: TrackSort,
+
+    /// Track paths in the JSON responses are relative to this.
+    pub music_root: PathBuf
+}
+
+impl RouterSettings {
+    /// 30 second timeout, writable, no entity cache, no file paths and tracks sorted by name.
+    pub fn new<P: Into<PathBuf>>(music_root: P) -> Self {
+        Self {
+            request_timeout: Duration::from_secs(30),
+            read_only: false,
+            entity_cache_size: None,
+            expose_file_paths: false,
+            default_track_sort: TrackSort::Name,
+            music_root: music_root.into()
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            request_timeout: Duration::from_secs(config.server.request_timeout_secs),
+            read_only: config.server.read_only,
+            entity_cache_size: config.server.entity_cache_size,
+            expose_file_paths: config.server.expose_file_paths,
+            default_track_sort: config.server.default_track_sort,
+            music_root: config.media.music_path.clone()
+        }
+    }
+}
+
+/// Builds the app router. Every route answers with 408 once `settings.request_timeout` is exceeded, except:
+///
+/// * `/tracks/{id}` and `/api/tracks/{id}/stream` - streaming a track takes as long as the track plays, transcoded or not
+/// * `/api/export/tracks.csv` - the export is streamed and grows with the library
+/// * `/api/tracks/{id}/resample` - ffmpeg keeps running after a timeout, the response would just get lost
+/// * `/api/scan/preview` and `/api/sync/preview` - both scan the whole library, the scan would carry on unanswered
+/// * `/static/*` - plain file downloads
+///
+/// The rest of `settings` is described on `RouterSettings`.
+/// While `startup` says the initial sync is running, mutating requests get 503 and `/health` reports it.
+pub async fn create_router(pool: &'static SqlitePool, settings: RouterSettings, startup: Arc<StartupStatus>) -> Result<Router<()>, WebLayerError> {
+    let (app, _) = create_router_and_state(pool, settings, startup).await?;
 
-    let app: Router<()> = Router::new()
+    Ok(app)
+}
+
+/// Same as `create_router`, along with a handle on the state the handlers share, for work done outside
+/// of a request that has to keep it up to date, like clearing the entity cache after the initial sync.
+pub async fn create_router_and_state(pool: &'static SqlitePool, settings: RouterSettings, startup: Arc<StartupStatus>) -> Result<(Router<()>, AppState), WebLayerError> {
+    // built right away when possible, so a broken template fails the startup instead of the first request
+    let index_html = match startup.is_starting() {
+        true => OnceCell::new(),
+        false => OnceCell::new_with(Some(build_index_page(pool).await?))
+    };
+
+    let app_state = AppState {
+        pool,
+        index_html: Arc::new(index_html),
+        metadata_provider: Arc::new(MusicBrainzProvider::new()),
+        file_locks: Arc::new(TrackFileLocks::default()),
+        covers: Arc::new(CoverCache::default()),
+        transcodes: Arc::new(Semaphore::new(MAX_CONCURRENT_TRANSCODES)),
+        entity_cache: settings.entity_cache_size.and_then(NonZeroUsize::new).map(|capacity| Arc::new(EntityCache::new(capacity))),
+        expose_file_paths: settings.expose_file_paths,
+        music_root: settings.music_root,
+        default_track_sort: settings.default_track_sort,
+        startup: Arc::clone(&startup),
+        sync_job: Arc::new(SyncJob::default())
+    };
+
+    let timed: Router<AppState> = Router::new()
         .route("/", get(serve_index))
+        .route("/health", get(health))
+        .route("/api/tracks", get(list_tracks))
+        .route("/api/tracks/{id}", get(get_track).delete(delete_track))
+        .route("/api/tracks/{id}/detail", get(track_detail))
+        .route("/api/tracks/{id}/uploaded", patch(update_track_uploaded))
+        .route("/api/maintenance/albums-without-art", get(albums_without_art))
+        .route("/api/maintenance/unprobed", get(unprobed_tracks))
+        .route("/api/maintenance/incomplete-albums", get(incomplete_albums))
+        .route("/api/artists", get(list_artists))
+        .route("/api/artists/{id}/albums", get(artist_albums))
+        .route("/api/albums/{id}/tracks", get(album_tracks))
+        .route("/api/albums/{id}/enrich", post(enrich_album))
+        .route("/api/albums/{id}/cover", get(album_cover))
+        .route("/api/sync", post(start_sync))
+        .route("/api/sync/cancel", post(cancel_sync))
+        .route("/api/playlists", get(list_playlists).post(create_playlist))
+        .route("/api/playlists/{id}", get(get_playlist))
+        .route("/api/playlists/{id}/tracks", post(add_playlist_track))
+        .route("/api/playlists/{id}/playlist.m3u", get(playlist_m3u_file))
+        .layer(TimeoutLayer::new(settings.request_timeout));
+
+    // long by design, a timeout here would cut off perfectly healthy transfers
+    let untimed: Router<AppState> = Router::new()
         .route("/tracks/{id}", get(serve_track)) 
-        .nest_service("/static", ServeDir::new("static"))
-        .with_state(app_state);
+        .route("/api/tracks/{id}/stream", get(serve_track).head(head_track))
+        .route("/api/export/tracks.csv", get(export_tracks_csv))
+        .route("/api/tracks/{id}/resample", post(resample_track))
+        // scan the whole library, which can take longer than the timeout on a big one
+        .route("/api/scan/preview", get(scan_preview))
+        .route("/api/sync/preview", get(sync_preview))
+        .nest_service("/static", ServeDir::new("static"));
 
-    Ok(app)
+    let mut app: Router<AppState> = timed.merge(untimed);
+
+    app = app.layer(from_fn_with_state(startup, startup_gate));
+
+    if settings.read_only {
+        app = app.layer(from_fn(read_only_gate));
+    }
+
+    Ok((app.with_state(app_state.clone()), app_state))
 }
\ No newline at end of file